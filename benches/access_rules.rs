@@ -0,0 +1,70 @@
+//! Access rule evaluation cost as rule counts grow.
+//!
+//! Run with `cargo bench --bench access_rules`. Each request is evaluated by
+//! a client no rule denies, so every compiled matcher is consulted.
+
+use std::{hint::black_box, time::Instant};
+
+use cf_proxy::{AccessMatcher, AccessRule, ClientAttributes};
+
+/// Requests evaluated per rule count
+const ITERATIONS: u32 = 20_000;
+
+fn rule(rule_type: &str, pattern: String) -> AccessRule {
+    AccessRule {
+        rule_type: rule_type.to_string(),
+        pattern,
+        label: None,
+        id: None,
+    }
+}
+
+/// A mix of CIDR, country and user agent rules, `count` of each
+fn rules(count: usize) -> Vec<AccessRule> {
+    let mut rules = Vec::with_capacity(count * 3);
+    for i in 0..count {
+        rules.push(rule(
+            "deny_ip",
+            format!("10.{}.{}.0/24", i / 256 % 256, i % 256),
+        ));
+        rules.push(rule(
+            "deny_country",
+            format!(
+                "{}{}",
+                char::from(b'A' + (i / 26 % 26) as u8),
+                char::from(b'A' + (i % 26) as u8)
+            ),
+        ));
+        rules.push(rule("deny_user_agent", format!("(?i)scanner-{i}/\\d+")));
+    }
+    rules
+}
+
+fn main() {
+    let client = ClientAttributes {
+        ip: Some("203.0.113.7".to_string()),
+        country: Some("ZZ".to_string()),
+        continent: Some("EU".to_string()),
+        user_agent: Some(
+            "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko)".to_string(),
+        ),
+    };
+
+    for count in [10, 100, 1_000] {
+        let rules = rules(count);
+        let start = Instant::now();
+        let matcher = AccessMatcher::compile(&rules);
+        let compile = start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            black_box(matcher.deny_client(black_box(&client)));
+        }
+        let per_request = start.elapsed() / ITERATIONS;
+
+        println!(
+            "{:>5} rules: compile {compile:>10.2?}, evaluate {per_request:>10.2?}/request",
+            rules.len()
+        );
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
+
+use regex::{Regex, RegexSet};
+use worker::*;
+
+use crate::{config::AccessRule, utils};
+
+/// IP network parsed from an address or CIDR pattern
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpNetwork {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Parse "1.2.3.4", "10.0.0.0/8" or "2001:db8::/32"
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let (address, prefix) = match pattern.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix.parse::<u8>().ok()?)),
+            None => (pattern, None),
+        };
+
+        let network: IpAddr = address.trim().parse().ok()?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max_prefix);
+        if prefix > max_prefix {
+            return None;
+        }
+
+        Some(Self { network, prefix })
+    }
+
+    /// First and last address of an IPv4 network
+    pub fn v4_bounds(&self) -> Option<(u32, u32)> {
+        match self.network {
+            IpAddr::V4(network) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                let start = u32::from(network) & mask;
+                Some((start, start | !mask))
+            }
+            IpAddr::V6(_) => None,
+        }
+    }
+
+    /// First and last address of an IPv6 network
+    pub fn v6_bounds(&self) -> Option<(u128, u128)> {
+        match self.network {
+            IpAddr::V6(network) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                let start = u128::from(network) & mask;
+                Some((start, start | !mask))
+            }
+            IpAddr::V4(_) => None,
+        }
+    }
+
+    /// Check if address belongs to this network
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Condition of a rule that labels matching requests instead of blocking them
+#[derive(Debug)]
+enum LabelCondition {
+    Network(IpNetwork),
+    Country(String),
+    Continent(String),
+    Asn(u32),
+    UserAgent(Regex),
+}
+
+/// Access rules compiled once at config load
+#[derive(Debug, Default)]
+pub struct AccessMatcher {
+    deny_networks: Vec<(IpNetwork, String)>,
+    allow_countries: HashSet<String>,
+    allow_countries_rule: String,
+    deny_countries: HashMap<String, String>,
+    allow_continents: HashSet<String>,
+    allow_continents_rule: String,
+    deny_continents: HashMap<String, String>,
+    deny_user_agents: Option<RegexSet>,
+    deny_user_agent_rules: Vec<String>,
+    deny_lists: Vec<String>,
+    allow_lists: Vec<String>,
+    labels: Vec<(LabelCondition, String)>,
+    label_lists: Vec<(String, String)>,
+    min_reputation: Option<(u8, String)>,
+}
+
+/// Request attributes access rules are evaluated against
+#[derive(Debug, Clone, Default)]
+pub struct ClientAttributes {
+    pub ip: Option<String>, // CF-Connecting-IP
+    pub country: Option<String>,
+    pub continent: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientAttributes {
+    pub fn from_request(req: &Request) -> Self {
+        let cf = req.cf();
+        Self {
+            ip: req.headers().get("CF-Connecting-IP").ok().flatten(),
+            country: cf.and_then(|cf| cf.country()),
+            continent: cf.and_then(|cf| cf.continent()),
+            user_agent: req.headers().get("User-Agent").ok().flatten(),
+        }
+    }
+}
+
+/// Denied access with the rule responsible for it
+#[derive(Debug, Clone)]
+pub struct AccessDenial {
+    pub reason: String,
+    pub rule_id: String,
+    pub geo: bool, // Denied by a country or continent rule
+}
+
+impl AccessMatcher {
+    /// Compile access rules into sets and a single regex set
+    pub fn compile(rules: &[AccessRule]) -> Self {
+        let mut matcher = Self::default();
+        let mut user_agent_patterns = Vec::new();
+
+        for rule in rules {
+            let rule_id = rule
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("{}:{}", rule.rule_type, rule.pattern));
+            match rule.rule_type.as_str() {
+                "deny_ip" => match IpNetwork::parse(&rule.pattern) {
+                    Some(network) => matcher.deny_networks.push((network, rule_id)),
+                    None => console_log!("Ignoring invalid deny_ip pattern: {}", rule.pattern),
+                },
+                "allow_country" => {
+                    // The allowlist is reported under the id of its first rule
+                    if matcher.allow_countries.is_empty() {
+                        matcher.allow_countries_rule = rule.id.clone().unwrap_or(rule_id);
+                    }
+                    matcher.allow_countries.insert(rule.pattern.to_uppercase());
+                }
+                "deny_country" => {
+                    matcher
+                        .deny_countries
+                        .insert(rule.pattern.to_uppercase(), rule_id);
+                }
+                "allow_continent" => {
+                    if matcher.allow_continents.is_empty() {
+                        matcher.allow_continents_rule = rule.id.clone().unwrap_or(rule_id);
+                    }
+                    matcher.allow_continents.insert(rule.pattern.to_uppercase());
+                }
+                "deny_continent" => {
+                    matcher
+                        .deny_continents
+                        .insert(rule.pattern.to_uppercase(), rule_id);
+                }
+                "deny_user_agent" => {
+                    // Validate individually so one bad pattern doesn't disable the rest
+                    if Regex::new(&rule.pattern).is_ok() {
+                        user_agent_patterns.push(rule.pattern.clone());
+                        matcher.deny_user_agent_rules.push(rule_id);
+                    } else {
+                        console_log!("Ignoring invalid deny_user_agent pattern: {}", rule.pattern);
+                    }
+                }
+                // Large lists live in KV and are evaluated by access_lists
+                "deny_list" => matcher.deny_lists.push(rule.pattern.clone()),
+                "allow_list" => matcher.allow_lists.push(rule.pattern.clone()),
+                // Evaluated once the client's reputation is known
+                "deny_reputation_below" => match rule.pattern.trim().parse::<u8>() {
+                    Ok(threshold) => matcher.min_reputation = Some((threshold, rule_id)),
+                    Err(_) => console_log!(
+                        "Ignoring invalid deny_reputation_below pattern: {}",
+                        rule.pattern
+                    ),
+                },
+                rule_type if rule_type.starts_with("label_") => matcher.compile_label(rule),
+                _ => {}
+            }
+        }
+
+        if !user_agent_patterns.is_empty() {
+            matcher.deny_user_agents = RegexSet::new(&user_agent_patterns).ok();
+        }
+
+        matcher
+    }
+
+    /// Compile a "label_*" rule, ignoring it if pattern or label is invalid
+    fn compile_label(&mut self, rule: &AccessRule) {
+        let label = match rule.label.as_deref().map(str::trim) {
+            Some(label) if !label.is_empty() && !label.contains(',') => label.to_string(),
+            _ => {
+                console_log!("Ignoring {} rule without a valid label", rule.rule_type);
+                return;
+            }
+        };
+
+        let condition = match rule.rule_type.as_str() {
+            "label_ip" => IpNetwork::parse(&rule.pattern).map(LabelCondition::Network),
+            "label_country" => Some(LabelCondition::Country(rule.pattern.to_uppercase())),
+            "label_continent" => Some(LabelCondition::Continent(rule.pattern.to_uppercase())),
+            "label_asn" => rule
+                .pattern
+                .trim_start_matches("AS")
+                .parse()
+                .ok()
+                .map(LabelCondition::Asn),
+            "label_user_agent" => Regex::new(&rule.pattern)
+                .ok()
+                .map(LabelCondition::UserAgent),
+            "label_list" => {
+                self.label_lists.push((rule.pattern.clone(), label));
+                return;
+            }
+            _ => None,
+        };
+
+        match condition {
+            Some(condition) => self.labels.push((condition, label)),
+            None => console_log!(
+                "Ignoring invalid {} pattern: {}",
+                rule.rule_type,
+                rule.pattern
+            ),
+        }
+    }
+
+    /// KV-stored lists whose matching clients get a label, as (list, label)
+    pub fn label_lists(&self) -> &[(String, String)] {
+        &self.label_lists
+    }
+
+    /// Labels of all label rules matching the request
+    pub fn labels(&self, req: &Request) -> Vec<String> {
+        if self.labels.is_empty() {
+            return Vec::new();
+        }
+
+        let ip = req
+            .headers()
+            .get("CF-Connecting-IP")
+            .ok()
+            .flatten()
+            .and_then(|ip| ip.parse::<IpAddr>().ok());
+        let cf = req.cf();
+        let country = cf.and_then(|cf| cf.country()).map(|c| c.to_uppercase());
+        let continent = cf.and_then(|cf| cf.continent()).map(|c| c.to_uppercase());
+        let asn = cf.map(|cf| cf.asn());
+        let user_agent = req.headers().get("User-Agent").ok().flatten();
+
+        let mut labels: Vec<String> = Vec::new();
+        for (condition, label) in &self.labels {
+            let matched = match condition {
+                LabelCondition::Network(network) => ip.is_some_and(|ip| network.contains(&ip)),
+                LabelCondition::Country(code) => country.as_ref() == Some(code),
+                LabelCondition::Continent(code) => continent.as_ref() == Some(code),
+                LabelCondition::Asn(number) => asn == Some(*number),
+                LabelCondition::UserAgent(regex) => {
+                    user_agent.as_deref().is_some_and(|ua| regex.is_match(ua))
+                }
+            };
+            if matched && !labels.contains(label) {
+                labels.push(label.clone());
+            }
+        }
+        labels
+    }
+
+    /// Names of KV-stored lists denying matching clients
+    pub fn deny_lists(&self) -> &[String] {
+        &self.deny_lists
+    }
+
+    /// Names of KV-stored lists of which clients must match at least one
+    pub fn allow_lists(&self) -> &[String] {
+        &self.allow_lists
+    }
+
+    /// Deny clients whose reputation score is below a `deny_reputation_below` threshold
+    pub fn deny_reputation(&self, score: u8) -> Option<AccessDenial> {
+        let (threshold, rule_id) = self.min_reputation.as_ref()?;
+        (score < *threshold).then(|| AccessDenial {
+            reason: format!("reputation: {score}"),
+            rule_id: rule_id.clone(),
+            geo: false,
+        })
+    }
+
+    /// Evaluate request, returning the denial if access is denied
+    pub fn deny(&self, req: &Request) -> Option<AccessDenial> {
+        self.deny_client(&ClientAttributes::from_request(req))
+    }
+
+    /// Evaluate the client attributes of a request, returning the denial if
+    /// access is denied
+    pub fn deny_client(&self, client: &ClientAttributes) -> Option<AccessDenial> {
+        let denial = |reason: String, rule_id: &str, geo: bool| AccessDenial {
+            reason,
+            rule_id: rule_id.to_string(),
+            geo,
+        };
+
+        if !self.deny_networks.is_empty() {
+            if let Some(ip) = &client.ip {
+                if let Ok(addr) = ip.parse::<IpAddr>() {
+                    if let Some((_, rule_id)) = self
+                        .deny_networks
+                        .iter()
+                        .find(|(net, _)| net.contains(&addr))
+                    {
+                        return Some(denial(format!("IP: {ip}"), rule_id, false));
+                    }
+                }
+            }
+        }
+
+        if !self.allow_countries.is_empty() || !self.deny_countries.is_empty() {
+            if let Some(country) = &client.country {
+                let country = country.to_uppercase();
+                if let Some(rule_id) = self.deny_countries.get(&country) {
+                    return Some(denial(format!("country: {country}"), rule_id, true));
+                }
+                if !self.allow_countries.is_empty() && !self.allow_countries.contains(&country) {
+                    return Some(denial(
+                        format!("country: {country}"),
+                        &self.allow_countries_rule,
+                        true,
+                    ));
+                }
+            }
+        }
+
+        if !self.allow_continents.is_empty() || !self.deny_continents.is_empty() {
+            if let Some(continent) = &client.continent {
+                let continent = continent.to_uppercase();
+                if let Some(rule_id) = self.deny_continents.get(&continent) {
+                    return Some(denial(format!("continent: {continent}"), rule_id, true));
+                }
+                if !self.allow_continents.is_empty() && !self.allow_continents.contains(&continent)
+                {
+                    return Some(denial(
+                        format!("continent: {continent}"),
+                        &self.allow_continents_rule,
+                        true,
+                    ));
+                }
+            }
+        }
+
+        if let Some(user_agents) = &self.deny_user_agents {
+            if let Some(user_agent) = &client.user_agent {
+                if let Some(index) = user_agents.matches(user_agent).iter().next() {
+                    return Some(denial(
+                        format!("User-Agent: {user_agent}"),
+                        &self.deny_user_agent_rules[index],
+                        false,
+                    ));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Header name matcher compiled from a list of names and `*` globs
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMatcher {
+    exact: HashSet<String>,
+    globs: Vec<String>,
+}
+
+impl HeaderMatcher {
+    pub fn new(patterns: &[String]) -> Self {
+        let mut matcher = Self::default();
+        for pattern in patterns {
+            let pattern = pattern.to_lowercase();
+            if pattern.contains('*') {
+                matcher.globs.push(pattern);
+            } else {
+                matcher.exact.insert(pattern);
+            }
+        }
+        matcher
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.globs.is_empty()
+    }
+
+    /// Check lowercase header name against the patterns
+    pub fn matches(&self, name: &str) -> bool {
+        self.exact.contains(name)
+            || self
+                .globs
+                .iter()
+                .any(|glob| utils::path_matches(glob, name))
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    rc::Rc,
+};
+
+use chrono::Utc;
+use worker::{kv::KvStore, *};
+
+use crate::{
+    access::IpNetwork,
+    admin::{AdminIdentity, AuditEntry, record_audit},
+    config::ProxyConfig,
+    webhooks::{ProxyEvent, WebhookEmitter},
+};
+
+/// KV key prefix for compiled access lists
+const LIST_PREFIX: &str = "access_list:";
+
+/// Header of the compiled list format
+const MAGIC: &[u8; 4] = b"CFAL";
+
+/// Version of the compiled list format
+const FORMAT_VERSION: u8 = 1;
+
+/// Maximum number of invalid lines reported when compiling a list
+const MAX_REPORTED_INVALID: usize = 20;
+
+/// Missing lists, and failed reads without a good copy, are retried after this long
+const NEGATIVE_TTL_MS: f64 = 10_000.0;
+
+thread_local! {
+    /// Compiled lists memoized per isolate with the time they expire
+    static LOADED_LISTS: RefCell<HashMap<String, (f64, Rc<AccessList>)>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Large IP range / ASN list, stored in KV as a compiled binary blob
+#[derive(Debug, Default)]
+pub struct AccessList {
+    v4: Vec<(u32, u32)>,
+    v6: Vec<(u128, u128)>,
+    asns: Vec<u32>,
+}
+
+impl AccessList {
+    /// Parse one entry per line: IP, CIDR or "AS<number>" ('#' starts a comment).
+    /// Returns the list and the lines that could not be parsed.
+    pub fn parse(text: &str) -> (Self, Vec<String>) {
+        let mut list = Self::default();
+        let mut invalid = Vec::new();
+
+        for line in text.lines() {
+            let entry = line.split('#').next().unwrap_or("").trim();
+            if entry.is_empty() {
+                continue;
+            }
+
+            if let Some(asn) = entry
+                .strip_prefix("AS")
+                .or_else(|| entry.strip_prefix("as"))
+            {
+                match asn.parse::<u32>() {
+                    Ok(asn) => list.asns.push(asn),
+                    Err(_) => invalid.push(entry.to_string()),
+                }
+                continue;
+            }
+
+            match IpNetwork::parse(entry) {
+                Some(network) => {
+                    if let Some(bounds) = network.v4_bounds() {
+                        list.v4.push(bounds);
+                    } else if let Some(bounds) = network.v6_bounds() {
+                        list.v6.push(bounds);
+                    }
+                }
+                None => invalid.push(entry.to_string()),
+            }
+        }
+
+        list.v4 = merge_ranges(list.v4, |value| value.checked_add(1));
+        list.v6 = merge_ranges(list.v6, |value| value.checked_add(1));
+        list.asns.sort_unstable();
+        list.asns.dedup();
+        (list, invalid)
+    }
+
+    /// Serialize to the compiled binary format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            MAGIC.len() + 13 + self.v4.len() * 8 + self.v6.len() * 32 + self.asns.len() * 4,
+        );
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(FORMAT_VERSION);
+
+        bytes.extend_from_slice(&(self.v4.len() as u32).to_be_bytes());
+        for (start, end) in &self.v4 {
+            bytes.extend_from_slice(&start.to_be_bytes());
+            bytes.extend_from_slice(&end.to_be_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.v6.len() as u32).to_be_bytes());
+        for (start, end) in &self.v6 {
+            bytes.extend_from_slice(&start.to_be_bytes());
+            bytes.extend_from_slice(&end.to_be_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.asns.len() as u32).to_be_bytes());
+        for asn in &self.asns {
+            bytes.extend_from_slice(&asn.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    /// Deserialize from the compiled binary format
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+        if reader.take(MAGIC.len())? != MAGIC || reader.take(1)?[0] != FORMAT_VERSION {
+            return None;
+        }
+
+        let mut list = Self::default();
+        for _ in 0..reader.u32()? {
+            list.v4.push((reader.u32()?, reader.u32()?));
+        }
+        for _ in 0..reader.u32()? {
+            list.v6.push((reader.u128()?, reader.u128()?));
+        }
+        for _ in 0..reader.u32()? {
+            list.asns.push(reader.u32()?);
+        }
+
+        Some(list)
+    }
+
+    /// Check if client IP or ASN is on the list
+    pub fn matches(&self, ip: Option<&IpAddr>, asn: Option<u32>) -> bool {
+        let ip_match = match ip {
+            Some(IpAddr::V4(ip)) => range_contains(&self.v4, u32::from(*ip)),
+            Some(IpAddr::V6(ip)) => range_contains(&self.v6, u128::from(*ip)),
+            None => false,
+        };
+
+        ip_match || asn.is_some_and(|asn| self.asns.binary_search(&asn).is_ok())
+    }
+
+    fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "ipv4_ranges": self.v4.len(),
+            "ipv6_ranges": self.v6.len(),
+            "asns": self.asns.len()
+        })
+    }
+}
+
+/// Cursor over a compiled list blob
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl ByteReader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let slice = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(slice)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u128(&mut self) -> Option<u128> {
+        Some(u128::from_be_bytes(self.take(16)?.try_into().ok()?))
+    }
+}
+
+/// Sort ranges and merge overlapping or adjacent ones for binary search
+fn merge_ranges<T: Ord + Copy>(mut ranges: Vec<(T, T)>, next: fn(T) -> Option<T>) -> Vec<(T, T)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(T, T)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 || next(last.1) == Some(start) => {
+                last.1 = last.1.max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Binary search sorted, non-overlapping ranges
+fn range_contains<T: Ord + Copy>(ranges: &[(T, T)], value: T) -> bool {
+    let index = ranges.partition_point(|(start, _)| *start <= value);
+    index > 0 && ranges[index - 1].1 >= value
+}
+
+/// Evaluate KV-stored allow/deny lists, returning the reason if access is denied
+pub async fn deny_reason(req: &Request, env: &Env, config: &ProxyConfig) -> Option<String> {
+    let matcher = &config.access_matcher;
+    if matcher.deny_lists().is_empty() && matcher.allow_lists().is_empty() {
+        return None;
+    }
+
+    let ip = req
+        .headers()
+        .get("CF-Connecting-IP")
+        .ok()
+        .flatten()
+        .and_then(|ip| ip.parse::<IpAddr>().ok());
+    let asn = req.cf().map(|cf| cf.asn());
+
+    for name in matcher.deny_lists() {
+        if load_list(env, config, name).await.matches(ip.as_ref(), asn) {
+            return Some(format!("deny list: {name}"));
+        }
+    }
+
+    if !matcher.allow_lists().is_empty() {
+        for name in matcher.allow_lists() {
+            if load_list(env, config, name).await.matches(ip.as_ref(), asn) {
+                return None;
+            }
+        }
+        return Some("client not on any allow list".to_string());
+    }
+
+    None
+}
+
+/// Collect labels from label rules, including KV-stored label lists
+pub async fn labels(req: &Request, env: &Env, config: &ProxyConfig) -> Vec<String> {
+    let matcher = &config.access_matcher;
+    let mut labels = matcher.labels(req);
+    if matcher.label_lists().is_empty() {
+        return labels;
+    }
+
+    let ip = req
+        .headers()
+        .get("CF-Connecting-IP")
+        .ok()
+        .flatten()
+        .and_then(|ip| ip.parse::<IpAddr>().ok());
+    let asn = req.cf().map(|cf| cf.asn());
+
+    for (name, label) in matcher.label_lists() {
+        if !labels.contains(label) && load_list(env, config, name).await.matches(ip.as_ref(), asn) {
+            labels.push(label.clone());
+        }
+    }
+    labels
+}
+
+/// Load compiled list from KV, memoized per isolate. Read errors and corrupt
+/// blobs keep the last good copy, so a failed read never lifts a ban.
+async fn load_list(env: &Env, config: &ProxyConfig, name: &str) -> Rc<AccessList> {
+    let now = js_sys::Date::now();
+    let cached = LOADED_LISTS.with(|lists| lists.borrow().get(name).cloned());
+    if let Some((expires_at, list)) = &cached {
+        if now < *expires_at {
+            return list.clone();
+        }
+    }
+
+    let read = match env.kv("PROXY_KV") {
+        Ok(kv) => kv
+            .get(&format!("{LIST_PREFIX}{name}"))
+            .bytes()
+            .await
+            .map_err(Error::from),
+        Err(e) => Err(e),
+    };
+    let parsed = read.map(|bytes| bytes.map(|bytes: Vec<u8>| AccessList::from_bytes(&bytes)));
+    let (list, ttl_ms) = match parsed {
+        Ok(Some(Some(list))) => (Rc::new(list), config.access_list_refresh as f64 * 1000.0),
+        Ok(None) => {
+            if config
+                .access_matcher
+                .deny_lists()
+                .iter()
+                .any(|list| list == name)
+            {
+                console_error!(
+                    "Deny list {} not found in KV, nothing is denied by it",
+                    name
+                );
+            } else {
+                console_log!("Access list {} not found in KV", name);
+            }
+            (Rc::new(AccessList::default()), NEGATIVE_TTL_MS)
+        }
+        Ok(Some(None)) => {
+            console_error!("Access list {} has an invalid format", name);
+            let last_good = cached.map(|(_, list)| list).unwrap_or_default();
+            (last_good, NEGATIVE_TTL_MS)
+        }
+        Err(e) => {
+            console_error!("Failed to read access list {}: {:?}", name, e);
+            let last_good = cached.map(|(_, list)| list).unwrap_or_default();
+            (last_good, NEGATIVE_TTL_MS)
+        }
+    };
+
+    LOADED_LISTS.with(|lists| {
+        lists
+            .borrow_mut()
+            .insert(name.to_string(), (now + ttl_ms, list.clone()))
+    });
+    list
+}
+
+/// List stored access lists
+pub async fn handle_list(env: &Env) -> Result<Response> {
+    let kv = env.kv("PROXY_KV")?;
+    let listing = kv.list().prefix(LIST_PREFIX.to_string()).execute().await?;
+    let names: Vec<String> = listing
+        .keys
+        .iter()
+        .filter_map(|key| key.name.strip_prefix(LIST_PREFIX).map(str::to_string))
+        .collect();
+
+    Response::from_json(&serde_json::json!({
+        "lists": names,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
+
+/// Compile a plain-text list and store it in KV
+pub async fn handle_upload(
+    req: &mut Request,
+    url: &Url,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let name = match list_name(url) {
+        Some(name) => name,
+        None => return Response::error("List name is required", 400),
+    };
+
+    let text = req.text().await?;
+    let (list, invalid) = AccessList::parse(&text);
+
+    let kv = env.kv("PROXY_KV")?;
+    kv.put_bytes(&format!("{LIST_PREFIX}{name}"), &list.to_bytes())?
+        .execute()
+        .await?;
+    LOADED_LISTS.with(|lists| lists.borrow_mut().remove(&name));
+
+    let summary = list.summary();
+    let entry = AuditEntry::new(req, &identity.name, "upload_access_list", &name)
+        .with_change(None, Some(summary.clone()));
+    record_audit(env, config, entry).await;
+
+    // Uploading a list that deny rules use bans its entries
+    if config.access_matcher.deny_lists().contains(&name) {
+        WebhookEmitter::new(config)
+            .emit(
+                ProxyEvent::BanApplied,
+                serde_json::json!({ "list": name, "compiled": summary }),
+            )
+            .await;
+    }
+
+    Response::from_json(&serde_json::json!({
+        "name": name,
+        "compiled": summary,
+        "invalid_count": invalid.len(),
+        "invalid": invalid.into_iter().take(MAX_REPORTED_INVALID).collect::<Vec<_>>()
+    }))
+}
+
+/// Delete a stored access list
+pub async fn handle_delete(
+    req: &Request,
+    url: &Url,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let name = match list_name(url) {
+        Some(name) => name,
+        None => return Response::error("List name is required", 400),
+    };
+
+    env.kv("PROXY_KV")?
+        .delete(&format!("{LIST_PREFIX}{name}"))
+        .await?;
+    LOADED_LISTS.with(|lists| lists.borrow_mut().remove(&name));
+
+    let entry = AuditEntry::new(req, &identity.name, "delete_access_list", &name);
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({ "deleted": name }))
+}
+
+/// Compiled blobs of all stored lists, for state export
+pub async fn export_lists(kv: &KvStore) -> Result<BTreeMap<String, Vec<u8>>> {
+    let listing = kv.list().prefix(LIST_PREFIX.to_string()).execute().await?;
+    let mut lists = BTreeMap::new();
+    for key in listing.keys {
+        if let Some(name) = key.name.strip_prefix(LIST_PREFIX) {
+            if let Some(bytes) = kv.get(&key.name).bytes().await? {
+                lists.insert(name.to_string(), bytes);
+            }
+        }
+    }
+    Ok(lists)
+}
+
+/// Check imported list blobs, returning the first invalid list name
+pub fn check_import(lists: &BTreeMap<String, Vec<u8>>) -> Option<String> {
+    lists
+        .iter()
+        .find(|(name, bytes)| !is_valid_name(name) || AccessList::from_bytes(bytes).is_none())
+        .map(|(name, _)| name.clone())
+}
+
+/// Replace all stored lists with imported ones
+pub async fn import_lists(kv: &KvStore, lists: &BTreeMap<String, Vec<u8>>) -> Result<()> {
+    for (name, bytes) in lists {
+        kv.put_bytes(&format!("{LIST_PREFIX}{name}"), bytes)?
+            .execute()
+            .await?;
+    }
+
+    let listing = kv.list().prefix(LIST_PREFIX.to_string()).execute().await?;
+    for key in listing.keys {
+        if let Some(name) = key.name.strip_prefix(LIST_PREFIX) {
+            if !lists.contains_key(name) {
+                kv.delete(&key.name).await?;
+            }
+        }
+    }
+
+    LOADED_LISTS.with(|loaded| loaded.borrow_mut().clear());
+    Ok(())
+}
+
+/// Get list name from the `name` query parameter
+fn list_name(url: &Url) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == "name")
+        .map(|(_, name)| name.to_string())
+        .filter(|name| is_valid_name(name))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
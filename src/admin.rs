@@ -0,0 +1,575 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::*;
+
+use crate::{
+    access_lists, backpressure, cache, cache_warm, canary, config::ProxyConfig, config_store,
+    content_watch, contracts, drain, grafana, maintenance, mirror, monitoring::Metrics, secrets,
+    selftest, state, tls_watch, utils,
+};
+
+/// KV key prefix for audit log entries
+const AUDIT_PREFIX: &str = "audit:";
+
+/// Maximum number of audit entries returned per request
+const MAX_AUDIT_ENTRIES: u64 = 100;
+
+/// KV key prefix for named admin tokens (keyed by token hash)
+const TOKEN_PREFIX: &str = "admin_token:";
+
+/// Authenticated management endpoints
+const ADMIN_ROUTES: &[&str] = &[
+    "/_proxy/stats",
+    "/_proxy/audit",
+    "/_proxy/tokens",
+    "/_proxy/config",
+    "/_proxy/config/promote",
+    "/_proxy/config/rollback",
+    "/_proxy/config/migrate",
+    "/_proxy/access-lists",
+    "/_proxy/mirror/report",
+    "/_proxy/cache/warm/status",
+    "/_proxy/tls/status",
+    "/_proxy/selftest",
+    "/_proxy/cache/purge",
+    "/_proxy/state",
+    "/_proxy/grafana",
+    "/_proxy/grafana/metrics",
+    "/_proxy/grafana/search",
+    "/_proxy/grafana/query",
+    "/_proxy/secrets",
+    "/_proxy/secrets/rotate",
+    "/_proxy/backends/drain",
+    "/_proxy/backends/maintenance",
+    "/_proxy/canary/cohorts",
+    "/_proxy/canary/assignments",
+    "/_proxy/canary/split",
+    "/_proxy/content-watch",
+    "/_proxy/content-watch/accept",
+];
+
+/// Capabilities that can be granted to admin tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capability {
+    ReadStats,
+    ReadAudit,
+    PurgeCache,
+    EditConfig,
+    ManageBackends,
+    ManageTokens,
+    ManageSecrets,
+}
+
+impl Capability {
+    const ALL: &'static [Capability] = &[
+        Capability::ReadStats,
+        Capability::ReadAudit,
+        Capability::PurgeCache,
+        Capability::EditConfig,
+        Capability::ManageBackends,
+        Capability::ManageTokens,
+        Capability::ManageSecrets,
+    ];
+
+    /// Capability named by a token scope
+    fn parse(scope: &str) -> Option<Capability> {
+        Capability::ALL
+            .iter()
+            .copied()
+            .find(|capability| capability.as_str() == scope)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::ReadStats => "read-stats",
+            Capability::ReadAudit => "read-audit",
+            Capability::PurgeCache => "purge-cache",
+            Capability::EditConfig => "edit-config",
+            Capability::ManageBackends => "manage-backends",
+            Capability::ManageTokens => "manage-tokens",
+            Capability::ManageSecrets => "manage-secrets",
+        }
+    }
+}
+
+/// Named admin token stored in KV
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminToken {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: String,
+}
+
+/// Authenticated admin identity
+#[derive(Debug, Clone)]
+pub struct AdminIdentity {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl AdminIdentity {
+    /// Check if identity holds capability ("*" grants everything)
+    pub fn can(&self, capability: Capability) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope == "*" || scope == capability.as_str())
+    }
+}
+
+/// Audit log entry for a management API mutation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: String,
+    pub timestamp: String,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub previous: Option<Value>,
+    pub current: Option<Value>,
+    pub client_ip: Option<String>,
+}
+
+impl AuditEntry {
+    /// Create audit entry for a mutation performed by actor
+    pub fn new(req: &Request, actor: &str, action: &str, target: &str) -> Self {
+        Self {
+            id: utils::generate_request_id(),
+            timestamp: Utc::now().to_rfc3339(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            previous: None,
+            current: None,
+            client_ip: utils::get_client_ip(req.headers(), req.cf()),
+        }
+    }
+
+    /// Create audit entry for an automatic action without a client request
+    pub fn system(actor: &str, action: &str, target: &str) -> Self {
+        Self {
+            id: utils::generate_request_id(),
+            timestamp: Utc::now().to_rfc3339(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            previous: None,
+            current: None,
+            client_ip: None,
+        }
+    }
+
+    /// Attach previous and new values of the mutated resource
+    pub fn with_change(mut self, previous: Option<Value>, current: Option<Value>) -> Self {
+        self.previous = previous;
+        self.current = current;
+        self
+    }
+}
+
+/// Check if path belongs to the authenticated management API
+pub fn is_admin_path(path: &str) -> bool {
+    ADMIN_ROUTES.contains(&path)
+}
+
+/// Authenticate admin request against the root token and named KV tokens
+pub async fn authorize(req: &Request, env: &Env, config: &ProxyConfig) -> Option<AdminIdentity> {
+    let provided = req.headers().get("Authorization").ok().flatten()?;
+    let token = provided.strip_prefix("Bearer ")?.trim();
+
+    if let Some(root) = &config.admin_token {
+        if utils::constant_time_eq(token, root) {
+            return Some(AdminIdentity {
+                name: "admin".to_string(),
+                scopes: vec!["*".to_string()],
+            });
+        }
+    }
+
+    // Named tokens are stored by hash so KV contents never reveal usable tokens
+    let kv = env.kv("PROXY_KV").ok()?;
+    let key = format!("{TOKEN_PREFIX}{}", utils::sha256_hash(token));
+    let stored = kv.get(&key).json::<AdminToken>().await.ok().flatten()?;
+
+    Some(AdminIdentity {
+        name: stored.name,
+        scopes: stored.scopes,
+    })
+}
+
+/// Route authenticated management API requests
+pub async fn handle_admin_request(
+    mut req: Request,
+    env: &Env,
+    config: &ProxyConfig,
+) -> Result<Response> {
+    if config.admin_token.is_none() {
+        return Response::error("Admin API is not configured", 403);
+    }
+
+    let identity = match authorize(&req, env, config).await {
+        Some(identity) => identity,
+        None => return Response::error("Unauthorized", 401),
+    };
+
+    let url = req.url()?;
+    let method = req.method();
+    console_log!(
+        "Admin request by {}: {} {}",
+        identity.name,
+        method.to_string(),
+        url.path()
+    );
+
+    let required = match (&method, url.path()) {
+        (Method::Get, "/_proxy/audit") => Capability::ReadAudit,
+        (
+            Method::Get,
+            "/_proxy/stats"
+            | "/_proxy/mirror/report"
+            | "/_proxy/cache/warm/status"
+            | "/_proxy/tls/status"
+            | "/_proxy/selftest"
+            | "/_proxy/backends/drain"
+            | "/_proxy/backends/maintenance"
+            | "/_proxy/canary/cohorts"
+            | "/_proxy/content-watch",
+        ) => Capability::ReadStats,
+        (
+            Method::Get | Method::Post,
+            "/_proxy/grafana"
+            | "/_proxy/grafana/metrics"
+            | "/_proxy/grafana/search"
+            | "/_proxy/grafana/query",
+        ) => Capability::ReadStats,
+        (Method::Post, "/_proxy/cache/purge") => Capability::PurgeCache,
+        (Method::Post | Method::Delete, "/_proxy/backends/drain")
+        | (Method::Put | Method::Delete, "/_proxy/backends/maintenance") => {
+            Capability::ManageBackends
+        }
+        (Method::Get | Method::Put | Method::Delete, "/_proxy/tokens") => Capability::ManageTokens,
+        (Method::Get | Method::Put | Method::Delete, "/_proxy/secrets")
+        | (Method::Post, "/_proxy/secrets/rotate") => Capability::ManageSecrets,
+        (Method::Get | Method::Put, "/_proxy/config")
+        | (Method::Get | Method::Post, "/_proxy/config/migrate")
+        | (Method::Post, "/_proxy/config/promote" | "/_proxy/config/rollback")
+        | (Method::Get | Method::Put | Method::Delete, "/_proxy/access-lists")
+        | (Method::Get | Method::Put, "/_proxy/state")
+        | (Method::Put, "/_proxy/canary/cohorts" | "/_proxy/canary/split")
+        | (Method::Post, "/_proxy/content-watch/accept")
+        | (Method::Get | Method::Put | Method::Delete, "/_proxy/canary/assignments") => {
+            Capability::EditConfig
+        }
+        _ => return Response::error("Method Not Allowed", 405),
+    };
+
+    if !identity.can(required) {
+        console_log!(
+            "Admin token {} lacks capability {}",
+            identity.name,
+            required.as_str()
+        );
+        return Response::error("Forbidden", 403);
+    }
+
+    match (method, url.path()) {
+        (Method::Get, "/_proxy/stats") => get_stats().await,
+        (Method::Get, "/_proxy/audit") => list_audit_entries(&url, env).await,
+        (Method::Get, "/_proxy/mirror/report") => mirror::handle_report(&url, env).await,
+        (Method::Get, "/_proxy/cache/warm/status") => cache_warm::handle_status(env).await,
+        (Method::Get, "/_proxy/tls/status") => tls_watch::handle_status(env).await,
+        (Method::Get, "/_proxy/selftest") => selftest::handle_selftest(env, config).await,
+        (Method::Get, "/_proxy/backends/drain") => drain::handle_list(env, config).await,
+        (Method::Get, "/_proxy/backends/maintenance") => {
+            maintenance::handle_list(env, config).await
+        }
+        (Method::Put, "/_proxy/backends/maintenance") => {
+            maintenance::handle_enable(&mut req, env, config, &identity).await
+        }
+        (Method::Delete, "/_proxy/backends/maintenance") => {
+            maintenance::handle_disable(&req, &url, env, config, &identity).await
+        }
+        (Method::Get, "/_proxy/canary/cohorts") => canary::handle_list(env, config).await,
+        (Method::Put, "/_proxy/canary/cohorts") => {
+            canary::handle_define(&mut req, env, config, &identity).await
+        }
+        (Method::Get, "/_proxy/content-watch") => content_watch::handle_status(env).await,
+        (Method::Post, "/_proxy/content-watch/accept") => {
+            content_watch::handle_accept(&mut req, env, config, &identity).await
+        }
+        (Method::Put, "/_proxy/canary/split") => {
+            canary::handle_split(&mut req, env, config, &identity).await
+        }
+        (Method::Get, "/_proxy/canary/assignments") => {
+            canary::handle_list_forced(&url, env, config).await
+        }
+        (Method::Put, "/_proxy/canary/assignments") => {
+            canary::handle_force(&mut req, env, config, &identity).await
+        }
+        (Method::Delete, "/_proxy/canary/assignments") => {
+            canary::handle_unforce(&req, &url, env, config, &identity).await
+        }
+        (Method::Post, "/_proxy/backends/drain") => {
+            drain::handle_start(&mut req, env, config, &identity).await
+        }
+        (Method::Delete, "/_proxy/backends/drain") => {
+            drain::handle_cancel(&req, &url, env, config, &identity).await
+        }
+        (_, path) if path.starts_with("/_proxy/grafana") => {
+            grafana::handle_request(req, env, config).await
+        }
+        (Method::Post, "/_proxy/cache/purge") => {
+            cache::handle_purge(&mut req, env, config, &identity).await
+        }
+        (Method::Get, "/_proxy/tokens") => list_tokens(env).await,
+        (Method::Put, "/_proxy/tokens") => create_token(&mut req, env, config, &identity).await,
+        (Method::Delete, "/_proxy/tokens") => {
+            delete_token(&req, &url, env, config, &identity).await
+        }
+        (Method::Get, "/_proxy/secrets") => secrets::handle_list(env).await,
+        (Method::Put, "/_proxy/secrets") => {
+            secrets::handle_put(&mut req, env, config, &identity).await
+        }
+        (Method::Delete, "/_proxy/secrets") => {
+            secrets::handle_delete(&req, &url, env, config, &identity).await
+        }
+        (Method::Post, "/_proxy/secrets/rotate") => {
+            secrets::handle_rotate(&req, env, config, &identity).await
+        }
+        (Method::Get, "/_proxy/config") => config_store::handle_get_config(env).await,
+        (Method::Put, "/_proxy/config") => {
+            config_store::handle_publish(&mut req, env, config, &identity).await
+        }
+        (Method::Post, "/_proxy/config/promote") => {
+            config_store::handle_promote(&req, env, config, &identity).await
+        }
+        (Method::Post, "/_proxy/config/rollback") => {
+            config_store::handle_rollback(&req, env, config, &identity).await
+        }
+        (Method::Get | Method::Post, "/_proxy/config/migrate") => {
+            config_store::handle_migrate(&req, env, config, &identity).await
+        }
+        (Method::Get, "/_proxy/access-lists") => access_lists::handle_list(env).await,
+        (Method::Put, "/_proxy/access-lists") => {
+            access_lists::handle_upload(&mut req, &url, env, config, &identity).await
+        }
+        (Method::Delete, "/_proxy/access-lists") => {
+            access_lists::handle_delete(&req, &url, env, config, &identity).await
+        }
+        (Method::Get, "/_proxy/state") => state::handle_export(env).await,
+        (Method::Put, "/_proxy/state") => {
+            state::handle_import(&mut req, env, config, &identity).await
+        }
+        _ => Response::error("Method Not Allowed", 405),
+    }
+}
+
+/// Request body for creating a named admin token
+#[derive(Debug, Deserialize)]
+struct CreateTokenRequest {
+    name: String,
+    scopes: Vec<String>,
+}
+
+/// List named admin tokens (names and scopes only)
+async fn list_tokens(env: &Env) -> Result<Response> {
+    let tokens = load_tokens(env).await?;
+    let tokens: Vec<&AdminToken> = tokens.iter().map(|(_, token)| token).collect();
+    Response::from_json(&serde_json::json!({ "tokens": tokens }))
+}
+
+/// Create a named admin token, returning the secret once
+async fn create_token(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let body: CreateTokenRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return Response::error("Invalid token definition", 400),
+    };
+
+    if body.name.is_empty() {
+        return Response::error("Token name is required", 400);
+    }
+    // Tokens can only be granted capabilities their creator holds
+    for scope in &body.scopes {
+        if scope == "*" {
+            if !identity.scopes.iter().any(|held| held == "*") {
+                return Response::error("Cannot grant scope *", 403);
+            }
+            continue;
+        }
+        match Capability::parse(scope) {
+            Some(capability) if identity.can(capability) => {}
+            Some(_) => return Response::error(format!("Cannot grant scope {scope}"), 403),
+            None => return Response::error(format!("Unknown scope: {scope}"), 400),
+        }
+    }
+
+    let tokens = load_tokens(env).await?;
+    if tokens.iter().any(|(_, token)| token.name == body.name) {
+        return Response::error("Token name already exists", 409);
+    }
+
+    let secret = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let token = AdminToken {
+        name: body.name,
+        scopes: body.scopes,
+        created_at: Utc::now().to_rfc3339(),
+    };
+
+    let kv = env.kv("PROXY_KV")?;
+    let key = format!("{TOKEN_PREFIX}{}", utils::sha256_hash(&secret));
+    kv.put(&key, serde_json::to_string(&token)?)?
+        .execute()
+        .await?;
+
+    let entry = AuditEntry::new(req, &identity.name, "create_token", &token.name)
+        .with_change(None, Some(serde_json::to_value(&token)?));
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({
+        "name": token.name,
+        "scopes": token.scopes,
+        "token": secret
+    }))
+    .map(|response| response.with_status(201))
+}
+
+/// Revoke a named admin token
+async fn delete_token(
+    req: &Request,
+    url: &Url,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let name = match url.query_pairs().find(|(key, _)| key == "name") {
+        Some((_, name)) => name.to_string(),
+        None => return Response::error("Token name is required", 400),
+    };
+
+    let tokens = load_tokens(env).await?;
+    let (key, token) = match tokens.into_iter().find(|(_, token)| token.name == name) {
+        Some(found) => found,
+        None => return Response::error("Token not found", 404),
+    };
+
+    env.kv("PROXY_KV")?.delete(&key).await?;
+
+    let entry = AuditEntry::new(req, &identity.name, "delete_token", &name)
+        .with_change(Some(serde_json::to_value(&token)?), None);
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({ "deleted": name }))
+}
+
+/// Load all named admin tokens with their KV keys
+async fn load_tokens(env: &Env) -> Result<Vec<(String, AdminToken)>> {
+    let kv = env.kv("PROXY_KV")?;
+    let mut tokens = Vec::new();
+    let mut cursor = None;
+    loop {
+        let mut list = kv.list().prefix(TOKEN_PREFIX.to_string());
+        if let Some(cursor) = cursor.take() {
+            list = list.cursor(cursor);
+        }
+        let listing = list.execute().await?;
+
+        for key in listing.keys {
+            if let Ok(Some(token)) = kv.get(&key.name).json::<AdminToken>().await {
+                tokens.push((key.name, token));
+            }
+        }
+
+        match listing.cursor {
+            Some(next) if !listing.list_complete => cursor = Some(next),
+            _ => return Ok(tokens),
+        }
+    }
+}
+
+/// Statistics of this isolate
+async fn get_stats() -> Result<Response> {
+    let mut stats = Metrics::new().get_stats().await;
+    stats["contract_violations"] = serde_json::json!(contracts::violation_counts());
+    stats["backend_queues"] = serde_json::json!(backpressure::queue_stats());
+    Response::from_json(&stats)
+}
+
+/// Append a management API mutation to the audit log
+pub async fn record_audit(env: &Env, config: &ProxyConfig, entry: AuditEntry) {
+    // Inverted timestamp keeps the newest entries first in KV listings,
+    // and a unique key per entry keeps the log append-only
+    let inverted = u64::MAX - Utc::now().timestamp_millis() as u64;
+    let key = format!("{AUDIT_PREFIX}{inverted:020}:{}", entry.id);
+
+    let kv = match env.kv("PROXY_KV") {
+        Ok(kv) => kv,
+        Err(e) => {
+            console_log!(
+                "Audit log unavailable, dropping entry {}: {:?}",
+                entry.id,
+                e
+            );
+            return;
+        }
+    };
+
+    let serialized = match serde_json::to_string(&entry) {
+        Ok(serialized) => serialized,
+        Err(_) => return,
+    };
+
+    let result = match kv.put(&key, serialized) {
+        Ok(put) => {
+            put.expiration_ttl(config.audit_retention_days * 86400)
+                .execute()
+                .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => console_log!("Audit: {} {} {}", entry.actor, entry.action, entry.target),
+        Err(e) => console_log!("Failed to write audit entry {}: {:?}", entry.id, e),
+    }
+}
+
+/// List recent audit log entries, newest first
+async fn list_audit_entries(url: &Url, env: &Env) -> Result<Response> {
+    let limit = url
+        .query_pairs()
+        .find(|(key, _)| key == "limit")
+        .and_then(|(_, value)| value.parse::<u64>().ok())
+        .unwrap_or(50)
+        .min(MAX_AUDIT_ENTRIES);
+
+    let kv = env.kv("PROXY_KV")?;
+    let listing = kv
+        .list()
+        .prefix(AUDIT_PREFIX.to_string())
+        .limit(limit)
+        .execute()
+        .await?;
+
+    let mut entries = Vec::new();
+    for key in listing.keys {
+        if let Ok(Some(entry)) = kv.get(&key.name).json::<AuditEntry>().await {
+            entries.push(entry);
+        }
+    }
+
+    let count = entries.len();
+    Response::from_json(&serde_json::json!({
+        "entries": entries,
+        "count": count,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
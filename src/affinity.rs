@@ -0,0 +1,60 @@
+use worker::*;
+
+use crate::{config::ProxyConfig, utils};
+
+/// Hex characters of the HMAC kept in the cookie
+const SIGNATURE_LENGTH: usize = 32;
+
+/// Opaque id of a backend, so cookies never reveal backend URLs
+fn backend_id(backend: &str) -> String {
+    utils::sha256_hash(backend)[..12].to_string()
+}
+
+fn signature(backend_id: &str, config: &ProxyConfig) -> String {
+    utils::hmac_sha256_hex(&config.sticky_session_secret, backend_id.as_bytes())[..SIGNATURE_LENGTH]
+        .to_string()
+}
+
+/// Whether requests are pinned to backends by an affinity cookie
+pub fn is_enabled(config: &ProxyConfig) -> bool {
+    config.sticky_sessions_enabled && !config.sticky_session_secret.is_empty()
+}
+
+/// Backend of `backends` named by a valid affinity cookie
+pub fn pinned_backend(
+    req: &Request,
+    backends: &[String],
+    config: &ProxyConfig,
+) -> Result<Option<String>> {
+    if !is_enabled(config) {
+        return Ok(None);
+    }
+
+    let cookie = match utils::cookie_value(req, &config.sticky_cookie_name)? {
+        Some(cookie) => cookie,
+        None => return Ok(None),
+    };
+    let (id, provided) = match cookie.split_once('.') {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    if !utils::constant_time_eq(provided, &signature(id, config)) {
+        return Ok(None);
+    }
+
+    Ok(backends
+        .iter()
+        .find(|backend| backend_id(backend) == id)
+        .cloned())
+}
+
+/// `Set-Cookie` value pinning the client to `backend`
+pub fn cookie(backend: &str, config: &ProxyConfig) -> String {
+    let id = backend_id(backend);
+    format!(
+        "{}={id}.{}; Path=/; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+        config.sticky_cookie_name,
+        signature(&id, config),
+        config.sticky_session_ttl
+    )
+}
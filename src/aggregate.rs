@@ -0,0 +1,150 @@
+use futures_util::future::join_all;
+use serde_json::{Map, Value};
+use worker::*;
+
+use crate::{
+    config::{AggregationRoute, AggregationSource, ProxyConfig},
+    context::RequestContext,
+    latency, utils,
+};
+
+/// Client headers never copied to sources
+const SKIPPED_HEADERS: &[&str] = &["host", "content-length", "connection", "accept-encoding"];
+
+/// Aggregation route matching the path, if any
+pub fn route_for<'a>(config: &'a ProxyConfig, path: &str) -> Option<&'a AggregationRoute> {
+    config
+        .aggregation_routes
+        .iter()
+        .find(|route| utils::path_matches(&route.path, path))
+}
+
+/// Fetch all sources of the route in parallel and merge their JSON bodies
+/// into one object keyed by source. Sources that fail, time out or return
+/// something other than a 2xx JSON body are `null` and listed under
+/// `errors`; the response is a `502` only when every source failed.
+pub async fn aggregate(
+    req: &Request,
+    route: &AggregationRoute,
+    backend: Option<&str>,
+    request_ctx: &RequestContext,
+    config: &ProxyConfig,
+) -> Result<Response> {
+    let query = req.url()?.query().map(str::to_string);
+    let results =
+        join_all(route.sources.iter().map(|source| {
+            fetch_source(req, source, backend, query.as_deref(), request_ctx, config)
+        }))
+        .await;
+
+    let mut merged = Map::new();
+    let mut errors = Map::new();
+    for (source, result) in route.sources.iter().zip(results) {
+        match result {
+            Ok(value) => {
+                merged.insert(source.key.clone(), value);
+            }
+            Err(error) => {
+                console_log!(
+                    "Aggregation source {} failed for {}: {}",
+                    source.key,
+                    request_ctx.request_id,
+                    error
+                );
+                merged.insert(source.key.clone(), Value::Null);
+                errors.insert(source.key.clone(), Value::String(error));
+            }
+        }
+    }
+
+    let status = if !route.sources.is_empty() && errors.len() == route.sources.len() {
+        502
+    } else {
+        200
+    };
+    if !errors.is_empty() {
+        merged.insert("errors".to_string(), Value::Object(errors));
+    }
+
+    let response = Response::from_json(&Value::Object(merged))?.with_status(status);
+    response.headers().set("Cache-Control", "no-store")?;
+    Ok(response)
+}
+
+/// Fetch one source, returning its JSON body or a short error
+async fn fetch_source(
+    req: &Request,
+    source: &AggregationSource,
+    backend: Option<&str>,
+    query: Option<&str>,
+    request_ctx: &RequestContext,
+    config: &ProxyConfig,
+) -> std::result::Result<Value, String> {
+    let url = source_url(&source.url, backend, query, config)
+        .ok_or_else(|| "source is not a configured backend".to_string())?;
+
+    let headers = Headers::new();
+    for (name, value) in req.headers().entries() {
+        if !SKIPPED_HEADERS.contains(&name.as_str()) {
+            headers.set(&name, &value).map_err(|e| e.to_string())?;
+        }
+    }
+    headers
+        .set("X-Request-ID", &request_ctx.request_id)
+        .map_err(|e| e.to_string())?;
+    if let Some(client_ip) = &request_ctx.client_ip {
+        headers
+            .set("X-Forwarded-For", client_ip)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Get).with_headers(headers);
+    let request = Request::new_with_init(&url, &init).map_err(|e| e.to_string())?;
+
+    let timeout_ms = source.timeout_ms.unwrap_or(config.timeout * 1000);
+    let mut response = latency::fetch_with_timeout(request, timeout_ms)
+        .await
+        .map_err(|e| {
+            if latency::is_timeout(&e) {
+                "timeout".to_string()
+            } else {
+                e.to_string()
+            }
+        })?;
+    let status = response.status_code();
+    if !(200..300).contains(&status) {
+        return Err(format!("status {status}"));
+    }
+    response
+        .json::<Value>()
+        .await
+        .map_err(|_| "invalid JSON".to_string())
+}
+
+/// Resolve a source against the selected backend, refusing foreign origins.
+/// Relative sources get the client's query string unless they have their own.
+fn source_url(
+    src: &str,
+    backend: Option<&str>,
+    query: Option<&str>,
+    config: &ProxyConfig,
+) -> Option<String> {
+    if src.starts_with('/') && !src.starts_with("//") {
+        let mut url = format!("{}{src}", backend?.trim_end_matches('/'));
+        if let Some(query) = query.filter(|_| !src.contains('?')) {
+            url.push('?');
+            url.push_str(query);
+        }
+        return Some(url);
+    }
+
+    let url = Url::parse(src).ok()?;
+    let origin = url.origin().ascii_serialization();
+    let allowed = config
+        .backends
+        .iter()
+        .chain(config.backend_pools.iter().flat_map(|pool| &pool.backends))
+        .any(|backend| backend.trim_end_matches('/') == origin);
+    allowed.then(|| url.to_string())
+}
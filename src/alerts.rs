@@ -0,0 +1,191 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use chrono::Utc;
+use worker::*;
+
+use crate::{config::ProxyConfig, reports};
+
+/// MailChannels transactional email API
+const MAILCHANNELS_SEND_URL: &str = "https://api.mailchannels.net/tx/v1/send";
+
+/// KV key prefix marking recently sent alerts (`alert:sent:{alert}`)
+const ALERT_SENT_PREFIX: &str = "alert:sent:";
+
+/// Requests needed in a day before the error budget is judged
+const MIN_BUDGET_REQUESTS: u64 = 100;
+
+/// Critical conditions operators are paged for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alert {
+    AllBackendsDown,
+    ErrorBudgetExhausted,
+    BackendTlsFailing,
+    ContentChanged,
+}
+
+impl Alert {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Alert::AllBackendsDown => "all_backends_down",
+            Alert::ErrorBudgetExhausted => "error_budget_exhausted",
+            Alert::BackendTlsFailing => "backend_tls_failing",
+            Alert::ContentChanged => "content_changed",
+        }
+    }
+
+    fn subject(&self) -> &'static str {
+        match self {
+            Alert::AllBackendsDown => "All backends are down",
+            Alert::ErrorBudgetExhausted => "Error budget exhausted",
+            Alert::BackendTlsFailing => "Backend TLS connections failing",
+            Alert::ContentChanged => "Watched page content changed",
+        }
+    }
+}
+
+thread_local! {
+    /// When this isolate last sent or suppressed each alert, saving KV reads
+    /// while a condition persists
+    static LAST_SENT: RefCell<HashMap<&'static str, f64>> = RefCell::new(HashMap::new());
+}
+
+/// Whether email alerts are configured
+pub fn is_enabled(config: &ProxyConfig) -> bool {
+    !config.alert_email_to.is_empty() && config.alert_email_from.is_some()
+}
+
+/// Email an alert unless the same alert was sent within the throttle period
+pub async fn send(alert: Alert, details: String, config: ProxyConfig, env: Env) {
+    if !is_enabled(&config) {
+        return;
+    }
+
+    let now = js_sys::Date::now();
+    let throttle_ms = config.alert_throttle_seconds as f64 * 1000.0;
+    let recent = LAST_SENT.with(|last_sent| {
+        let mut last_sent = last_sent.borrow_mut();
+        match last_sent.get(alert.as_str()) {
+            Some(sent_at) if now - sent_at < throttle_ms => true,
+            _ => {
+                last_sent.insert(alert.as_str(), now);
+                false
+            }
+        }
+    });
+    if recent {
+        return;
+    }
+
+    // Throttle across isolates, the marker expires with the throttle period
+    let kv = match env.kv("PROXY_KV") {
+        Ok(kv) => kv,
+        Err(_) => return,
+    };
+    let sent_key = format!("{ALERT_SENT_PREFIX}{}", alert.as_str());
+    if let Ok(Some(_)) = kv.get(&sent_key).text().await {
+        return;
+    }
+    if let Ok(put) = kv.put(&sent_key, Utc::now().to_rfc3339()) {
+        if let Err(e) = put
+            .expiration_ttl(config.alert_throttle_seconds.max(60))
+            .execute()
+            .await
+        {
+            console_log!("Failed to store alert marker: {:?}", e);
+        }
+    }
+
+    match send_email(alert, &details, &config).await {
+        Ok(()) => console_log!("Sent {} alert", alert.as_str()),
+        Err(e) => console_log!("Failed to send {} alert: {:?}", alert.as_str(), e),
+    }
+}
+
+/// Alert when today's error rate used up the budget left by `ALERT_SLO_TARGET`
+pub async fn check_error_budget(config: &ProxyConfig, env: &Env) {
+    if !is_enabled(config) || config.alert_slo_target <= 0.0 {
+        return;
+    }
+
+    let today = Utc::now().date_naive();
+    let stats = match reports::load_days(env, today, today).await {
+        Ok(mut days) => match days.pop() {
+            Some((_, stats)) => stats,
+            None => return,
+        },
+        Err(e) => {
+            console_log!("Failed to load traffic stats for error budget: {:?}", e);
+            return;
+        }
+    };
+    if stats.requests < MIN_BUDGET_REQUESTS {
+        return;
+    }
+
+    let budget = (100.0 - config.alert_slo_target) / 100.0 * stats.requests as f64;
+    if stats.errors as f64 >= budget {
+        let details = format!(
+            "{} of {} requests failed today ({:.2}%), exceeding the error budget of the {}% availability target.",
+            stats.errors,
+            stats.requests,
+            stats.errors as f64 / stats.requests as f64 * 100.0,
+            config.alert_slo_target
+        );
+        send(
+            Alert::ErrorBudgetExhausted,
+            details,
+            config.clone(),
+            env.clone(),
+        )
+        .await;
+    }
+}
+
+async fn send_email(alert: Alert, details: &str, config: &ProxyConfig) -> Result<()> {
+    let from = config
+        .alert_email_from
+        .as_deref()
+        .ok_or_else(|| Error::from("ALERT_EMAIL_FROM not set"))?;
+    let to: Vec<_> = config
+        .alert_email_to
+        .iter()
+        .map(|email| serde_json::json!({ "email": email }))
+        .collect();
+    let body = serde_json::json!({
+        "personalizations": [{ "to": to }],
+        "from": { "email": from, "name": config.proxy_id },
+        "subject": format!("[{}] {}", config.proxy_id, alert.subject()),
+        "content": [{
+            "type": "text/plain",
+            "value": format!(
+                "{}\n\n{}\n\nAlert: {}\nTime: {}\n",
+                alert.subject(),
+                details,
+                alert.as_str(),
+                Utc::now().to_rfc3339()
+            )
+        }]
+    });
+
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    if let Some(api_key) = &config.mailchannels_api_key {
+        headers.set("X-Api-Key", api_key)?;
+    }
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_headers(headers)
+        .with_body(Some(body.to_string().into()));
+
+    let mut response = Fetch::Request(Request::new_with_init(MAILCHANNELS_SEND_URL, &init)?)
+        .send()
+        .await?;
+    if !(200..300).contains(&response.status_code()) {
+        return Err(Error::from(format!(
+            "MailChannels answered with status {}: {}",
+            response.status_code(),
+            response.text().await.unwrap_or_default()
+        )));
+    }
+    Ok(())
+}
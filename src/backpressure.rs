@@ -0,0 +1,125 @@
+use std::{cell::RefCell, collections::HashMap, time::Duration};
+
+use serde::Serialize;
+use worker::*;
+
+use crate::config::ProxyConfig;
+
+/// Interval between checks for a free slot while queued
+const QUEUE_POLL_MS: u64 = 10;
+
+/// Per-backend concurrency and queue counters
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackendQueue {
+    pub in_flight: u32,
+    pub queued: u32,
+    pub max_queued: u32,
+    pub shed: u64,
+}
+
+thread_local! {
+    /// Counters of this isolate, keyed by backend base URL
+    static QUEUES: RefCell<HashMap<String, BackendQueue>> = RefCell::new(HashMap::new());
+}
+
+/// Concurrency slot on a backend, released when dropped
+pub struct Permit {
+    backend: Option<String>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(backend) = &self.backend {
+            with_queue(backend, |queue| {
+                queue.in_flight = queue.in_flight.saturating_sub(1)
+            });
+        }
+    }
+}
+
+/// Acquire a concurrency slot on the backend, waiting up to the configured
+/// time when it is saturated. Returns `None` when the request should be shed.
+pub async fn acquire(backend: &str, config: &ProxyConfig) -> Option<Permit> {
+    // Unlimited backends are still counted, for latency-aware load balancing
+    let limit = config.backend_concurrency_limit;
+    if limit == 0 {
+        with_queue(backend, |queue| queue.in_flight += 1);
+        return Some(Permit {
+            backend: Some(backend.to_string()),
+        });
+    }
+
+    if try_acquire(backend, limit) {
+        return Some(Permit {
+            backend: Some(backend.to_string()),
+        });
+    }
+
+    // Bounded queue: shed immediately when queueing is off or the queue is full
+    let enqueued = with_queue(backend, |queue| {
+        if config.queue_max_wait_ms == 0 || queue.queued >= config.queue_max_depth {
+            queue.shed += 1;
+            false
+        } else {
+            queue.queued += 1;
+            queue.max_queued = queue.max_queued.max(queue.queued);
+            true
+        }
+    });
+    if !enqueued {
+        return None;
+    }
+
+    let deadline = js_sys::Date::now() + config.queue_max_wait_ms as f64;
+    let acquired = loop {
+        Delay::from(Duration::from_millis(QUEUE_POLL_MS)).await;
+        if try_acquire(backend, limit) {
+            break true;
+        }
+        if js_sys::Date::now() >= deadline {
+            break false;
+        }
+    };
+
+    with_queue(backend, |queue| {
+        queue.queued = queue.queued.saturating_sub(1);
+        if !acquired {
+            queue.shed += 1;
+        }
+    });
+
+    acquired.then(|| Permit {
+        backend: Some(backend.to_string()),
+    })
+}
+
+/// Take a slot if the backend is below its limit
+fn try_acquire(backend: &str, limit: u32) -> bool {
+    with_queue(backend, |queue| {
+        if queue.in_flight < limit {
+            queue.in_flight += 1;
+            true
+        } else {
+            false
+        }
+    })
+}
+
+fn with_queue<T>(backend: &str, f: impl FnOnce(&mut BackendQueue) -> T) -> T {
+    QUEUES.with(|queues| f(queues.borrow_mut().entry(backend.to_string()).or_default()))
+}
+
+/// Requests this isolate has in flight to the backend
+pub fn in_flight(backend: &str) -> u32 {
+    QUEUES.with(|queues| {
+        queues
+            .borrow()
+            .get(backend)
+            .map_or(0, |queue| queue.in_flight)
+    })
+}
+
+/// Concurrency and queue counters observed by this isolate
+pub fn queue_stats() -> HashMap<String, BackendQueue> {
+    QUEUES.with(|queues| queues.borrow().clone())
+}
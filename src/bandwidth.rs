@@ -0,0 +1,153 @@
+use std::{cell::RefCell, collections::HashMap, time::Duration};
+
+use futures_util::{StreamExt, stream};
+use worker::*;
+
+use crate::{
+    config::{BandwidthTier, ProxyConfig},
+    utils,
+};
+
+/// Pacing granularity: chunks are split into slices of this much sending time
+const SLICE_MS: u64 = 100;
+
+/// Smallest slice sent at once, so low caps don't degrade into tiny writes
+const MIN_SLICE_BYTES: usize = 1024;
+
+thread_local! {
+    /// Time from which each throttled client may send again, shared by its
+    /// concurrent downloads served by this isolate
+    static READY_AT: RefCell<HashMap<String, f64>> = RefCell::new(HashMap::new());
+}
+
+/// Bandwidth cap of a request: the client it is counted against and the tier
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    client: String,
+    bytes_per_sec: u64,
+}
+
+/// API key of a request, from a bearer token or `X-API-Key`
+fn api_key(req: &Request) -> Result<Option<String>> {
+    let headers = req.headers();
+    Ok(match headers.get("Authorization")? {
+        Some(authorization) => authorization
+            .strip_prefix("Bearer ")
+            .map(|token| token.trim().to_string()),
+        None => headers.get("X-API-Key")?,
+    })
+}
+
+/// Whether a tier applies to the request path
+fn matches_path(tier: &BandwidthTier, path: &str) -> bool {
+    tier.paths.is_empty()
+        || tier
+            .paths
+            .iter()
+            .any(|pattern| utils::path_matches(pattern, path))
+}
+
+/// Bandwidth cap of a request: the first tier listing its API key, else the
+/// first tier without keys. Keyed clients are counted by key, others by IP.
+pub fn for_request(req: &Request, config: &ProxyConfig) -> Result<Option<Throttle>> {
+    if config.bandwidth_tiers.is_empty() || req.method() == Method::Head {
+        return Ok(None);
+    }
+    let path = req.path();
+    let tiers = config
+        .bandwidth_tiers
+        .iter()
+        .filter(|tier| matches_path(tier, &path));
+
+    let key = api_key(req)?;
+    let keyed = key.as_ref().and_then(|key| {
+        tiers.clone().find(|tier| {
+            tier.api_keys
+                .iter()
+                .any(|known| utils::constant_time_eq(key, known))
+        })
+    });
+    let (tier, client) = match (keyed, key) {
+        (Some(tier), Some(key)) => (tier, format!("key:{}", utils::sha256_hash(&key))),
+        _ => match tiers.clone().find(|tier| tier.api_keys.is_empty()) {
+            Some(tier) => {
+                let ip = utils::get_client_ip(req.headers(), None).unwrap_or_default();
+                (tier, format!("ip:{ip}"))
+            }
+            None => return Ok(None),
+        },
+    };
+
+    if tier.bytes_per_sec == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Throttle {
+        client: format!("{}|{client}", tier.name),
+        bytes_per_sec: tier.bytes_per_sec,
+    }))
+}
+
+/// Reserve sending time for `bytes` and return how long to wait before sending
+fn reserve(client: &str, bytes: usize, bytes_per_sec: u64) -> f64 {
+    let now = js_sys::Date::now();
+    READY_AT.with(|ready_at| {
+        let mut ready_at = ready_at.borrow_mut();
+        // Forget clients that have been idle
+        ready_at.retain(|_, ready| *ready > now);
+        let start = ready_at.get(client).copied().unwrap_or(now).max(now);
+        ready_at.insert(
+            client.to_string(),
+            start + bytes as f64 * 1000.0 / bytes_per_sec as f64,
+        );
+        start - now
+    })
+}
+
+/// Pace the response body to the client's bandwidth cap. `sent_as_stored`
+/// bodies keep their `Content-Encoding` and are re-framed without encoding.
+pub fn apply(mut response: Response, throttle: Throttle, sent_as_stored: bool) -> Result<Response> {
+    // Upgrades and bodiless responses have nothing to pace
+    if matches!(response.status_code(), 101 | 204 | 304) {
+        return Ok(response);
+    }
+    let slice_bytes = ((throttle.bytes_per_sec * SLICE_MS / 1000) as usize).max(MIN_SLICE_BYTES);
+    // Bodies built by the proxy itself are small and not streamable
+    let input = match response.stream() {
+        Ok(input) => input,
+        Err(_) => return Ok(response),
+    };
+    let body = input
+        .flat_map(move |chunk| {
+            let slices: Vec<Result<Vec<u8>>> = match chunk {
+                Ok(chunk) => chunk
+                    .chunks(slice_bytes)
+                    .map(|slice| Ok(slice.to_vec()))
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(slices)
+        })
+        .then(move |slice| {
+            let throttle = throttle.clone();
+            async move {
+                if let Ok(slice) = &slice {
+                    let wait_ms = reserve(&throttle.client, slice.len(), throttle.bytes_per_sec);
+                    if wait_ms >= 1.0 {
+                        Delay::from(Duration::from_millis(wait_ms as u64)).await;
+                    }
+                }
+                slice
+            }
+        });
+
+    let builder = Response::builder()
+        .with_status(response.status_code())
+        .with_headers(response.headers().clone());
+    if sent_as_stored {
+        builder
+            .with_encode_body(EncodeBody::Manual)
+            .from_stream(body)
+    } else {
+        builder.from_stream(body)
+    }
+}
@@ -0,0 +1,66 @@
+use worker::*;
+
+use crate::{access::AccessDenial, config::ProxyConfig, request_id, utils::escape_html};
+
+/// Status used when a block page doesn't set one
+const DEFAULT_BLOCK_STATUS: u16 = 451;
+
+/// Build the response for a denied request, using a block page for geo blocks
+pub fn blocked_response(
+    req: &Request,
+    denial: &AccessDenial,
+    config: &ProxyConfig,
+    request_id: &str,
+) -> Result<Response> {
+    let country = req
+        .cf()
+        .and_then(|cf| cf.country())
+        .map(|c| c.to_uppercase());
+    let continent = req
+        .cf()
+        .and_then(|cf| cf.continent())
+        .map(|c| c.to_uppercase());
+
+    // Most specific page wins: country, then continent, then the "*" fallback
+    let page = if denial.geo {
+        [country.as_deref(), continent.as_deref(), Some("*")]
+            .into_iter()
+            .flatten()
+            .find_map(|region| {
+                config
+                    .geo_block_pages
+                    .iter()
+                    .find(|page| page.regions.iter().any(|r| r.eq_ignore_ascii_case(region)))
+            })
+    } else {
+        None
+    };
+
+    let page = match page {
+        Some(page) => page,
+        None => return Response::error("Access denied", 403),
+    };
+
+    let html = page
+        .html
+        .replace("{{reason}}", &escape_html(&denial.reason))
+        .replace("{{rule_id}}", &escape_html(&denial.rule_id))
+        .replace(
+            "{{country}}",
+            &escape_html(country.as_deref().unwrap_or("")),
+        )
+        .replace("{{request_id}}", &escape_html(request_id))
+        .replace(
+            "{{ray_id}}",
+            &escape_html(&request_id::ray_id(req).unwrap_or_default()),
+        );
+
+    let headers = Headers::new();
+    headers.set("Content-Type", "text/html; charset=utf-8")?;
+    headers.set("Cache-Control", "no-store")?;
+    headers.set("X-Block-Rule-Id", &denial.rule_id)?;
+
+    Ok(Response::ok(html)?
+        .with_status(page.status.unwrap_or(DEFAULT_BLOCK_STATUS))
+        .with_headers(headers))
+}
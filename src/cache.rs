@@ -1,50 +1,756 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    io::{Read, Write},
+};
+
+use chrono::{DateTime, Utc};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
 use worker::*;
 
-use crate::config::ProxyConfig;
+use crate::{
+    admin::{AdminIdentity, AuditEntry, record_audit},
+    client_hints,
+    config::{CacheRouteRule, ProxyConfig},
+    context::RequestContext,
+    health, html_rewrite, latency,
+    middleware::apply_response_middleware,
+    normalization::{self, UnkeyedHeaders},
+    utils,
+};
+
+/// Redirects cached like successful responses, `Location` included
+const CACHEABLE_REDIRECTS: &[u16] = &[301, 308];
+
+/// Cached response envelope, stored as JSON by every backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+    #[serde(default)]
+    base64: bool, // Body is base64-encoded binary data
+    cached_at: String,
+    #[serde(default)]
+    expires_at: Option<i64>, // Unix seconds, KV keeps entries for at least 60s
+    #[serde(default)]
+    purged: bool, // Soft-purged, served stale until refreshed
+    #[serde(default)]
+    encoding: Option<String>, /* "gzip" when the stored body is compressed, the only encoding at rest */
+    #[serde(default)]
+    owner: Option<String>, // Credential hash of private entries
+    #[serde(default)]
+    origin: Option<String>, // Backend the response came from
+}
+
+impl CachedResponse {
+    /// Capture status, headers and body of a response
+    async fn capture(response: Response) -> Result<Self> {
+        match Self::capture_limited(response, 0, false).await? {
+            Some(cached) => Ok(cached),
+            None => Err(Error::from("Response body exceeds limit")),
+        }
+    }
+
+    /// Capture a response whose body is at most `limit` bytes (0 = no limit),
+    /// giving up as soon as a streamed body grows beyond it. Compressible
+    /// bodies are gzipped when `compress` is set.
+    async fn capture_limited(
+        mut response: Response,
+        limit: u64,
+        compress: bool,
+    ) -> Result<Option<Self>> {
+        let bytes = if limit == 0 {
+            response.bytes().await?
+        } else {
+            let mut stream = response.stream()?;
+            let mut bytes = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                bytes.extend_from_slice(&chunk?);
+                if bytes.len() as u64 > limit {
+                    return Ok(None);
+                }
+            }
+            bytes
+        };
+
+        let content_type = response.headers().get("Content-Type")?.unwrap_or_default();
+        let compressed = if compress && is_compressible(&content_type, bytes.len()) {
+            gzip(&bytes)
+                .ok()
+                .filter(|compressed| compressed.len() < bytes.len())
+        } else {
+            None
+        };
+        // Text stays readable in storage, anything else is base64-encoded
+        let (body, base64, encoding) = match compressed {
+            Some(compressed) => (
+                utils::base64_encode(&compressed),
+                true,
+                Some("gzip".to_string()),
+            ),
+            None => match String::from_utf8(bytes) {
+                Ok(text) => (text, false, None),
+                Err(e) => (utils::base64_encode(e.as_bytes()), true, None),
+            },
+        };
+
+        Ok(Some(Self {
+            status: response.status_code(),
+            headers: response
+                .headers()
+                .entries()
+                .filter(|(name, _)| !UNCACHED_HEADERS.contains(&name.to_lowercase().as_str()))
+                .collect(),
+            body,
+            base64,
+            cached_at: Utc::now().to_rfc3339(),
+            expires_at: None,
+            purged: false,
+            encoding,
+            owner: None,
+            origin: None,
+        }))
+    }
+
+    /// Rebuild the original response. Compressed bodies are kept compressed
+    /// with `keep_encoding`, for clients accepting gzip, and decompressed otherwise.
+    fn restore(self, keep_encoding: bool) -> Result<Response> {
+        let headers = Headers::new();
+        for (name, value) in &self.headers {
+            headers.append(name, value)?;
+        }
+
+        // Age covers the time spent in upstream caches plus the time stored here
+        let upstream_age = self
+            .header("Age")
+            .and_then(|age| age.trim().parse::<i64>().ok())
+            .unwrap_or(0);
+        let stored_for = DateTime::parse_from_rfc3339(&self.cached_at)
+            .map(|cached_at| (Utc::now() - cached_at.with_timezone(&Utc)).num_seconds())
+            .unwrap_or(0)
+            .max(0);
+        headers.set("Age", &(upstream_age + stored_for).to_string())?;
+
+        // Null body statuses must not carry a body, even an empty one
+        if matches!(self.status, 204 | 205 | 304) {
+            return Ok(Response::empty()?
+                .with_status(self.status)
+                .with_headers(headers));
+        }
+
+        let body = if self.base64 {
+            utils::base64_decode(&self.body)?
+        } else {
+            self.body.into_bytes()
+        };
+        match self.encoding.as_deref() {
+            // Sent as stored, the runtime must not encode the body again
+            Some("gzip") if keep_encoding => {
+                headers.set("Content-Encoding", "gzip")?;
+                let vary = headers.get("Vary")?.unwrap_or_default();
+                if !vary.to_lowercase().contains("accept-encoding") {
+                    headers.append("Vary", "Accept-Encoding")?;
+                }
+                Ok(Response::builder()
+                    .with_status(self.status)
+                    .with_headers(headers)
+                    .with_encode_body(EncodeBody::Manual)
+                    .fixed(body))
+            }
+            Some("gzip") => Ok(Response::from_bytes(gunzip(&body)?)?
+                .with_status(self.status)
+                .with_headers(headers)),
+            _ => Ok(Response::from_bytes(body)?
+                .with_status(self.status)
+                .with_headers(headers)),
+        }
+    }
+
+    /// Get a stored header value
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Check if the origin can revalidate this entry with a conditional request
+    fn has_validators(&self) -> bool {
+        self.header("ETag").is_some() || self.header("Last-Modified").is_some()
+    }
+
+    /// Comma-separated tags from the origin's `Cache-Tag` header
+    fn tags(&self) -> Vec<String> {
+        self.header("Cache-Tag").map(parse_tags).unwrap_or_default()
+    }
+}
+
+/// Result of a cache lookup
+pub enum CacheLookup {
+    Fresh(Response),
+    /// Expired entry kept for conditional revalidation with the origin
+    Stale(StaleEntry),
+    /// Soft-purged entry, served while a fresh copy is fetched in the background
+    Purged(Response),
+    /// Expired entry whose backend is failing, served within the outage TTL
+    Outage(Response),
+    Miss,
+}
+
+/// Expired cache entry with ETag / Last-Modified validators
+pub struct StaleEntry {
+    cached: CachedResponse,
+}
+
+impl StaleEntry {
+    pub fn etag(&self) -> Option<&str> {
+        self.cached.header("ETag")
+    }
+
+    pub fn last_modified(&self) -> Option<&str> {
+        self.cached.header("Last-Modified")
+    }
+
+    /// Apply the headers of a 304 response, which replace the stored ones.
+    /// The entry counts as stored anew from now on.
+    pub fn update(&mut self, headers: &Headers) {
+        self.cached.cached_at = Utc::now().to_rfc3339();
+        for (name, value) in headers.entries() {
+            if UNCACHED_HEADERS.contains(&name.to_lowercase().as_str()) {
+                continue;
+            }
+            self.cached
+                .headers
+                .retain(|(key, _)| !key.eq_ignore_ascii_case(&name));
+            self.cached.headers.push((name, value));
+        }
+    }
+
+    /// Rebuild the stored response
+    pub fn to_response(&self) -> Result<Response> {
+        self.cached.clone().restore(false)
+    }
+}
+
+thread_local! {
+    /// Keys of soft-purged entries this isolate is refreshing
+    static REFRESHING: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/// Bodies smaller than this are stored uncompressed
+const COMPRESS_MIN_BYTES: usize = 1024;
+
+/// Headers that describe the original transfer, not the cached content
+const UNCACHED_HEADERS: &[&str] = &["content-length", "transfer-encoding", "connection"];
+
+/// Synthetic origin for Cache API entries, which are keyed by URL
+const CACHE_API_ORIGIN: &str = "https://cf-proxy.cache";
+
+/// KV key prefix for tag to cache key mappings (`cache_tag:{tag}:{key}`)
+const TAG_PREFIX: &str = "cache_tag:";
+
+/// Maximum number of tags indexed per cached response
+const MAX_TAGS_PER_RESPONSE: usize = 32;
+
+/// Maximum tag length, keeping mapping keys below the KV key size limit
+const MAX_TAG_LENGTH: usize = 256;
+
+/// Storage backend for cached responses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheBackend {
+    /// Workers KV, global but eventually consistent
+    Kv,
+    /// Workers Cache API (`caches.default`), local to each data center
+    CacheApi,
+    /// Cache API first, KV as the global second tier backfilling cold colos
+    Tiered,
+}
+
+impl From<&str> for CacheBackend {
+    fn from(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "cache_api" => CacheBackend::CacheApi,
+            "tiered" => CacheBackend::Tiered,
+            _ => CacheBackend::Kv,
+        }
+    }
+}
+
+impl CacheBackend {
+    fn name(&self) -> &'static str {
+        match self {
+            CacheBackend::Kv => "KV Store",
+            CacheBackend::CacheApi => "Cache API",
+            CacheBackend::Tiered => "Cache API and KV Store",
+        }
+    }
+
+    /// Read a cached envelope, with the Cache API write to run after
+    /// responding when a tiered read was served by KV
+    async fn get(
+        &self,
+        cache_key: &str,
+        env: &Env,
+    ) -> Result<(Option<CachedResponse>, Option<Backfill>)> {
+        match self {
+            CacheBackend::Kv => Ok((kv_get(cache_key, env).await?, None)),
+            CacheBackend::CacheApi => Ok((cache_api_get(cache_key).await?, None)),
+            CacheBackend::Tiered => {
+                if let Some(cached) = cache_api_get(cache_key).await? {
+                    return Ok((Some(cached), None));
+                }
+
+                let cached = kv_get(cache_key, env).await?;
+                // Backfill this colo with fresh entries, expired ones are
+                // written to both tiers once revalidated
+                let backfill = cached.as_ref().and_then(|cached| {
+                    let remaining = cached
+                        .expires_at
+                        .map_or(0, |expires_at| expires_at - Utc::now().timestamp());
+                    (remaining > 0).then(|| Backfill {
+                        cache_key: cache_key.to_string(),
+                        cached: cached.clone(),
+                        ttl: remaining as u64,
+                    })
+                });
+                Ok((cached, backfill))
+            }
+        }
+    }
+
+    /// Write a cached envelope with a TTL in seconds
+    async fn put(
+        &self,
+        cache_key: &str,
+        cached: &CachedResponse,
+        ttl: u64,
+        env: &Env,
+    ) -> Result<()> {
+        match self {
+            CacheBackend::Kv => kv_put(cache_key, cached, ttl, env).await,
+            CacheBackend::CacheApi => cache_api_put(cache_key, cached, ttl).await,
+            CacheBackend::Tiered => {
+                cache_api_put(cache_key, cached, ttl).await?;
+                kv_put(cache_key, cached, ttl, env).await
+            }
+        }
+    }
+
+    /// Delete a cached envelope
+    async fn delete(&self, cache_key: &str, env: &Env) -> Result<()> {
+        if matches!(self, CacheBackend::Kv | CacheBackend::Tiered) {
+            env.kv("PROXY_KV")?.delete(cache_key).await?;
+        }
+        if matches!(self, CacheBackend::CacheApi | CacheBackend::Tiered) {
+            Cache::default()
+                .delete(format!("{CACHE_API_ORIGIN}/{cache_key}"), true)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy of a KV entry for this colo's Cache API, written off the critical path
+pub struct Backfill {
+    cache_key: String,
+    cached: CachedResponse,
+    ttl: u64,
+}
+
+impl Backfill {
+    pub async fn run(self) {
+        if let Err(e) = cache_api_put(&self.cache_key, &self.cached, self.ttl).await {
+            console_log!(
+                "Failed to backfill Cache API for {}: {:?}",
+                self.cache_key,
+                e
+            );
+        }
+    }
+}
+
+async fn kv_get(cache_key: &str, env: &Env) -> Result<Option<CachedResponse>> {
+    Ok(env.kv("PROXY_KV")?.get(cache_key).json().await?)
+}
+
+async fn kv_put(cache_key: &str, cached: &CachedResponse, ttl: u64, env: &Env) -> Result<()> {
+    env.kv("PROXY_KV")?
+        .put(cache_key, serde_json::to_string(cached)?)?
+        // KV rejects expirations shorter than 60 seconds
+        .expiration_ttl(ttl.max(60))
+        .execute()
+        .await?;
+    Ok(())
+}
+
+async fn cache_api_get(cache_key: &str) -> Result<Option<CachedResponse>> {
+    match Cache::default()
+        .get(format!("{CACHE_API_ORIGIN}/{cache_key}"), true)
+        .await?
+    {
+        Some(mut response) => Ok(Some(response.json().await?)),
+        None => Ok(None),
+    }
+}
+
+async fn cache_api_put(cache_key: &str, cached: &CachedResponse, ttl: u64) -> Result<()> {
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    headers.set("Cache-Control", &format!("max-age={ttl}"))?;
+    let response = Response::ok(serde_json::to_string(cached)?)?.with_headers(headers);
+    Cache::default()
+        .put(format!("{CACHE_API_ORIGIN}/{cache_key}"), response)
+        .await?;
+    Ok(())
+}
 
 /// Cache manager
+#[derive(Clone)]
 pub struct CacheManager {
     config: ProxyConfig,
+    backend: CacheBackend,
+    route: Option<CacheRouteRule>, // Policy of the request path, from `CACHE_ROUTES`
+    serve_encoded: bool,           // Client takes compressed entries as stored
+    identity: Option<String>,      // Credential hash on private routes
+    origin: Option<String>,        // Backend of the responses being stored
 }
 
 impl CacheManager {
     pub fn new(config: &ProxyConfig) -> Self {
         Self {
             config: config.clone(),
+            backend: CacheBackend::from(config.cache_backend.as_str()),
+            route: None,
+            serve_encoded: false,
+            identity: None,
+            origin: None,
         }
     }
 
-    /// Get cached response
-    pub async fn get_cached_response(&self, req: &Request, env: &Env) -> Result<Option<Response>> {
-        if !self.config.cache_enabled {
-            return Ok(None);
+    /// Cache manager applying the first `CACHE_ROUTES` rule matching the path
+    pub fn for_route(&self, path: &str) -> Self {
+        let route = self
+            .config
+            .cache_routes
+            .iter()
+            .find(|rule| utils::path_matches(&rule.path, path))
+            .cloned();
+        Self {
+            route,
+            serve_encoded: false,
+            identity: None,
+            ..self.clone()
         }
+    }
 
-        let cache_key = self.generate_cache_key(req)?;
+    /// Owner of the entries of a private route, from `credential_identity`
+    pub fn with_identity(mut self, identity: Option<String>) -> Self {
+        self.identity = identity;
+        self
+    }
 
-        // Try to get cache from KV storage
-        if let Ok(kv) = env.kv("PROXY_KV") {
-            if let Ok(Some(cached_data)) = kv.get(&cache_key).text().await {
+    /// Whether this route caches authenticated responses per credential
+    pub fn is_private(&self) -> bool {
+        self.route.as_ref().is_some_and(|route| route.private)
+    }
+
+    /// Backend the stored responses come from, whose outages extend their TTL
+    pub fn with_origin(mut self, backend: &str) -> Self {
+        self.origin = Some(backend.to_string());
+        self
+    }
+
+    /// Private routes never read or write entries without an owner
+    fn lacks_identity(&self) -> bool {
+        self.is_private() && self.identity.is_none()
+    }
+
+    /// Entries are only served to the credential that stored them
+    fn owns(&self, cached: &CachedResponse) -> bool {
+        cached.owner == self.identity
+    }
+
+    /// Serve compressed entries as stored, for clients accepting gzip whose
+    /// responses are not rewritten on every hit
+    pub fn with_client_encoding(mut self, accepts_gzip: bool) -> Self {
+        self.serve_encoded = accepts_gzip;
+        self
+    }
+
+    /// Whether responses of this route are cached
+    pub fn is_enabled(&self) -> bool {
+        self.route
+            .as_ref()
+            .and_then(|route| route.enabled)
+            .unwrap_or(self.config.cache_enabled)
+    }
+
+    /// Whether this route always goes to the backend, refreshing the cache
+    pub fn is_bypassed(&self) -> bool {
+        self.route.as_ref().is_some_and(|route| route.bypass)
+    }
+
+    /// Whether the request carries a cookie or header of `CACHE_BYPASS_COOKIES`
+    /// / `CACHE_BYPASS_HEADERS`, e.g. a logged-in session, and must neither be
+    /// served from nor stored in the shared cache
+    pub fn bypasses_request(&self, req: &Request) -> Result<bool> {
+        let headers = req.headers();
+        for name in &self.config.cache_bypass_headers {
+            if headers.has(name)? {
+                return Ok(true);
+            }
+        }
+
+        if self.config.cache_bypass_cookies.is_empty() {
+            return Ok(false);
+        }
+        let cookies = headers.get("Cookie")?.unwrap_or_default();
+        Ok(cookies.split(';').any(|cookie| {
+            let name = cookie.split('=').next().unwrap_or("").trim();
+            !name.is_empty()
+                && self
+                    .config
+                    .cache_bypass_cookies
+                    .iter()
+                    .any(|pattern| utils::path_matches(pattern, name))
+        }))
+    }
+
+    /// Fallback and maximum TTL of this route in seconds, capped by
+    /// `CACHE_PRIVATE_MAX_TTL` for private entries
+    fn max_ttl(&self) -> u64 {
+        let ttl = self
+            .route
+            .as_ref()
+            .and_then(|route| route.ttl)
+            .unwrap_or(self.config.cache_ttl);
+        if self.identity.is_some() {
+            ttl.min(self.config.cache_private_max_ttl)
+        } else {
+            ttl
+        }
+    }
+
+    /// Get cached response if it is still fresh
+    pub async fn get_cached_response(
+        &self,
+        cache_key: &str,
+        env: &Env,
+    ) -> Result<Option<Response>> {
+        match self.lookup_entry(cache_key, env).await?.0 {
+            CacheLookup::Fresh(response) => Ok(Some(response)),
+            CacheLookup::Stale(_)
+            | CacheLookup::Purged(_)
+            | CacheLookup::Outage(_)
+            | CacheLookup::Miss => Ok(None),
+        }
+    }
+
+    /// Look up a cached response, returning expired entries that can be
+    /// revalidated. Tiered hits served by KV are copied to the Cache API
+    /// after responding.
+    pub async fn lookup(&self, cache_key: &str, env: &Env, ctx: &Context) -> Result<CacheLookup> {
+        let (lookup, backfill) = self.lookup_entry(cache_key, env).await?;
+        if let Some(backfill) = backfill {
+            ctx.wait_until(backfill.run());
+        }
+        Ok(lookup)
+    }
+
+    async fn lookup_entry(
+        &self,
+        cache_key: &str,
+        env: &Env,
+    ) -> Result<(CacheLookup, Option<Backfill>)> {
+        if !self.is_enabled() || self.is_bypassed() || self.lacks_identity() {
+            return Ok((CacheLookup::Miss, None));
+        }
+
+        match self.backend.get(cache_key, env).await {
+            Ok((cached, backfill)) => Ok((self.classify(cache_key, cached)?, backfill)),
+            Err(e) => {
+                console_log!("Cache read failed for key {}: {:?}", cache_key, e);
+                Ok((self.classify(cache_key, None)?, None))
+            }
+        }
+    }
+
+    /// Whether a read envelope can be served, revalidated or is a miss
+    fn classify(&self, cache_key: &str, cached: Option<CachedResponse>) -> Result<CacheLookup> {
+        match cached {
+            Some(cached) if !self.owns(&cached) => {
+                console_log!("Cache entry {} belongs to another owner", cache_key);
+            }
+            Some(cached) if cached.purged => {
+                console_log!("Serving soft-purged entry for key: {}", cache_key);
+                return cached.restore(self.serve_encoded).map(CacheLookup::Purged);
+            }
+            Some(cached)
+                if cached
+                    .expires_at
+                    .is_none_or(|expires_at| expires_at > Utc::now().timestamp()) =>
+            {
                 console_log!("Cache hit for key: {}", cache_key);
-                // Here should deserialize response data
-                // Simplified implementation: return text response
-                return Ok(Some(Response::ok(cached_data)?));
+                return cached.restore(self.serve_encoded).map(CacheLookup::Fresh);
+            }
+            Some(cached) if self.within_outage_ttl(&cached) => {
+                console_log!("Backend failing, extending TTL of key: {}", cache_key);
+                return cached.restore(self.serve_encoded).map(CacheLookup::Outage);
+            }
+            Some(cached) if self.config.cache_revalidate_window > 0 && cached.has_validators() => {
+                console_log!("Cache entry expired, revalidating key: {}", cache_key);
+                return Ok(CacheLookup::Stale(StaleEntry { cached }));
             }
+            _ => {}
         }
 
         console_log!("Cache miss for key: {}", cache_key);
-        Ok(None)
+        Ok(CacheLookup::Miss)
+    }
+
+    /// Whether an expired entry's backend is failing and the entry is within
+    /// its TTL times `CACHE_OUTAGE_TTL_MULTIPLIER`
+    fn within_outage_ttl(&self, cached: &CachedResponse) -> bool {
+        let multiplier = self.config.cache_outage_ttl_multiplier;
+        let (origin, expires_at) = match (&cached.origin, cached.expires_at) {
+            (Some(origin), Some(expires_at)) if multiplier > 1 => (origin, expires_at),
+            _ => return false,
+        };
+        let cached_at = match DateTime::parse_from_rfc3339(&cached.cached_at) {
+            Ok(cached_at) => cached_at.timestamp(),
+            Err(_) => return false,
+        };
+        let ttl = (expires_at - cached_at).max(0);
+        health::is_failing(origin, &self.config)
+            && cached_at + ttl * multiplier as i64 > Utc::now().timestamp()
+    }
+
+    /// Expired entry to serve when the backend failed, while it is within
+    /// `CACHE_STALE_IF_ERROR` seconds past its expiry
+    pub async fn lookup_stale(&self, cache_key: &str, env: &Env) -> Result<Option<Response>> {
+        if !self.is_enabled()
+            || self.is_bypassed()
+            || self.lacks_identity()
+            || self.config.cache_stale_if_error == 0
+        {
+            return Ok(None);
+        }
+
+        let stale_until = |expires_at: i64| expires_at + self.config.cache_stale_if_error as i64;
+        // Stale entries are expired, so there is nothing to backfill
+        match self.backend.get(cache_key, env).await {
+            Ok((Some(cached), _))
+                if self.owns(&cached)
+                    && cached.expires_at.is_none_or(|expires_at| {
+                        stale_until(expires_at) > Utc::now().timestamp()
+                    }) =>
+            {
+                cached.restore(self.serve_encoded).map(Some)
+            }
+            Ok(_) => Ok(None),
+            Err(e) => {
+                console_log!("Cache read failed for key {}: {:?}", cache_key, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Store an entry the origin confirmed unchanged, with a fresh TTL
+    pub async fn store_revalidated(
+        &self,
+        cache_key: &str,
+        entry: StaleEntry,
+        env: &Env,
+    ) -> Result<()> {
+        let ttl = self.ttl_for(&entry.to_response()?);
+        self.store(cache_key, entry.cached, ttl, env).await
     }
 
     /// Cache response
-    #[allow(dead_code)]
     pub async fn cache_response(
         &self,
-        mut response: Response,
+        cache_key: &str,
+        response: Response,
+        env: &Env,
+    ) -> Result<()> {
+        let ttl = self.ttl_for(&response);
+        let revalidatable = self.config.cache_revalidate_window > 0
+            && (response.headers().has("ETag")? || response.headers().has("Last-Modified")?);
+        if ttl == 0 && !revalidatable {
+            return Ok(());
+        }
+        self.cache_response_with_ttl(cache_key, response, ttl, env)
+            .await
+    }
+
+    /// Derive TTL in seconds from the origin's freshness headers.
+    /// `s-maxage` wins over `max-age`, which wins over `Expires`; the route's
+    /// TTL (or `CACHE_TTL`) is used when none is present and caps the derived value.
+    pub fn ttl_for(&self, response: &Response) -> u64 {
+        let headers = response.headers();
+        let directive = |name: &str| {
+            headers
+                .get("Cache-Control")
+                .ok()
+                .flatten()
+                .and_then(|cache_control| {
+                    cache_control.split(',').find_map(|directive| {
+                        let (key, value) = directive.trim().split_once('=')?;
+                        if key.trim().eq_ignore_ascii_case(name) {
+                            value.trim().trim_matches('"').parse::<u64>().ok()
+                        } else {
+                            None
+                        }
+                    })
+                })
+        };
+
+        let freshness = directive("s-maxage")
+            .or_else(|| directive("max-age"))
+            .or_else(|| {
+                let expires = headers.get("Expires").ok().flatten()?;
+                // Invalid dates such as "0" mean already expired
+                let expires = match DateTime::parse_from_rfc2822(&expires) {
+                    Ok(expires) => expires.with_timezone(&Utc),
+                    Err(_) => return Some(0),
+                };
+                let date = headers
+                    .get("Date")
+                    .ok()
+                    .flatten()
+                    .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+                    .map(|date| date.with_timezone(&Utc))
+                    .unwrap_or_else(Utc::now);
+                Some((expires - date).num_seconds().max(0) as u64)
+            });
+
+        match freshness {
+            Some(seconds) => {
+                // Time already spent in upstream caches counts against freshness
+                let age = headers
+                    .get("Age")
+                    .ok()
+                    .flatten()
+                    .and_then(|age| age.trim().parse::<u64>().ok())
+                    .unwrap_or(0);
+                seconds.saturating_sub(age).min(self.max_ttl())
+            }
+            None => self.max_ttl(),
+        }
+    }
+
+    /// Cache response with an explicit TTL in seconds
+    pub async fn cache_response_with_ttl(
+        &self,
+        cache_key: &str,
+        response: Response,
+        ttl: u64,
         env: &Env,
-        _ctx: &Context,
     ) -> Result<()> {
-        if !self.config.cache_enabled {
+        if !self.is_enabled() || self.lacks_identity() {
             return Ok(());
         }
 
@@ -53,80 +759,334 @@ impl CacheManager {
             return Ok(());
         }
 
-        let cache_key = self.generate_cache_key_from_response(&response)?;
-
-        // Get response content
-        let response_text = response.text().await?;
+        let compress = self.config.cache_compression == "gzip";
+        match CachedResponse::capture_limited(response, self.config.cache_max_body_size, compress)
+            .await?
+        {
+            Some(cached) => self.store(cache_key, cached, ttl, env).await,
+            None => {
+                console_log!("Response too large to cache for key: {}", cache_key);
+                Ok(())
+            }
+        }
+    }
 
-        // Store to KV (simplified implementation)
-        if let Ok(kv) = env.kv("PROXY_KV") {
-            let expiration_ttl = self.config.cache_ttl;
+    /// Write an envelope fresh for `ttl` seconds. Entries are kept longer so
+    /// they can be revalidated (with validators) or served on backend errors
+    /// once expired.
+    async fn store(
+        &self,
+        cache_key: &str,
+        mut cached: CachedResponse,
+        ttl: u64,
+        env: &Env,
+    ) -> Result<()> {
+        cached.expires_at = Some(Utc::now().timestamp() + ttl as i64);
+        cached.owner = self.identity.clone();
+        if self.origin.is_some() {
+            cached.origin = self.origin.clone();
+        }
+        let revalidate_window = if cached.has_validators() {
+            self.config.cache_revalidate_window
+        } else {
+            0
+        };
+        let outage_window = ttl * self.config.cache_outage_ttl_multiplier.saturating_sub(1);
+        let storage_ttl = ttl
+            + revalidate_window
+                .max(self.config.cache_stale_if_error)
+                .max(outage_window);
 
-            // Simplified cache implementation
-            if let Err(e) = kv
-                .put(&cache_key, &response_text)?
-                .expiration_ttl(expiration_ttl)
-                .execute()
-                .await
-            {
-                console_log!("Failed to cache response: {:?}", e);
-            } else {
-                console_log!(
-                    "Cached response with key: {} (TTL: {}s)",
-                    cache_key,
-                    expiration_ttl
-                );
-            }
+        if let Err(e) = self.backend.put(cache_key, &cached, storage_ttl, env).await {
+            console_log!("Failed to cache response: {:?}", e);
+        } else {
+            self.index_tags(cache_key, &cached.tags(), storage_ttl, env)
+                .await;
+            console_log!(
+                "Cached response in {} with key: {} (TTL: {}s)",
+                self.backend.name(),
+                cache_key,
+                ttl
+            );
         }
 
         Ok(())
     }
 
-    /// Generate cache key
-    fn generate_cache_key(&self, req: &Request) -> Result<String> {
+    /// Generate cache key from the configured template
+    pub fn generate_cache_key(&self, req: &Request, tenant: Option<&str>) -> Result<String> {
         let url = req.url()?;
-        let path = url.path();
-        let query = url.query().unwrap_or("");
-        let method = req.method().to_string();
+        // Namespace entries per tenant so tenants never share cached content
+        let tenant = tenant.unwrap_or("-");
 
-        // Simple cache key generation, can be made more complex as needed
-        let cache_key = format!("proxy:{method}:{path}:{query}");
+        // Private entries are namespaced per credential as well
+        let mut key = match &self.identity {
+            Some(identity) => format!("private:{identity}:{tenant}:"),
+            None => format!("proxy:{tenant}:"),
+        };
+        let mut rest = self
+            .route
+            .as_ref()
+            .and_then(|route| route.key_template.as_deref())
+            .unwrap_or(&self.config.cache_key_template);
+        while let Some(start) = rest.find('{') {
+            key.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            let placeholder = &rest[start + 1..end];
+            match placeholder {
+                "method" => key.push_str(req.method().as_ref()),
+                "host" => key.push_str(url.host_str().unwrap_or("")),
+                "path" => key.push_str(url.path()),
+                "query" => key.push_str(&self.normalize_query(&url)),
+                _ => {
+                    if let Some(name) = placeholder.strip_prefix("header:") {
+                        key.push_str(&req.headers().get(name)?.unwrap_or_default());
+                    } else if let Some(name) = placeholder.strip_prefix("cookie:") {
+                        key.push_str(&utils::cookie_value(req, name)?.unwrap_or_default());
+                    } else if let Some(name) = placeholder.strip_prefix("hint:") {
+                        key.push_str(&client_hints::hint(req, name)?.unwrap_or_default());
+                    } else {
+                        // Unknown placeholders are kept literally
+                        key.push_str(&rest[start..=end]);
+                    }
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        key.push_str(rest);
+
+        // Unkeyed headers that reach the backend must vary the entry
+        if normalization::mode(&self.config, self.route.as_ref()) == UnkeyedHeaders::Key {
+            key.push_str(&normalization::key_part(req)?);
+        }
 
         // Use SHA-256 hash to ensure reasonable key length
         use sha2::{Digest, Sha256};
         let mut hasher = Sha256::new();
-        hasher.update(cache_key.as_bytes());
+        hasher.update(key.as_bytes());
         let hash = hasher.finalize();
         Ok(hex::encode(hash))
     }
 
-    /// Generate cache key from response (simplified implementation)
-    #[allow(dead_code)]
-    fn generate_cache_key_from_response(&self, _response: &Response) -> Result<String> {
-        // Here should generate key based on original request, simplified implementation
-        Ok(uuid::Uuid::new_v4().to_string())
+    /// Query string for cache keys, without ignored parameters and
+    /// optionally sorted by name
+    fn normalize_query(&self, url: &Url) -> String {
+        let query = url.query().unwrap_or("");
+        if !self.config.cache_query_sort && self.config.cache_query_ignore.is_empty() {
+            return query.to_string();
+        }
+
+        let mut params: Vec<(String, &str)> = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let name = param.split('=').next().unwrap_or("");
+                (utils::percent_decode(&name.replace('+', " ")), param)
+            })
+            .filter(|(name, _)| {
+                !self
+                    .config
+                    .cache_query_ignore
+                    .iter()
+                    .any(|pattern| utils::path_matches(pattern, name))
+            })
+            .collect();
+
+        // Stable sort keeps the order of repeated parameters
+        if self.config.cache_query_sort {
+            params.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        params
+            .iter()
+            .map(|(_, param)| *param)
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Record tag mappings of a cached entry, expiring along with it
+    async fn index_tags(&self, cache_key: &str, tags: &[String], ttl: u64, env: &Env) {
+        if tags.is_empty() {
+            return;
+        }
+
+        let kv = match env.kv("PROXY_KV") {
+            Ok(kv) => kv,
+            Err(_) => return,
+        };
+
+        for tag in tags {
+            let key = format!("{TAG_PREFIX}{tag}:{cache_key}");
+            if let Ok(put) = kv.put(&key, "") {
+                if let Err(e) = put.expiration_ttl(ttl.max(60)).execute().await {
+                    console_log!("Failed to index cache tag {}: {:?}", tag, e);
+                }
+            }
+        }
+    }
+
+    /// Delete every cached entry carrying one of the tags, returning the
+    /// number of entries purged. Soft purges mark the entries stale instead.
+    pub async fn purge_tags(&self, tags: &[String], soft: bool, env: &Env) -> Result<usize> {
+        let kv = env.kv("PROXY_KV")?;
+        let mut purged = 0;
+
+        for tag in tags {
+            let prefix = format!("{TAG_PREFIX}{tag}:");
+            let mut cursor = None;
+            loop {
+                let mut list = kv.list().prefix(prefix.clone());
+                if let Some(cursor) = cursor.take() {
+                    list = list.cursor(cursor);
+                }
+                let listing = list.execute().await?;
+
+                for key in &listing.keys {
+                    // Cache keys are hex digests, longer matches belong to tags containing ':'
+                    let cache_key = key
+                        .name
+                        .strip_prefix(&prefix)
+                        .filter(|cache_key| !cache_key.contains(':'));
+                    if let Some(cache_key) = cache_key {
+                        if soft {
+                            // The entry keeps its tags, the mapping stays
+                            if self.mark_purged(cache_key, env).await? {
+                                purged += 1;
+                            }
+                        } else {
+                            self.backend.delete(cache_key, env).await?;
+                            kv.delete(&key.name).await?;
+                            purged += 1;
+                        }
+                    }
+                }
+
+                match listing.cursor {
+                    Some(next) if !listing.list_complete => cursor = Some(next),
+                    _ => break,
+                }
+            }
+        }
+
+        console_log!(
+            "{} {} cache entries for tags {:?}",
+            if soft { "Soft-purged" } else { "Purged" },
+            purged,
+            tags
+        );
+        Ok(purged)
+    }
+
+    /// Mark an entry stale so the next request refreshes it in the background.
+    /// It is kept for `CACHE_REVALIDATE_WINDOW` seconds unless refreshed earlier.
+    async fn mark_purged(&self, cache_key: &str, env: &Env) -> Result<bool> {
+        let mut cached = match self.backend.get(cache_key, env).await?.0 {
+            Some(cached) => cached,
+            None => return Ok(false),
+        };
+        cached.purged = true;
+        cached.expires_at = Some(Utc::now().timestamp());
+        self.backend
+            .put(
+                cache_key,
+                &cached,
+                self.config.cache_revalidate_window.max(60),
+                env,
+            )
+            .await?;
+        Ok(true)
+    }
+
+    /// Fetch a soft-purged entry from the backend and store it like a proxied
+    /// response. Each isolate refreshes a key once at a time.
+    pub async fn refresh(
+        &self,
+        cache_key: String,
+        proxy_req: Request,
+        timeout_ms: u64,
+        request_ctx: RequestContext,
+        env: Env,
+    ) {
+        let started =
+            REFRESHING.with(|refreshing| refreshing.borrow_mut().insert(cache_key.clone()));
+        if !started {
+            return;
+        }
+
+        match self
+            .fetch_refresh(&cache_key, proxy_req, timeout_ms, &request_ctx, &env)
+            .await
+        {
+            Ok(()) => console_log!("Refreshed soft-purged entry for key: {}", cache_key),
+            Err(e) => console_log!("Failed to refresh cache entry {}: {:?}", cache_key, e),
+        }
+        REFRESHING.with(|refreshing| refreshing.borrow_mut().remove(&cache_key));
+    }
+
+    async fn fetch_refresh(
+        &self,
+        cache_key: &str,
+        proxy_req: Request,
+        timeout_ms: u64,
+        request_ctx: &RequestContext,
+        env: &Env,
+    ) -> Result<()> {
+        // The refreshed copy is shared, never a 304 for this client's validators
+        proxy_req.headers().delete("If-None-Match")?;
+        proxy_req.headers().delete("If-Modified-Since")?;
+        let response = latency::fetch_with_timeout(proxy_req, timeout_ms).await?;
+        if !self.is_cacheable(&response) {
+            return Err(Error::from(format!(
+                "uncacheable response with status {}",
+                response.status_code()
+            )));
+        }
+
+        // Same response processing as proxied requests, so refreshed entries match
+        let response = html_rewrite::apply_html_rewrites(response, &self.config, request_ctx)?;
+        let response = apply_response_middleware(response, &self.config, request_ctx)?;
+        self.cache_response(cache_key, response, env).await
     }
 
     /// Check if response is cacheable
-    #[allow(dead_code)]
-    fn is_cacheable(&self, response: &Response) -> bool {
+    pub fn is_cacheable(&self, response: &Response) -> bool {
         let status = response.status_code();
 
-        // Only cache successful responses
-        if !(200..300).contains(&status) {
+        if !is_cacheable_status(status) {
             return false;
         }
 
-        // Check Cache-Control header
+        // Skip bodies known to exceed the size limit without reading them
+        if self.config.cache_max_body_size > 0 {
+            if let Ok(Some(length)) = response.headers().get("Content-Length") {
+                if length
+                    .trim()
+                    .parse::<u64>()
+                    .is_ok_and(|length| length > self.config.cache_max_body_size)
+                {
+                    return false;
+                }
+            }
+        }
+
+        // Check Cache-Control header, `private` responses are fine for their own credential
         if let Ok(Some(cache_control)) = response.headers().get("Cache-Control") {
             if cache_control.contains("no-cache")
                 || cache_control.contains("no-store")
-                || cache_control.contains("private")
+                || (cache_control.contains("private") && self.identity.is_none())
             {
                 return false;
             }
         }
 
+        // Never share responses that set per-client cookies
+        if let Ok(Some(_)) = response.headers().get("Set-Cookie") {
+            return false;
+        }
+
         // Check Vary header, don't cache if too variable
         if let Ok(Some(vary)) = response.headers().get("Vary") {
             if vary.to_lowercase().contains("*") {
@@ -137,6 +1097,11 @@ impl CacheManager {
         true
     }
 
+    /// Delete a single cached entry
+    pub async fn remove(&self, cache_key: &str, env: &Env) -> Result<()> {
+        self.backend.delete(cache_key, env).await
+    }
+
     /// Clear cache
     #[allow(dead_code)]
     pub async fn clear_cache(&self, _env: &Env) -> Result<()> {
@@ -153,7 +1118,252 @@ impl CacheManager {
         Ok(serde_json::json!({
             "cache_enabled": self.config.cache_enabled,
             "cache_ttl": self.config.cache_ttl,
-            "cache_type": "KV Store"
+            "cache_type": self.backend.name()
         }))
     }
 }
+
+/// Headers a `304 Not Modified` carries over from the full response
+const NOT_MODIFIED_HEADERS: &[&str] = &[
+    "Cache-Control",
+    "Content-Location",
+    "Date",
+    "ETag",
+    "Expires",
+    "Last-Modified",
+    "Vary",
+];
+
+/// Build a `304 Not Modified` when the client's validators match the response.
+/// `If-None-Match` takes precedence, `If-Modified-Since` is only used without it.
+pub fn not_modified(response: &Response, ctx: &RequestContext) -> Result<Option<Response>> {
+    let headers = response.headers();
+    let matches = match ctx.header("If-None-Match") {
+        Some(if_none_match) => headers.get("ETag")?.is_some_and(|etag| {
+            let etag = etag.trim().trim_start_matches("W/");
+            if_none_match.split(',').any(|candidate| {
+                let candidate = candidate.trim();
+                candidate == "*" || candidate.trim_start_matches("W/") == etag
+            })
+        }),
+        None => match (
+            ctx.header("If-Modified-Since"),
+            headers.get("Last-Modified")?,
+        ) {
+            (Some(since), Some(last_modified)) => {
+                match (
+                    DateTime::parse_from_rfc2822(&since),
+                    DateTime::parse_from_rfc2822(&last_modified),
+                ) {
+                    (Ok(since), Ok(last_modified)) => last_modified <= since,
+                    _ => false,
+                }
+            }
+            _ => false,
+        },
+    };
+    if !matches {
+        return Ok(None);
+    }
+
+    let not_modified = Headers::new();
+    for name in NOT_MODIFIED_HEADERS {
+        if let Some(value) = headers.get(name)? {
+            not_modified.set(name, &value)?;
+        }
+    }
+    Ok(Some(
+        Response::empty()?
+            .with_status(304)
+            .with_headers(not_modified),
+    ))
+}
+
+/// Successful complete responses, and permanent redirects that are
+/// cacheable by default (RFC 9111 4.2.2)
+pub fn is_cacheable_status(status: u16) -> bool {
+    ((200..300).contains(&status) && status != 206) || CACHEABLE_REDIRECTS.contains(&status)
+}
+
+/// Serve the client's `Range` from a complete cached response, as a `206`
+/// or a `416` when the range lies beyond the body. Multiple ranges, ranges
+/// of other units and a stale `If-Range` get the full response instead.
+pub async fn byte_range(response: Response, ctx: &RequestContext) -> Result<Response> {
+    let range = match ctx.header("Range") {
+        Some(range) if response.status_code() == 200 => range,
+        _ => return Ok(response),
+    };
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim().to_string(),
+        _ => return Ok(response),
+    };
+
+    // If-Range only matches a strong ETag or the exact Last-Modified date
+    if let Some(if_range) = ctx.header("If-Range") {
+        let if_range = if_range.trim();
+        let current = if if_range.starts_with('"') || if_range.starts_with("W/") {
+            response.headers().get("ETag")?
+        } else {
+            response.headers().get("Last-Modified")?
+        };
+        if if_range.starts_with("W/") || current.as_deref().map(str::trim) != Some(if_range) {
+            return Ok(response);
+        }
+    }
+
+    let mut response = response;
+    let body = response.bytes().await?;
+    let length = body.len() as u64;
+    let (start, end) = match spec.split_once('-') {
+        Some(("", suffix)) => match suffix.parse::<u64>() {
+            Ok(0) => (length, length), // Empty suffix, never satisfiable
+            Ok(suffix) => (length.saturating_sub(suffix), length.saturating_sub(1)),
+            Err(_) => return utils::rebuild_response(&response, body),
+        },
+        Some((start, end)) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(length.saturating_sub(1))),
+            (Ok(start), Err(_)) if end.is_empty() => (start, length.saturating_sub(1)),
+            _ => return utils::rebuild_response(&response, body),
+        },
+        None => return utils::rebuild_response(&response, body),
+    };
+
+    if start >= length {
+        let headers = Headers::new();
+        headers.set("Content-Range", &format!("bytes */{length}"))?;
+        return Ok(Response::empty()?.with_status(416).with_headers(headers));
+    }
+
+    let partial = body[start as usize..=end as usize].to_vec();
+    let response = utils::rebuild_response(&response, partial)?.with_status(206);
+    response
+        .headers()
+        .set("Content-Range", &format!("bytes {start}-{end}/{length}"))?;
+    Ok(response)
+}
+
+/// Serialize a response as a cache envelope, e.g. to share it between requests
+pub async fn encode_response(response: Response) -> Result<String> {
+    let cached = CachedResponse::capture(response).await?;
+    Ok(serde_json::to_string(&cached)?)
+}
+
+/// Rebuild a response from an envelope produced by `encode_response`
+pub fn decode_response(envelope: &str) -> Result<Response> {
+    serde_json::from_str::<CachedResponse>(envelope)?.restore(false)
+}
+
+/// Split a `Cache-Tag` header value into valid, unique tags
+fn parse_tags(value: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in value.split(',').map(str::trim) {
+        if !tag.is_empty() && tag.len() <= MAX_TAG_LENGTH && !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags.truncate(MAX_TAGS_PER_RESPONSE);
+    tags
+}
+
+/// Request body for purging cached entries by tag
+#[derive(Debug, Deserialize)]
+struct PurgeRequest {
+    tags: Vec<String>,
+    #[serde(default)]
+    soft: bool, // Mark entries stale instead of deleting them
+}
+
+/// Purge all cached entries carrying any of the given tags
+pub async fn handle_purge(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let body: PurgeRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return Response::error("Invalid purge request", 400),
+    };
+
+    let tags = parse_tags(&body.tags.join(","));
+    if tags.is_empty() {
+        return Response::error("At least one tag is required", 400);
+    }
+
+    let purged = CacheManager::new(config)
+        .purge_tags(&tags, body.soft, env)
+        .await?;
+
+    let action = if body.soft {
+        "soft_purge_cache_tags"
+    } else {
+        "purge_cache_tags"
+    };
+    let entry = AuditEntry::new(req, &identity.name, action, &tags.join(","))
+        .with_change(None, Some(serde_json::json!({ "purged": purged })));
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({
+        "tags": tags,
+        "soft": body.soft,
+        "purged": purged
+    }))
+}
+
+/// Hash of the request's credential (`Authorization` or `X-API-Key`), which
+/// owns its entries on private routes. Tokens are not verified here, so the
+/// whole credential is hashed rather than a claim a client could forge.
+pub fn credential_identity(req: &Request) -> Result<Option<String>> {
+    let headers = req.headers();
+    let credential = match headers.get("Authorization")? {
+        Some(authorization) => Some(authorization),
+        None => headers.get("X-API-Key")?,
+    };
+    Ok(credential
+        .map(|credential| credential.trim().to_string())
+        .filter(|credential| !credential.is_empty())
+        .map(|credential| utils::sha256_hash(&credential)))
+}
+
+/// Whether an `Accept-Encoding` header allows a content coding
+pub fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let refused = params.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                == Some(0.0)
+        });
+        (name.eq_ignore_ascii_case(encoding) || name == "*") && !refused
+    })
+}
+
+/// Text-like bodies worth compressing, already compressed media is stored as is.
+/// Bodies are compressed with gzip only, Brotli is not implemented at rest.
+fn is_compressible(content_type: &str, length: usize) -> bool {
+    let content_type = content_type.to_lowercase();
+    length >= COMPRESS_MIN_BYTES
+        && (content_type.starts_with("text/")
+            || ["json", "javascript", "xml", "svg", "wasm"]
+                .iter()
+                .any(|kind| content_type.contains(kind)))
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| Error::from(e.to_string()))
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(data)
+        .read_to_end(&mut decoded)
+        .map_err(|e| Error::from(e.to_string()))?;
+    Ok(decoded)
+}
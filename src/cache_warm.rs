@@ -0,0 +1,329 @@
+use chrono::Utc;
+use futures_util::future::join_all;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{
+    ReverseProxy, context::RequestContext, html_rewrite, latency,
+    middleware::apply_response_middleware, oauth2, origin_signing, sigv4, utils,
+};
+
+/// KV key holding progress and stats of the last warming run
+const WARM_STATUS_KEY: &str = "cache_warm:status";
+
+/// URLs warmed concurrently (Workers allow six open connections per request)
+const WARM_BATCH_SIZE: usize = 6;
+
+/// Maximum number of nested sitemaps read from a sitemap index
+const MAX_NESTED_SITEMAPS: usize = 10;
+
+/// Progress and stats of a cache warming run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WarmStatus {
+    pub running: bool,
+    pub sitemaps: Vec<String>,
+    pub total: usize,
+    pub warmed: usize,
+    pub already_cached: usize,
+    pub failed: usize,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub last_error: Option<String>,
+}
+
+/// Sitemap entry with its crawl hints
+#[derive(Debug, Clone)]
+struct SitemapEntry {
+    loc: String,
+    priority: f64,
+    changefreq: u8,
+}
+
+/// Outcome of warming a single URL
+enum WarmOutcome {
+    Warmed,
+    AlreadyCached,
+    Failed(String),
+}
+
+/// Warm the cache with the top sitemap URLs, recording progress in KV
+pub async fn run(proxy: &ReverseProxy, env: &Env) {
+    let config = &proxy.config;
+    if !config.cache_warm_enabled || !config.cache_enabled {
+        return;
+    }
+
+    let backend = match proxy
+        .load_balancer
+        .get_backend(&proxy.health_checker, None)
+        .await
+    {
+        Some(backend) => backend,
+        None => {
+            console_log!("Cache warming skipped: no healthy backend");
+            return;
+        }
+    };
+
+    let mut status = WarmStatus {
+        running: true,
+        started_at: Some(Utc::now().to_rfc3339()),
+        ..Default::default()
+    };
+
+    status.sitemaps = match &config.cache_warm_sitemap {
+        Some(sitemap) if sitemap.starts_with('/') => vec![format!("{backend}{sitemap}")],
+        Some(sitemap) => vec![sitemap.clone()],
+        None => discover_sitemaps(&backend, config.timeout * 1000).await,
+    };
+
+    let mut entries = Vec::new();
+    for sitemap in &status.sitemaps {
+        match read_sitemap(sitemap, config.timeout * 1000).await {
+            Ok(found) => entries.extend(found),
+            Err(e) => status.last_error = Some(format!("{sitemap}: {e:?}")),
+        }
+    }
+
+    // Highest priority first, then most frequently changing
+    entries.sort_by(|a, b| {
+        b.priority
+            .total_cmp(&a.priority)
+            .then(b.changefreq.cmp(&a.changefreq))
+    });
+    let mut seen = std::collections::HashSet::new();
+    entries.retain(|entry| seen.insert(entry.loc.clone()));
+    entries.truncate(config.cache_warm_top_n);
+    status.total = entries.len();
+    save_status(env, &status).await;
+
+    for batch in entries.chunks(WARM_BATCH_SIZE) {
+        let outcomes = join_all(
+            batch
+                .iter()
+                .map(|entry| warm_url(proxy, &backend, &entry.loc, env)),
+        )
+        .await;
+        for outcome in outcomes {
+            match outcome {
+                WarmOutcome::Warmed => status.warmed += 1,
+                WarmOutcome::AlreadyCached => status.already_cached += 1,
+                WarmOutcome::Failed(error) => {
+                    status.failed += 1;
+                    status.last_error = Some(error);
+                }
+            }
+        }
+        save_status(env, &status).await;
+    }
+
+    status.running = false;
+    status.finished_at = Some(Utc::now().to_rfc3339());
+    save_status(env, &status).await;
+    console_log!(
+        "Cache warming finished: {} warmed, {} already cached, {} failed",
+        status.warmed,
+        status.already_cached,
+        status.failed
+    );
+}
+
+/// Find sitemaps listed in robots.txt, falling back to /sitemap.xml
+async fn discover_sitemaps(backend: &str, timeout_ms: u64) -> Vec<String> {
+    let sitemaps: Vec<String> = match fetch_text(&format!("{backend}/robots.txt"), timeout_ms).await
+    {
+        Ok(robots) => robots
+            .lines()
+            .filter_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.trim()
+                    .eq_ignore_ascii_case("sitemap")
+                    .then(|| value.trim().to_string())
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    if sitemaps.is_empty() {
+        vec![format!("{backend}/sitemap.xml")]
+    } else {
+        sitemaps
+    }
+}
+
+/// Parse a sitemap, following one level of sitemap index
+async fn read_sitemap(url: &str, timeout_ms: u64) -> Result<Vec<SitemapEntry>> {
+    let xml = fetch_text(url, timeout_ms).await?;
+    if !xml.contains("<sitemapindex") {
+        return parse_urlset(&xml);
+    }
+
+    let mut entries = Vec::new();
+    for nested in xml_values(&xml, "loc")?
+        .into_iter()
+        .take(MAX_NESTED_SITEMAPS)
+    {
+        match fetch_text(&nested, timeout_ms).await {
+            Ok(nested_xml) => entries.extend(parse_urlset(&nested_xml)?),
+            Err(e) => console_log!("Failed to read sitemap {}: {:?}", nested, e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Parse `<url>` entries of a sitemap urlset
+fn parse_urlset(xml: &str) -> Result<Vec<SitemapEntry>> {
+    let url_block = Regex::new(r"(?s)<url>(.*?)</url>").map_err(|e| e.to_string())?;
+    let mut entries = Vec::new();
+    for caps in url_block.captures_iter(xml) {
+        let block = &caps[1];
+        let loc = match xml_values(block, "loc")?.into_iter().next() {
+            Some(loc) => loc,
+            None => continue,
+        };
+        let priority = xml_values(block, "priority")?
+            .first()
+            .and_then(|priority| priority.parse().ok())
+            .unwrap_or(0.5);
+        let changefreq = xml_values(block, "changefreq")?
+            .first()
+            .map(|changefreq| changefreq_rank(changefreq))
+            .unwrap_or(0);
+        entries.push(SitemapEntry {
+            loc,
+            priority,
+            changefreq,
+        });
+    }
+    Ok(entries)
+}
+
+/// Text content of all elements with the given tag name
+fn xml_values(xml: &str, tag: &str) -> Result<Vec<String>> {
+    let element =
+        Regex::new(&format!(r"(?s)<{tag}>\s*(.*?)\s*</{tag}>")).map_err(|e| e.to_string())?;
+    Ok(element
+        .captures_iter(xml)
+        .map(|caps| {
+            caps[1]
+                .trim_start_matches("<![CDATA[")
+                .trim_end_matches("]]>")
+                .replace("&amp;", "&")
+        })
+        .collect())
+}
+
+/// Rank changefreq hints, more frequent changes rank higher
+fn changefreq_rank(changefreq: &str) -> u8 {
+    match changefreq.trim().to_lowercase().as_str() {
+        "always" => 6,
+        "hourly" => 5,
+        "daily" => 4,
+        "weekly" => 3,
+        "monthly" => 2,
+        "yearly" => 1,
+        _ => 0,
+    }
+}
+
+/// Fetch a URL as text, failing on non-2xx responses
+async fn fetch_text(url: &str, timeout_ms: u64) -> Result<String> {
+    let request = Request::new(url, Method::Get)?;
+    let mut response = latency::fetch_with_timeout(request, timeout_ms).await?;
+    if !(200..300).contains(&response.status_code()) {
+        return Err(Error::from(format!("status {}", response.status_code())));
+    }
+    response.text().await
+}
+
+/// Fetch a sitemap URL through the backend and store it like a proxied response
+async fn warm_url(proxy: &ReverseProxy, backend: &str, loc: &str, env: &Env) -> WarmOutcome {
+    match try_warm_url(proxy, backend, loc, env).await {
+        Ok(outcome) => outcome,
+        Err(e) => WarmOutcome::Failed(format!("{loc}: {e:?}")),
+    }
+}
+
+async fn try_warm_url(
+    proxy: &ReverseProxy,
+    backend: &str,
+    loc: &str,
+    env: &Env,
+) -> Result<WarmOutcome> {
+    // Sitemap URLs carry no client headers or cookies, so templates using them
+    // only match entries of clients that did not send those
+    let req = Request::new(loc, Method::Get)?;
+    let cache_manager = proxy.cache_manager.for_route(&req.path());
+    if !cache_manager.is_enabled() {
+        return Ok(WarmOutcome::Failed(format!(
+            "{loc}: caching disabled for route"
+        )));
+    }
+    if cache_manager.is_private() {
+        return Ok(WarmOutcome::Failed(format!(
+            "{loc}: private route is cached per credential"
+        )));
+    }
+    let cache_key = cache_manager.generate_cache_key(&req, None)?;
+    if cache_manager
+        .get_cached_response(&cache_key, env)
+        .await?
+        .is_some()
+    {
+        return Ok(WarmOutcome::AlreadyCached);
+    }
+
+    let target_url = proxy.build_target_url(&req, backend)?;
+    let request = Request::new(&target_url, Method::Get)?;
+    let backend_base = target_url.split('/').take(3).collect::<Vec<_>>().join("/");
+    oauth2::authorize(request.headers(), &backend_base, &proxy.config, env).await?;
+    origin_signing::sign(&request, &proxy.config).await?;
+    sigv4::sign(&request, &req.path(), &proxy.config, env).await?;
+    let response = latency::fetch_with_timeout(request, proxy.config.timeout * 1000).await?;
+    if !(200..300).contains(&response.status_code()) {
+        return Ok(WarmOutcome::Failed(format!(
+            "{loc}: status {}",
+            response.status_code()
+        )));
+    }
+
+    // Same response processing as proxied requests, so warmed entries match
+    let request_ctx =
+        RequestContext::from_request(&req, &utils::generate_request_id(), &proxy.config)?;
+    let response = html_rewrite::apply_html_rewrites(response, &proxy.config, &request_ctx)?;
+    let response = apply_response_middleware(response, &proxy.config, &request_ctx)?;
+
+    cache_manager
+        .with_origin(&backend_base)
+        .cache_response(&cache_key, response, env)
+        .await?;
+    Ok(WarmOutcome::Warmed)
+}
+
+/// Persist warming progress
+async fn save_status(env: &Env, status: &WarmStatus) {
+    if let Ok(kv) = env.kv("PROXY_KV") {
+        if let Ok(serialized) = serde_json::to_string(status) {
+            if let Ok(put) = kv.put(WARM_STATUS_KEY, serialized) {
+                if let Err(e) = put.execute().await {
+                    console_log!("Failed to store cache warming status: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Report progress and stats of the last warming run
+pub async fn handle_status(env: &Env) -> Result<Response> {
+    let status = env
+        .kv("PROXY_KV")?
+        .get(WARM_STATUS_KEY)
+        .json::<WarmStatus>()
+        .await?;
+
+    Response::from_json(&serde_json::json!({
+        "status": status,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
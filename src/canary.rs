@@ -0,0 +1,496 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::{kv::KvStore, *};
+
+use crate::{
+    admin::{AdminIdentity, AuditEntry, record_audit},
+    config::ProxyConfig,
+    context::RequestContext,
+    load_balancer, utils,
+};
+
+/// KV key prefix of cohort definitions (`canary:cohorts:{proxy_id}`)
+const COHORTS_PREFIX: &str = "canary:cohorts:";
+
+/// KV key prefix of forced assignments (`canary:member:{proxy_id}:{cohort}:{identity hash}`)
+const MEMBER_PREFIX: &str = "canary:member:";
+
+/// Cohort definitions read from KV are reused for this long by an isolate
+const COHORTS_CACHE_MS: f64 = 10_000.0;
+
+/// Forced assignments read from KV are reused for this long by an isolate
+const ASSIGNMENT_CACHE_MS: f64 = 60_000.0;
+
+/// A group of clients routed to canary backends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cohort {
+    pub name: String,
+    pub percentage: u8,        // Share of eligible clients in the canary, 0-100
+    pub backends: Vec<String>, // Canary backends
+    #[serde(default)]
+    pub rules: Vec<CohortRule>, // All must match for a client to be eligible
+    #[serde(default)]
+    pub per_request: bool, // Split requests rather than clients, nothing is sticky
+}
+
+/// Targeting rule of a cohort
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohortRule {
+    pub key: String,     // "path", "country", "ip", "header:<name>" or "cookie:<name>"
+    pub pattern: String, // Path pattern for "path", exact value otherwise
+}
+
+/// Forced assignment of a client to a cohort
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Assignment {
+    canary: bool,
+    forced: bool,
+    assigned_at: i64, // Unix milliseconds
+}
+
+/// Assignment summary kept as KV key metadata, so counts need no reads
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MemberMetadata {
+    canary: bool,
+    forced: bool,
+    #[serde(default)]
+    identity: Option<String>, // Plain text, kept until the assignment is deleted
+}
+
+/// Whether each forced member key of a cohort is in its canary
+type ForcedMembers = HashMap<String, bool>;
+
+thread_local! {
+    static COHORTS: RefCell<Option<(f64, Vec<Cohort>)>> = const { RefCell::new(None) };
+    /// Forced assignments by cohort, with their load time
+    static FORCED: RefCell<HashMap<String, (f64, ForcedMembers)>> =
+        RefCell::new(HashMap::new());
+}
+
+fn cohorts_key(config: &ProxyConfig) -> String {
+    format!("{COHORTS_PREFIX}{}", config.proxy_id)
+}
+
+fn members_prefix(config: &ProxyConfig, cohort: &str) -> String {
+    format!("{MEMBER_PREFIX}{}:{cohort}:", config.proxy_id)
+}
+
+/// Identities are hashed in keys; forced assignments keep theirs, entered by an
+/// operator, in plain text in the key metadata
+fn member_key(config: &ProxyConfig, cohort: &str, identity: &str) -> String {
+    format!(
+        "{}{}",
+        members_prefix(config, cohort),
+        &utils::sha256_hash(identity)[..32]
+    )
+}
+
+async fn read_cohorts(kv: &KvStore, config: &ProxyConfig) -> Result<Vec<Cohort>> {
+    Ok(kv
+        .get(&cohorts_key(config))
+        .json::<Vec<Cohort>>()
+        .await?
+        .unwrap_or_default())
+}
+
+async fn load_cohorts(env: &Env, config: &ProxyConfig) -> Vec<Cohort> {
+    let now = js_sys::Date::now();
+    let cached = COHORTS.with(|cohorts| {
+        cohorts
+            .borrow()
+            .as_ref()
+            .filter(|(loaded_at, _)| now - loaded_at < COHORTS_CACHE_MS)
+            .map(|(_, cohorts)| cohorts.clone())
+    });
+    if let Some(cohorts) = cached {
+        return cohorts;
+    }
+
+    let cohorts = match env.kv("PROXY_KV") {
+        Ok(kv) => read_cohorts(&kv, config).await.unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+    COHORTS.with(|cached| *cached.borrow_mut() = Some((now, cohorts.clone())));
+    cohorts
+}
+
+async fn store_assignment(
+    kv: &KvStore,
+    key: &str,
+    assignment: &Assignment,
+    identity: &str,
+) -> Result<()> {
+    let metadata = MemberMetadata {
+        canary: assignment.canary,
+        forced: assignment.forced,
+        identity: Some(identity.to_string()),
+    };
+    kv.put(key, serde_json::to_string(assignment)?)?
+        .metadata(metadata)?
+        .execute()
+        .await?;
+    Ok(())
+}
+
+/// Forced assignments of a cohort, read once per `ASSIGNMENT_CACHE_MS`
+async fn load_forced(env: &Env, config: &ProxyConfig, cohort: &str) -> ForcedMembers {
+    let now = js_sys::Date::now();
+    let cached = FORCED.with(|forced| {
+        forced
+            .borrow()
+            .get(cohort)
+            .filter(|(loaded_at, _)| now - loaded_at < ASSIGNMENT_CACHE_MS)
+            .map(|(_, members)| members.clone())
+    });
+    if let Some(members) = cached {
+        return members;
+    }
+
+    let members: ForcedMembers = match env.kv("PROXY_KV") {
+        Ok(kv) => match list_forced(&kv, config, cohort).await {
+            Ok(members) => members
+                .into_iter()
+                .map(|(key, member)| (key, member.canary))
+                .collect(),
+            Err(e) => {
+                console_log!("Failed to load forced canary assignments: {:?}", e);
+                HashMap::new()
+            }
+        },
+        Err(_) => HashMap::new(),
+    };
+    FORCED.with(|forced| {
+        forced
+            .borrow_mut()
+            .insert(cohort.to_string(), (now, members.clone()))
+    });
+    members
+}
+
+fn rule_matches(rule: &CohortRule, req: &Request, request_ctx: &RequestContext) -> bool {
+    match rule.key.as_str() {
+        "path" => utils::path_matches(&rule.pattern, &request_ctx.path),
+        "country" => req
+            .cf()
+            .and_then(|cf| cf.country())
+            .is_some_and(|country| country.eq_ignore_ascii_case(&rule.pattern)),
+        source => load_balancer::hash_key(req, request_ctx.client_ip.as_deref(), source)
+            .ok()
+            .flatten()
+            .is_some_and(|value| value == rule.pattern),
+    }
+}
+
+/// Client's position in a cohort, stable across requests
+fn bucket(cohort: &str, identity: &str) -> u64 {
+    let hash = utils::sha256_hash(&format!("{cohort}:{identity}"));
+    u64::from_str_radix(&hash[..8], 16).unwrap_or(0) % 100
+}
+
+/// Canary cohort of the client and its backends, if the client is eligible
+/// for a cohort and assigned to its canary. Assignments are sticky: a client
+/// keeps its bucket, so raising the percentage only adds clients. Per-request
+/// cohorts send each request to the canary with the cohort's probability.
+pub async fn route(
+    req: &Request,
+    request_ctx: &RequestContext,
+    env: &Env,
+    config: &ProxyConfig,
+) -> Option<(String, Vec<String>)> {
+    let cohorts = load_cohorts(env, config).await;
+    if cohorts.is_empty() {
+        return None;
+    }
+    // Per-request cohorts draw without an identity, clients without one only join those
+    let identity = load_balancer::hash_key(
+        req,
+        request_ctx.client_ip.as_deref(),
+        &config.canary_identity,
+    )
+    .ok()
+    .flatten();
+
+    for cohort in &cohorts {
+        if cohort.backends.is_empty()
+            || !cohort
+                .rules
+                .iter()
+                .all(|rule| rule_matches(rule, req, request_ctx))
+        {
+            continue;
+        }
+        let canary = if cohort.per_request {
+            js_sys::Math::random() * 100.0 < f64::from(cohort.percentage.min(100))
+        } else {
+            match &identity {
+                Some(identity) => assigned(cohort, identity, env, config).await,
+                None => false,
+            }
+        };
+        if canary {
+            return Some((cohort.name.clone(), cohort.backends.clone()));
+        }
+    }
+    None
+}
+
+/// Whether the client is in the cohort's canary: a forced assignment wins,
+/// otherwise the client's bucket is compared with the current percentage.
+/// Only forced assignments are stored, computed ones need no KV access.
+async fn assigned(cohort: &Cohort, identity: &str, env: &Env, config: &ProxyConfig) -> bool {
+    let key = member_key(config, &cohort.name, identity);
+    if let Some(canary) = load_forced(env, config, &cohort.name).await.get(&key) {
+        return *canary;
+    }
+    bucket(&cohort.name, identity) < u64::from(cohort.percentage.min(100))
+}
+
+/// Forced assignments of a cohort by member key
+async fn list_forced(
+    kv: &KvStore,
+    config: &ProxyConfig,
+    cohort: &str,
+) -> Result<Vec<(String, MemberMetadata)>> {
+    let mut members = Vec::new();
+    let mut cursor = None;
+    loop {
+        let mut list = kv.list().prefix(members_prefix(config, cohort));
+        if let Some(cursor) = cursor.take() {
+            list = list.cursor(cursor);
+        }
+        let listing = list.execute().await?;
+
+        // Computed assignments stored by earlier versions expire on their own
+        members.extend(listing.keys.iter().filter_map(|key| {
+            let metadata = key.metadata.clone()?;
+            serde_json::from_value::<MemberMetadata>(metadata)
+                .ok()
+                .filter(|member| member.forced)
+                .map(|member| (key.name.clone(), member))
+        }));
+
+        match listing.cursor {
+            Some(next) if !listing.list_complete => cursor = Some(next),
+            _ => return Ok(members),
+        }
+    }
+}
+
+/// Cohort definitions with their forced assignment counts. Computed
+/// assignments follow from the buckets, so the canary share of the other
+/// eligible clients is the cohort's percentage.
+pub async fn handle_list(env: &Env, config: &ProxyConfig) -> Result<Response> {
+    let kv = env.kv("PROXY_KV")?;
+    let mut cohorts = Vec::new();
+    for cohort in read_cohorts(&kv, config).await? {
+        let forced = if cohort.per_request {
+            Vec::new()
+        } else {
+            list_forced(&kv, config, &cohort.name).await?
+        };
+        cohorts.push(serde_json::json!({
+            "canary_share": f64::from(cohort.percentage.min(100)) / 100.0,
+            "forced": forced.len(),
+            "forced_canary": forced.iter().filter(|(_, member)| member.canary).count(),
+            "cohort": cohort,
+        }));
+    }
+
+    Response::from_json(&serde_json::json!({
+        "identity": config.canary_identity,
+        "cohorts": cohorts,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
+
+/// Replace the cohort definitions
+pub async fn handle_define(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let cohorts: Vec<Cohort> = match req.json().await {
+        Ok(cohorts) => cohorts,
+        Err(_) => return Response::error("Invalid cohort definitions", 400),
+    };
+    if let Some(cohort) = cohorts
+        .iter()
+        .find(|cohort| cohort.name.is_empty() || cohort.name.contains(':'))
+    {
+        return Response::error(format!("Invalid cohort name: {:?}", cohort.name), 400);
+    }
+    if let Some(cohort) = cohorts.iter().find(|cohort| cohort.percentage > 100) {
+        return Response::error(format!("Percentage above 100 for {}", cohort.name), 400);
+    }
+
+    let kv = env.kv("PROXY_KV")?;
+    let previous = read_cohorts(&kv, config).await?;
+    kv.put(&cohorts_key(config), serde_json::to_string(&cohorts)?)?
+        .execute()
+        .await?;
+    COHORTS.with(|cached| cached.borrow_mut().take());
+
+    let entry = AuditEntry::new(req, &identity.name, "define_canary_cohorts", "cohorts")
+        .with_change(
+            Some(serde_json::to_value(previous)?),
+            Some(serde_json::to_value(&cohorts)?),
+        );
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({ "cohorts": cohorts.len() }))
+}
+
+/// Request body adjusting a cohort's split
+#[derive(Debug, Deserialize)]
+struct SplitRequest {
+    cohort: String,
+    percentage: u8,
+}
+
+/// Change the percentage of a cohort without replacing the definitions
+pub async fn handle_split(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let body: SplitRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return Response::error("Invalid split", 400),
+    };
+    if body.percentage > 100 {
+        return Response::error("Percentage above 100", 400);
+    }
+
+    let kv = env.kv("PROXY_KV")?;
+    let mut cohorts = read_cohorts(&kv, config).await?;
+    let cohort = match cohorts.iter_mut().find(|cohort| cohort.name == body.cohort) {
+        Some(cohort) => cohort,
+        None => return Response::error(format!("Unknown cohort: {}", body.cohort), 404),
+    };
+    let previous = std::mem::replace(&mut cohort.percentage, body.percentage);
+    kv.put(&cohorts_key(config), serde_json::to_string(&cohorts)?)?
+        .execute()
+        .await?;
+    COHORTS.with(|cached| cached.borrow_mut().take());
+
+    let entry = AuditEntry::new(req, &identity.name, "set_canary_split", &body.cohort).with_change(
+        Some(serde_json::json!({ "percentage": previous })),
+        Some(serde_json::json!({ "percentage": body.percentage })),
+    );
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({
+        "cohort": body.cohort,
+        "previous": previous,
+        "percentage": body.percentage
+    }))
+}
+
+/// Request body forcing a client into or out of a cohort's canary
+#[derive(Debug, Deserialize)]
+struct ForceRequest {
+    cohort: String,
+    identity: String, // Value of CANARY_IDENTITY, e.g. a client IP or user id
+    canary: bool,
+}
+
+/// Forced assignments of a cohort
+pub async fn handle_list_forced(url: &Url, env: &Env, config: &ProxyConfig) -> Result<Response> {
+    let cohort = match url.query_pairs().find(|(key, _)| key == "cohort") {
+        Some((_, cohort)) => cohort.to_string(),
+        None => return Response::error("Cohort is required", 400),
+    };
+
+    let kv = env.kv("PROXY_KV")?;
+    let forced: Vec<Value> = list_forced(&kv, config, &cohort)
+        .await?
+        .into_iter()
+        .map(|(_, member)| serde_json::json!({ "identity": member.identity, "canary": member.canary }))
+        .collect();
+
+    Response::from_json(&serde_json::json!({ "cohort": cohort, "forced": forced }))
+}
+
+/// Force a client into or out of a cohort's canary
+pub async fn handle_force(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let body: ForceRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return Response::error("Invalid assignment", 400),
+    };
+    if body.identity.is_empty() {
+        return Response::error("Identity is required", 400);
+    }
+
+    let kv = env.kv("PROXY_KV")?;
+    let cohorts = read_cohorts(&kv, config).await?;
+    match cohorts.iter().find(|cohort| cohort.name == body.cohort) {
+        None => return Response::error(format!("Unknown cohort: {}", body.cohort), 404),
+        Some(cohort) if cohort.per_request => {
+            return Response::error("Per-request cohorts have no assignments", 400);
+        }
+        Some(_) => {}
+    }
+
+    let assignment = Assignment {
+        canary: body.canary,
+        forced: true,
+        assigned_at: Utc::now().timestamp_millis(),
+    };
+    let key = member_key(config, &body.cohort, &body.identity);
+    store_assignment(&kv, &key, &assignment, &body.identity).await?;
+    FORCED.with(|forced| forced.borrow_mut().remove(&body.cohort));
+
+    let entry = AuditEntry::new(req, &identity.name, "force_canary_assignment", &body.cohort)
+        .with_change(
+            None,
+            Some(serde_json::json!({ "identity": body.identity, "canary": body.canary })),
+        );
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({
+        "cohort": body.cohort,
+        "identity": body.identity,
+        "canary": body.canary
+    }))
+}
+
+/// Drop a client's forced assignment, so its bucket decides again
+pub async fn handle_unforce(
+    req: &Request,
+    url: &Url,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+    };
+    let (cohort, client) = match (param("cohort"), param("identity")) {
+        (Some(cohort), Some(client)) => (cohort, client),
+        _ => return Response::error("Cohort and identity are required", 400),
+    };
+
+    let key = member_key(config, &cohort, &client);
+    env.kv("PROXY_KV")?.delete(&key).await?;
+    FORCED.with(|forced| forced.borrow_mut().remove(&cohort));
+
+    let entry = AuditEntry::new(req, &identity.name, "clear_canary_assignment", &cohort)
+        .with_change(Some(serde_json::json!({ "identity": client })), None);
+    record_audit(env, config, entry).await;
+
+    Response::from_json(
+        &serde_json::json!({ "cohort": cohort, "identity": client, "cleared": true }),
+    )
+}
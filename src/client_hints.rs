@@ -0,0 +1,68 @@
+use worker::*;
+
+use crate::config::ProxyConfig;
+
+/// Largest device pixel ratio forwarded, higher ratios get the same images
+const MAX_DPR: f64 = 3.0;
+
+/// Width hints are rounded up to a multiple of this many CSS pixels
+const WIDTH_STEP: u64 = 100;
+
+/// Normalized value of a client hint, so near-identical devices share
+/// backend responses and cache entries: pixel ratios round up to a whole
+/// number (at most 3), widths to the next multiple of 100. Other hints are
+/// only trimmed.
+pub fn normalize(name: &str, value: &str) -> String {
+    let value = value.trim();
+    match name.to_ascii_lowercase().as_str() {
+        "dpr" | "sec-ch-dpr" => match value.parse::<f64>() {
+            Ok(dpr) if dpr > 0.0 => format!("{}", dpr.ceil().min(MAX_DPR)),
+            _ => String::new(),
+        },
+        "viewport-width" | "sec-ch-viewport-width" | "width" | "sec-ch-width" => {
+            match value.parse::<f64>() {
+                Ok(width) if width > 0.0 => {
+                    ((width.ceil() as u64).div_ceil(WIDTH_STEP) * WIDTH_STEP).to_string()
+                }
+                _ => String::new(),
+            }
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// Normalized value of a `CLIENT_HINTS` hint sent by the client
+pub fn hint(req: &Request, name: &str) -> Result<Option<String>> {
+    Ok(req
+        .headers()
+        .get(name)?
+        .map(|value| normalize(name, &value))
+        .filter(|value| !value.is_empty()))
+}
+
+/// Forward the configured hints normalized, also in strict header allowlist mode
+pub fn forward(req: &Request, headers: &Headers, config: &ProxyConfig) -> Result<()> {
+    for name in &config.client_hints {
+        match hint(req, name)? {
+            Some(value) => headers.set(name, &value)?,
+            None => headers.delete(name)?,
+        }
+    }
+    Ok(())
+}
+
+/// Ask browsers for the configured hints on HTML pages (`Accept-CH`)
+pub fn request_hints(response: &Response, config: &ProxyConfig) -> Result<()> {
+    if !config.client_hints_accept_ch || config.client_hints.is_empty() {
+        return Ok(());
+    }
+
+    let headers = response.headers();
+    let is_html = headers
+        .get("Content-Type")?
+        .is_some_and(|content_type| content_type.to_lowercase().starts_with("text/html"));
+    if is_html && !headers.has("Accept-CH")? {
+        headers.set("Accept-CH", &config.client_hints.join(", "))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use std::{cell::RefCell, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{cache, config::ProxyConfig};
+
+/// Durable Object binding coordinating requests per cache key
+const COALESCER_BINDING: &str = "CACHE_COALESCER";
+
+/// Interval between checks for the leader's result while waiting
+const FLIGHT_POLL_MS: u64 = 20;
+
+/// How long a finished flight's result is still handed to late joiners
+const FLIGHT_LINGER_MS: f64 = 1000.0;
+
+/// Largest response envelope shared through the Durable Object
+const MAX_SHARED_BYTES: usize = 4 * 1024 * 1024;
+
+/// Role of a request that missed the cache
+pub enum Flight {
+    /// Fetch from the backend and share the result
+    Leader,
+    /// Another request fetched the response
+    Follower(Response),
+    /// Coalescing is off, failed, or the leader did not deliver in time
+    Bypass,
+}
+
+/// Reply of the coordinator to a join request
+#[derive(Debug, Serialize, Deserialize)]
+struct JoinReply {
+    leader: bool,
+    #[serde(default)]
+    response: Option<String>, // Cache envelope of the leader's response
+}
+
+/// Result posted by the leader
+#[derive(Debug, Serialize, Deserialize)]
+struct CompleteRequest {
+    response: Option<String>,
+}
+
+/// Join the in-flight backend request for a cache key, waiting for its
+/// result when another request is already fetching it
+pub async fn join(cache_key: &str, env: &Env, config: &ProxyConfig) -> Flight {
+    if !config.cache_coalescing_enabled {
+        return Flight::Bypass;
+    }
+
+    let url = format!(
+        "https://coalescer/join?wait_ms={}",
+        config.cache_coalescing_wait_ms
+    );
+    let reply = match call(cache_key, &url, None, env).await {
+        Ok(mut response) => response.json::<JoinReply>().await,
+        Err(e) => Err(e),
+    };
+
+    match reply {
+        Ok(JoinReply { leader: true, .. }) => Flight::Leader,
+        Ok(JoinReply {
+            response: Some(envelope),
+            ..
+        }) => match cache::decode_response(&envelope) {
+            Ok(response) => Flight::Follower(response),
+            Err(_) => Flight::Bypass,
+        },
+        Ok(_) => Flight::Bypass,
+        Err(e) => {
+            console_log!("Request coalescing unavailable for {}: {:?}", cache_key, e);
+            Flight::Bypass
+        }
+    }
+}
+
+/// Hand the leader's response (or `None` when it must not be shared) to
+/// waiting requests
+pub async fn complete(cache_key: &str, response: Option<Response>, env: &Env) {
+    let envelope = match response {
+        Some(response) => cache::encode_response(response)
+            .await
+            .ok()
+            .filter(|envelope| envelope.len() <= MAX_SHARED_BYTES),
+        None => None,
+    };
+
+    let body = match serde_json::to_string(&CompleteRequest { response: envelope }) {
+        Ok(body) => body,
+        Err(_) => return,
+    };
+    if let Err(e) = call(cache_key, "https://coalescer/complete", Some(body), env).await {
+        console_log!("Failed to complete flight for {}: {:?}", cache_key, e);
+    }
+}
+
+/// Flight led by this request. Dropping it without `complete` hands waiting
+/// requests `None`, so every early return or error of the leader releases
+/// them instead of leaving them to wait out `CACHE_COALESCING_WAIT_MS`.
+pub struct LeaderGuard<'a> {
+    cache_key: Option<String>,
+    env: Env,
+    ctx: &'a Context,
+}
+
+impl<'a> LeaderGuard<'a> {
+    pub fn new(cache_key: &str, env: &Env, ctx: &'a Context) -> Self {
+        Self {
+            cache_key: Some(cache_key.to_string()),
+            env: env.clone(),
+            ctx,
+        }
+    }
+
+    /// Hand the leader's response to waiting requests after the response is sent
+    pub fn complete(mut self, response: Option<Response>) {
+        self.finish(response);
+    }
+
+    fn finish(&mut self, response: Option<Response>) {
+        if let Some(cache_key) = self.cache_key.take() {
+            let env = self.env.clone();
+            self.ctx
+                .wait_until(async move { complete(&cache_key, response, &env).await });
+        }
+    }
+}
+
+impl Drop for LeaderGuard<'_> {
+    fn drop(&mut self) {
+        self.finish(None);
+    }
+}
+
+/// Check that the coordinator of a key answers
+pub async fn ping(key: &str, env: &Env) -> Result<()> {
+    let response = call(key, "https://coalescer/ping", None, env).await?;
+    if response.status_code() != 200 {
+        return Err(Error::from(format!(
+            "coalescer answered with status {}",
+            response.status_code()
+        )));
+    }
+    Ok(())
+}
+
+/// Send a request to the coordinator of a cache key
+async fn call(cache_key: &str, url: &str, body: Option<String>, env: &Env) -> Result<Response> {
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post);
+    if let Some(body) = body {
+        init.with_body(Some(body.into()));
+    }
+    let request = Request::new_with_init(url, &init)?;
+
+    env.durable_object(COALESCER_BINDING)?
+        .id_from_name(cache_key)?
+        .get_stub()?
+        .fetch_with_request(request)
+        .await
+}
+
+/// Backend request in progress for one cache key
+struct InFlight {
+    started_at: f64,
+    finished_at: Option<f64>,
+    response: Option<String>,
+}
+
+/// Coordinates concurrent cache misses of one key: the first request becomes
+/// the leader, later ones wait for the leader's response
+#[durable_object]
+pub struct CacheCoalescer {
+    flight: RefCell<Option<InFlight>>,
+}
+
+impl DurableObject for CacheCoalescer {
+    fn new(_state: State, _env: Env) -> Self {
+        Self {
+            flight: RefCell::new(None),
+        }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let url = req.url()?;
+        match url.path() {
+            "/join" => {
+                let wait_ms = url
+                    .query_pairs()
+                    .find(|(key, _)| key == "wait_ms")
+                    .and_then(|(_, value)| value.parse::<f64>().ok())
+                    .unwrap_or(0.0);
+                Response::from_json(&self.join(wait_ms).await)
+            }
+            "/complete" => {
+                let complete: CompleteRequest = req.json().await?;
+                if let Some(flight) = self.flight.borrow_mut().as_mut() {
+                    flight.finished_at = Some(js_sys::Date::now());
+                    flight.response = complete.response;
+                }
+                Response::empty()
+            }
+            "/ping" => Response::ok("OK"),
+            _ => Response::error("Not Found", 404),
+        }
+    }
+}
+
+impl CacheCoalescer {
+    async fn join(&self, wait_ms: f64) -> JoinReply {
+        let now = js_sys::Date::now();
+        // Abandoned flights (leader failed before completing) expire after the wait time
+        let active = self
+            .flight
+            .borrow()
+            .as_ref()
+            .is_some_and(|flight| match flight.finished_at {
+                Some(finished_at) => now - finished_at < FLIGHT_LINGER_MS,
+                None => now - flight.started_at < wait_ms,
+            });
+
+        if !active {
+            *self.flight.borrow_mut() = Some(InFlight {
+                started_at: now,
+                finished_at: None,
+                response: None,
+            });
+            return JoinReply {
+                leader: true,
+                response: None,
+            };
+        }
+
+        let deadline = now + wait_ms;
+        loop {
+            let finished = self
+                .flight
+                .borrow()
+                .as_ref()
+                .and_then(|flight| flight.finished_at.map(|_| flight.response.clone()));
+            if let Some(response) = finished {
+                return JoinReply {
+                    leader: false,
+                    response,
+                };
+            }
+            if js_sys::Date::now() >= deadline {
+                return JoinReply {
+                    leader: false,
+                    response: None,
+                };
+            }
+            Delay::from(Duration::from_millis(FLIGHT_POLL_MS)).await;
+        }
+    }
+}
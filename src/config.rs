@@ -1,6 +1,13 @@
+use std::{collections::HashMap, sync::Arc};
+
 use serde::{Deserialize, Serialize};
 use worker::*;
 
+use crate::{
+    access::{AccessMatcher, HeaderMatcher},
+    cors,
+};
+
 /// Path rewrite rule
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PathRewriteRule {
@@ -15,17 +22,275 @@ pub struct BackendConfig {
     pub weight: u32,
     pub health_check_path: Option<String>,
     pub timeout: Option<u64>,
+    #[serde(default)]
+    pub health_check_method: Option<String>, // "GET" (default), "HEAD" or "POST"
+    #[serde(default)]
+    pub health_check_headers: HashMap<String, String>, // e.g. auth tokens for the health route
+    #[serde(default)]
+    pub health_check_status: Vec<u16>, // Healthy status codes, empty means any 2xx
+    #[serde(default)]
+    pub name: Option<String>, // Public name on the status page instead of the URL
+    #[serde(default)]
+    pub oauth2: Option<OAuth2ClientConfig>, // Client credentials for backends behind OAuth2
+    #[serde(default)]
+    pub group: Option<String>, // "primary" (default) or "backup"
+    #[serde(default)]
+    pub alternatives: Vec<String>, /* Other base URLs of the same origin, tried in order when unreachable */
+}
+
+/// OAuth2 client credentials of a backend, the id and secret are read from
+/// the named secrets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuth2ClientConfig {
+    pub token_url: String,
+    pub client_id_var: String,
+    pub client_secret_var: String,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub audience: Option<String>,
+    #[serde(default)]
+    pub auth_method: Option<String>, // "basic" (default) or "post"
 }
 
 /// Access control rule
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccessRule {
-    pub rule_type: String, // "allow" or "deny"
-    pub pattern: String,   // IP, CIDR, or country code
+    pub rule_type: String, // e.g. "deny_ip", "allow_country" or "label_user_agent"
+    pub pattern: String,   // IP, CIDR, country code, ASN, regex or list name
+    #[serde(default)]
+    pub label: Option<String>, // Label attached by "label_*" rules
+    #[serde(default)]
+    pub id: Option<String>, // Rule id shown on block pages for appeals
+}
+
+/// Response watermark rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatermarkRule {
+    pub path: String, // Path pattern, e.g. "/reports/*"
+    pub mode: String, // "header", "html_comment" or "json_field"
+    #[serde(default)]
+    pub field: Option<String>, // Header name or JSON field name
+}
+
+/// Content-Disposition override rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentDispositionRule {
+    #[serde(default)]
+    pub path: Option<String>, // Path pattern, e.g. "/downloads/*"
+    #[serde(default)]
+    pub extensions: Vec<String>, // File extensions without dot, e.g. ["pdf", "zip"]
+    #[serde(default)]
+    pub disposition: Option<String>, // "attachment" (default) or "inline"
+    #[serde(default)]
+    pub filename: Option<String>, // Template with {filename}, {name}, {ext}, {date}
+}
+
+/// Body-based routing rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyRoutingRule {
+    pub path: String,  // Path pattern, e.g. "/webhooks"
+    pub field: String, // Dot-separated JSON field, e.g. "event.type"
+    #[serde(default)]
+    pub equals: Option<String>, // Exact value match
+    #[serde(default)]
+    pub pattern: Option<String>, // Regex value match
+    pub backend: String, // Backend URL receiving matching requests
+}
+
+/// Per-route response header policy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseHeaderRule {
+    pub path: String, // Path pattern, e.g. "/api/*"
+    #[serde(default)]
+    pub allow: Vec<String>, // If set, only these headers reach the client
+    #[serde(default)]
+    pub strip: Vec<String>, // Headers always removed, e.g. "X-Internal-*"
+    #[serde(skip)]
+    pub allow_matcher: HeaderMatcher,
+    #[serde(skip)]
+    pub strip_matcher: HeaderMatcher,
+}
+
+/// JSON Schema validation rule for API bodies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaRule {
+    pub path: String, // Path pattern, e.g. "/api/orders"
+    #[serde(default)]
+    pub methods: Vec<String>, // Methods to validate, empty means all
+    #[serde(default)]
+    pub request: Option<serde_json::Value>, // Schema for request bodies
+    #[serde(default)]
+    pub response: Option<serde_json::Value>, // Schema for response bodies (debug mode)
+}
+
+/// Latency budget for a route
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyBudget {
+    pub path: String, // Path pattern, e.g. "/search"
+    pub max_ms: u64,  // Budget in milliseconds
+}
+
+/// Cache-Control override rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheControlRule {
+    pub path: String,          // Path pattern, e.g. "/assets/*"
+    pub cache_control: String, // Replacement value, e.g. "public, max-age=3600"
+}
+
+/// Per-route cache policy, overriding the global cache settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheRouteRule {
+    pub path: String, // Path pattern, e.g. "/static/*"
+    #[serde(default)]
+    pub enabled: Option<bool>, // Overrides CACHE_ENABLED
+    #[serde(default)]
+    pub ttl: Option<u64>, // Overrides CACHE_TTL
+    #[serde(default)]
+    pub key_template: Option<String>, // Overrides CACHE_KEY_TEMPLATE
+    #[serde(default)]
+    pub bypass: bool, // Skip cache lookups, still storing fresh responses
+    #[serde(default)]
+    pub private: bool, // Cache authenticated responses per credential
+    #[serde(default)]
+    pub unkeyed_headers: Option<String>, /* "forward", "strip" or "key", overrides NORMALIZATION_PROFILE */
+}
+
+/// Contract check verified against live responses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractCheck {
+    pub path: String, // Path pattern, e.g. "/api/users/*"
+    #[serde(default)]
+    pub methods: Vec<String>, // Methods to check, empty means all
+    #[serde(default)]
+    pub status: Vec<u16>, // Expected status codes, empty means any
+    #[serde(default)]
+    pub required_fields: Vec<String>, // Dot-separated JSON fields, e.g. "data.id"
+    #[serde(default)]
+    pub required_headers: Vec<String>, // Headers that must be present
+}
+
+/// Backends serving the paths of a route, balanced separately from `BACKENDS`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendPool {
+    pub path: String, // Path pattern, e.g. "/api/*"
+    pub backends: Vec<String>,
+    #[serde(default)]
+    pub strategy: Option<String>, // Overrides LOAD_BALANCER_STRATEGY
+}
+
+/// Route answered by merging the JSON responses of several sources
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregationRoute {
+    pub path: String, // Path pattern, e.g. "/bff/dashboard"
+    pub sources: Vec<AggregationSource>,
+}
+
+/// Source of an aggregation route, fetched in parallel with the others
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregationSource {
+    pub key: String, // Key of the response in the merged payload
+    pub url: String, // Backend path like "/api/user", or a URL of a configured backend
+    #[serde(default)]
+    pub timeout_ms: Option<u64>, // Defaults to TIMEOUT
+}
+
+/// Rule tagging matching requests for metrics. Tag values are fixed by the
+/// rules, keeping their cardinality low.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricTagRule {
+    pub tag: String,   // Dimension, e.g. "product"
+    pub value: String, // Value of the dimension, e.g. "checkout"
+    #[serde(default)]
+    pub path: Option<String>, // Path pattern the request must match
+    #[serde(default)]
+    pub header: Option<String>, // Header the request must carry
+    #[serde(default)]
+    pub pattern: Option<String>, // Glob over the header value, defaults to any value
+}
+
+/// Response bandwidth cap for a class of clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BandwidthTier {
+    pub name: String,
+    pub bytes_per_sec: u64, // 0 leaves the tier unthrottled
+    #[serde(default)]
+    pub api_keys: Vec<String>, // Keys of the tier, empty for clients without a listed key
+    #[serde(default)]
+    pub paths: Vec<String>, // Path patterns the cap applies to, empty for all
+}
+
+/// Static response served for a route instead of proxying
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockResponse {
+    pub path: String, // Path pattern, e.g. "/api/v2/*"
+    #[serde(default)]
+    pub methods: Vec<String>, // Methods to answer, empty means all
+    #[serde(default)]
+    pub status: Option<u16>, // Defaults to 200
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: String, // Template with {{request_id}}, {{path}}, {{query.<name>}}, ...
+}
+
+/// Critical page checked for unexpected content changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentWatchPage {
+    pub url: String, // Absolute URL, or a path on the first backend
+    #[serde(default)]
+    pub ignore: Vec<String>, // Regexes of volatile content, e.g. CSRF tokens
+}
+
+/// Backend placement override for Cloudflare data centers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColoRoutingRule {
+    pub colos: Vec<String>, // IATA codes of data centers, e.g. ["SIN", "HKG"]
+    #[serde(default)]
+    pub backends: Vec<String>, // Preferred backend pool, empty keeps all backends
+    #[serde(default)]
+    pub weights: std::collections::HashMap<String, u32>, /* Per-backend weights, 0 excludes a backend */
+}
+
+/// Block page served for geo-blocked requests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeoBlockPage {
+    pub regions: Vec<String>, // Country or continent codes, "*" for the fallback page
+    pub html: String,         // Template with {{reason}}, {{rule_id}}, {{country}}, {{request_id}}
+    #[serde(default)]
+    pub status: Option<u16>, // Defaults to 451 Unavailable For Legal Reasons
+}
+
+/// HTML rewrite rule applied to matching elements
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HtmlRewriteRule {
+    pub path: String,     // Path pattern, e.g. "/blog/*"
+    pub selector: String, // CSS selector, e.g. "div.ad-banner"
+    pub action: String,   // "set_attribute", "remove", "append", ...
+    #[serde(default)]
+    pub attribute: Option<String>, // Attribute name for attribute actions
+    #[serde(default)]
+    pub content: Option<String>, // Attribute value or inserted content
+    #[serde(default)]
+    pub text: bool, // Insert content as escaped text instead of HTML
+}
+
+/// AWS Signature Version 4 signing of backend requests for a route
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigV4Route {
+    pub path: String,    // Path pattern, e.g. "/dynamodb/*"
+    pub service: String, // Signing name of the service, e.g. "s3" or "execute-api"
+    pub region: String,  // e.g. "us-east-1"
+    #[serde(default)]
+    pub access_key_id_var: Option<String>, // Defaults to AWS_ACCESS_KEY_ID
+    #[serde(default)]
+    pub secret_access_key_var: Option<String>, // Defaults to AWS_SECRET_ACCESS_KEY
+    #[serde(default)]
+    pub session_token_var: Option<String>, // Defaults to AWS_SESSION_TOKEN, optional
 }
 
 /// Proxy configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ProxyConfig {
     pub backends: Vec<String>,
     pub backend_configs: Vec<BackendConfig>,
@@ -42,6 +307,135 @@ pub struct ProxyConfig {
     pub log_level: String,
     pub timeout: u64,
     pub retry_attempts: u32,
+    pub retry_statuses: Vec<u16>,
+    pub retry_backoff_ms: u64,
+    pub retry_non_idempotent: bool,
+    pub watermark_rules: Vec<WatermarkRule>,
+    pub watermark_secret: String,
+    pub watermark_key_header: String,
+    pub content_disposition_rules: Vec<ContentDispositionRule>,
+    pub content_type_fixup_enabled: bool,
+    pub content_type_overrides: std::collections::HashMap<String, String>,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: String,
+    pub webhook_events: Vec<String>,
+    pub admin_token: Option<String>,
+    pub audit_retention_days: u64,
+    pub config_rollout_min_requests: u64,
+    pub config_rollout_error_tolerance: f64,
+    pub config_rollout_promote_after: u64,
+    pub body_routing_rules: Vec<BodyRoutingRule>,
+    pub body_routing_max_bytes: usize,
+    pub tenant_source: String,
+    pub tenant_header: String,
+    pub tenant_jwt_claim: String,
+    pub tenant_jwt_secret: String, // HS256 key verifying tenant JWTs
+    pub tenant_host_pattern: String,
+    pub url_proxy_partitioned_cookies: bool,
+    pub request_header_allowlist: Vec<String>,
+    pub response_header_rules: Vec<ResponseHeaderRule>,
+    pub schema_rules: Vec<SchemaRule>,
+    pub schema_validate_responses: bool,
+    pub soft_404_patterns: Vec<String>,
+    pub soft_404_convert: bool,
+    pub latency_budgets: Vec<LatencyBudget>,
+    pub latency_budget_header: bool,
+    pub adaptive_timeout_enabled: bool,
+    pub adaptive_timeout_multiplier: f64,
+    pub adaptive_timeout_min_ms: u64,
+    pub adaptive_timeout_max_ms: u64,
+    pub cache_control_rules: Vec<CacheControlRule>,
+    pub access_list_refresh: u64,
+    pub mirror_url: Option<String>,
+    pub mirror_sample_rate: f64,
+    pub mirror_compare_bodies: bool,
+    pub mirror_ignore_fields: Vec<String>,
+    pub mirror_mode: String, // "compare" or "shadow" (fire and forget)
+    pub mirror_methods: Vec<String>, // Methods mirrored, others are never copied
+    pub contract_checks: Vec<ContractCheck>,
+    pub colo_routing_rules: Vec<ColoRoutingRule>,
+    pub geo_block_pages: Vec<GeoBlockPage>,
+    pub cache_backend: String,
+    pub esi_paths: Vec<String>,
+    pub html_rewrite_rules: Vec<HtmlRewriteRule>,
+    pub cache_warm_enabled: bool,
+    pub cache_warm_sitemap: Option<String>,
+    pub cache_warm_top_n: usize,
+    pub backend_concurrency_limit: u32,
+    pub queue_max_wait_ms: u64,
+    pub queue_max_depth: u32,
+    pub cache_revalidate_window: u64,
+    pub cache_stale_if_error: u64,
+    pub cache_compression: String,
+    pub cache_private_max_ttl: u64,
+    pub tail_events_enabled: bool,
+    pub cache_key_template: String,
+    pub cache_query_sort: bool,
+    pub cache_query_ignore: Vec<String>,
+    pub route_auth_tokens: Vec<String>,
+    pub auth_bypass_paths: Vec<String>,
+    pub auth_bypass_networks: Vec<String>,
+    pub auth_bypass_verified_bots: bool,
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allow_credentials: bool,
+    pub proxy_id: String,
+    pub cache_coalescing_enabled: bool,
+    pub cache_coalescing_wait_ms: u64,
+    pub url_proxy_max_redirects: u32,
+    pub cache_max_body_size: u64,
+    pub cache_routes: Vec<CacheRouteRule>,
+    pub cache_bypass_cookies: Vec<String>,
+    pub cache_bypass_headers: Vec<String>,
+    pub reports_enabled: bool,
+    pub reports_retention_days: u32,
+    pub grafana_enabled: bool,
+    pub metrics_push_url: Option<String>,
+    pub metrics_push_format: String,
+    pub metrics_push_auth: Option<String>,
+    pub alert_email_to: Vec<String>,
+    pub alert_email_from: Option<String>,
+    pub mailchannels_api_key: Option<String>,
+    pub alert_throttle_seconds: u64,
+    pub alert_slo_target: f64,
+    pub status_page_enabled: bool,
+    pub status_page_title: String,
+    pub tls_watch_enabled: bool,
+    pub tls_watch_failure_threshold: u32,
+    pub sigv4_routes: Vec<SigV4Route>,
+    pub origin_signing_secret: String,
+    pub origin_signing_previous_secret: Option<String>,
+    pub load_balancer_hash_key: String,
+    pub sticky_sessions_enabled: bool,
+    pub sticky_cookie_name: String,
+    pub sticky_session_ttl: u64,
+    pub sticky_session_secret: String,
+    pub cache_outage_ttl_multiplier: u64,
+    pub client_hints: Vec<String>,
+    pub client_hints_accept_ch: bool,
+    pub content_sha256_enabled: bool,
+    pub content_sha256_verify: bool,
+    pub backend_pools: Vec<BackendPool>,
+    pub reputation_enabled: bool,
+    pub aggregation_routes: Vec<AggregationRoute>,
+    pub slow_start_window: u64,
+    pub drain_grace_period: u64,
+    pub canary_identity: String,
+    pub backend_discovery_key: Option<String>,
+    pub backend_discovery_ttl: u64,
+    pub normalization_profile: String,
+    pub bandwidth_tiers: Vec<BandwidthTier>,
+    pub metric_tags: Vec<MetricTagRule>,
+    pub precompressed_routes: Vec<String>,
+    pub hedge_delay_ms: u64,
+    pub hedge_routes: Vec<String>,
+    pub request_id_format: String,
+    pub mock_responses: Vec<MockResponse>,
+    pub content_watch_pages: Vec<ContentWatchPage>,
+    pub config_allowed_hosts: Vec<String>, // Hosts stored configuration may send requests to
+    #[serde(skip)]
+    pub access_matcher: Arc<AccessMatcher>,
+    #[serde(skip)]
+    pub request_header_matcher: Arc<HeaderMatcher>,
 }
 
 impl Default for ProxyConfig {
@@ -61,6 +455,133 @@ impl Default for ProxyConfig {
             log_level: "info".to_string(),
             timeout: 30,
             retry_attempts: 3,
+            retry_statuses: vec![502, 503, 504],
+            retry_backoff_ms: 100,
+            retry_non_idempotent: false,
+            watermark_rules: vec![],
+            watermark_secret: String::new(),
+            watermark_key_header: "X-API-Key".to_string(),
+            content_disposition_rules: vec![],
+            content_type_fixup_enabled: false,
+            content_type_overrides: std::collections::HashMap::new(),
+            webhook_url: None,
+            webhook_secret: String::new(),
+            webhook_events: vec![],
+            admin_token: None,
+            audit_retention_days: 365,
+            config_rollout_min_requests: 100,
+            config_rollout_error_tolerance: 2.0,
+            config_rollout_promote_after: 600,
+            body_routing_rules: vec![],
+            body_routing_max_bytes: 65536,
+            tenant_source: "none".to_string(),
+            tenant_header: "X-Tenant-Id".to_string(),
+            tenant_jwt_claim: "tenant".to_string(),
+            tenant_jwt_secret: String::new(),
+            tenant_host_pattern: String::new(),
+            url_proxy_partitioned_cookies: false,
+            request_header_allowlist: vec![],
+            response_header_rules: vec![],
+            schema_rules: vec![],
+            schema_validate_responses: false,
+            soft_404_patterns: vec![],
+            soft_404_convert: false,
+            latency_budgets: vec![],
+            latency_budget_header: false,
+            adaptive_timeout_enabled: false,
+            adaptive_timeout_multiplier: 3.0,
+            adaptive_timeout_min_ms: 1000,
+            adaptive_timeout_max_ms: 30000,
+            cache_control_rules: vec![],
+            access_list_refresh: 300,
+            mirror_url: None,
+            mirror_sample_rate: 0.1,
+            mirror_compare_bodies: true,
+            mirror_ignore_fields: vec![],
+            mirror_mode: "compare".to_string(),
+            mirror_methods: vec!["GET".to_string(), "HEAD".to_string()],
+            contract_checks: vec![],
+            colo_routing_rules: vec![],
+            geo_block_pages: vec![],
+            cache_backend: "kv".to_string(),
+            esi_paths: vec![],
+            html_rewrite_rules: vec![],
+            cache_warm_enabled: false,
+            cache_warm_sitemap: None,
+            cache_warm_top_n: 50,
+            backend_concurrency_limit: 0,
+            queue_max_wait_ms: 0,
+            queue_max_depth: 100,
+            cache_revalidate_window: 3600,
+            cache_stale_if_error: 0,
+            cache_compression: "gzip".to_string(),
+            cache_private_max_ttl: 60,
+            tail_events_enabled: false,
+            cache_key_template: "{method}:{path}:{query}".to_string(),
+            cache_query_sort: false,
+            cache_query_ignore: vec![],
+            route_auth_tokens: vec![],
+            auth_bypass_paths: vec![],
+            auth_bypass_networks: vec![],
+            auth_bypass_verified_bots: false,
+            cors_allowed_origins: vec!["*".to_string()],
+            cors_allow_credentials: false,
+            proxy_id: "cf-proxy".to_string(),
+            cache_coalescing_enabled: false,
+            cache_coalescing_wait_ms: 5000,
+            url_proxy_max_redirects: 5,
+            cache_max_body_size: 0,
+            cache_routes: vec![],
+            cache_bypass_cookies: vec![],
+            cache_bypass_headers: vec![],
+            reports_enabled: false,
+            reports_retention_days: 90,
+            grafana_enabled: false,
+            metrics_push_url: None,
+            metrics_push_format: "influx".to_string(),
+            metrics_push_auth: None,
+            alert_email_to: vec![],
+            alert_email_from: None,
+            mailchannels_api_key: None,
+            alert_throttle_seconds: 3600,
+            alert_slo_target: 99.9,
+            status_page_enabled: false,
+            status_page_title: "Service Status".to_string(),
+            tls_watch_enabled: false,
+            tls_watch_failure_threshold: 3,
+            sigv4_routes: vec![],
+            origin_signing_secret: String::new(),
+            origin_signing_previous_secret: None,
+            load_balancer_hash_key: "ip".to_string(),
+            sticky_sessions_enabled: false,
+            sticky_cookie_name: "__proxy_affinity".to_string(),
+            sticky_session_ttl: 3600,
+            sticky_session_secret: String::new(),
+            cache_outage_ttl_multiplier: 1,
+            client_hints: vec![],
+            client_hints_accept_ch: false,
+            content_sha256_enabled: false,
+            content_sha256_verify: false,
+            backend_pools: vec![],
+            reputation_enabled: false,
+            aggregation_routes: vec![],
+            slow_start_window: 0,
+            drain_grace_period: 300,
+            canary_identity: "ip".to_string(),
+            backend_discovery_key: None,
+            backend_discovery_ttl: 30,
+            normalization_profile: "standard".to_string(),
+            bandwidth_tiers: vec![],
+            metric_tags: vec![],
+            precompressed_routes: vec![],
+            hedge_delay_ms: 0,
+            hedge_routes: vec![],
+            request_id_format: "uuid".to_string(),
+            mock_responses: vec![],
+            content_watch_pages: vec![],
+            config_allowed_hosts: vec![],
+            access_matcher: Arc::default(),
+            request_header_matcher: Arc::default(),
         }
     }
 }
@@ -68,86 +589,814 @@ impl Default for ProxyConfig {
 impl ProxyConfig {
     /// Create configuration from environment variables
     pub fn from_env(env: &Env) -> Result<Self> {
+        Self::from_env_with_overrides(env, &std::collections::HashMap::new())
+    }
+
+    /// Create configuration from environment variables, with stored overrides taking precedence
+    pub fn from_env_with_overrides(
+        env: &Env,
+        overrides: &std::collections::HashMap<String, String>,
+    ) -> Result<Self> {
+        Self::from_vars(|name| {
+            overrides
+                .get(name)
+                .cloned()
+                .or_else(|| env.var(name).ok().map(|value| value.to_string()))
+        })
+    }
+
+    /// Create configuration from a variable lookup
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
         let mut config = Self::default();
 
         // Parse backend URL list
-        if let Ok(backends_json) = env.var("BACKEND_URLS") {
-            if let Ok(backends) = serde_json::from_str::<Vec<String>>(&backends_json.to_string()) {
+        if let Some(backends_json) = var("BACKEND_URLS") {
+            if let Ok(backends) = serde_json::from_str::<Vec<String>>(&backends_json) {
                 config.backends = backends;
             }
         }
 
         // Parse backend configurations
-        if let Ok(backend_configs_json) = env.var("BACKEND_CONFIGS") {
+        if let Some(backend_configs_json) = var("BACKEND_CONFIGS") {
             if let Ok(backend_configs) =
-                serde_json::from_str::<Vec<BackendConfig>>(&backend_configs_json.to_string())
+                serde_json::from_str::<Vec<BackendConfig>>(&backend_configs_json)
             {
                 config.backend_configs = backend_configs;
             }
         }
 
         // Load balancer strategy
-        if let Ok(strategy) = env.var("LOAD_BALANCER_STRATEGY") {
-            config.load_balancer_strategy = strategy.to_string();
+        if let Some(strategy) = var("LOAD_BALANCER_STRATEGY") {
+            config.load_balancer_strategy = strategy;
+        }
+
+        if let Some(key) = var("LOAD_BALANCER_HASH_KEY") {
+            if !key.is_empty() {
+                config.load_balancer_hash_key = key;
+            }
         }
 
         // Health check configuration
-        if let Ok(enabled) = env.var("HEALTH_CHECK_ENABLED") {
-            config.health_check_enabled = enabled.to_string().parse().unwrap_or(true);
+        if let Some(enabled) = var("HEALTH_CHECK_ENABLED") {
+            config.health_check_enabled = enabled.parse().unwrap_or(true);
+        }
+
+        if let Some(interval) = var("HEALTH_CHECK_INTERVAL") {
+            config.health_check_interval = interval.parse().unwrap_or(30);
         }
 
-        if let Ok(interval) = env.var("HEALTH_CHECK_INTERVAL") {
-            config.health_check_interval = interval.to_string().parse().unwrap_or(30);
+        if let Some(timeout) = var("HEALTH_CHECK_TIMEOUT") {
+            config.health_check_timeout = timeout.parse().unwrap_or(5);
         }
 
         // Cache configuration
-        if let Ok(enabled) = env.var("CACHE_ENABLED") {
-            config.cache_enabled = enabled.to_string().parse().unwrap_or(false);
+        if let Some(enabled) = var("CACHE_ENABLED") {
+            config.cache_enabled = enabled.parse().unwrap_or(false);
         }
 
-        if let Ok(ttl) = env.var("CACHE_TTL") {
-            config.cache_ttl = ttl.to_string().parse().unwrap_or(300);
+        if let Some(ttl) = var("CACHE_TTL") {
+            config.cache_ttl = ttl.parse().unwrap_or(300);
         }
 
         // Path rewrite rules
-        if let Ok(rules_json) = env.var("PATH_REWRITE_RULES") {
-            if let Ok(rules) = serde_json::from_str::<Vec<PathRewriteRule>>(&rules_json.to_string())
-            {
+        if let Some(rules_json) = var("PATH_REWRITE_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<PathRewriteRule>>(&rules_json) {
                 config.path_rewrite_rules = rules;
             }
         }
 
         // Custom headers
-        if let Ok(headers_json) = env.var("CUSTOM_HEADERS") {
-            if let Ok(headers) = serde_json::from_str::<std::collections::HashMap<String, String>>(
-                &headers_json.to_string(),
-            ) {
+        if let Some(headers_json) = var("CUSTOM_HEADERS") {
+            if let Ok(headers) =
+                serde_json::from_str::<std::collections::HashMap<String, String>>(&headers_json)
+            {
                 config.custom_headers = headers;
             }
         }
 
         // Access control rules
-        if let Ok(rules_json) = env.var("ACCESS_RULES") {
-            if let Ok(rules) = serde_json::from_str::<Vec<AccessRule>>(&rules_json.to_string()) {
+        if let Some(rules_json) = var("ACCESS_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<AccessRule>>(&rules_json) {
                 config.access_rules = rules;
             }
         }
 
         // Log level
-        if let Ok(log_level) = env.var("LOG_LEVEL") {
-            config.log_level = log_level.to_string();
+        if let Some(log_level) = var("LOG_LEVEL") {
+            config.log_level = log_level;
         }
 
         // Timeout configuration
-        if let Ok(timeout) = env.var("TIMEOUT") {
-            config.timeout = timeout.to_string().parse().unwrap_or(30);
+        if let Some(timeout) = var("TIMEOUT") {
+            config.timeout = timeout.parse().unwrap_or(30);
         }
 
         // Retry attempts
-        if let Ok(retry) = env.var("RETRY_ATTEMPTS") {
-            config.retry_attempts = retry.to_string().parse().unwrap_or(3);
+        if let Some(retry) = var("RETRY_ATTEMPTS") {
+            config.retry_attempts = retry.parse().unwrap_or(3);
+        }
+
+        if let Some(statuses) = var("RETRY_STATUSES") {
+            if let Ok(statuses) = serde_json::from_str::<Vec<u16>>(&statuses) {
+                config.retry_statuses = statuses;
+            }
+        }
+
+        if let Some(backoff) = var("RETRY_BACKOFF_MS") {
+            config.retry_backoff_ms = backoff.parse().unwrap_or(100);
+        }
+
+        if let Some(non_idempotent) = var("RETRY_NON_IDEMPOTENT") {
+            config.retry_non_idempotent = non_idempotent.parse().unwrap_or(false);
+        }
+
+        // Response watermarking
+        if let Some(rules_json) = var("WATERMARK_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<WatermarkRule>>(&rules_json) {
+                config.watermark_rules = rules;
+            }
+        }
+
+        if let Some(secret) = var("WATERMARK_SECRET") {
+            config.watermark_secret = secret;
+        }
+
+        if let Some(header) = var("WATERMARK_KEY_HEADER") {
+            config.watermark_key_header = header;
+        }
+
+        // Content-Disposition overrides
+        if let Some(rules_json) = var("CONTENT_DISPOSITION_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<ContentDispositionRule>>(&rules_json) {
+                config.content_disposition_rules = rules;
+            }
+        }
+
+        // Content-Type fixup for misconfigured origins
+        if let Some(enabled) = var("CONTENT_TYPE_FIXUP_ENABLED") {
+            config.content_type_fixup_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        if let Some(overrides_json) = var("CONTENT_TYPE_OVERRIDES") {
+            if let Ok(overrides) =
+                serde_json::from_str::<std::collections::HashMap<String, String>>(&overrides_json)
+            {
+                config.content_type_overrides = overrides;
+            }
+        }
+
+        // Lifecycle event webhooks
+        if let Some(url) = var("WEBHOOK_URL") {
+            if !url.is_empty() {
+                config.webhook_url = Some(url);
+            }
+        }
+
+        if let Some(secret) = var("WEBHOOK_SECRET") {
+            config.webhook_secret = secret;
+        }
+
+        if let Some(events_json) = var("WEBHOOK_EVENTS") {
+            if let Ok(events) = serde_json::from_str::<Vec<String>>(&events_json) {
+                config.webhook_events = events;
+            }
+        }
+
+        // Management API
+        if let Some(token) = var("ADMIN_TOKEN") {
+            if !token.is_empty() {
+                config.admin_token = Some(token);
+            }
+        }
+
+        if let Some(days) = var("AUDIT_RETENTION_DAYS") {
+            config.audit_retention_days = days.parse().unwrap_or(365);
+        }
+
+        // Gradual configuration rollout
+        if let Some(min_requests) = var("CONFIG_ROLLOUT_MIN_REQUESTS") {
+            config.config_rollout_min_requests = min_requests.parse().unwrap_or(100);
+        }
+
+        if let Some(tolerance) = var("CONFIG_ROLLOUT_ERROR_TOLERANCE") {
+            config.config_rollout_error_tolerance = tolerance.parse().unwrap_or(2.0);
+        }
+
+        if let Some(promote_after) = var("CONFIG_ROLLOUT_PROMOTE_AFTER") {
+            config.config_rollout_promote_after = promote_after.parse().unwrap_or(600);
+        }
+
+        // Body-based routing
+        if let Some(rules_json) = var("BODY_ROUTING_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<BodyRoutingRule>>(&rules_json) {
+                config.body_routing_rules = rules;
+            }
+        }
+
+        if let Some(max_bytes) = var("BODY_ROUTING_MAX_BYTES") {
+            config.body_routing_max_bytes = max_bytes.parse().unwrap_or(65536);
+        }
+
+        // Tenant resolution
+        if let Some(source) = var("TENANT_SOURCE") {
+            config.tenant_source = source.to_lowercase();
+        }
+
+        if let Some(header) = var("TENANT_HEADER") {
+            config.tenant_header = header;
+        }
+
+        if let Some(claim) = var("TENANT_JWT_CLAIM") {
+            config.tenant_jwt_claim = claim;
+        }
+
+        if let Some(secret) = var("TENANT_JWT_SECRET") {
+            config.tenant_jwt_secret = secret;
+        }
+
+        if let Some(pattern) = var("TENANT_HOST_PATTERN") {
+            config.tenant_host_pattern = pattern;
+        }
+
+        // Cookie partitioning for embedded URL proxy use
+        if let Some(enabled) = var("URL_PROXY_PARTITIONED_COOKIES") {
+            config.url_proxy_partitioned_cookies = enabled.parse().unwrap_or(false);
+        }
+
+        // Strict outbound header allowlist
+        if let Some(allowlist_json) = var("REQUEST_HEADER_ALLOWLIST") {
+            if let Ok(allowlist) = serde_json::from_str::<Vec<String>>(&allowlist_json) {
+                config.request_header_allowlist = allowlist;
+            }
+        }
+
+        // Per-route response header policies
+        if let Some(rules_json) = var("RESPONSE_HEADER_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<ResponseHeaderRule>>(&rules_json) {
+                config.response_header_rules = rules;
+            }
+        }
+
+        // Body schema validation
+        if let Some(rules_json) = var("SCHEMA_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<SchemaRule>>(&rules_json) {
+                config.schema_rules = rules;
+            }
+        }
+
+        if let Some(enabled) = var("SCHEMA_VALIDATE_RESPONSES") {
+            config.schema_validate_responses = enabled.parse().unwrap_or(false);
+        }
+
+        // Soft 404 detection
+        if let Some(patterns_json) = var("SOFT_404_PATTERNS") {
+            if let Ok(patterns) = serde_json::from_str::<Vec<String>>(&patterns_json) {
+                config.soft_404_patterns = patterns;
+            }
+        }
+
+        if let Some(convert) = var("SOFT_404_CONVERT") {
+            config.soft_404_convert = convert.parse().unwrap_or(false);
+        }
+
+        // Latency budgets
+        if let Some(budgets_json) = var("LATENCY_BUDGETS") {
+            if let Ok(budgets) = serde_json::from_str::<Vec<LatencyBudget>>(&budgets_json) {
+                config.latency_budgets = budgets;
+            }
+        }
+
+        if let Some(header) = var("LATENCY_BUDGET_HEADER") {
+            config.latency_budget_header = header.parse().unwrap_or(false);
+        }
+
+        // Adaptive backend timeouts
+        if let Some(enabled) = var("ADAPTIVE_TIMEOUT_ENABLED") {
+            config.adaptive_timeout_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        if let Some(multiplier) = var("ADAPTIVE_TIMEOUT_MULTIPLIER") {
+            config.adaptive_timeout_multiplier = multiplier.parse().unwrap_or(3.0);
+        }
+
+        if let Some(min_ms) = var("ADAPTIVE_TIMEOUT_MIN_MS") {
+            config.adaptive_timeout_min_ms = min_ms.parse().unwrap_or(1000);
+        }
+
+        if let Some(max_ms) = var("ADAPTIVE_TIMEOUT_MAX_MS") {
+            config.adaptive_timeout_max_ms = max_ms.parse().unwrap_or(30000);
+        }
+
+        // Cache-Control overrides
+        if let Some(rules_json) = var("CACHE_CONTROL_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<CacheControlRule>>(&rules_json) {
+                config.cache_control_rules = rules;
+            }
+        }
+
+        // KV-stored access lists
+        if let Some(refresh) = var("ACCESS_LIST_REFRESH") {
+            config.access_list_refresh = refresh.parse().unwrap_or(300);
+        }
+
+        // Shadow traffic mirroring
+        if let Some(mirror_url) = var("MIRROR_URL") {
+            if !mirror_url.is_empty() {
+                config.mirror_url = Some(mirror_url);
+            }
+        }
+
+        if let Some(rate) = var("MIRROR_SAMPLE_RATE") {
+            config.mirror_sample_rate = rate.parse().unwrap_or(0.1);
+        }
+
+        if let Some(compare) = var("MIRROR_COMPARE_BODIES") {
+            config.mirror_compare_bodies = compare.parse().unwrap_or(true);
+        }
+
+        if let Some(fields_json) = var("MIRROR_IGNORE_FIELDS") {
+            if let Ok(fields) = serde_json::from_str::<Vec<String>>(&fields_json) {
+                config.mirror_ignore_fields = fields;
+            }
+        }
+
+        if let Some(mode) = var("MIRROR_MODE") {
+            config.mirror_mode = mode.to_lowercase();
+        }
+
+        if let Some(methods_json) = var("MIRROR_METHODS") {
+            if let Ok(methods) = serde_json::from_str::<Vec<String>>(&methods_json) {
+                config.mirror_methods = methods;
+            }
+        }
+
+        // Production contract checks
+        if let Some(checks_json) = var("CONTRACT_CHECKS") {
+            if let Ok(checks) = serde_json::from_str::<Vec<ContractCheck>>(&checks_json) {
+                config.contract_checks = checks;
+            }
+        }
+
+        // Per-colo routing overrides
+        if let Some(rules_json) = var("COLO_ROUTING_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<ColoRoutingRule>>(&rules_json) {
+                config.colo_routing_rules = rules;
+            }
+        }
+
+        // Localized geo block pages
+        if let Some(pages_json) = var("GEO_BLOCK_PAGES") {
+            if let Ok(pages) = serde_json::from_str::<Vec<GeoBlockPage>>(&pages_json) {
+                config.geo_block_pages = pages;
+            }
+        }
+
+        // Cache storage backend
+        if let Some(backend) = var("CACHE_BACKEND") {
+            config.cache_backend = backend.to_lowercase();
+        }
+
+        // Edge-side include processing
+        if let Some(paths_json) = var("ESI_PATHS") {
+            if let Ok(paths) = serde_json::from_str::<Vec<String>>(&paths_json) {
+                config.esi_paths = paths;
+            }
+        }
+
+        // HTML rewrite rules
+        if let Some(rules_json) = var("HTML_REWRITE_RULES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<HtmlRewriteRule>>(&rules_json) {
+                config.html_rewrite_rules = rules;
+            }
+        }
+
+        // Scheduled cache warming
+        if let Some(enabled) = var("CACHE_WARM_ENABLED") {
+            config.cache_warm_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        if let Some(sitemap) = var("CACHE_WARM_SITEMAP") {
+            config.cache_warm_sitemap = Some(sitemap);
+        }
+
+        if let Some(top_n) = var("CACHE_WARM_TOP_N") {
+            config.cache_warm_top_n = top_n.parse().unwrap_or(50);
+        }
+
+        // Backend concurrency caps and bounded queueing
+        if let Some(limit) = var("BACKEND_CONCURRENCY_LIMIT") {
+            config.backend_concurrency_limit = limit.parse().unwrap_or(0);
+        }
+
+        if let Some(wait) = var("QUEUE_MAX_WAIT_MS") {
+            config.queue_max_wait_ms = wait.parse().unwrap_or(0);
+        }
+
+        if let Some(depth) = var("QUEUE_MAX_DEPTH") {
+            config.queue_max_depth = depth.parse().unwrap_or(100);
+        }
+
+        // Conditional revalidation of expired cache entries
+        if let Some(window) = var("CACHE_REVALIDATE_WINDOW") {
+            config.cache_revalidate_window = window.parse().unwrap_or(3600);
+        }
+
+        // Expired cache entries served while backends fail
+        if let Some(window) = var("CACHE_STALE_IF_ERROR") {
+            config.cache_stale_if_error = window.parse().unwrap_or(0);
+        }
+
+        // Compression of cached bodies at rest
+        if let Some(compression) = var("CACHE_COMPRESSION") {
+            match compression.to_lowercase().as_str() {
+                "gzip" | "none" => config.cache_compression = compression.to_lowercase(),
+                _ => console_log!("Unknown CACHE_COMPRESSION {}, using gzip", compression),
+            }
+        }
+
+        // Per-credential caching on private routes
+        if let Some(ttl) = var("CACHE_PRIVATE_MAX_TTL") {
+            config.cache_private_max_ttl = ttl.parse().unwrap_or(60);
+        }
+
+        // Structured request events for tail workers
+        if let Some(enabled) = var("TAIL_EVENTS_ENABLED") {
+            config.tail_events_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        // Cache key composition
+        if let Some(template) = var("CACHE_KEY_TEMPLATE") {
+            config.cache_key_template = template;
+        }
+
+        // Cache key query normalization
+        if let Some(sort) = var("CACHE_QUERY_SORT") {
+            config.cache_query_sort = sort.parse().unwrap_or(false);
+        }
+
+        if let Some(ignore_json) = var("CACHE_QUERY_IGNORE") {
+            if let Ok(ignore) = serde_json::from_str::<Vec<String>>(&ignore_json) {
+                config.cache_query_ignore = ignore;
+            }
+        }
+
+        // Route authentication and its bypass lists
+        if let Some(tokens_json) = var("ROUTE_AUTH_TOKENS") {
+            if let Ok(tokens) = serde_json::from_str::<Vec<String>>(&tokens_json) {
+                config.route_auth_tokens = tokens;
+            }
+        }
+
+        if let Some(paths_json) = var("AUTH_BYPASS_PATHS") {
+            if let Ok(paths) = serde_json::from_str::<Vec<String>>(&paths_json) {
+                config.auth_bypass_paths = paths;
+            }
+        }
+
+        if let Some(networks_json) = var("AUTH_BYPASS_NETWORKS") {
+            if let Ok(networks) = serde_json::from_str::<Vec<String>>(&networks_json) {
+                config.auth_bypass_networks = networks;
+            }
+        }
+
+        if let Some(bypass) = var("AUTH_BYPASS_VERIFIED_BOTS") {
+            config.auth_bypass_verified_bots = bypass.parse().unwrap_or(false);
+        }
+
+        // CORS origins, validated here so misconfigured entries are reported once
+        if let Some(origins_json) = var("CORS_ALLOWED_ORIGINS") {
+            if let Ok(origins) = serde_json::from_str::<Vec<String>>(&origins_json) {
+                config.cors_allowed_origins = origins
+                    .iter()
+                    .filter_map(|origin| {
+                        let normalized = cors::normalize_origin(origin.trim());
+                        if normalized.is_none() {
+                            console_log!("Ignoring invalid CORS origin: {}", origin);
+                        }
+                        normalized
+                    })
+                    .collect();
+            }
+        }
+
+        if let Some(credentials) = var("CORS_ALLOW_CREDENTIALS") {
+            config.cors_allow_credentials = credentials.parse().unwrap_or(false);
+        }
+
+        // Proxy identity for Via and loop detection
+        if let Some(id) = var("PROXY_ID") {
+            config.proxy_id = id;
+        }
+
+        if config.cors_allow_credentials && config.cors_allowed_origins.iter().any(|o| o == "*") {
+            console_log!(
+                "CORS credentials are only sent to explicitly listed origins, not to '*' matches"
+            );
+        }
+
+        // Single-flight backend requests on cache misses
+        if let Some(enabled) = var("CACHE_COALESCING_ENABLED") {
+            config.cache_coalescing_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        if let Some(wait) = var("CACHE_COALESCING_WAIT_MS") {
+            config.cache_coalescing_wait_ms = wait.parse().unwrap_or(5000);
+        }
+
+        // Server-side redirect following for the URL proxy
+        if let Some(max) = var("URL_PROXY_MAX_REDIRECTS") {
+            config.url_proxy_max_redirects = max.parse().unwrap_or(5);
+        }
+
+        // Largest response body stored in the cache
+        if let Some(size) = var("CACHE_MAX_BODY_SIZE") {
+            config.cache_max_body_size = size.parse().unwrap_or(0);
+        }
+
+        // Per-route cache policies, first matching rule applies
+        if let Some(rules_json) = var("CACHE_ROUTES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<CacheRouteRule>>(&rules_json) {
+                config.cache_routes = rules;
+            }
+        }
+
+        // Requests never served from or stored in the shared cache
+        if let Some(cookies_json) = var("CACHE_BYPASS_COOKIES") {
+            if let Ok(cookies) = serde_json::from_str::<Vec<String>>(&cookies_json) {
+                config.cache_bypass_cookies = cookies;
+            }
+        }
+
+        if let Some(headers_json) = var("CACHE_BYPASS_HEADERS") {
+            if let Ok(headers) = serde_json::from_str::<Vec<String>>(&headers_json) {
+                config.cache_bypass_headers = headers;
+            }
+        }
+
+        // Scheduled traffic reports to R2
+        if let Some(enabled) = var("REPORTS_ENABLED") {
+            config.reports_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        if let Some(days) = var("REPORTS_RETENTION_DAYS") {
+            config.reports_retention_days = days.parse().unwrap_or(90);
+        }
+
+        // Grafana JSON datasource API
+        if let Some(enabled) = var("GRAFANA_ENABLED") {
+            config.grafana_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        // Metrics push sink, flushed on cron triggers
+        if let Some(url) = var("METRICS_PUSH_URL") {
+            if !url.is_empty() {
+                config.metrics_push_url = Some(url);
+            }
+        }
+
+        if let Some(format) = var("METRICS_PUSH_FORMAT") {
+            match format.as_str() {
+                "influx" | "json" => config.metrics_push_format = format,
+                _ => console_log!("Unknown METRICS_PUSH_FORMAT {}, using influx", format),
+            }
+        }
+
+        if let Some(authorization) = var("METRICS_PUSH_AUTH") {
+            if !authorization.is_empty() {
+                config.metrics_push_auth = Some(authorization);
+            }
+        }
+
+        // Email alerts via MailChannels
+        if let Some(to_json) = var("ALERT_EMAIL_TO") {
+            if let Ok(to) = serde_json::from_str::<Vec<String>>(&to_json) {
+                config.alert_email_to = to;
+            }
+        }
+
+        if let Some(from) = var("ALERT_EMAIL_FROM") {
+            if !from.is_empty() {
+                config.alert_email_from = Some(from);
+            }
+        }
+
+        if let Some(api_key) = var("MAILCHANNELS_API_KEY") {
+            if !api_key.is_empty() {
+                config.mailchannels_api_key = Some(api_key);
+            }
+        }
+
+        if let Some(throttle) = var("ALERT_THROTTLE_SECONDS") {
+            config.alert_throttle_seconds = throttle.parse().unwrap_or(3600);
+        }
+
+        if let Some(target) = var("ALERT_SLO_TARGET") {
+            config.alert_slo_target = target.parse().unwrap_or(99.9);
+        }
+
+        // Public status page
+        if let Some(enabled) = var("STATUS_PAGE_ENABLED") {
+            config.status_page_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        if let Some(title) = var("STATUS_PAGE_TITLE") {
+            if !title.is_empty() {
+                config.status_page_title = title;
+            }
+        }
+
+        // Backend TLS watchdog, run on cron triggers
+        if let Some(enabled) = var("TLS_WATCH_ENABLED") {
+            config.tls_watch_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        if let Some(threshold) = var("TLS_WATCH_FAILURE_THRESHOLD") {
+            config.tls_watch_failure_threshold = threshold.parse().unwrap_or(3).max(1);
+        }
+
+        // AWS SigV4 signing of requests to AWS service backends
+        if let Some(rules_json) = var("SIGV4_ROUTES") {
+            if let Ok(rules) = serde_json::from_str::<Vec<SigV4Route>>(&rules_json) {
+                config.sigv4_routes = rules;
+            }
+        }
+
+        // HMAC signatures proving to origins that requests came through the proxy
+        if let Some(secret) = var("ORIGIN_SIGNING_SECRET") {
+            config.origin_signing_secret = secret;
+        }
+
+        if let Some(secret) = var("ORIGIN_SIGNING_PREVIOUS_SECRET") {
+            if !secret.is_empty() {
+                config.origin_signing_previous_secret = Some(secret);
+            }
+        }
+
+        // Sticky sessions pinning clients to a backend with a signed cookie
+        if let Some(enabled) = var("STICKY_SESSIONS_ENABLED") {
+            config.sticky_sessions_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        if let Some(name) = var("STICKY_COOKIE_NAME") {
+            if !name.is_empty() {
+                config.sticky_cookie_name = name;
+            }
+        }
+
+        if let Some(ttl) = var("STICKY_SESSION_TTL") {
+            config.sticky_session_ttl = ttl.parse().unwrap_or(3600);
+        }
+
+        if let Some(secret) = var("STICKY_SESSION_SECRET") {
+            config.sticky_session_secret = secret;
+        }
+
+        // Cache TTL extension while an entry's backend is failing
+        if let Some(multiplier) = var("CACHE_OUTAGE_TTL_MULTIPLIER") {
+            config.cache_outage_ttl_multiplier = multiplier.parse().unwrap_or(1).max(1);
+        }
+
+        // Client hints forwarded normalized, and requested on HTML pages
+        if let Some(hints_json) = var("CLIENT_HINTS") {
+            if let Ok(hints) = serde_json::from_str::<Vec<String>>(&hints_json) {
+                config.client_hints = hints;
+            }
+        }
+
+        if let Some(enabled) = var("CLIENT_HINTS_ACCEPT_CH") {
+            config.client_hints_accept_ch = enabled.parse().unwrap_or(false);
+        }
+
+        // Request body digests for integrity checks
+        if let Some(enabled) = var("CONTENT_SHA256_ENABLED") {
+            config.content_sha256_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        if let Some(verify) = var("CONTENT_SHA256_VERIFY") {
+            config.content_sha256_verify = verify.parse().unwrap_or(false);
+        }
+
+        // Per-route backend pools
+        if let Some(pools_json) = var("BACKEND_POOLS") {
+            if let Ok(pools) = serde_json::from_str::<Vec<BackendPool>>(&pools_json) {
+                config.backend_pools = pools;
+            }
+        }
+
+        // Client reputation scoring
+        if let Some(enabled) = var("REPUTATION_ENABLED") {
+            config.reputation_enabled = enabled.parse().unwrap_or(false);
+        }
+
+        // Fan-out aggregation routes
+        if let Some(routes) = var("AGGREGATION_ROUTES") {
+            if let Ok(routes) = serde_json::from_str::<Vec<AggregationRoute>>(&routes) {
+                config.aggregation_routes = routes;
+            }
+        }
+
+        // Slow start of recovered backends
+        if let Some(window) = var("SLOW_START_WINDOW") {
+            config.slow_start_window = window.parse().unwrap_or(0);
+        }
+
+        // Connection draining
+        if let Some(grace_period) = var("DRAIN_GRACE_PERIOD") {
+            config.drain_grace_period = grace_period.parse().unwrap_or(300);
+        }
+
+        // Canary cohorts
+        if let Some(identity) = var("CANARY_IDENTITY") {
+            if !identity.is_empty() {
+                config.canary_identity = identity;
+            }
+        }
+
+        // Backend discovery from KV
+        if let Some(key) = var("BACKEND_DISCOVERY_KEY") {
+            if !key.is_empty() {
+                config.backend_discovery_key = Some(key);
+            }
+        }
+
+        if let Some(ttl) = var("BACKEND_DISCOVERY_TTL") {
+            config.backend_discovery_ttl = ttl.parse().unwrap_or(30);
+        }
+
+        // Request normalization
+        if let Some(profile) = var("NORMALIZATION_PROFILE") {
+            config.normalization_profile = profile;
+        }
+
+        // Per-client bandwidth caps
+        if let Some(tiers) = var("BANDWIDTH_TIERS") {
+            if let Ok(tiers) = serde_json::from_str::<Vec<BandwidthTier>>(&tiers) {
+                config.bandwidth_tiers = tiers;
+            }
+        }
+
+        // Metric tags
+        if let Some(rules) = var("METRIC_TAGS") {
+            if let Ok(rules) = serde_json::from_str::<Vec<MetricTagRule>>(&rules) {
+                config.metric_tags = rules;
+            }
+        }
+
+        // Pre-compressed static assets
+        if let Some(routes) = var("PRECOMPRESSED_ROUTES") {
+            if let Ok(routes) = serde_json::from_str::<Vec<String>>(&routes) {
+                config.precompressed_routes = routes;
+            }
+        }
+
+        // Hedged requests
+        if let Some(delay) = var("HEDGE_DELAY_MS") {
+            config.hedge_delay_ms = delay.parse().unwrap_or(0);
+        }
+
+        if let Some(routes) = var("HEDGE_ROUTES") {
+            if let Ok(routes) = serde_json::from_str::<Vec<String>>(&routes) {
+                config.hedge_routes = routes;
+            }
+        }
+
+        // Request IDs
+        if let Some(format) = var("REQUEST_ID_FORMAT") {
+            config.request_id_format = format.to_lowercase();
         }
 
+        // Mock responses
+        if let Some(mocks) = var("MOCK_RESPONSES") {
+            if let Ok(mocks) = serde_json::from_str::<Vec<MockResponse>>(&mocks) {
+                config.mock_responses = mocks;
+            }
+        }
+
+        // Defacement detection
+        if let Some(pages) = var("CONTENT_WATCH_PAGES") {
+            if let Ok(pages) = serde_json::from_str::<Vec<ContentWatchPage>>(&pages) {
+                config.content_watch_pages = pages;
+            }
+        }
+
+        // Hosts runtime configuration may point at
+        if let Some(hosts) = var("CONFIG_ALLOWED_HOSTS") {
+            if let Ok(hosts) = serde_json::from_str::<Vec<String>>(&hosts) {
+                config.config_allowed_hosts = hosts;
+            }
+        }
+
+        config.compile_matchers();
         Ok(config)
     }
+
+    /// Compile access rules and header policies once per config load
+    fn compile_matchers(&mut self) {
+        self.access_matcher = Arc::new(AccessMatcher::compile(&self.access_rules));
+        self.request_header_matcher = Arc::new(HeaderMatcher::new(&self.request_header_allowlist));
+        for rule in &mut self.response_header_rules {
+            rule.allow_matcher = HeaderMatcher::new(&rule.allow);
+            rule.strip_matcher = HeaderMatcher::new(&rule.strip);
+        }
+    }
 }
@@ -0,0 +1,963 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use worker::{kv::KvStore, *};
+
+use crate::{
+    admin::{AdminIdentity, AuditEntry, record_audit},
+    config::ProxyConfig,
+    discovery, drain, utils,
+    webhooks::{ProxyEvent, WebhookEmitter},
+};
+
+/// KV key of the active configuration document
+const ACTIVE_KEY: &str = "config:active";
+
+/// KV key of the candidate configuration being rolled out
+const ROLLOUT_KEY: &str = "config:rollout";
+
+/// KV key prefix for per-version rollout statistics
+const ROLLOUT_STATS_PREFIX: &str = "config:rollout_stats:";
+
+/// How long stored configuration is memoized in the isolate
+const STATE_CACHE_TTL_MS: f64 = 10_000.0;
+
+/// Minimum interval between rollout statistics flushes per isolate
+const STATS_FLUSH_INTERVAL_MS: f64 = 10_000.0;
+
+/// Schema version of the first configuration documents
+const FIRST_SCHEMA_VERSION: u32 = 1;
+
+/// Schema migrations, index N upgrades a document from schema
+/// `FIRST_SCHEMA_VERSION + N` to the next one
+const MIGRATIONS: &[fn(&mut Value, &mut Vec<String>)] = &[];
+
+/// Schema version of configuration documents written by this worker
+const CONFIG_SCHEMA_VERSION: u32 = FIRST_SCHEMA_VERSION + MIGRATIONS.len() as u32;
+
+/// Variables that stored configuration may override. Secrets, and settings
+/// that would send one or client requests to another host (`METRICS_PUSH_URL`
+/// carries `METRICS_PUSH_AUTH`, `MIRROR_URL` copies client credentials,
+/// `WEBHOOK_URL` receives signed events), can only be set through the
+/// environment. URLs in the others must be on `CONFIG_ALLOWED_HOSTS`.
+const EDITABLE_VARS: &[&str] = &[
+    "ACCESS_LIST_REFRESH",
+    "ACCESS_RULES",
+    "ADAPTIVE_TIMEOUT_ENABLED",
+    "ADAPTIVE_TIMEOUT_MAX_MS",
+    "ADAPTIVE_TIMEOUT_MIN_MS",
+    "ADAPTIVE_TIMEOUT_MULTIPLIER",
+    "AGGREGATION_ROUTES",
+    "ALERT_EMAIL_FROM",
+    "ALERT_EMAIL_TO",
+    "ALERT_SLO_TARGET",
+    "ALERT_THROTTLE_SECONDS",
+    "AUDIT_RETENTION_DAYS",
+    "AUTH_BYPASS_NETWORKS",
+    "AUTH_BYPASS_PATHS",
+    "AUTH_BYPASS_VERIFIED_BOTS",
+    "BACKEND_CONCURRENCY_LIMIT",
+    "BACKEND_CONFIGS",
+    "BACKEND_DISCOVERY_KEY",
+    "BACKEND_DISCOVERY_TTL",
+    "BACKEND_POOLS",
+    "BACKEND_URLS",
+    "BODY_ROUTING_MAX_BYTES",
+    "BODY_ROUTING_RULES",
+    "CACHE_BACKEND",
+    "CACHE_BYPASS_COOKIES",
+    "CACHE_BYPASS_HEADERS",
+    "CACHE_COALESCING_ENABLED",
+    "CACHE_COALESCING_WAIT_MS",
+    "CACHE_COMPRESSION",
+    "CACHE_CONTROL_RULES",
+    "CACHE_ENABLED",
+    "CACHE_KEY_TEMPLATE",
+    "CACHE_MAX_BODY_SIZE",
+    "CACHE_OUTAGE_TTL_MULTIPLIER",
+    "CACHE_PRIVATE_MAX_TTL",
+    "CACHE_QUERY_IGNORE",
+    "CACHE_QUERY_SORT",
+    "CACHE_REVALIDATE_WINDOW",
+    "CACHE_ROUTES",
+    "CACHE_STALE_IF_ERROR",
+    "CACHE_TTL",
+    "CACHE_WARM_ENABLED",
+    "CACHE_WARM_SITEMAP",
+    "CACHE_WARM_TOP_N",
+    "CANARY_IDENTITY",
+    "CLIENT_HINTS",
+    "CLIENT_HINTS_ACCEPT_CH",
+    "COLO_ROUTING_RULES",
+    "CONFIG_ROLLOUT_ERROR_TOLERANCE",
+    "CONFIG_ROLLOUT_MIN_REQUESTS",
+    "CONFIG_ROLLOUT_PROMOTE_AFTER",
+    "CONTENT_DISPOSITION_RULES",
+    "CONTENT_SHA256_ENABLED",
+    "CONTENT_SHA256_VERIFY",
+    "CONTENT_TYPE_FIXUP_ENABLED",
+    "CONTENT_TYPE_OVERRIDES",
+    "CONTENT_WATCH_PAGES",
+    "CONTRACT_CHECKS",
+    "CORS_ALLOWED_ORIGINS",
+    "CORS_ALLOW_CREDENTIALS",
+    "CUSTOM_HEADERS",
+    "DRAIN_GRACE_PERIOD",
+    "ESI_PATHS",
+    "GEO_BLOCK_PAGES",
+    "GRAFANA_ENABLED",
+    "HEALTH_CHECK_ENABLED",
+    "HEALTH_CHECK_INTERVAL",
+    "HEALTH_CHECK_TIMEOUT",
+    "HEDGE_DELAY_MS",
+    "HEDGE_ROUTES",
+    "HTML_REWRITE_RULES",
+    "LATENCY_BUDGETS",
+    "LATENCY_BUDGET_HEADER",
+    "LOAD_BALANCER_HASH_KEY",
+    "LOAD_BALANCER_STRATEGY",
+    "LOG_LEVEL",
+    "METRICS_PUSH_FORMAT",
+    "METRIC_TAGS",
+    "MIRROR_COMPARE_BODIES",
+    "MIRROR_IGNORE_FIELDS",
+    "MIRROR_METHODS",
+    "MIRROR_MODE",
+    "MIRROR_SAMPLE_RATE",
+    "MOCK_RESPONSES",
+    "NORMALIZATION_PROFILE",
+    "PATH_REWRITE_RULES",
+    "PRECOMPRESSED_ROUTES",
+    "PROXY_ID",
+    "QUEUE_MAX_DEPTH",
+    "QUEUE_MAX_WAIT_MS",
+    "REPORTS_ENABLED",
+    "REPORTS_RETENTION_DAYS",
+    "REPUTATION_ENABLED",
+    "REQUEST_HEADER_ALLOWLIST",
+    "REQUEST_ID_FORMAT",
+    "RESPONSE_HEADER_RULES",
+    "RETRY_ATTEMPTS",
+    "RETRY_BACKOFF_MS",
+    "RETRY_NON_IDEMPOTENT",
+    "RETRY_STATUSES",
+    "SCHEMA_RULES",
+    "SCHEMA_VALIDATE_RESPONSES",
+    "SIGV4_ROUTES",
+    "SLOW_START_WINDOW",
+    "SOFT_404_CONVERT",
+    "SOFT_404_PATTERNS",
+    "STATUS_PAGE_ENABLED",
+    "STATUS_PAGE_TITLE",
+    "STICKY_COOKIE_NAME",
+    "STICKY_SESSIONS_ENABLED",
+    "STICKY_SESSION_TTL",
+    "TAIL_EVENTS_ENABLED",
+    "TENANT_HEADER",
+    "TENANT_HOST_PATTERN",
+    "TENANT_JWT_CLAIM",
+    "TENANT_SOURCE",
+    "TIMEOUT",
+    "TLS_WATCH_ENABLED",
+    "TLS_WATCH_FAILURE_THRESHOLD",
+    "URL_PROXY_MAX_REDIRECTS",
+    "URL_PROXY_PARTITIONED_COOKIES",
+    "WATERMARK_KEY_HEADER",
+    "WATERMARK_RULES",
+    "WEBHOOK_EVENTS",
+];
+
+/// Prefix of the secrets stored configuration may name in `*_var` fields,
+/// e.g. OAuth2 `client_secret_var`, so it can't point one at another secret
+const CREDENTIAL_VAR_PREFIX: &str = "BACKEND_SECRET_";
+
+/// Stored configuration document (environment variable overrides)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigDocument {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub version: u64,
+    pub vars: HashMap<String, String>,
+    pub published_at: String,
+    pub published_by: String,
+}
+
+/// Candidate configuration activated for a percentage of clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigRollout {
+    pub candidate: ConfigDocument,
+    pub percentage: u8,
+    pub started_at: i64,
+}
+
+/// Request and error counts for both sides of a rollout
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RolloutStats {
+    pub stable_requests: u64,
+    pub stable_errors: u64,
+    pub candidate_requests: u64,
+    pub candidate_errors: u64,
+}
+
+impl RolloutStats {
+    fn add(&mut self, other: &RolloutStats) {
+        self.stable_requests += other.stable_requests;
+        self.stable_errors += other.stable_errors;
+        self.candidate_requests += other.candidate_requests;
+        self.candidate_errors += other.candidate_errors;
+    }
+
+    fn stable_error_rate(&self) -> f64 {
+        error_rate(self.stable_errors, self.stable_requests)
+    }
+
+    fn candidate_error_rate(&self) -> f64 {
+        error_rate(self.candidate_errors, self.candidate_requests)
+    }
+}
+
+/// Which configuration a request was served with during a rollout
+#[derive(Debug, Clone, Copy)]
+pub struct RolloutAssignment {
+    pub candidate: bool,
+    pub version: Option<u64>,
+}
+
+/// Stored configuration state
+#[derive(Debug, Clone, Default)]
+struct StoredState {
+    active: Option<ConfigDocument>,
+    rollout: Option<ConfigRollout>,
+}
+
+/// Rollout statistics not yet flushed to KV
+struct PendingStats {
+    version: u64,
+    stats: RolloutStats,
+    last_flush: f64,
+}
+
+thread_local! {
+    static STATE_CACHE: RefCell<Option<(f64, StoredState)>> = const { RefCell::new(None) };
+    static PENDING_STATS: RefCell<Option<PendingStats>> = const { RefCell::new(None) };
+    /// Parsed configurations (with compiled matchers) keyed by override fingerprint
+    static BUILT_CONFIGS: RefCell<Vec<(String, ProxyConfig)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Configurations kept parsed per isolate (active and rollout candidate)
+const MAX_BUILT_CONFIGS: usize = 2;
+
+/// Select the configuration for a request, honoring an in-progress rollout
+pub async fn select_config(req: &Request, env: &Env) -> Result<(ProxyConfig, RolloutAssignment)> {
+    let state = load_state(env).await;
+
+    if let Some(rollout) = &state.rollout {
+        let version = rollout.candidate.version;
+        let client = utils::get_client_ip(req.headers(), req.cf())
+            .unwrap_or_else(|| "anonymous".to_string());
+
+        // Deterministic per client, so a client never flips between versions
+        if client_bucket(&client, version) < u64::from(rollout.percentage) {
+            let mut config = build_config(env, &rollout.candidate.vars)?;
+            discovery::apply(&mut config, env).await;
+            return Ok((
+                config,
+                RolloutAssignment {
+                    candidate: true,
+                    version: Some(version),
+                },
+            ));
+        }
+    }
+
+    let vars = state
+        .active
+        .as_ref()
+        .map(|doc| doc.vars.clone())
+        .unwrap_or_default();
+    let mut config = build_config(env, &vars)?;
+    discovery::apply(&mut config, env).await;
+
+    Ok((
+        config,
+        RolloutAssignment {
+            candidate: false,
+            version: state.rollout.map(|rollout| rollout.candidate.version),
+        },
+    ))
+}
+
+/// Active configuration for work not tied to a client (scheduled jobs)
+pub async fn active_config(env: &Env) -> Result<ProxyConfig> {
+    let state = load_state(env).await;
+    let vars = state
+        .active
+        .as_ref()
+        .map(|doc| doc.vars.clone())
+        .unwrap_or_default();
+    let mut config = build_config(env, &vars)?;
+    discovery::apply(&mut config, env).await;
+    Ok(config)
+}
+
+/// Parse configuration for the given overrides, reusing it across requests.
+/// Environment variables are fixed for the lifetime of an isolate, so the
+/// overrides alone identify the resulting configuration.
+fn build_config(env: &Env, vars: &HashMap<String, String>) -> Result<ProxyConfig> {
+    let mut sorted: Vec<(&String, &String)> = vars.iter().collect();
+    sorted.sort();
+    let fingerprint = utils::sha256_hash(&serde_json::to_string(&sorted)?);
+
+    let cached = BUILT_CONFIGS.with(|built| {
+        built
+            .borrow()
+            .iter()
+            .find(|(key, _)| *key == fingerprint)
+            .map(|(_, config)| config.clone())
+    });
+    if let Some(config) = cached {
+        return Ok(config);
+    }
+
+    let config = ProxyConfig::from_env_with_overrides(env, vars)?;
+    BUILT_CONFIGS.with(|built| {
+        let mut built = built.borrow_mut();
+        if built.len() >= MAX_BUILT_CONFIGS {
+            built.remove(0);
+        }
+        built.push((fingerprint, config.clone()));
+    });
+    Ok(config)
+}
+
+/// Record request outcome for rollout error-rate comparison
+pub fn record_outcome(assignment: RolloutAssignment, is_error: bool) {
+    let version = match assignment.version {
+        Some(version) => version,
+        None => return,
+    };
+
+    PENDING_STATS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let entry = match pending.as_mut() {
+            Some(entry) if entry.version == version => entry,
+            _ => pending.insert(PendingStats {
+                version,
+                stats: RolloutStats::default(),
+                last_flush: js_sys::Date::now(),
+            }),
+        };
+
+        if assignment.candidate {
+            entry.stats.candidate_requests += 1;
+            entry.stats.candidate_errors += u64::from(is_error);
+        } else {
+            entry.stats.stable_requests += 1;
+            entry.stats.stable_errors += u64::from(is_error);
+        }
+    });
+}
+
+/// Flush rollout statistics to KV and promote or roll back when conclusive
+pub async fn flush_rollout_stats(env: Env, config: ProxyConfig) {
+    let now = js_sys::Date::now();
+    let due = PENDING_STATS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        match pending.as_mut() {
+            Some(entry) if now - entry.last_flush >= STATS_FLUSH_INTERVAL_MS => {
+                entry.last_flush = now;
+                Some((entry.version, std::mem::take(&mut entry.stats)))
+            }
+            _ => None,
+        }
+    });
+
+    let (version, delta) = match due {
+        Some(due) => due,
+        None => return,
+    };
+
+    let kv = match env.kv("PROXY_KV") {
+        Ok(kv) => kv,
+        Err(_) => return,
+    };
+
+    // Read-modify-write is approximate under concurrency, which is fine for rates
+    let stats_key = format!("{ROLLOUT_STATS_PREFIX}{version}");
+    let mut stats = kv
+        .get(&stats_key)
+        .json::<RolloutStats>()
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    stats.add(&delta);
+
+    if let Ok(serialized) = serde_json::to_string(&stats) {
+        if let Ok(put) = kv.put(&stats_key, serialized) {
+            if let Err(e) = put.expiration_ttl(7 * 86400).execute().await {
+                console_log!("Failed to store rollout stats: {:?}", e);
+            }
+        }
+    }
+
+    let rollout = match read_rollout(&kv).await {
+        Ok(Some(rollout)) if rollout.candidate.version == version => rollout,
+        _ => return,
+    };
+
+    if stats.candidate_requests < config.config_rollout_min_requests {
+        return;
+    }
+
+    let candidate_rate = stats.candidate_error_rate();
+    let stable_rate = stats.stable_error_rate();
+    let elapsed_secs = (Utc::now().timestamp_millis() - rollout.started_at) / 1000;
+
+    let result = if candidate_rate > stable_rate + config.config_rollout_error_tolerance {
+        console_log!(
+            "Rolling back config v{}: error rate {:.2}% vs stable {:.2}%",
+            version,
+            candidate_rate,
+            stable_rate
+        );
+        rollback(&env, &config, None, "rollout").await.map(|_| ())
+    } else if elapsed_secs >= config.config_rollout_promote_after as i64 {
+        console_log!(
+            "Promoting config v{}: error rate {:.2}% vs stable {:.2}%",
+            version,
+            candidate_rate,
+            stable_rate
+        );
+        promote(&env, &config, None, "rollout").await.map(|_| ())
+    } else {
+        Ok(())
+    };
+
+    if let Err(e) = result {
+        console_log!("Automatic rollout decision failed: {:?}", e);
+    }
+}
+
+/// Describe active configuration and rollout progress
+pub async fn handle_get_config(env: &Env) -> Result<Response> {
+    let kv = env.kv("PROXY_KV")?;
+    let active = read_active(&kv).await?;
+    let rollout = read_rollout(&kv).await?;
+    let stats = match &rollout {
+        Some(rollout) => {
+            kv.get(&format!(
+                "{ROLLOUT_STATS_PREFIX}{}",
+                rollout.candidate.version
+            ))
+            .json::<RolloutStats>()
+            .await?
+        }
+        None => None,
+    };
+
+    Response::from_json(&serde_json::json!({
+        "active": active,
+        "rollout": rollout,
+        "rollout_stats": stats,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
+
+/// Request body for publishing a configuration version
+#[derive(Debug, Deserialize)]
+struct PublishRequest {
+    vars: HashMap<String, String>,
+    #[serde(default)]
+    percentage: Option<u8>,
+}
+
+/// Publish a new configuration version, optionally as a gradual rollout
+pub async fn handle_publish(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let body: PublishRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return Response::error("Invalid configuration document", 400),
+    };
+
+    if let Some(name) = body.vars.keys().find(|name| !is_editable_var(name)) {
+        return Response::error(format!("Variable cannot be set at runtime: {name}"), 400);
+    }
+    if let Some(reason) = check_credential_refs(&body.vars)
+        .or_else(|| check_hosts(&body.vars, &config.config_allowed_hosts))
+    {
+        return Response::error(reason, 400);
+    }
+
+    let kv = env.kv("PROXY_KV")?;
+    let active = read_active(&kv).await?;
+    let previous_rollout = read_rollout(&kv).await?;
+
+    let version = active
+        .as_ref()
+        .map(|doc| doc.version)
+        .into_iter()
+        .chain(previous_rollout.as_ref().map(|r| r.candidate.version))
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    let document = ConfigDocument {
+        schema_version: CONFIG_SCHEMA_VERSION,
+        version,
+        vars: body.vars,
+        published_at: Utc::now().to_rfc3339(),
+        published_by: identity.name.clone(),
+    };
+    let percentage = body.percentage.unwrap_or(100).min(100);
+
+    if percentage >= 100 {
+        kv.put(ACTIVE_KEY, serde_json::to_string(&document)?)?
+            .execute()
+            .await?;
+        kv.delete(ROLLOUT_KEY).await?;
+    } else {
+        let rollout = ConfigRollout {
+            candidate: document.clone(),
+            percentage,
+            started_at: Utc::now().timestamp_millis(),
+        };
+        kv.put(ROLLOUT_KEY, serde_json::to_string(&rollout)?)?
+            .execute()
+            .await?;
+    }
+    invalidate_state_cache();
+
+    // Backends removed by a full publish drain instead of dropping their sessions
+    if percentage >= 100 {
+        let mut published = build_config(env, &document.vars)?;
+        discovery::apply(&mut published, env).await;
+        let removed: Vec<String> = all_backends(config)
+            .filter(|backend| !all_backends(&published).any(|kept| kept == *backend))
+            .map(str::to_string)
+            .collect();
+        if !removed.is_empty() {
+            drain::start(
+                &kv,
+                config,
+                &removed,
+                config.drain_grace_period,
+                &identity.name,
+            )
+            .await?;
+        }
+    }
+
+    let entry = AuditEntry::new(
+        req,
+        &identity.name,
+        "publish_config",
+        &format!("v{version}"),
+    )
+    .with_change(
+        active.map(|doc| serde_json::to_value(doc).unwrap_or_default()),
+        Some(serde_json::json!({ "document": &document, "percentage": percentage })),
+    );
+    record_audit(env, config, entry).await;
+
+    WebhookEmitter::new(config)
+        .emit(
+            ProxyEvent::ConfigChanged,
+            serde_json::json!({ "version": version, "percentage": percentage }),
+        )
+        .await;
+
+    Response::from_json(&serde_json::json!({
+        "version": version,
+        "percentage": percentage
+    }))
+}
+
+/// Promote the candidate configuration to active
+pub async fn handle_promote(
+    req: &Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    if !promote(env, config, Some(req), &identity.name).await? {
+        return Response::error("No rollout in progress", 409);
+    }
+    Response::from_json(&serde_json::json!({ "promoted": true }))
+}
+
+/// Abort the candidate rollout, keeping the active configuration
+pub async fn handle_rollback(
+    req: &Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    if !rollback(env, config, Some(req), &identity.name).await? {
+        return Response::error("No rollout in progress", 409);
+    }
+    Response::from_json(&serde_json::json!({ "rolled_back": true }))
+}
+
+/// Promote the candidate, false when no rollout is in progress
+async fn promote(
+    env: &Env,
+    config: &ProxyConfig,
+    req: Option<&Request>,
+    actor: &str,
+) -> Result<bool> {
+    let kv = env.kv("PROXY_KV")?;
+    let rollout = match read_rollout(&kv).await? {
+        Some(rollout) => rollout,
+        None => return Ok(false),
+    };
+    let previous = read_active(&kv).await?;
+
+    kv.put(ACTIVE_KEY, serde_json::to_string(&rollout.candidate)?)?
+        .execute()
+        .await?;
+    kv.delete(ROLLOUT_KEY).await?;
+    invalidate_state_cache();
+
+    let target = format!("v{}", rollout.candidate.version);
+    let entry = match req {
+        Some(req) => AuditEntry::new(req, actor, "promote_config", &target),
+        None => AuditEntry::system(actor, "promote_config", &target),
+    }
+    .with_change(
+        previous.map(|doc| serde_json::to_value(doc).unwrap_or_default()),
+        Some(serde_json::to_value(&rollout.candidate)?),
+    );
+    record_audit(env, config, entry).await;
+
+    WebhookEmitter::new(config)
+        .emit(
+            ProxyEvent::ConfigChanged,
+            serde_json::json!({ "version": rollout.candidate.version, "promoted": true }),
+        )
+        .await;
+    Ok(true)
+}
+
+/// Abort the rollout, false when none is in progress
+async fn rollback(
+    env: &Env,
+    config: &ProxyConfig,
+    req: Option<&Request>,
+    actor: &str,
+) -> Result<bool> {
+    let kv = env.kv("PROXY_KV")?;
+    let rollout = match read_rollout(&kv).await? {
+        Some(rollout) => rollout,
+        None => return Ok(false),
+    };
+
+    kv.delete(ROLLOUT_KEY).await?;
+    invalidate_state_cache();
+
+    let target = format!("v{}", rollout.candidate.version);
+    let entry = match req {
+        Some(req) => AuditEntry::new(req, actor, "rollback_config", &target),
+        None => AuditEntry::system(actor, "rollback_config", &target),
+    }
+    .with_change(Some(serde_json::to_value(&rollout)?), None);
+    record_audit(env, config, entry).await;
+
+    WebhookEmitter::new(config)
+        .emit(
+            ProxyEvent::ConfigChanged,
+            serde_json::json!({ "version": rollout.candidate.version, "rolled_back": true }),
+        )
+        .await;
+    Ok(true)
+}
+
+/// Stored active document and rollout, for state export
+pub async fn export_documents(
+    kv: &KvStore,
+) -> Result<(Option<ConfigDocument>, Option<ConfigRollout>)> {
+    Ok((read_active(kv).await?, read_rollout(kv).await?))
+}
+
+/// Check imported documents, returning the reason they cannot be stored
+pub fn check_import(
+    active: Option<&ConfigDocument>,
+    rollout: Option<&ConfigRollout>,
+    allowed_hosts: &[String],
+) -> Option<String> {
+    let documents = active
+        .into_iter()
+        .chain(rollout.map(|rollout| &rollout.candidate));
+    for document in documents {
+        if document.schema_version > CONFIG_SCHEMA_VERSION {
+            return Some(format!(
+                "Configuration v{} has unsupported schema {}",
+                document.version, document.schema_version
+            ));
+        }
+        if let Some(name) = document.vars.keys().find(|name| !is_editable_var(name)) {
+            return Some(format!("Variable cannot be set at runtime: {name}"));
+        }
+        if let Some(reason) = check_credential_refs(&document.vars)
+            .or_else(|| check_hosts(&document.vars, allowed_hosts))
+        {
+            return Some(reason);
+        }
+    }
+    None
+}
+
+/// Replace the stored active document and rollout with imported ones
+pub async fn import_documents(
+    kv: &KvStore,
+    active: Option<&ConfigDocument>,
+    rollout: Option<&ConfigRollout>,
+) -> Result<()> {
+    match active {
+        Some(active) => {
+            kv.put(ACTIVE_KEY, serde_json::to_string(active)?)?
+                .execute()
+                .await?
+        }
+        None => kv.delete(ACTIVE_KEY).await?,
+    }
+    match rollout {
+        Some(rollout) => {
+            kv.put(ROLLOUT_KEY, serde_json::to_string(rollout)?)?
+                .execute()
+                .await?
+        }
+        None => kv.delete(ROLLOUT_KEY).await?,
+    }
+    invalidate_state_cache();
+    Ok(())
+}
+
+/// Load stored configuration state with in-isolate memoization
+async fn load_state(env: &Env) -> StoredState {
+    let now = js_sys::Date::now();
+    let cached = STATE_CACHE.with(|cache| {
+        cache
+            .borrow()
+            .as_ref()
+            .filter(|(fetched_at, _)| now - fetched_at < STATE_CACHE_TTL_MS)
+            .map(|(_, state)| state.clone())
+    });
+    if let Some(state) = cached {
+        return state;
+    }
+
+    // Without a KV binding the environment configuration is used as-is
+    let kv = match env.kv("PROXY_KV") {
+        Ok(kv) => kv,
+        Err(_) => return StoredState::default(),
+    };
+
+    let state = StoredState {
+        active: read_active(&kv).await.ok().flatten(),
+        rollout: read_rollout(&kv).await.ok().flatten(),
+    };
+    STATE_CACHE.with(|cache| *cache.borrow_mut() = Some((now, state.clone())));
+    state
+}
+
+/// Read the active document, migrated to the current schema
+async fn read_active(kv: &KvStore) -> Result<Option<ConfigDocument>> {
+    read_migrated(kv, ACTIVE_KEY).await
+}
+
+/// Read the rollout, with its candidate migrated to the current schema
+async fn read_rollout(kv: &KvStore) -> Result<Option<ConfigRollout>> {
+    read_migrated(kv, ROLLOUT_KEY).await
+}
+
+/// Read stored JSON and migrate it in memory before deserializing
+async fn read_migrated<T: DeserializeOwned>(kv: &KvStore, key: &str) -> Result<Option<T>> {
+    match kv.get(key).json::<Value>().await? {
+        Some(raw) => {
+            let (migrated, _) = migrate_stored(key, raw);
+            Ok(Some(serde_json::from_value(migrated)?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Migrate a stored value (document or rollout) to the current schema,
+/// returning the migrated value and a description of each change
+fn migrate_stored(key: &str, mut raw: Value) -> (Value, Vec<String>) {
+    if key == ROLLOUT_KEY {
+        let candidate = raw.get_mut("candidate").map(Value::take);
+        return match candidate {
+            Some(candidate) => {
+                let (candidate, changes) = migrate_document(candidate);
+                raw["candidate"] = candidate;
+                (raw, changes)
+            }
+            None => (raw, vec![]),
+        };
+    }
+    migrate_document(raw)
+}
+
+/// Apply schema migrations to a configuration document in order
+fn migrate_document(mut doc: Value) -> (Value, Vec<String>) {
+    let mut changes = Vec::new();
+    let mut schema = doc
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .map_or(FIRST_SCHEMA_VERSION, |schema| schema as u32)
+        .max(FIRST_SCHEMA_VERSION);
+
+    while let Some(migration) = MIGRATIONS.get((schema - FIRST_SCHEMA_VERSION) as usize) {
+        migration(&mut doc, &mut changes);
+        schema += 1;
+        changes.push(format!("schema_version -> {schema}"));
+    }
+
+    doc["schema_version"] = Value::from(schema);
+    (doc, changes)
+}
+
+/// Show (GET) or apply (POST) pending schema migrations of stored documents
+pub async fn handle_migrate(
+    req: &Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let dry_run = req.method() != Method::Post;
+    let kv = env.kv("PROXY_KV")?;
+    let mut report = Vec::new();
+
+    for key in [ACTIVE_KEY, ROLLOUT_KEY] {
+        let raw = match kv.get(key).json::<Value>().await? {
+            Some(raw) => raw,
+            None => continue,
+        };
+
+        let (migrated, changes) = migrate_stored(key, raw.clone());
+        if changes.is_empty() {
+            continue;
+        }
+
+        if !dry_run {
+            kv.put(key, serde_json::to_string(&migrated)?)?
+                .execute()
+                .await?;
+
+            let entry = AuditEntry::new(req, &identity.name, "migrate_config", key)
+                .with_change(Some(raw), Some(migrated));
+            record_audit(env, config, entry).await;
+        }
+
+        report.push(serde_json::json!({ "key": key, "changes": changes }));
+    }
+
+    if !dry_run {
+        invalidate_state_cache();
+    }
+
+    Response::from_json(&serde_json::json!({
+        "dry_run": dry_run,
+        "schema_version": CONFIG_SCHEMA_VERSION,
+        "migrations": report
+    }))
+}
+
+/// Backends of `BACKEND_URLS` and all backend pools
+fn all_backends(config: &ProxyConfig) -> impl Iterator<Item = &str> {
+    config
+        .backends
+        .iter()
+        .chain(config.backend_pools.iter().flat_map(|pool| &pool.backends))
+        .map(|backend| backend.trim_end_matches('/'))
+}
+
+fn invalidate_state_cache() {
+    STATE_CACHE.with(|cache| *cache.borrow_mut() = None);
+}
+
+/// Check that a variable name may be overridden by stored configuration
+fn is_editable_var(name: &str) -> bool {
+    EDITABLE_VARS.contains(&name)
+}
+
+/// Check the secrets named by `*_var` fields at any depth of JSON variables,
+/// returning the reason they cannot be stored
+fn check_credential_refs(vars: &HashMap<String, String>) -> Option<String> {
+    fn find(value: &Value) -> Option<String> {
+        match value {
+            Value::Object(map) => map.iter().find_map(|(key, value)| match value {
+                Value::String(name)
+                    if key.ends_with("_var") && !name.starts_with(CREDENTIAL_VAR_PREFIX) =>
+                {
+                    Some(format!(
+                        "{key} must name a secret starting with {CREDENTIAL_VAR_PREFIX}: {name}"
+                    ))
+                }
+                value => find(value),
+            }),
+            Value::Array(items) => items.iter().find_map(find),
+            _ => None,
+        }
+    }
+
+    vars.values()
+        .filter_map(|value| serde_json::from_str::<Value>(value).ok())
+        .find_map(|value| find(&value))
+}
+
+/// Check that every URL in the variables, at any depth of JSON values, is on
+/// one of the allowed hosts, so stored configuration can't send backend
+/// credentials (e.g. OAuth2 `token_url`) or client requests elsewhere
+fn check_hosts(vars: &HashMap<String, String>, allowed_hosts: &[String]) -> Option<String> {
+    fn find(value: &Value, allowed_hosts: &[String]) -> Option<String> {
+        match value {
+            Value::String(text) if text.starts_with("http://") || text.starts_with("https://") => {
+                let host = Url::parse(text)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_lowercase));
+                match host {
+                    Some(host)
+                        if allowed_hosts
+                            .iter()
+                            .any(|allowed| allowed.eq_ignore_ascii_case(&host)) =>
+                    {
+                        None
+                    }
+                    _ => Some(format!("URL host is not in CONFIG_ALLOWED_HOSTS: {text}")),
+                }
+            }
+            Value::Object(map) => map.values().find_map(|value| find(value, allowed_hosts)),
+            Value::Array(items) => items.iter().find_map(|value| find(value, allowed_hosts)),
+            _ => None,
+        }
+    }
+
+    vars.values().find_map(|value| {
+        // Plain values are checked as a single string
+        let value =
+            serde_json::from_str::<Value>(value).unwrap_or_else(|_| Value::String(value.clone()));
+        find(&value, allowed_hosts)
+    })
+}
+
+/// Map client onto a stable bucket in 0..100 for the given version
+fn client_bucket(client: &str, version: u64) -> u64 {
+    let hash = utils::sha256_hash(&format!("{version}:{client}"));
+    u64::from_str_radix(&hash[..8], 16).unwrap_or(0) % 100
+}
+
+fn error_rate(errors: u64, requests: u64) -> f64 {
+    if requests == 0 {
+        0.0
+    } else {
+        errors as f64 / requests as f64 * 100.0
+    }
+}
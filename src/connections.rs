@@ -0,0 +1,140 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::config::ProxyConfig;
+
+/// Durable Object binding counting in-flight requests per backend
+const TRACKER_BINDING: &str = "CONNECTION_TRACKER";
+
+/// In-flight request to a backend, released when the request completes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lease {
+    pub backend: String,
+    id: u64,
+}
+
+/// Backends a request may be sent to
+#[derive(Debug, Serialize, Deserialize)]
+struct AcquireRequest {
+    backends: Vec<String>,
+    max_age_ms: f64, // Leases never released (e.g. crashed requests) expire after this
+}
+
+/// Pick the backend with the fewest in-flight requests and count this
+/// request against it. `None` when the tracker is unavailable.
+pub async fn acquire(backends: &[String], config: &ProxyConfig, env: &Env) -> Option<Lease> {
+    let body = serde_json::to_string(&AcquireRequest {
+        backends: backends.to_vec(),
+        max_age_ms: (config.timeout * 1000 * 2) as f64,
+    })
+    .ok()?;
+
+    match call(config, "https://tracker/acquire", body, env).await {
+        Ok(mut response) if response.status_code() == 200 => response.json::<Lease>().await.ok(),
+        Ok(response) => {
+            console_log!(
+                "Connection tracker answered with status {}",
+                response.status_code()
+            );
+            None
+        }
+        Err(e) => {
+            console_log!("Connection tracker unavailable: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Stop counting a completed request
+pub async fn release(lease: Lease, config: ProxyConfig, env: Env) {
+    let body = match serde_json::to_string(&lease) {
+        Ok(body) => body,
+        Err(_) => return,
+    };
+    if let Err(e) = call(&config, "https://tracker/release", body, &env).await {
+        console_log!("Failed to release connection to {}: {:?}", lease.backend, e);
+    }
+}
+
+/// Send a request to the tracker shared by all requests of this proxy
+async fn call(config: &ProxyConfig, url: &str, body: String, env: &Env) -> Result<Response> {
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post).with_body(Some(body.into()));
+    let request = Request::new_with_init(url, &init)?;
+
+    env.durable_object(TRACKER_BINDING)?
+        .id_from_name(&config.proxy_id)?
+        .get_stub()?
+        .fetch_with_request(request)
+        .await
+}
+
+/// Counts in-flight requests per backend, as leases with their start time
+#[durable_object]
+pub struct ConnectionTracker {
+    leases: RefCell<HashMap<String, HashMap<u64, f64>>>,
+    next_id: Cell<u64>,
+    rotation: Cell<usize>, // Spreads ties between equally loaded backends
+}
+
+impl DurableObject for ConnectionTracker {
+    fn new(_state: State, _env: Env) -> Self {
+        Self {
+            leases: RefCell::new(HashMap::new()),
+            next_id: Cell::new(0),
+            rotation: Cell::new(0),
+        }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        match req.path().as_str() {
+            "/acquire" => {
+                let acquire: AcquireRequest = req.json().await?;
+                match self.acquire(acquire) {
+                    Some(lease) => Response::from_json(&lease),
+                    None => Response::error("No backends", 400),
+                }
+            }
+            "/release" => {
+                let lease: Lease = req.json().await?;
+                if let Some(leases) = self.leases.borrow_mut().get_mut(&lease.backend) {
+                    leases.remove(&lease.id);
+                }
+                Response::empty()
+            }
+            _ => Response::error("Not Found", 404),
+        }
+    }
+}
+
+impl ConnectionTracker {
+    fn acquire(&self, acquire: AcquireRequest) -> Option<Lease> {
+        if acquire.backends.is_empty() {
+            return None;
+        }
+
+        let now = js_sys::Date::now();
+        let mut leases = self.leases.borrow_mut();
+        for backend_leases in leases.values_mut() {
+            backend_leases.retain(|_, started_at| now - *started_at < acquire.max_age_ms);
+        }
+
+        let offset = self.rotation.get();
+        self.rotation.set(offset.wrapping_add(1));
+        let count = |backend: &String| leases.get(backend).map_or(0, HashMap::len);
+        let backend = (0..acquire.backends.len())
+            .map(|i| &acquire.backends[(offset + i) % acquire.backends.len()])
+            .min_by_key(|backend| count(backend))?
+            .clone();
+
+        let id = self.next_id.get();
+        self.next_id.set(id.wrapping_add(1));
+        leases.entry(backend.clone()).or_default().insert(id, now);
+        Some(Lease { backend, id })
+    }
+}
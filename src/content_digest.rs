@@ -0,0 +1,53 @@
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use worker::*;
+
+use crate::{config::ProxyConfig, utils};
+
+/// Hex SHA-256 of the request body, as sent to backends and by clients
+pub const CONTENT_SHA256_HEADER: &str = "X-Content-SHA256";
+
+/// Hex SHA-256 of a request body, hashed chunk by chunk as it streams in.
+/// With `keep`, the body is also collected so it can be forwarded.
+pub async fn stream_sha256(req: &mut Request, keep: bool) -> Result<(String, Vec<u8>)> {
+    let mut hasher = Sha256::new();
+    let mut body = Vec::new();
+    // Requests without a body have nothing to stream
+    if let Ok(mut input) = req.stream() {
+        while let Some(chunk) = input.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            if keep {
+                body.extend(chunk);
+            }
+        }
+    }
+    Ok((hex::encode(hasher.finalize()), body))
+}
+
+/// Reject requests whose body does not match the `X-Content-SHA256` the
+/// client sent, when `CONTENT_SHA256_VERIFY` is on. Requests without the
+/// header are not checked.
+pub async fn verify_request(req: &Request, config: &ProxyConfig) -> Result<Option<Response>> {
+    if !config.content_sha256_verify {
+        return Ok(None);
+    }
+    let expected = match req.headers().get(CONTENT_SHA256_HEADER)? {
+        Some(expected) => expected.trim().to_lowercase(),
+        None => return Ok(None),
+    };
+
+    // Hash a clone so the original body can still be forwarded
+    let (actual, _) = stream_sha256(&mut req.clone()?, false).await?;
+    if utils::constant_time_eq(&expected, &actual) {
+        return Ok(None);
+    }
+
+    console_log!(
+        "Rejecting {} {}: body does not match {}",
+        req.method().to_string(),
+        req.path(),
+        CONTENT_SHA256_HEADER
+    );
+    Response::error("Request body does not match X-Content-SHA256", 400).map(Some)
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use futures_util::future::join_all;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{
+    admin::{AdminIdentity, AuditEntry, record_audit},
+    alerts::{self, Alert},
+    config::{ContentWatchPage, ProxyConfig},
+    latency, utils,
+    webhooks::{ProxyEvent, WebhookEmitter},
+};
+
+/// KV key holding the baseline and latest observation of every watched page
+const CONTENT_WATCH_KEY: &str = "content_watch:pages";
+
+/// Baseline and latest state of a watched page, updated on every cron check
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PageObservation {
+    pub baseline: Option<String>, // SHA-256 of the accepted normalized content
+    pub baseline_at: Option<String>,
+    pub current: Option<String>, // SHA-256 of the last fetched normalized content
+    pub checked_at: Option<String>,
+    pub changed_since: Option<String>,
+    pub last_error: Option<String>,
+}
+
+/// Watched pages by URL
+type Observations = BTreeMap<String, PageObservation>;
+
+/// Absolute URL of a watched page, paths resolve against the first backend
+fn page_url(page: &ContentWatchPage, config: &ProxyConfig) -> Option<String> {
+    if page.url.starts_with('/') {
+        let backend = config.backends.first()?;
+        Some(format!("{}{}", backend.trim_end_matches('/'), page.url))
+    } else {
+        Some(page.url.clone())
+    }
+}
+
+/// Content with the page's volatile parts removed and whitespace collapsed,
+/// so reformatting or per-request tokens don't count as changes
+fn normalize(content: &str, page: &ContentWatchPage) -> String {
+    let mut content = content.to_string();
+    for pattern in &page.ignore {
+        match Regex::new(pattern) {
+            Ok(regex) => content = regex.replace_all(&content, "").into_owned(),
+            Err(e) => console_log!("Invalid content watch pattern {}: {:?}", pattern, e),
+        }
+    }
+    content.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Hash of the page's normalized content
+async fn fetch_hash(url: &str, page: &ContentWatchPage, timeout_ms: u64) -> Result<String> {
+    let headers = Headers::new();
+    headers.set("Cache-Control", "no-cache")?;
+    let mut init = RequestInit::new();
+    init.with_method(Method::Get).with_headers(headers);
+    let req = Request::new_with_init(url, &init)?;
+
+    let mut response = latency::fetch_with_timeout(req, timeout_ms).await?;
+    if !(200..300).contains(&response.status_code()) {
+        return Err(Error::from(format!("status {}", response.status_code())));
+    }
+    let content = response.text().await?;
+    Ok(utils::sha256_hash(&normalize(&content, page)))
+}
+
+async fn read(env: &Env) -> Result<Observations> {
+    Ok(env
+        .kv("PROXY_KV")?
+        .get(CONTENT_WATCH_KEY)
+        .json::<Observations>()
+        .await?
+        .unwrap_or_default())
+}
+
+async fn write(env: &Env, observations: &Observations) -> Result<()> {
+    env.kv("PROXY_KV")?
+        .put(CONTENT_WATCH_KEY, serde_json::to_string(observations)?)?
+        .execute()
+        .await?;
+    Ok(())
+}
+
+/// Fetch every watched page and alert on content that differs from its baseline
+pub async fn run(config: &ProxyConfig, env: &Env) {
+    if config.content_watch_pages.is_empty() {
+        return;
+    }
+
+    if let Err(e) = check_pages(config, env).await {
+        console_log!("Content watch failed: {:?}", e);
+    }
+}
+
+async fn check_pages(config: &ProxyConfig, env: &Env) -> Result<()> {
+    let previous = read(env).await?;
+    let pages: Vec<(String, &ContentWatchPage)> = config
+        .content_watch_pages
+        .iter()
+        .filter_map(|page| page_url(page, config).map(|url| (url, page)))
+        .collect();
+    let hashes = join_all(
+        pages
+            .iter()
+            .map(|(url, page)| fetch_hash(url, page, config.health_check_timeout * 1000)),
+    )
+    .await;
+
+    let now = Utc::now().to_rfc3339();
+    let mut observations = Observations::new();
+    let mut changed = Vec::new();
+    let mut newly_changed = Vec::new();
+    for ((url, _), hash) in pages.into_iter().zip(hashes) {
+        let mut observation = previous.get(&url).cloned().unwrap_or_default();
+        observation.checked_at = Some(now.clone());
+        match hash {
+            Ok(hash) => {
+                observation.last_error = None;
+                match &observation.baseline {
+                    // The first successful fetch becomes the baseline
+                    None => {
+                        observation.baseline = Some(hash.clone());
+                        observation.baseline_at = Some(now.clone());
+                    }
+                    Some(baseline) if *baseline != hash => {
+                        if observation.changed_since.is_none() {
+                            console_log!("Content of {} changed from its baseline", url);
+                            newly_changed.push(url.clone());
+                        }
+                        let since = observation.changed_since.get_or_insert_with(|| now.clone());
+                        changed.push(format!("{url} - changed since {since}"));
+                    }
+                    Some(_) => observation.changed_since = None,
+                }
+                observation.current = Some(hash);
+            }
+            // Unreachable pages are the health checks' concern, not a change
+            Err(e) => observation.last_error = Some(e.to_string()),
+        }
+        observations.insert(url, observation);
+    }
+    write(env, &observations).await?;
+
+    if !newly_changed.is_empty() {
+        WebhookEmitter::new(config)
+            .emit(
+                ProxyEvent::ContentChanged,
+                serde_json::json!({ "pages": newly_changed }),
+            )
+            .await;
+    }
+    if !changed.is_empty() {
+        let details = format!(
+            "These pages no longer match their accepted content, check them for defacement and accept intended changes:\n\n{}",
+            changed.join("\n")
+        );
+        alerts::send(Alert::ContentChanged, details, config.clone(), env.clone()).await;
+    }
+    Ok(())
+}
+
+/// Baselines and latest observations of the watched pages
+pub async fn handle_status(env: &Env) -> Result<Response> {
+    Response::from_json(&serde_json::json!({
+        "pages": read(env).await?,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
+
+/// Request body accepting the current content of pages as their baseline
+#[derive(Debug, Default, Deserialize)]
+struct AcceptRequest {
+    #[serde(default)]
+    url: Option<String>, // All changed pages when not set
+}
+
+/// Accept the current content of a page, or of every changed page, after an
+/// intended change
+pub async fn handle_accept(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let body: AcceptRequest = match req.text().await {
+        Ok(text) if text.trim().is_empty() => AcceptRequest::default(),
+        Ok(text) => match serde_json::from_str(&text) {
+            Ok(body) => body,
+            Err(_) => return Response::error("Invalid accept request", 400),
+        },
+        Err(_) => return Response::error("Invalid accept request", 400),
+    };
+
+    let mut observations = read(env).await?;
+    let now = Utc::now().to_rfc3339();
+    let mut accepted = Vec::new();
+    for (url, observation) in observations.iter_mut() {
+        if body.url.as_ref().is_some_and(|wanted| wanted != url)
+            || observation.changed_since.is_none()
+        {
+            continue;
+        }
+        observation.baseline = observation.current.clone();
+        observation.baseline_at = Some(now.clone());
+        observation.changed_since = None;
+        accepted.push(url.clone());
+    }
+    if accepted.is_empty() {
+        return Response::error("No changed page to accept", 404);
+    }
+    write(env, &observations).await?;
+
+    let target = body.url.as_deref().unwrap_or("*");
+    let entry = AuditEntry::new(req, &identity.name, "accept_content_baseline", target)
+        .with_change(None, Some(serde_json::json!({ "pages": accepted })));
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({ "accepted": accepted }))
+}
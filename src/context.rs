@@ -0,0 +1,44 @@
+use worker::*;
+
+use crate::{config::ProxyConfig, request_id, tenant, utils};
+
+/// Per-request information shared across proxy stages
+#[derive(Clone)]
+pub struct RequestContext {
+    pub request_id: String,
+    pub ray_id: Option<String>, // Cloudflare Ray ID
+    pub path: String,
+    pub client_ip: Option<String>,
+    pub tenant: Option<String>,
+    pub headers: Headers,
+    pub labels: Vec<String>,
+    pub reputation: Option<u8>, // Client reputation score, when scoring is enabled
+    pub canary: Option<String>, // Canary cohort the request is routed to
+}
+
+impl RequestContext {
+    /// Capture request context before the request is consumed
+    pub fn from_request(req: &Request, request_id: &str, config: &ProxyConfig) -> Result<Self> {
+        let url = req.url()?;
+        let headers = req.headers().clone();
+        let client_ip = utils::get_client_ip(&headers, req.cf());
+        let tenant = tenant::resolve_tenant(req, config)?;
+
+        Ok(Self {
+            request_id: request_id.to_string(),
+            ray_id: request_id::ray_id(req),
+            path: url.path().to_string(),
+            client_ip,
+            tenant,
+            headers,
+            labels: Vec::new(),
+            reputation: None,
+            canary: None,
+        })
+    }
+
+    /// Get a request header value
+    pub fn header(&self, name: &str) -> Option<String> {
+        utils::get_header_value(&self.headers, name)
+    }
+}
@@ -0,0 +1,125 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use serde_json::Value;
+use worker::*;
+
+use crate::{
+    config::{ContractCheck, ProxyConfig},
+    routing, utils,
+    webhooks::{ProxyEvent, WebhookEmitter},
+};
+
+/// Minimum interval between webhooks for the same contract per isolate
+const ALERT_INTERVAL_MS: f64 = 60_000.0;
+
+thread_local! {
+    /// Violations observed by this isolate, keyed by contract path pattern
+    static VIOLATIONS: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+    /// Last webhook time per contract path pattern
+    static LAST_ALERTS: RefCell<HashMap<String, f64>> = RefCell::new(HashMap::new());
+}
+
+/// Live response to verify against its route contract
+pub struct ContractJob {
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    pub response: Response,
+}
+
+/// Find the first contract check for path and method
+pub fn find_check<'a>(
+    config: &'a ProxyConfig,
+    path: &str,
+    method: &str,
+) -> Option<&'a ContractCheck> {
+    config.contract_checks.iter().find(|check| {
+        utils::path_matches(&check.path, path)
+            && (check.methods.is_empty()
+                || check.methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+    })
+}
+
+/// Verify response against its contract, counting and reporting violations
+pub async fn verify(mut job: ContractJob, config: ProxyConfig) {
+    let check = match find_check(&config, &job.path, &job.method) {
+        Some(check) => check,
+        None => return,
+    };
+
+    let mut violations = Vec::new();
+
+    let status = job.response.status_code();
+    if !check.status.is_empty() && !check.status.contains(&status) {
+        violations.push(format!("unexpected status {status}"));
+    }
+
+    for header in &check.required_headers {
+        if !matches!(job.response.headers().get(header), Ok(Some(_))) {
+            violations.push(format!("missing header {header}"));
+        }
+    }
+
+    if !check.required_fields.is_empty() {
+        let body = job.response.bytes().await.unwrap_or_default();
+        match serde_json::from_slice::<Value>(&body) {
+            Ok(payload) => {
+                for field in &check.required_fields {
+                    if routing::lookup_json_path(&payload, field).is_none() {
+                        violations.push(format!("missing field {field}"));
+                    }
+                }
+            }
+            Err(_) => violations.push("body is not valid JSON".to_string()),
+        }
+    }
+
+    if violations.is_empty() {
+        return;
+    }
+
+    console_log!(
+        "Contract violation on {} {} ({}): {}",
+        job.method,
+        job.path,
+        job.request_id,
+        violations.join(", ")
+    );
+    VIOLATIONS.with(|counts| {
+        *counts.borrow_mut().entry(check.path.clone()).or_insert(0) += 1;
+    });
+
+    // Throttle webhooks so a broken route doesn't flood the receiver
+    let now = js_sys::Date::now();
+    let should_alert = LAST_ALERTS.with(|alerts| {
+        let mut alerts = alerts.borrow_mut();
+        match alerts.get(&check.path) {
+            Some(last) if now - last < ALERT_INTERVAL_MS => false,
+            _ => {
+                alerts.insert(check.path.clone(), now);
+                true
+            }
+        }
+    });
+
+    if should_alert {
+        WebhookEmitter::new(&config)
+            .emit(
+                ProxyEvent::ContractViolation,
+                serde_json::json!({
+                    "contract": check.path,
+                    "method": job.method,
+                    "path": job.path,
+                    "request_id": job.request_id,
+                    "status": status,
+                    "violations": violations
+                }),
+            )
+            .await;
+    }
+}
+
+/// Contract violations observed by this isolate
+pub fn violation_counts() -> HashMap<String, u64> {
+    VIOLATIONS.with(|counts| counts.borrow().clone())
+}
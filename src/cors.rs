@@ -0,0 +1,64 @@
+use worker::*;
+
+use crate::config::ProxyConfig;
+
+const ALLOW_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS, HEAD, PATCH";
+
+const ALLOW_HEADERS: &str = "Content-Type, Authorization, X-Requested-With, Accept, Origin, User-Agent, DNT, Cache-Control, X-Mx-ReqToken, Keep-Alive, X-Requested-With, If-Modified-Since";
+
+/// Add CORS headers for the request origin. Configured origins are reflected
+/// and may receive credentials; origins only allowed by `*` never do.
+pub fn add_cors_headers(
+    response: &mut Response,
+    config: &ProxyConfig,
+    origin: Option<&str>,
+) -> Result<()> {
+    let origins = &config.cors_allowed_origins;
+    let wildcard = origins.iter().any(|allowed| allowed == "*");
+    let headers = response.headers();
+
+    // Reflected origins make the response differ per requesting origin
+    if origins.iter().any(|allowed| allowed != "*") {
+        let vary = headers.get("Vary")?.unwrap_or_default();
+        if !vary
+            .split(',')
+            .any(|value| value.trim().eq_ignore_ascii_case("origin"))
+        {
+            headers.append("Vary", "Origin")?;
+        }
+    }
+
+    match origin.filter(|origin| origins.iter().any(|allowed| allowed == origin)) {
+        Some(origin) => {
+            headers.set("Access-Control-Allow-Origin", origin)?;
+            if config.cors_allow_credentials {
+                headers.set("Access-Control-Allow-Credentials", "true")?;
+            }
+        }
+        None if wildcard => headers.set("Access-Control-Allow-Origin", "*")?,
+        None => return Ok(()),
+    }
+
+    headers.set("Access-Control-Allow-Methods", ALLOW_METHODS)?;
+    headers.set("Access-Control-Allow-Headers", ALLOW_HEADERS)?;
+    headers.set("Access-Control-Max-Age", "86400")?;
+    Ok(())
+}
+
+/// Normalize a configured origin to its serialized form (`scheme://host[:port]`),
+/// rejecting values with paths, credentials or non-HTTP schemes
+pub fn normalize_origin(origin: &str) -> Option<String> {
+    if origin == "*" {
+        return Some(origin.to_string());
+    }
+
+    let url = Url::parse(origin).ok()?;
+    let valid = matches!(url.scheme(), "http" | "https")
+        && url.host_str().is_some()
+        && url.username().is_empty()
+        && url.password().is_none()
+        && url.path() == "/"
+        && url.query().is_none()
+        && url.fragment().is_none();
+    valid.then(|| url.origin().ascii_serialization())
+}
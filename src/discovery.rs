@@ -0,0 +1,88 @@
+use std::cell::RefCell;
+
+use serde::Deserialize;
+use worker::*;
+
+use crate::config::{BackendConfig, ProxyConfig};
+
+/// Entry of the discovered backend list, a plain URL or a full backend configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum DiscoveredBackend {
+    Url(String),
+    Config(Box<BackendConfig>),
+}
+
+/// Backend list read from KV by this isolate
+struct Discovered {
+    key: String,
+    loaded_at: f64,
+    backends: Option<Vec<DiscoveredBackend>>,
+}
+
+thread_local! {
+    static DISCOVERED: RefCell<Option<Discovered>> = const { RefCell::new(None) };
+}
+
+/// Replace `BACKEND_URLS` with the list stored under `BACKEND_DISCOVERY_KEY`,
+/// reusing it for `BACKEND_DISCOVERY_TTL` seconds. A missing, invalid or
+/// empty list keeps the configured backends.
+pub async fn apply(config: &mut ProxyConfig, env: &Env) {
+    let key = match &config.backend_discovery_key {
+        Some(key) => key.clone(),
+        None => return,
+    };
+
+    let now = js_sys::Date::now();
+    let ttl_ms = config.backend_discovery_ttl as f64 * 1000.0;
+    let cached = DISCOVERED.with(|discovered| {
+        discovered
+            .borrow()
+            .as_ref()
+            .filter(|discovered| discovered.key == key && now - discovered.loaded_at < ttl_ms)
+            .map(|discovered| discovered.backends.clone())
+    });
+    let backends = match cached {
+        Some(backends) => backends,
+        None => {
+            let backends = match env.kv("PROXY_KV") {
+                Ok(kv) => match kv.get(&key).json::<Vec<DiscoveredBackend>>().await {
+                    Ok(backends) => backends,
+                    Err(e) => {
+                        console_log!("Invalid backend list in KV key {}: {:?}", key, e);
+                        None
+                    }
+                },
+                Err(_) => None,
+            };
+            DISCOVERED.with(|discovered| {
+                *discovered.borrow_mut() = Some(Discovered {
+                    key: key.clone(),
+                    loaded_at: now,
+                    backends: backends.clone(),
+                });
+            });
+            backends
+        }
+    };
+
+    let backends = match backends {
+        Some(backends) if !backends.is_empty() => backends,
+        _ => return,
+    };
+    config.backends = backends
+        .into_iter()
+        .map(|backend| match backend {
+            DiscoveredBackend::Url(url) => url,
+            DiscoveredBackend::Config(backend_config) => {
+                let url = backend_config.url.clone();
+                // Discovered settings replace those of BACKEND_CONFIGS
+                config
+                    .backend_configs
+                    .retain(|existing| existing.url != backend_config.url);
+                config.backend_configs.push(*backend_config);
+                url
+            }
+        })
+        .collect();
+}
@@ -0,0 +1,213 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use worker::{kv::KvStore, *};
+
+use crate::{
+    admin::{AdminIdentity, AuditEntry, record_audit},
+    config::ProxyConfig,
+};
+
+/// KV key prefix of the draining backends of a proxy (`drain:{proxy_id}`)
+const DRAIN_PREFIX: &str = "drain:";
+
+/// Drain state read from KV is reused for this long by an isolate
+const DRAIN_CACHE_MS: f64 = 5_000.0;
+
+/// A backend taken out of rotation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrainEntry {
+    pub started_at: i64, // Unix milliseconds
+    pub until: i64,      // End of the grace period, Unix milliseconds
+    pub started_by: String,
+}
+
+impl DrainEntry {
+    /// Whether pinned clients may still reach the backend
+    pub fn in_grace_period(&self) -> bool {
+        Utc::now().timestamp_millis() < self.until
+    }
+}
+
+/// Draining backends by URL
+pub type DrainState = HashMap<String, DrainEntry>;
+
+thread_local! {
+    /// Drain state of this isolate, with its load time
+    static LOADED: RefCell<Option<(f64, DrainState)>> = const { RefCell::new(None) };
+}
+
+fn drain_key(config: &ProxyConfig) -> String {
+    format!("{DRAIN_PREFIX}{}", config.proxy_id)
+}
+
+async fn read(kv: &KvStore, config: &ProxyConfig) -> Result<DrainState> {
+    Ok(kv
+        .get(&drain_key(config))
+        .json::<DrainState>()
+        .await?
+        .unwrap_or_default())
+}
+
+async fn write(kv: &KvStore, config: &ProxyConfig, state: &DrainState) -> Result<()> {
+    kv.put(&drain_key(config), serde_json::to_string(state)?)?
+        .execute()
+        .await?;
+    LOADED.with(|loaded| loaded.borrow_mut().take());
+    Ok(())
+}
+
+fn is_configured(config: &ProxyConfig, backend: &str) -> bool {
+    config
+        .backends
+        .iter()
+        .chain(config.backend_pools.iter().flat_map(|pool| &pool.backends))
+        .any(|configured| configured.trim_end_matches('/') == backend)
+}
+
+/// Draining backends, empty when none are draining or KV is unavailable
+pub async fn load(env: &Env, config: &ProxyConfig) -> DrainState {
+    let now = js_sys::Date::now();
+    let cached = LOADED.with(|loaded| {
+        loaded
+            .borrow()
+            .as_ref()
+            .filter(|(loaded_at, _)| now - loaded_at < DRAIN_CACHE_MS)
+            .map(|(_, state)| state.clone())
+    });
+    if let Some(state) = cached {
+        return state;
+    }
+
+    let state = match env.kv("PROXY_KV") {
+        Ok(kv) => read(&kv, config).await.unwrap_or_default(),
+        Err(_) => DrainState::new(),
+    };
+    LOADED.with(|loaded| *loaded.borrow_mut() = Some((now, state.clone())));
+    state
+}
+
+/// Start draining backends: they get no new traffic, and clients pinned to
+/// them by sticky sessions stay for `grace_period` seconds
+pub async fn start(
+    kv: &KvStore,
+    config: &ProxyConfig,
+    backends: &[String],
+    grace_period: u64,
+    started_by: &str,
+) -> Result<DrainState> {
+    let mut state = read(kv, config).await?;
+    let now = Utc::now().timestamp_millis();
+    for backend in backends {
+        state.insert(
+            backend.trim_end_matches('/').to_string(),
+            DrainEntry {
+                started_at: now,
+                until: now + grace_period as i64 * 1000,
+                started_by: started_by.to_string(),
+            },
+        );
+    }
+    // Forget drained backends that are gone from the configuration
+    state.retain(|backend, entry| entry.in_grace_period() || is_configured(config, backend));
+    write(kv, config, &state).await?;
+    Ok(state)
+}
+
+/// Drain request body
+#[derive(Debug, Deserialize)]
+struct DrainRequest {
+    backend: String,
+    #[serde(default)]
+    grace_period: Option<u64>, // Seconds, defaults to DRAIN_GRACE_PERIOD
+}
+
+/// List draining backends
+pub async fn handle_list(env: &Env, config: &ProxyConfig) -> Result<Response> {
+    let state = read(&env.kv("PROXY_KV")?, config).await?;
+    let now = Utc::now().timestamp_millis();
+    let backends: Vec<_> = state
+        .iter()
+        .map(|(backend, entry)| {
+            serde_json::json!({
+                "backend": backend,
+                "state": if entry.in_grace_period() { "draining" } else { "drained" },
+                "grace_remaining_secs": (entry.until - now).max(0) / 1000,
+                "started_at": entry.started_at,
+                "started_by": entry.started_by,
+            })
+        })
+        .collect();
+
+    Response::from_json(&serde_json::json!({
+        "backends": backends,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
+
+/// Start draining a backend
+pub async fn handle_start(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let body: DrainRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return Response::error("Invalid drain request", 400),
+    };
+    let backend = body.backend.trim_end_matches('/').to_string();
+    if !is_configured(config, &backend) {
+        return Response::error(format!("Unknown backend: {backend}"), 400);
+    }
+    let grace_period = body.grace_period.unwrap_or(config.drain_grace_period);
+
+    let kv = env.kv("PROXY_KV")?;
+    let state = start(
+        &kv,
+        config,
+        std::slice::from_ref(&backend),
+        grace_period,
+        &identity.name,
+    )
+    .await?;
+
+    let entry = AuditEntry::new(req, &identity.name, "drain_backend", &backend).with_change(
+        None,
+        Some(serde_json::json!({ "grace_period": grace_period })),
+    );
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({
+        "backend": backend,
+        "grace_period": grace_period,
+        "draining": state.len()
+    }))
+}
+
+/// Return a drained backend to rotation
+pub async fn handle_cancel(
+    req: &Request,
+    url: &Url,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let backend = match url.query_pairs().find(|(key, _)| key == "backend") {
+        Some((_, backend)) => backend.trim_end_matches('/').to_string(),
+        None => return Response::error("Backend is required", 400),
+    };
+
+    let kv = env.kv("PROXY_KV")?;
+    let mut state = read(&kv, config).await?;
+    if state.remove(&backend).is_none() {
+        return Response::error("Backend is not draining", 404);
+    }
+    write(&kv, config, &state).await?;
+
+    let entry = AuditEntry::new(req, &identity.name, "undrain_backend", &backend);
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({ "backend": backend, "draining": false }))
+}
@@ -0,0 +1,226 @@
+use futures_util::future::join_all;
+use regex::Regex;
+use worker::*;
+
+use crate::{
+    authorize_backend,
+    cache::{CacheLookup, CacheManager},
+    config::ProxyConfig,
+    context::RequestContext,
+    latency, utils,
+};
+
+/// Maximum number of includes resolved per page
+const MAX_INCLUDES: usize = 32;
+
+// Compiled once per isolate
+thread_local! {
+    /// `<esi:remove>` blocks
+    static REMOVE: Option<Regex> = Regex::new(r"(?s)<esi:remove>.*?</esi:remove>").ok();
+    /// `<esi:include>` tags, capturing their attributes
+    static INCLUDE: Option<Regex> =
+        Regex::new(r"(?s)<esi:include\s([^>]*?)/?>(?:\s*</esi:include>)?").ok();
+    /// Quoted tag attributes
+    static ATTRIBUTE: Option<Regex> = Regex::new(r#"(\w+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).ok();
+}
+
+/// Check if ESI processing is enabled for the path
+pub fn is_esi_route(config: &ProxyConfig, path: &str) -> bool {
+    config
+        .esi_paths
+        .iter()
+        .any(|pattern| utils::path_matches(pattern, path))
+}
+
+/// Resolve `<esi:include>` tags of an HTML page by fetching its fragments
+/// from the backend. Fragments are cached with their own TTL, so the page
+/// itself can be cached longer than its dynamic parts.
+pub async fn assemble(
+    mut response: Response,
+    backend: &str,
+    cache: &CacheManager,
+    env: &Env,
+    ctx: &Context,
+    request_ctx: &RequestContext,
+    config: &ProxyConfig,
+) -> Result<Response> {
+    let content_type = response
+        .headers()
+        .get("Content-Type")?
+        .unwrap_or_default()
+        .to_lowercase();
+    if !content_type.contains("text/html") {
+        return Ok(response);
+    }
+
+    let body = response.bytes().await?;
+    let html = match String::from_utf8(body) {
+        Ok(html) if html.contains("<esi:") => html,
+        Ok(html) => return utils::rebuild_response(&response, html.into_bytes()),
+        Err(e) => return utils::rebuild_response(&response, e.into_bytes()),
+    };
+
+    // <esi:remove> holds fallback content for clients without ESI processing
+    let html = REMOVE.with(|remove| match remove {
+        Some(remove) => remove.replace_all(&html, "").to_string(),
+        None => html,
+    });
+
+    let tags: Vec<(String, Include)> = INCLUDE.with(|include| {
+        include
+            .iter()
+            .flat_map(|include| include.captures_iter(&html))
+            .take(MAX_INCLUDES)
+            .map(|caps| (caps[0].to_string(), Include::parse(&caps[1])))
+            .collect()
+    });
+
+    let fragments = join_all(tags.iter().map(|(_, include)| {
+        resolve_include(include, backend, cache, env, ctx, request_ctx, config)
+    }))
+    .await;
+
+    let mut assembled = html;
+    for ((tag, include), fragment) in tags.iter().zip(fragments) {
+        let fragment = fragment.unwrap_or_else(|| {
+            console_log!(
+                "ESI include {} failed for {} (onerror: {})",
+                include.src,
+                request_ctx.request_id,
+                include.onerror.as_deref().unwrap_or("-")
+            );
+            String::new()
+        });
+        assembled = assembled.replacen(tag.as_str(), &fragment, 1);
+    }
+
+    utils::rebuild_response(&response, assembled.into_bytes())
+}
+
+/// Attributes of an `<esi:include>` tag
+struct Include {
+    src: String,
+    alt: Option<String>,
+    onerror: Option<String>,
+}
+
+impl Include {
+    fn parse(attributes: &str) -> Self {
+        let mut include = Include {
+            src: String::new(),
+            alt: None,
+            onerror: None,
+        };
+
+        ATTRIBUTE.with(|attr| {
+            for caps in attr.iter().flat_map(|attr| attr.captures_iter(attributes)) {
+                let value = caps
+                    .get(2)
+                    .or_else(|| caps.get(3))
+                    .map(|value| value.as_str().to_string())
+                    .unwrap_or_default();
+                match &caps[1] {
+                    "src" => include.src = value,
+                    "alt" => include.alt = Some(value),
+                    "onerror" => include.onerror = Some(value),
+                    _ => {}
+                }
+            }
+        });
+
+        include
+    }
+}
+
+/// Fetch the include source, falling back to `alt`
+async fn resolve_include(
+    include: &Include,
+    backend: &str,
+    cache: &CacheManager,
+    env: &Env,
+    ctx: &Context,
+    request_ctx: &RequestContext,
+    config: &ProxyConfig,
+) -> Option<String> {
+    let sources = std::iter::once(&include.src).chain(include.alt.as_ref());
+    for src in sources {
+        if let Some(url) = fragment_url(src, backend, config) {
+            if let Some(fragment) = fetch_fragment(&url, cache, env, ctx, request_ctx, config).await
+            {
+                return Some(fragment);
+            }
+        }
+    }
+    None
+}
+
+/// Resolve fragment source against the backend, refusing foreign origins
+fn fragment_url(src: &str, backend: &str, config: &ProxyConfig) -> Option<String> {
+    if src.starts_with('/') && !src.starts_with("//") {
+        return Some(format!("{}{src}", backend.trim_end_matches('/')));
+    }
+
+    let url = Url::parse(src).ok()?;
+    let origin = url.origin().ascii_serialization();
+    let allowed = std::iter::once(backend)
+        .chain(config.backends.iter().map(String::as_str))
+        .any(|backend| backend.trim_end_matches('/') == origin);
+    allowed.then(|| url.to_string())
+}
+
+/// Get fragment from cache or backend
+async fn fetch_fragment(
+    url: &str,
+    cache: &CacheManager,
+    env: &Env,
+    ctx: &Context,
+    request_ctx: &RequestContext,
+    config: &ProxyConfig,
+) -> Option<String> {
+    let tenant = request_ctx.tenant.as_deref().unwrap_or("-");
+    let cache_key = utils::sha256_hash(&format!("esi:{tenant}:{url}"));
+    // Fragments follow the cache policy of their own route, not the page's
+    let cache = &cache.for_route(Url::parse(url).ok()?.path());
+    if let Ok(CacheLookup::Fresh(mut cached)) = cache.lookup(&cache_key, env, ctx).await {
+        return cached.text().await.ok();
+    }
+
+    let headers = Headers::new();
+    if let Some(tenant) = &request_ctx.tenant {
+        headers.set("X-Tenant-Id", tenant).ok()?;
+    }
+    let mut init = RequestInit::new();
+    init.with_method(Method::Get).with_headers(headers);
+    let request = Request::new_with_init(url, &init).ok()?;
+    // Fragments get the same backend credentials and signatures as the page
+    let backend_base = url.split('/').take(3).collect::<Vec<_>>().join("/");
+    if let Err(e) = authorize_backend(&request, &backend_base, &request.path(), config, env).await {
+        console_log!("Failed to authorize ESI fragment {}: {:?}", url, e);
+        return None;
+    }
+
+    let mut response = latency::fetch_with_timeout(request, config.timeout * 1000)
+        .await
+        .ok()?;
+    if !(200..300).contains(&response.status_code()) {
+        return None;
+    }
+
+    let ttl = cache.ttl_for(&response);
+    if ttl == 0 {
+        return response.text().await.ok();
+    }
+    let cached = response.cloned().ok()?;
+    let cache = cache.clone();
+    let env = env.clone();
+    ctx.wait_until(async move {
+        if let Err(e) = cache
+            .cache_response_with_ttl(&cache_key, cached, ttl, &env)
+            .await
+        {
+            console_log!("Failed to cache ESI fragment {}: {:?}", cache_key, e);
+        }
+    });
+
+    response.text().await.ok()
+}
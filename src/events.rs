@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use serde::Serialize;
+use worker::*;
+
+use crate::{config::ProxyConfig, request_id};
+
+/// Event type tail workers can filter on
+const REQUEST_EVENT_TYPE: &str = "cf_proxy.request";
+
+/// Structured record of a proxied request
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RequestEvent {
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub timestamp: String,
+    pub request_id: String,
+    pub ray_id: Option<String>, // Cloudflare Ray ID
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub duration_ms: f64,
+    pub backend: Option<String>,
+    pub retries: u32,                // Failed attempts retried on other backends
+    pub hedged: bool,                // Answered by a hedge request to a second backend
+    pub cache: Option<&'static str>, /* "hit", "coalesced", "stale", "miss", "bypass" or "revalidated" */
+    pub client_ip: Option<String>,
+    pub country: Option<String>,
+    pub colo: Option<String>,
+    pub user_agent: Option<String>,
+    pub tenant: Option<String>,
+    pub labels: Vec<String>,
+    pub tags: BTreeMap<String, String>,
+    pub error: Option<String>,
+}
+
+impl RequestEvent {
+    /// Start an event from the incoming request
+    pub fn start(req: &Request, request_id: &str) -> Self {
+        let cf = req.cf();
+        Self {
+            event_type: REQUEST_EVENT_TYPE,
+            timestamp: Utc::now().to_rfc3339(),
+            request_id: request_id.to_string(),
+            ray_id: request_id::ray_id(req),
+            method: req.method().to_string(),
+            path: req
+                .url()
+                .map(|url| url.path().to_string())
+                .unwrap_or_default(),
+            client_ip: req.headers().get("CF-Connecting-IP").ok().flatten(),
+            country: cf.and_then(|cf| cf.country()),
+            colo: cf.map(|cf| cf.colo()),
+            user_agent: req.headers().get("User-Agent").ok().flatten(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Emit the event as a single JSON log line, which tail workers receive in
+/// `event.logs[].message` and can parse without an HTTP sink
+pub fn emit(config: &ProxyConfig, event: &RequestEvent) {
+    if !config.tail_events_enabled || event.request_id.is_empty() {
+        return;
+    }
+
+    match serde_json::to_string(event) {
+        Ok(line) => console_log!("{}", line),
+        Err(e) => console_log!("Failed to serialize request event: {:?}", e),
+    }
+}
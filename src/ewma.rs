@@ -0,0 +1,133 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::config::ProxyConfig;
+
+/// Weight of the newest sample in the moving averages
+const ALPHA: f64 = 0.3;
+
+/// Score multiplier per unit of error rate, 10% errors double a backend's score
+const ERROR_PENALTY: f64 = 10.0;
+
+/// Averages not updated for this long are dropped, so backends avoided for
+/// being slow are probed again
+const MAX_AGE_MS: f64 = 30_000.0;
+
+/// Interval between syncs of this isolate's averages with KV
+const SYNC_INTERVAL_MS: f64 = 10_000.0;
+
+/// KV key prefix of the averages shared by all isolates (`ewma:{proxy_id}`)
+const EWMA_PREFIX: &str = "ewma:";
+
+/// Moving averages of a backend's response time and error rate
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BackendEwma {
+    latency_ms: f64,
+    error_rate: f64,
+    updated_at: f64, // Unix milliseconds of the last sample
+}
+
+thread_local! {
+    static AVERAGES: RefCell<HashMap<String, BackendEwma>> = RefCell::new(HashMap::new());
+    static LAST_SYNC: Cell<f64> = const { Cell::new(0.0) };
+}
+
+/// Whether backends are picked by their moving averages
+pub fn is_enabled(config: &ProxyConfig) -> bool {
+    config
+        .load_balancer_strategy
+        .eq_ignore_ascii_case("latency_aware")
+}
+
+/// Fold a backend response (or failure) into the backend's averages
+pub fn record(backend: &str, latency_ms: f64, failed: bool) {
+    let now = js_sys::Date::now();
+    let error = if failed { 1.0 } else { 0.0 };
+    AVERAGES.with(|averages| {
+        let mut averages = averages.borrow_mut();
+        match averages.get_mut(backend) {
+            Some(ewma) if now - ewma.updated_at < MAX_AGE_MS => {
+                ewma.latency_ms += ALPHA * (latency_ms - ewma.latency_ms);
+                ewma.error_rate += ALPHA * (error - ewma.error_rate);
+                ewma.updated_at = now;
+            }
+            _ => {
+                averages.insert(
+                    backend.to_string(),
+                    BackendEwma {
+                        latency_ms,
+                        error_rate: error,
+                        updated_at: now,
+                    },
+                );
+            }
+        }
+    });
+}
+
+/// Backend score, lower is better: average latency inflated by the error
+/// rate. `None` for backends without recent samples.
+pub fn score(backend: &str) -> Option<f64> {
+    let now = js_sys::Date::now();
+    AVERAGES.with(|averages| {
+        averages
+            .borrow()
+            .get(backend)
+            .filter(|ewma| now - ewma.updated_at < MAX_AGE_MS)
+            .map(|ewma| ewma.latency_ms * (1.0 + ERROR_PENALTY * ewma.error_rate))
+    })
+}
+
+/// Exchange averages with other isolates through KV at most every
+/// `SYNC_INTERVAL_MS`; the most recently updated average of a backend wins
+pub async fn sync(config: ProxyConfig, env: Env) {
+    let now = js_sys::Date::now();
+    if now - LAST_SYNC.with(Cell::get) < SYNC_INTERVAL_MS {
+        return;
+    }
+    LAST_SYNC.with(|last_sync| last_sync.set(now));
+
+    let kv = match env.kv("PROXY_KV") {
+        Ok(kv) => kv,
+        Err(_) => return,
+    };
+    let key = format!("{EWMA_PREFIX}{}", config.proxy_id);
+    let stored = kv
+        .get(&key)
+        .json::<HashMap<String, BackendEwma>>()
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+
+    // Read-modify-write is approximate under concurrency, which is fine for averages
+    let merged = AVERAGES.with(|averages| {
+        let mut averages = averages.borrow_mut();
+        for (backend, ewma) in stored {
+            if now - ewma.updated_at >= MAX_AGE_MS {
+                continue;
+            }
+            let newer = averages
+                .get(&backend)
+                .is_none_or(|local| local.updated_at < ewma.updated_at);
+            if newer {
+                averages.insert(backend, ewma);
+            }
+        }
+        averages.retain(|_, ewma| now - ewma.updated_at < MAX_AGE_MS);
+        averages.clone()
+    });
+
+    if let Ok(serialized) = serde_json::to_string(&merged) {
+        if let Ok(put) = kv.put(&key, serialized) {
+            if let Err(e) = put.expiration_ttl(3600).execute().await {
+                console_log!("Failed to store backend averages: {:?}", e);
+            }
+        }
+    }
+}
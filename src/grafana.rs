@@ -0,0 +1,230 @@
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{
+    config::ProxyConfig,
+    reports::{self, BackendTraffic, HourlyTraffic, TRAFFIC_STATS_DAYS},
+};
+
+/// Metrics available as time series
+const METRICS: &[&str] = &[
+    "requests",
+    "errors",
+    "error_rate",
+    "cache_hits",
+    "cache_misses",
+    "cache_hit_rate",
+];
+
+/// Prefix of per-backend availability metrics, e.g. "availability:https://api1.example.com"
+const AVAILABILITY_PREFIX: &str = "availability:";
+
+/// Prefix of per-tag request metrics, e.g. "tag:product=checkout"
+const TAG_PREFIX: &str = "tag:";
+
+/// Query of the Grafana JSON datasource
+#[derive(Debug, Deserialize)]
+struct QueryRequest {
+    range: QueryRange,
+    targets: Vec<QueryTarget>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryRange {
+    from: String, // RFC 3339
+    to: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryTarget {
+    #[serde(default)]
+    target: Option<String>,
+    #[serde(default)]
+    hide: bool,
+}
+
+/// Time series of one metric, values paired with Unix milliseconds
+#[derive(Debug, Serialize)]
+struct TimeSeries {
+    target: String,
+    datapoints: Vec<(f64, i64)>,
+}
+
+/// Metric names for the datasource's metric picker
+fn metric_names(config: &ProxyConfig) -> Vec<String> {
+    METRICS
+        .iter()
+        .map(|metric| metric.to_string())
+        .chain(
+            config
+                .backends
+                .iter()
+                .map(|backend| format!("{AVAILABILITY_PREFIX}{backend}")),
+        )
+        .chain(
+            config
+                .metric_tags
+                .iter()
+                .map(|rule| format!("{TAG_PREFIX}{}={}", rule.tag, rule.value)),
+        )
+        .fold(Vec::new(), |mut names, name| {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+            names
+        })
+}
+
+/// Handle the Grafana JSON datasource API under `/_proxy/grafana`
+pub async fn handle_request(mut req: Request, env: &Env, config: &ProxyConfig) -> Result<Response> {
+    if !config.grafana_enabled {
+        return Response::error("Not Found", 404);
+    }
+
+    let url = req.url()?;
+    match (req.method(), url.path()) {
+        // Connection test
+        (Method::Get, "/_proxy/grafana") => Response::ok("OK"),
+        (Method::Post, "/_proxy/grafana/metrics") => {
+            let metrics: Vec<_> = metric_names(config)
+                .into_iter()
+                .map(|name| serde_json::json!({ "label": name, "value": name }))
+                .collect();
+            Response::from_json(&metrics)
+        }
+        (Method::Post, "/_proxy/grafana/search") => Response::from_json(&metric_names(config)),
+        (Method::Post, "/_proxy/grafana/query") => {
+            let query: QueryRequest = match req.json().await {
+                Ok(query) => query,
+                Err(_) => return Response::error("Invalid query", 400),
+            };
+            let targets: Vec<String> = query
+                .targets
+                .into_iter()
+                .filter(|target| !target.hide)
+                .filter_map(|target| target.target)
+                .collect();
+            let (from, to) = match (parse_time(&query.range.from), parse_time(&query.range.to)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => return Response::error("Invalid time range", 400),
+            };
+            let series = query_series(env, &targets, from, to).await?;
+            Response::from_json(&series)
+        }
+        // Flat rows for the Infinity datasource:
+        // `?target=requests&from=${__from}&to=${__to}`
+        (Method::Get, "/_proxy/grafana/query") => {
+            let mut targets = Vec::new();
+            let mut from = None;
+            let mut to = None;
+            for (key, value) in url.query_pairs() {
+                match key.as_ref() {
+                    "target" => targets.push(value.to_string()),
+                    "from" => from = parse_time(&value),
+                    "to" => to = parse_time(&value),
+                    _ => {}
+                }
+            }
+            let to = to.unwrap_or_else(Utc::now);
+            let from = from.unwrap_or(to - Duration::days(1));
+
+            let rows: Vec<_> = query_series(env, &targets, from, to)
+                .await?
+                .into_iter()
+                .flat_map(|series| {
+                    series.datapoints.into_iter().map(move |(value, time)| {
+                        serde_json::json!({ "target": series.target, "time": time, "value": value })
+                    })
+                })
+                .collect();
+            Response::from_json(&rows)
+        }
+        _ => Response::error("Not Found", 404),
+    }
+}
+
+/// Unix milliseconds (Grafana's `${__from}`) or an RFC 3339 timestamp
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    match value.parse::<i64>() {
+        Ok(millis) => Utc.timestamp_millis_opt(millis).single(),
+        Err(_) => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+    }
+}
+
+/// Hourly series of the targets within the range, limited to the days kept in KV
+async fn query_series(
+    env: &Env,
+    targets: &[String],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<TimeSeries>> {
+    let oldest = Utc::now() - Duration::days(i64::from(TRAFFIC_STATS_DAYS));
+    let from = from.max(oldest);
+    if targets.is_empty() || from > to {
+        return Ok(targets
+            .iter()
+            .map(|target| TimeSeries {
+                target: target.clone(),
+                datapoints: vec![],
+            })
+            .collect());
+    }
+
+    // Hours without traffic have no entry and are left out of the series
+    let mut hours: Vec<(i64, HourlyTraffic)> = Vec::new();
+    for (date, stats) in reports::load_days(env, from.date_naive(), to.date_naive()).await? {
+        for (hour, traffic) in stats.hours {
+            let time =
+                NaiveTime::from_hms_opt(hour, 0, 0).map(|time| date.and_time(time).and_utc());
+            if let Some(time) = time.filter(|time| *time + Duration::hours(1) > from && *time <= to)
+            {
+                hours.push((time.timestamp_millis(), traffic));
+            }
+        }
+    }
+
+    Ok(targets
+        .iter()
+        .map(|target| TimeSeries {
+            target: target.clone(),
+            datapoints: hours
+                .iter()
+                .filter_map(|(time, traffic)| Some((metric_value(target, traffic)?, *time)))
+                .collect(),
+        })
+        .collect())
+}
+
+/// Value of a metric in one hour, `None` for unknown metrics and undefined rates
+fn metric_value(metric: &str, traffic: &HourlyTraffic) -> Option<f64> {
+    let percent = |part: u64, total: u64| (total > 0).then(|| part as f64 / total as f64 * 100.0);
+
+    match metric {
+        "requests" => Some(traffic.requests as f64),
+        "errors" => Some(traffic.errors as f64),
+        "error_rate" => percent(traffic.errors, traffic.requests),
+        "cache_hits" => Some(traffic.cache_hits as f64),
+        "cache_misses" => Some(traffic.cache_misses as f64),
+        "cache_hit_rate" => percent(
+            traffic.cache_hits,
+            traffic.cache_hits + traffic.cache_misses,
+        ),
+        _ if metric.starts_with(TAG_PREFIX) => {
+            let (tag, value) = metric[TAG_PREFIX.len()..].split_once('=')?;
+            let requests = traffic
+                .tags
+                .get(tag)
+                .and_then(|values| values.get(value))
+                .map_or(0, |traffic| traffic.requests);
+            Some(requests as f64)
+        }
+        _ => {
+            let backend = metric.strip_prefix(AVAILABILITY_PREFIX)?;
+            let BackendTraffic { requests, failures } = traffic.backends.get(backend)?;
+            percent(requests - failures, *requests)
+        }
+    }
+}
@@ -1,9 +1,34 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap};
 
 use chrono::{DateTime, Utc};
 use worker::*;
 
-use crate::config::ProxyConfig;
+use crate::{
+    config::{BackendConfig, ProxyConfig},
+    latency, origin_signing,
+};
+
+/// Smallest traffic share of a backend that just recovered
+const MIN_SLOW_START_SHARE: f64 = 0.1;
+
+thread_local! {
+    /// Backends whose requests failed in this isolate, by time of the last failure
+    static FAILING_BACKENDS: RefCell<HashMap<String, DateTime<Utc>>> =
+        RefCell::new(HashMap::new());
+    /// Backends ramping up after recovering in this isolate, by recovery time
+    static RECOVERED_BACKENDS: RefCell<HashMap<String, DateTime<Utc>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Whether requests to `backend` failed in this isolate within the last
+/// `HEALTH_CHECK_INTERVAL` seconds, without succeeding since
+pub fn is_failing(backend: &str, config: &ProxyConfig) -> bool {
+    FAILING_BACKENDS.with(|failing| {
+        failing.borrow().get(backend).is_some_and(|failed_at| {
+            Utc::now() < *failed_at + chrono::Duration::seconds(config.health_check_interval as i64)
+        })
+    })
+}
 
 /// Health checker
 pub struct HealthChecker {
@@ -37,41 +62,103 @@ impl HealthChecker {
         true
     }
 
+    /// Whether requests to the backend failed recently in this isolate
+    pub fn is_failing(&self, backend: &str) -> bool {
+        is_failing(backend.trim_end_matches('/'), &self.config)
+    }
+
+    /// Share of its normal traffic a backend gets, ramping up linearly from
+    /// 10% over `SLOW_START_WINDOW` seconds after it recovered
+    pub fn slow_start_share(&self, backend: &str) -> f64 {
+        let window = self.config.slow_start_window as f64;
+        if window <= 0.0 {
+            return 1.0;
+        }
+        RECOVERED_BACKENDS.with(|recovered| {
+            let mut recovered = recovered.borrow_mut();
+            let elapsed = match recovered.get(backend) {
+                Some(recovered_at) => {
+                    (Utc::now() - *recovered_at).num_milliseconds() as f64 / 1000.0
+                }
+                None => return 1.0,
+            };
+            if elapsed >= window {
+                recovered.remove(backend);
+                return 1.0;
+            }
+            (elapsed / window).max(MIN_SLOW_START_SHARE)
+        })
+    }
+
     /// Mark backend as unhealthy
     pub async fn mark_unhealthy(&mut self, backend: &str) {
         console_log!("Marking backend as unhealthy: {}", backend);
         self.unhealthy_backends
             .insert(backend.to_string(), Utc::now());
+        FAILING_BACKENDS.with(|failing| {
+            failing.borrow_mut().insert(backend.to_string(), Utc::now());
+        });
+        RECOVERED_BACKENDS.with(|recovered| recovered.borrow_mut().remove(backend));
     }
 
     /// Mark backend as healthy
-    #[allow(dead_code)]
     pub async fn mark_healthy(&mut self, backend: &str) {
-        if self.unhealthy_backends.remove(backend).is_some() {
+        let recovered = FAILING_BACKENDS.with(|failing| failing.borrow_mut().remove(backend));
+        if self.unhealthy_backends.remove(backend).is_some() || recovered.is_some() {
             console_log!("Marking backend as healthy: {}", backend);
+            if self.config.slow_start_window > 0 {
+                RECOVERED_BACKENDS.with(|recovered| {
+                    recovered
+                        .borrow_mut()
+                        .insert(backend.to_string(), Utc::now());
+                });
+            }
         }
     }
 
-    /// Perform health check
+    /// Perform health check using the backend's method, headers and
+    /// accepted status codes from `BACKEND_CONFIGS`
     #[allow(dead_code)]
     pub async fn perform_health_check(&mut self, backend: &str) -> bool {
         if !self.config.health_check_enabled {
             return true;
         }
 
-        let health_path = "/health"; // Default health check path
+        let backend_config = self
+            .config
+            .backend_configs
+            .iter()
+            .find(|b| b.url == backend);
+        let health_path = backend_config
+            .and_then(|b| b.health_check_path.as_deref())
+            .unwrap_or("/health");
         let check_url = format!("{backend}{health_path}");
 
         console_log!("Performing health check for: {}", check_url);
 
-        let request = match Request::new(&check_url, Method::Get) {
+        let request = match health_check_request(&check_url, backend_config) {
             Ok(req) => req,
-            Err(_) => return false,
+            Err(e) => {
+                console_log!("Invalid health check for {}: {:?}", backend, e);
+                return false;
+            }
         };
+        // Origins that only accept signed traffic must accept their health checks too
+        if let Err(e) = origin_signing::sign(&request, &self.config).await {
+            console_log!("Failed to sign health check for {}: {:?}", backend, e);
+            return false;
+        }
 
-        match Fetch::Request(request).send().await {
+        let timeout_ms = self.config.health_check_timeout * 1000;
+        match latency::fetch_with_timeout(request, timeout_ms).await {
             Ok(response) => {
-                let is_healthy = response.status_code() >= 200 && response.status_code() < 300;
+                let status = response.status_code();
+                let is_healthy = match backend_config {
+                    Some(b) if !b.health_check_status.is_empty() => {
+                        b.health_check_status.contains(&status)
+                    }
+                    _ => (200..300).contains(&status),
+                };
                 if is_healthy {
                     self.mark_healthy(backend).await;
                 } else {
@@ -88,8 +175,13 @@ impl HealthChecker {
 
     /// Get all healthy backends
     pub async fn get_healthy_backends(&self) -> Vec<String> {
+        self.healthy_among(&self.config.backends).await
+    }
+
+    /// Healthy backends of a pool
+    pub async fn healthy_among(&self, backends: &[String]) -> Vec<String> {
         let mut healthy = Vec::new();
-        for backend in &self.config.backends {
+        for backend in backends {
             if self.is_healthy(backend).await {
                 healthy.push(backend.clone());
             }
@@ -107,3 +199,27 @@ impl HealthChecker {
         status
     }
 }
+
+/// Build the health check request with the backend's method and headers
+fn health_check_request(url: &str, backend_config: Option<&BackendConfig>) -> Result<Request> {
+    let method = match backend_config.and_then(|b| b.health_check_method.as_deref()) {
+        None => Method::Get,
+        Some(method) => match method.to_ascii_uppercase().as_str() {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            other => return Err(Error::from(format!("Unsupported method: {other}"))),
+        },
+    };
+
+    let headers = Headers::new();
+    if let Some(backend_config) = backend_config {
+        for (name, value) in &backend_config.health_check_headers {
+            headers.set(name, value)?;
+        }
+    }
+
+    let mut init = RequestInit::new();
+    init.with_method(method).with_headers(headers);
+    Request::new_with_init(url, &init)
+}
@@ -0,0 +1,159 @@
+use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+use futures_util::{StreamExt, stream};
+use lol_html::{
+    ElementContentHandlers, HandlerResult, HtmlRewriter, Selector, Settings,
+    html_content::{ContentType, Element},
+};
+use worker::*;
+
+use crate::{
+    config::{HtmlRewriteRule, ProxyConfig},
+    context::RequestContext,
+    utils,
+};
+
+/// Supported rewrite actions
+const ACTIONS: &[&str] = &[
+    "set_attribute",
+    "remove_attribute",
+    "remove",
+    "prepend",
+    "append",
+    "before",
+    "after",
+    "replace",
+    "set_inner_content",
+];
+
+/// Stream HTML responses through the rewrite rules matching the request path
+pub fn apply_html_rewrites(
+    mut response: Response,
+    config: &ProxyConfig,
+    ctx: &RequestContext,
+) -> Result<Response> {
+    if config.html_rewrite_rules.is_empty() {
+        return Ok(response);
+    }
+
+    let content_type = response
+        .headers()
+        .get("Content-Type")?
+        .unwrap_or_default()
+        .to_lowercase();
+    if !content_type.contains("text/html") {
+        return Ok(response);
+    }
+
+    let mut handlers = Vec::new();
+    for rule in &config.html_rewrite_rules {
+        if !utils::path_matches(&rule.path, &ctx.path) {
+            continue;
+        }
+        if !ACTIONS.contains(&rule.action.as_str()) {
+            console_log!(
+                "Ignoring HTML rewrite rule with unknown action: {}",
+                rule.action
+            );
+            continue;
+        }
+        match rule.selector.parse::<Selector>() {
+            Ok(selector) => {
+                let rule = rule.clone();
+                handlers.push((
+                    Cow::Owned(selector),
+                    ElementContentHandlers::default().element(move |el| apply_action(el, &rule)),
+                ));
+            }
+            Err(e) => console_log!(
+                "Ignoring invalid HTML rewrite selector {}: {}",
+                rule.selector,
+                e
+            ),
+        }
+    }
+    if handlers.is_empty() {
+        return Ok(response);
+    }
+
+    // Rewritten output is collected per input chunk and forwarded as it is produced
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = {
+        let output = output.clone();
+        move |chunk: &[u8]| output.borrow_mut().extend_from_slice(chunk)
+    };
+    let rewriter = HtmlRewriter::new(
+        Settings {
+            element_content_handlers: handlers,
+            ..Settings::default()
+        },
+        sink,
+    );
+
+    let input = response.stream()?;
+    let body = stream::unfold(Some((input, rewriter, output)), |state| async move {
+        let (mut input, mut rewriter, output) = state?;
+        loop {
+            match input.next().await {
+                Some(Ok(chunk)) => {
+                    if let Err(e) = rewriter.write(&chunk) {
+                        return Some((Err(Error::from(e.to_string())), None));
+                    }
+                    let rewritten = std::mem::take(&mut *output.borrow_mut());
+                    if !rewritten.is_empty() {
+                        return Some((Ok(rewritten), Some((input, rewriter, output))));
+                    }
+                }
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => {
+                    if let Err(e) = rewriter.end() {
+                        return Some((Err(Error::from(e.to_string())), None));
+                    }
+                    let rewritten = std::mem::take(&mut *output.borrow_mut());
+                    return (!rewritten.is_empty()).then_some((Ok(rewritten), None));
+                }
+            }
+        }
+    });
+
+    let headers = response.headers().clone();
+    // Body length changes, let the runtime recompute it
+    headers.delete("Content-Length")?;
+
+    Ok(Response::from_stream(body)?
+        .with_status(response.status_code())
+        .with_headers(headers))
+}
+
+/// Apply a rule's action to a matched element
+fn apply_action(el: &mut Element, rule: &HtmlRewriteRule) -> HandlerResult {
+    let content = rule.content.as_deref().unwrap_or("");
+    let content_type = if rule.text {
+        ContentType::Text
+    } else {
+        ContentType::Html
+    };
+
+    match rule.action.as_str() {
+        "set_attribute" => {
+            if let Some(attribute) = &rule.attribute {
+                el.set_attribute(attribute, content)?;
+            }
+        }
+        "remove_attribute" => {
+            if let Some(attribute) = &rule.attribute {
+                el.remove_attribute(attribute);
+            }
+        }
+        "remove" => el.remove(),
+        "prepend" => el.prepend(content, content_type),
+        "append" => el.append(content, content_type),
+        "before" => el.before(content, content_type),
+        "after" => el.after(content, content_type),
+        "replace" => el.replace(content, content_type),
+        "set_inner_content" => el.set_inner_content(content, content_type),
+        _ => {}
+    }
+
+    Ok(())
+}
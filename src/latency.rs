@@ -0,0 +1,192 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    future::Future,
+    time::Duration,
+};
+
+use futures_util::future::{Either, select};
+use worker::*;
+
+use crate::config::ProxyConfig;
+
+/// Latency samples kept per backend
+const MAX_SAMPLES: usize = 200;
+
+/// Samples required before the adaptive timeout replaces the static one
+const MIN_SAMPLES: usize = 20;
+
+/// Start of the error message of timed out requests
+const TIMEOUT_MESSAGE: &str = "Backend request timed out";
+
+thread_local! {
+    /// Recent backend latencies observed by this isolate
+    static BACKEND_LATENCIES: RefCell<HashMap<String, VecDeque<f64>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Record backend latency sample
+pub fn record_latency(backend: &str, time_ms: f64) {
+    BACKEND_LATENCIES.with(|latencies| {
+        let mut latencies = latencies.borrow_mut();
+        let samples = latencies.entry(backend.to_string()).or_default();
+        samples.push_back(time_ms);
+        if samples.len() > MAX_SAMPLES {
+            samples.pop_front();
+        }
+    });
+}
+
+/// Get p99 latency for backend, if enough samples were observed
+pub fn p99_latency(backend: &str) -> Option<f64> {
+    BACKEND_LATENCIES.with(|latencies| {
+        let latencies = latencies.borrow();
+        let samples = latencies.get(backend)?;
+        if samples.len() < MIN_SAMPLES {
+            return None;
+        }
+
+        let mut sorted: Vec<f64> = samples.iter().copied().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let index = ((sorted.len() as f64) * 0.99).ceil() as usize - 1;
+        sorted.get(index).copied()
+    })
+}
+
+/// Mean of the backend's most recent latency samples, if any were observed
+pub fn recent_latency(backend: &str) -> Option<f64> {
+    BACKEND_LATENCIES.with(|latencies| {
+        let latencies = latencies.borrow();
+        let samples = latencies.get(backend)?;
+        let recent: Vec<f64> = samples.iter().rev().take(MIN_SAMPLES).copied().collect();
+        if recent.is_empty() {
+            return None;
+        }
+        Some(recent.iter().sum::<f64>() / recent.len() as f64)
+    })
+}
+
+/// Compute request timeout for backend in milliseconds. Backends are matched
+/// without a trailing `/`, then by origin, since requests are keyed by origin.
+pub fn timeout_for(backend: &str, config: &ProxyConfig) -> u64 {
+    let backend = backend.trim_end_matches('/');
+    let origin = |url: &str| url.split('/').take(3).collect::<Vec<_>>().join("/");
+    let static_timeout = config
+        .backend_configs
+        .iter()
+        .find(|b| b.url.trim_end_matches('/') == backend)
+        .or_else(|| {
+            config
+                .backend_configs
+                .iter()
+                .find(|b| origin(&b.url) == origin(backend))
+        })
+        .and_then(|b| b.timeout)
+        .unwrap_or(config.timeout)
+        * 1000;
+
+    if !config.adaptive_timeout_enabled {
+        return static_timeout;
+    }
+
+    match p99_latency(backend) {
+        Some(p99) => {
+            let adaptive = (p99 * config.adaptive_timeout_multiplier) as u64;
+            adaptive.clamp(
+                config.adaptive_timeout_min_ms,
+                config
+                    .adaptive_timeout_max_ms
+                    .max(config.adaptive_timeout_min_ms),
+            )
+        }
+        None => static_timeout,
+    }
+}
+
+/// Send request, failing when the timeout elapses first
+async fn send(req: Request, timeout_ms: u64, signal: &AbortSignal) -> Result<Response> {
+    let fetch = Fetch::Request(req);
+    let send = fetch.send_with_signal(signal);
+    let delay = Delay::from(Duration::from_millis(timeout_ms));
+
+    match select(Box::pin(send), delay).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(Error::from(format!(
+            "{TIMEOUT_MESSAGE} after {timeout_ms}ms"
+        ))),
+    }
+}
+
+/// Abort the request of a failed result, e.g. one that timed out
+fn settle(result: Result<Response>, controller: AbortController) -> Result<Response> {
+    if result.is_err() {
+        controller.abort();
+    }
+    result
+}
+
+/// Whether a result is a usable answer rather than a failure
+fn is_answer(result: &Result<Response>) -> bool {
+    matches!(result, Ok(response) if response.status_code() < 500)
+}
+
+/// Send request, aborting it when the timeout elapses first
+pub async fn fetch_with_timeout(req: Request, timeout_ms: u64) -> Result<Response> {
+    let controller = AbortController::default();
+    let signal = controller.signal();
+    settle(send(req, timeout_ms, &signal).await, controller)
+}
+
+/// Send request and, when it has not answered within `delay_ms`, the hedge
+/// request (with its timeout) built by `hedge` too. The first answer wins
+/// and the other request is aborted; a failure leaves the race to the other
+/// request. Returns the result and whether it is the hedge's.
+pub async fn fetch_hedged<H>(
+    req: Request,
+    timeout_ms: u64,
+    delay_ms: u64,
+    hedge: H,
+) -> (Result<Response>, bool)
+where
+    H: Future<Output = Option<(Request, u64)>>,
+{
+    let primary_controller = AbortController::default();
+    let primary_signal = primary_controller.signal();
+    let mut primary = Box::pin(send(req, timeout_ms, &primary_signal));
+    let delay = Delay::from(Duration::from_millis(delay_ms));
+    if let Either::Left((result, _)) = select(primary.as_mut(), delay).await {
+        return (settle(result, primary_controller), false);
+    }
+
+    let (hedge_req, hedge_timeout_ms) = match hedge.await {
+        Some(hedge) => hedge,
+        None => return (settle(primary.await, primary_controller), false),
+    };
+    let hedge_controller = AbortController::default();
+    let hedge_signal = hedge_controller.signal();
+    let hedge = Box::pin(send(hedge_req, hedge_timeout_ms, &hedge_signal));
+
+    match select(primary, hedge).await {
+        Either::Left((result, _)) if is_answer(&result) => {
+            hedge_controller.abort();
+            (result, false)
+        }
+        Either::Left((_, hedge)) => {
+            primary_controller.abort();
+            (settle(hedge.await, hedge_controller), true)
+        }
+        Either::Right((result, _)) if is_answer(&result) => {
+            primary_controller.abort();
+            (result, true)
+        }
+        Either::Right((_, primary)) => {
+            hedge_controller.abort();
+            (settle(primary.await, primary_controller), false)
+        }
+    }
+}
+
+/// Whether a request failed by timing out, rather than e.g. failing to connect
+pub fn is_timeout(error: &Error) -> bool {
+    error.to_string().starts_with(TIMEOUT_MESSAGE)
+}
@@ -1,23 +1,93 @@
+use std::{cell::Cell, time::Duration};
+
 use chrono::Utc;
 use regex::Regex;
-use uuid::Uuid;
 use worker::*;
 
+mod access;
+mod access_lists;
+mod admin;
+mod affinity;
+mod aggregate;
+mod alerts;
+mod backpressure;
+mod bandwidth;
+mod block_pages;
 mod cache;
+mod cache_warm;
+mod canary;
+mod client_hints;
+mod coalesce;
 mod config;
+mod config_store;
+mod connections;
+mod content_digest;
+mod content_watch;
+mod context;
+mod contracts;
+mod cors;
+mod discovery;
+mod drain;
+mod esi;
+mod events;
+mod ewma;
+mod grafana;
 mod health;
+mod html_rewrite;
+mod latency;
 mod load_balancer;
+mod maintenance;
+mod metrics_push;
 mod middleware;
+mod mirror;
+mod mock;
 mod monitoring;
+mod normalization;
+mod oauth2;
+mod origin_signing;
+mod precompressed;
+mod reports;
+mod reputation;
+mod request_id;
+mod route_auth;
+mod routing;
+mod secrets;
+mod selftest;
+mod sigv4;
+mod soft_404;
+mod state;
+mod status;
+mod tags;
+mod tenant;
+mod tls_watch;
 mod utils;
+mod validation;
+mod watermark;
+mod webhooks;
 
-use cache::CacheManager;
+// Exposed for the access rule benchmarks
+#[doc(hidden)]
+pub use access::{AccessMatcher, ClientAttributes};
+use alerts::Alert;
+use cache::{CacheLookup, CacheManager, StaleEntry};
+use coalesce::Flight;
+#[doc(hidden)]
+pub use config::AccessRule;
 use config::ProxyConfig;
+use connections::Lease;
+use context::RequestContext;
+use events::RequestEvent;
 use health::HealthChecker;
-use load_balancer::{LoadBalancer, LoadBalancerStrategy};
-use middleware::{apply_request_middleware, apply_response_middleware};
+use load_balancer::LoadBalancer;
+use middleware::{
+    apply_request_middleware, apply_response_middleware, check_access_control, partition_cookies,
+    retain_allowed_headers,
+};
 use monitoring::Metrics;
 
+/// Cloudflare statuses of origins that could not be reached, so never saw the request
+const UNREACHABLE_STATUSES: &[u16] = &[521, 522, 523, 525, 526, 530];
+
 /// Main structure for the reverse proxy
 pub struct ReverseProxy {
     config: ProxyConfig,
@@ -25,25 +95,36 @@ pub struct ReverseProxy {
     health_checker: HealthChecker,
     metrics: Metrics,
     cache_manager: CacheManager,
+    event: RequestEvent,
+    lease: Option<Lease>, // In-flight request counted by the least connections strategy
+    sent_as_stored: Cell<bool>, /* Body keeps its stored Content-Encoding, the runtime must not encode it */
 }
 
 impl ReverseProxy {
     /// Create reverse proxy instance from environment variables
+    #[allow(dead_code)]
     pub fn from_env(env: &Env) -> Result<Self> {
-        let config = ProxyConfig::from_env(env)?;
-        let strategy = LoadBalancerStrategy::from(config.load_balancer_strategy.as_str());
-        let load_balancer = LoadBalancer::with_strategy(&config.backends, strategy);
+        Ok(Self::new(ProxyConfig::from_env(env)?))
+    }
+
+    /// Create reverse proxy instance from resolved configuration
+    pub fn new(config: ProxyConfig) -> Self {
+        let load_balancer =
+            LoadBalancer::for_backends(&config, &config.backends, &config.load_balancer_strategy);
         let health_checker = HealthChecker::new(&config);
         let metrics = Metrics::new();
         let cache_manager = CacheManager::new(&config);
 
-        Ok(Self {
+        Self {
             config,
             load_balancer,
             health_checker,
             metrics,
             cache_manager,
-        })
+            event: RequestEvent::default(),
+            lease: None,
+            sent_as_stored: Cell::new(false),
+        }
     }
 
     /// Handle incoming requests
@@ -51,52 +132,324 @@ impl ReverseProxy {
         &mut self,
         mut req: Request,
         env: &Env,
-        _ctx: &Context,
+        ctx: &Context,
     ) -> Result<Response> {
-        let request_id = Uuid::new_v4().to_string();
+        let request_id = request_id::generate(&req, &self.config);
         let start_time = js_sys::Date::now();
+        self.event = RequestEvent::start(&req, &request_id);
 
         // Record request start
         self.metrics.record_request_start(&request_id);
 
-        // Handle CORS preflight requests
-        if req.method() == Method::Options {
-            return self.handle_cors_preflight();
+        // A request that already passed through this worker would loop forever
+        if self.is_proxy_loop(&req)? {
+            console_log!("Proxy loop detected for {}", request_id);
+            self.metrics.record_error(&request_id, "proxy_loop");
+            return Response::error("Loop Detected", 508);
+        }
+
+        // Answer CORS preflights, plain OPTIONS requests go to the backend
+        if is_cors_preflight(&req)? {
+            return self.handle_cors_preflight(&req);
+        }
+
+        // Access control, serving block pages for denied requests
+        if let Some(denial) = check_access_control(&req, &self.config) {
+            self.metrics.record_error(&request_id, "access_denied");
+            reputation::record_denial(&req, &self.config);
+            return block_pages::blocked_response(&req, &denial, &self.config, &request_id);
         }
 
         // Apply request middleware
         req = apply_request_middleware(req, &self.config)?;
+        if let Some(reason) = access_lists::deny_reason(&req, env, &self.config).await {
+            console_log!("Access denied for {}", reason);
+            self.metrics.record_error(&request_id, "access_denied");
+            reputation::record_denial(&req, &self.config);
+            return Response::error("Access denied", 403);
+        }
+        let reputation = reputation::score(&req, env, &self.config).await;
+        if let Some(denial) =
+            reputation.and_then(|score| self.config.access_matcher.deny_reputation(score))
+        {
+            console_log!(
+                "Access denied for {} (rule {})",
+                denial.reason,
+                denial.rule_id
+            );
+            self.metrics.record_error(&request_id, "access_denied");
+            reputation::record_denial(&req, &self.config);
+            return block_pages::blocked_response(&req, &denial, &self.config, &request_id);
+        }
+        if let Some(rejection) = route_auth::check(&req, &self.config)? {
+            self.metrics.record_error(&request_id, "unauthorized");
+            return Ok(rejection);
+        }
+        let mut request_ctx = RequestContext::from_request(&req, &request_id, &self.config)?;
+        request_ctx.reputation = reputation;
+        // Routes with their own backend pool are balanced across it
+        if !self.config.backend_pools.is_empty() {
+            self.load_balancer = LoadBalancer::for_path(&self.config, &request_ctx.path);
+        }
+        // Clients in a canary cohort go to its backends
+        if let Some((cohort, backends)) = canary::route(&req, &request_ctx, env, &self.config).await
+        {
+            self.load_balancer = LoadBalancer::for_backends(
+                &self.config,
+                &backends,
+                &self.config.load_balancer_strategy,
+            );
+            request_ctx.canary = Some(cohort);
+        }
+        self.load_balancer
+            .set_draining(drain::load(env, &self.config).await);
+        self.load_balancer
+            .set_maintenance(&maintenance::load(env, &self.config).await);
+        if let Some(tenant) = &request_ctx.tenant {
+            self.metrics.record_tenant(tenant);
+        }
+        request_ctx.labels = access_lists::labels(&req, env, &self.config).await;
+        for label in &request_ctx.labels {
+            self.metrics.record_label(label);
+        }
+        self.event.tenant = request_ctx.tenant.clone();
+        self.event.labels = request_ctx.labels.clone();
+        self.event.tags = tags::resolve(&self.config, &request_ctx);
+        for (tag, value) in &self.event.tags {
+            self.metrics.record_tag(tag, value);
+        }
 
         console_log!(
-            "Processing request: {} {}",
+            "Processing request {} (ray {}): {} {}",
+            request_id,
+            request_ctx.ray_id.as_deref().unwrap_or("-"),
             req.method().to_string(),
             req.url()?.path()
         );
 
+        // Reject malformed API payloads before they reach the backend
+        if let Some(rejection) = validation::validate_request(&req, &self.config).await? {
+            self.metrics.record_error(&request_id, "schema_validation");
+            return Ok(rejection);
+        }
+        // Reject bodies corrupted or truncated on the way from the client
+        if let Some(rejection) = content_digest::verify_request(&req, &self.config).await? {
+            self.metrics.record_error(&request_id, "content_digest");
+            return Ok(rejection);
+        }
+        // Mocked routes are answered by the proxy, e.g. before the backend exists
+        if let Some(mock) = mock::route_for(&self.config, &req.method(), &request_ctx.path) {
+            console_log!("Serving mock response for {}", request_id);
+            let mut response = mock::respond(mock, &req, &request_ctx)?;
+            self.add_cors_headers(&mut response, request_ctx.header("Origin").as_deref())?;
+            return Ok(response);
+        }
+        // Aggregation routes merge several backend responses instead of proxying one
+        if let Some(route) = aggregate::route_for(&self.config, &request_ctx.path) {
+            let colo = req.cf().map(|cf| cf.colo());
+            let backend = self
+                .load_balancer
+                .get_backend(&self.health_checker, colo.as_deref())
+                .await;
+            let mut response =
+                aggregate::aggregate(&req, route, backend.as_deref(), &request_ctx, &self.config)
+                    .await?;
+            self.add_cors_headers(&mut response, request_ctx.header("Origin").as_deref())?;
+            return Ok(response);
+        }
+        let method = req.method().to_string();
+
         // Check for URL path proxy pattern (e.g., /https://example.com/path)
-        let (target_url, is_url_proxy) = if let Some(url) =
+        let mut leader = None; // Flight this request fetches for waiting requests
+        let mut pin_backend = None; // Newly chosen backend to pin the client to
+        let (target_url, is_url_proxy, cache_key, stale) = if let Some(url) =
             self.extract_target_url_from_path(&req)?
         {
-            (url, true)
+            (url, true, None, None)
         } else {
-            // Check cache for normal proxy requests
-            if let Some(cached_response) = self.cache_manager.get_cached_response(&req, env).await?
+            // Check cache for normal proxy requests, under the policy of their route.
+            // Compressed entries go out as stored unless their body is rewritten or
+            // sliced into a byte range per hit.
+            let serve_encoded = request_ctx
+                .header("Accept-Encoding")
+                .is_some_and(|accept_encoding| cache::accepts_encoding(&accept_encoding, "gzip"))
+                && request_ctx.header("Range").is_none()
+                && !esi::is_esi_route(&self.config, &request_ctx.path)
+                && !watermark::is_watermarked(&self.config, &request_ctx.path);
+            let route_cache = self
+                .cache_manager
+                .for_route(&request_ctx.path)
+                .with_client_encoding(serve_encoded);
+            let private = route_cache.is_private();
+            let identity = if private {
+                cache::credential_identity(&req)?
+            } else {
+                None
+            };
+            let anonymous = identity.is_none();
+            self.cache_manager = route_cache.with_identity(identity);
+            // Canary responses never mix with the stable version's cache entries
+            let cache_key = if self.cache_manager.is_enabled()
+                && req.method() == Method::Get
+                && request_ctx.canary.is_none()
             {
-                self.metrics.record_cache_hit(&request_id);
-                return Ok(cached_response);
+                // Logged-in traffic never shares cached content, private routes
+                // cache it per credential and skip anonymous requests instead
+                let bypass = if private {
+                    anonymous
+                } else {
+                    self.cache_manager.bypasses_request(&req)?
+                };
+                if bypass {
+                    self.event.cache = Some("bypass");
+                    None
+                } else {
+                    Some(
+                        self.cache_manager
+                            .generate_cache_key(&req, request_ctx.tenant.as_deref())?,
+                    )
+                }
+            } else {
+                None
+            };
+
+            let mut stale = None;
+            if let Some(cache_key) = &cache_key {
+                let shared = match self.cache_manager.lookup(cache_key, env, ctx).await? {
+                    CacheLookup::Fresh(cached_response) => {
+                        self.event.cache = Some("hit");
+                        Some(cached_response)
+                    }
+                    CacheLookup::Stale(entry) => {
+                        stale = Some(entry);
+                        None
+                    }
+                    // The entry's backend is failing, keep serving it instead of retrying
+                    CacheLookup::Outage(stale_response) => {
+                        self.event.cache = Some("stale");
+                        Some(stale_response)
+                    }
+                    // Soft-purged: serve the stale copy, refresh it off the critical path
+                    CacheLookup::Purged(stale_response) => {
+                        self.refresh_in_background(&req, cache_key, &request_ctx, env, ctx)
+                            .await?;
+                        self.event.cache = Some("stale");
+                        Some(stale_response)
+                    }
+                    CacheLookup::Miss if self.cache_manager.is_bypassed() => {
+                        self.event.cache = Some("bypass");
+                        None
+                    }
+                    // Concurrent misses of a key share one backend request
+                    CacheLookup::Miss => match coalesce::join(cache_key, env, &self.config).await {
+                        Flight::Leader => {
+                            leader = Some(coalesce::LeaderGuard::new(cache_key, env, ctx));
+                            None
+                        }
+                        Flight::Follower(response) => {
+                            self.event.cache = Some("coalesced");
+                            Some(response)
+                        }
+                        Flight::Bypass => None,
+                    },
+                };
+
+                if let Some(cached_response) = shared {
+                    // Fragments need a backend, only pick one for ESI routes
+                    let backend = if esi::is_esi_route(&self.config, &request_ctx.path) {
+                        let colo = req.cf().map(|cf| cf.colo());
+                        self.load_balancer
+                            .get_backend(&self.health_checker, colo.as_deref())
+                            .await
+                    } else {
+                        None
+                    };
+                    return self
+                        .serve_cached(cached_response, backend.as_deref(), env, ctx, &request_ctx)
+                        .await;
+                }
+                self.event.cache.get_or_insert("miss");
             }
 
+            // Body routing rules take precedence over load balancing
+            let body_route = routing::select_body_route(&req, &self.config).await?;
+
             // Get healthy backend for load-balanced proxy
-            let backend = match self.load_balancer.get_backend(&self.health_checker).await {
-                Some(backend) => backend,
-                None => {
-                    self.metrics.record_error(&request_id, "no_healthy_backend");
-                    return Response::error("No healthy backends available", 503);
+            let colo = req.cf().map(|cf| cf.colo());
+            let hash_key = load_balancer::hash_key(
+                &req,
+                request_ctx.client_ip.as_deref(),
+                &self.config.load_balancer_hash_key,
+            )?;
+            // Clients pinned by an affinity cookie stay on their backend while it
+            // is healthy and in the active pool
+            let pinned = match affinity::pinned_backend(
+                &req,
+                &self.load_balancer.sticky_backends(),
+                &self.config,
+            )? {
+                Some(backend)
+                    if self
+                        .load_balancer
+                        .is_active(&backend, &self.health_checker)
+                        .await =>
+                {
+                    Some(backend)
                 }
+                _ => None,
+            };
+            let backend = match body_route.or(pinned) {
+                Some(backend) => backend,
+                None => match self
+                    .load_balancer
+                    .acquire_backend(
+                        &self.health_checker,
+                        colo.as_deref(),
+                        hash_key.as_deref(),
+                        &self.config,
+                        env,
+                    )
+                    .await
+                {
+                    Some((backend, lease)) => {
+                        self.lease = lease;
+                        if affinity::is_enabled(&self.config) {
+                            pin_backend = Some(backend.clone());
+                        }
+                        backend
+                    }
+                    None => {
+                        self.metrics.record_error(&request_id, "no_healthy_backend");
+                        if let Some(response) = self
+                            .stale_if_error(cache_key.as_deref(), env, ctx, &request_ctx)
+                            .await?
+                        {
+                            return Ok(response);
+                        }
+                        let backends = self.load_balancer.get_all_backends();
+                        if !backends.is_empty() {
+                            ctx.wait_until(alerts::send(
+                                Alert::AllBackendsDown,
+                                format!(
+                                    "No healthy backend is available for {} configured backends.",
+                                    backends.len()
+                                ),
+                                self.config.clone(),
+                                env.clone(),
+                            ));
+                        }
+                        return Response::error("No healthy backends available", 503);
+                    }
+                },
             };
 
             // Build target URL using configured backend
-            (self.build_target_url(&req, &backend)?, false)
+            (
+                self.build_target_url(&req, &backend)?,
+                false,
+                cache_key,
+                stale,
+            )
         };
 
         console_log!(
@@ -106,24 +459,268 @@ impl ReverseProxy {
             is_url_proxy
         );
 
+        // URL proxy targets are user-supplied, never fetch internal addresses
+        if is_url_proxy && !utils::is_public_url(&target_url) {
+            self.metrics.record_error(&request_id, "target_not_allowed");
+            return Response::error("Target not allowed", 403);
+        }
+
         // Create proxy request
-        let proxy_req = self.create_proxy_request(req, &target_url).await?;
+        let proxy_req = self
+            .create_proxy_request(req, &target_url, &request_ctx, stale.as_ref(), is_url_proxy)
+            .await?;
+        let redirect_headers = if is_url_proxy {
+            Some(proxy_req.headers().clone())
+        } else {
+            None
+        };
+
+        // Sample load-balanced requests for the shadow backend
+        let mirror_source =
+            if !is_url_proxy && mirror::should_mirror(&self.config, &proxy_req.method()) {
+                Some(proxy_req.clone()?)
+            } else {
+                None
+            };
+
+        // Keep an unsigned copy to retry on other backends
+        let failover_source = if !is_url_proxy && self.is_retryable(&proxy_req.method()) {
+            Some(proxy_req.clone()?)
+        } else {
+            None
+        };
+
+        // Keep an unsigned copy to hedge slow requests on a second backend
+        let hedge_source =
+            if !is_url_proxy && self.is_hedged(&proxy_req.method(), &request_ctx.path) {
+                Some(proxy_req.clone()?)
+            } else {
+                None
+            };
 
         // Send request to backend
-        let response = match Fetch::Request(proxy_req).send().await {
+        let backend_base = target_url.split('/').take(3).collect::<Vec<_>>().join("/");
+        self.event.backend = Some(backend_base.clone());
+
+        // Backends behind OAuth2 get a client-credentials token instead of the client's credentials
+        if !is_url_proxy {
+            if let Err(e) =
+                oauth2::authorize(proxy_req.headers(), &backend_base, &self.config, env).await
+            {
+                self.metrics.record_error(&request_id, "backend_auth");
+                console_log!("OAuth2 token for {} unavailable: {:?}", backend_base, e);
+                return Response::error("Backend authentication failed", 502);
+            }
+            if let Err(e) = origin_signing::sign(&proxy_req, &self.config).await {
+                self.metrics.record_error(&request_id, "backend_auth");
+                console_log!("Origin signing for {} failed: {:?}", request_id, e);
+                return Response::error("Backend authentication failed", 502);
+            }
+            // AWS service routes are signed last, after every header change
+            if let Err(e) = sigv4::sign(&proxy_req, &request_ctx.path, &self.config, env).await {
+                self.metrics.record_error(&request_id, "backend_auth");
+                console_log!("SigV4 signing for {} failed: {:?}", request_ctx.path, e);
+                return Response::error("Backend authentication failed", 502);
+            }
+        }
+
+        // Bound concurrent requests per backend, queueing short bursts before shedding
+        let _permit = if is_url_proxy {
+            None
+        } else {
+            match backpressure::acquire(&backend_base, &self.config).await {
+                Some(permit) => Some(permit),
+                None => {
+                    self.metrics.record_error(&request_id, "backend_saturated");
+                    let response = Response::error("Backend saturated, retry later", 503)?;
+                    response.headers().set("Retry-After", "1")?;
+                    return Ok(response);
+                }
+            }
+        };
+        let timeout_ms = latency::timeout_for(&backend_base, &self.config);
+        let mut fetch_start = js_sys::Date::now();
+        // Revalidations keep to the representation the cache holds
+        let variant = if !is_url_proxy && stale.is_none() {
+            self.fetch_precompressed(&proxy_req, &target_url, &request_ctx, timeout_ms, env)
+                .await?
+        } else {
+            None
+        };
+        let precompressed = variant.is_some();
+        let (fetched, target_url) = match (variant, hedge_source) {
+            (Some(response), _) => (Ok(response), target_url),
+            (None, _) if is_url_proxy => (
+                latency::fetch_with_timeout(proxy_req, timeout_ms).await,
+                target_url,
+            ),
+            (None, Some(source)) => {
+                self.fetch_hedged(
+                    proxy_req,
+                    source,
+                    target_url,
+                    &request_ctx.path,
+                    timeout_ms,
+                    env,
+                )
+                .await
+            }
+            (None, None) => (
+                self.fetch_backend(proxy_req, &target_url, &request_ctx.path, timeout_ms, env)
+                    .await,
+                target_url,
+            ),
+        };
+        let (fetched, target_url) = match failover_source {
+            Some(source) => {
+                self.fail_over(
+                    fetched,
+                    source,
+                    target_url,
+                    &request_ctx.path,
+                    &mut fetch_start,
+                    env,
+                )
+                .await?
+            }
+            None => (fetched, target_url),
+        };
+        // Hedged and failed over requests pin new clients to the backend that answered
+        let answered_base = target_url.split('/').take(3).collect::<Vec<_>>().join("/");
+        let backend_base = if answered_base != backend_base {
+            if let (Some(pin), Some(backend)) = (
+                pin_backend.as_mut(),
+                self.load_balancer.backend_of(&target_url),
+            ) {
+                *pin = backend.clone();
+            }
+            self.event.backend = Some(answered_base.clone());
+            answered_base
+        } else {
+            backend_base
+        };
+        let response = match fetched {
             Ok(response) => response,
             Err(e) => {
                 self.metrics.record_error(&request_id, "backend_error");
                 // Only mark backend unhealthy for load-balanced requests
                 if !is_url_proxy {
-                    // Extract backend URL for health check marking
-                    let backend_base = target_url.split('/').take(3).collect::<Vec<_>>().join("/");
                     self.health_checker.mark_unhealthy(&backend_base).await;
+                    ewma::record(&backend_base, js_sys::Date::now() - fetch_start, true);
                 }
                 console_log!("Backend error for {}: {:?}", request_id, e);
+                if let Some(response) = self
+                    .stale_if_error(cache_key.as_deref(), env, ctx, &request_ctx)
+                    .await?
+                {
+                    return Ok(response);
+                }
                 return Response::error("Backend unavailable", 502);
             }
         };
+        if !is_url_proxy {
+            let elapsed = js_sys::Date::now() - fetch_start;
+            latency::record_latency(&backend_base, elapsed);
+            ewma::record(&backend_base, elapsed, response.status_code() >= 500);
+            // Server errors count as failures, e.g. for the outage TTL of cached entries
+            if response.status_code() >= 500 {
+                self.health_checker.mark_unhealthy(&backend_base).await;
+            } else {
+                self.health_checker.mark_healthy(&backend_base).await;
+            }
+            // A rejected token may have been revoked early, fetch a new one next time
+            if response.status_code() == 401 {
+                oauth2::invalidate(&backend_base, &self.config, env).await;
+            }
+        }
+
+        // Prefer the expired cached copy over the backend's server error
+        if response.status_code() >= 500 {
+            if let Some(stale_response) = self
+                .stale_if_error(cache_key.as_deref(), env, ctx, &request_ctx)
+                .await?
+            {
+                return Ok(stale_response);
+            }
+        }
+
+        // Follow URL proxy redirects server-side, for clients that cannot follow
+        // cross-origin redirects themselves
+        let (mut response, target_url) = match redirect_headers {
+            Some(headers) if self.is_redirect_response(&response) => {
+                self.follow_redirects(response, target_url, headers, &method, timeout_ms)
+                    .await?
+            }
+            _ => (response, target_url),
+        };
+
+        // Origin confirmed the expired entry is unchanged, refresh it without re-downloading
+        if let (Some(mut entry), Some(cache_key)) = (stale, &cache_key) {
+            if response.status_code() == 304 {
+                entry.update(response.headers());
+                let cached_response = entry.to_response()?;
+                let cache_manager = self.cache_manager.clone();
+                let cache_key = cache_key.clone();
+                let env_clone = env.clone();
+                ctx.wait_until(async move {
+                    if let Err(e) = cache_manager
+                        .store_revalidated(&cache_key, entry, &env_clone)
+                        .await
+                    {
+                        console_log!("Failed to refresh cache entry {}: {:?}", cache_key, e);
+                    }
+                });
+                self.event.cache = Some("revalidated");
+                return self
+                    .serve_cached(cached_response, Some(&backend_base), env, ctx, &request_ctx)
+                    .await;
+            }
+        }
+
+        // The client's own validators matched, or a pre-compressed sibling goes out as
+        // stored: pass it through without body processing or caching. A leader's
+        // flight completes without a shared response as it drops.
+        if response.status_code() == 304 || precompressed {
+            let mut response = apply_response_middleware(response, &self.config, &request_ctx)?;
+            self.add_cors_headers(&mut response, request_ctx.header("Origin").as_deref())?;
+            self.metrics
+                .record_request_complete(&request_id, response.status_code());
+            return Ok(response);
+        }
+
+        if let Some(source) = mirror_source {
+            if let Some(shadow_url) = mirror::shadow_url(&self.config, &target_url) {
+                // Shadow mode discards the copy's response, nothing to compare it with
+                if self.config.mirror_mode == "shadow" {
+                    ctx.wait_until(mirror::shadow(
+                        source,
+                        request_id.clone(),
+                        shadow_url,
+                        self.config.clone(),
+                    ));
+                } else {
+                    let job = mirror::MirrorJob {
+                        request_id: request_id.clone(),
+                        path: request_ctx.path.clone(),
+                        source,
+                        shadow_url,
+                        primary: response.cloned()?,
+                    };
+                    ctx.wait_until(mirror::compare(job, env.clone(), self.config.clone()));
+                }
+            }
+        }
+
+        // Verify live responses against route contracts off the critical path
+        if contracts::find_check(&self.config, &request_ctx.path, &method).is_some() {
+            let job = contracts::ContractJob {
+                request_id: request_id.clone(),
+                method: method.clone(),
+                path: request_ctx.path.clone(),
+                response: response.cloned()?,
+            };
+            ctx.wait_until(contracts::verify(job, self.config.clone()));
+        }
 
         // Handle redirects for URL proxy mode
         let processed_response = if is_url_proxy && self.is_redirect_response(&response) {
@@ -132,27 +729,274 @@ impl ReverseProxy {
             response
         };
 
+        // Detect "not found" pages served with 200 so they can be reported and fixed
+        let (processed_response, is_soft_404) =
+            soft_404::detect_soft_404(processed_response, &self.config, &request_ctx).await?;
+        if is_soft_404 {
+            self.metrics.record_soft_404(&request_id, &request_ctx.path);
+        }
+
+        // Operator-defined content surgery on HTML responses
+        let processed_response =
+            html_rewrite::apply_html_rewrites(processed_response, &self.config, &request_ctx)?;
+
+        // Make proxied sessions usable when the URL proxy is embedded in iframes
+        if is_url_proxy && self.config.url_proxy_partitioned_cookies {
+            partition_cookies(&processed_response)?;
+        }
+
         // Record backend response time
         let response_time = js_sys::Date::now() - start_time;
         self.metrics
             .record_response_time(&request_id, response_time);
 
         // Apply response middleware and add CORS headers
-        let mut final_response = apply_response_middleware(processed_response, &self.config)?;
-        self.add_cors_headers(&mut final_response)?;
+        let final_response =
+            apply_response_middleware(processed_response, &self.config, &request_ctx)?;
+        let mut final_response =
+            validation::validate_response(final_response, &self.config, &request_ctx, &method)
+                .await?;
+
+        // Cache the shared representation, before per-client watermarks and CORS
+        if let Some(cache_key) = cache_key {
+            // Checked before cloning, so oversized bodies stream straight through
+            let cacheable = self.should_cache_response(&final_response)
+                && self.cache_manager.is_cacheable(&final_response);
+            if let Some(leader) = leader.take() {
+                // Only share what the cache would share, never per-client responses
+                let shared = if cacheable {
+                    Some(final_response.cloned()?)
+                } else {
+                    None
+                };
+                leader.complete(shared);
+            }
+            if cacheable {
+                let cached = final_response.cloned()?;
+                let cache_manager = self.cache_manager.clone().with_origin(&backend_base);
+                let env = env.clone();
+                ctx.wait_until(async move {
+                    if let Err(e) = cache_manager.cache_response(&cache_key, cached, &env).await {
+                        console_log!("Failed to cache response {}: {:?}", cache_key, e);
+                    }
+                });
+            }
+        }
+
+        // Assemble ESI fragments after caching so the page and fragments keep separate TTLs
+        let final_response = if !is_url_proxy && esi::is_esi_route(&self.config, &request_ctx.path)
+        {
+            esi::assemble(
+                final_response,
+                &backend_base,
+                &self.cache_manager,
+                env,
+                ctx,
+                &request_ctx,
+                &self.config,
+            )
+            .await?
+        } else {
+            final_response
+        };
+
+        let mut final_response =
+            watermark::apply_watermark(final_response, &self.config, &request_ctx).await?;
+        self.add_cors_headers(&mut final_response, request_ctx.header("Origin").as_deref())?;
+        // Set after caching, responses with cookies are never shared
+        if let Some(backend) = pin_backend {
+            final_response
+                .headers_mut()
+                .append("Set-Cookie", &affinity::cookie(&backend, &self.config))?;
+        }
+        self.check_latency_budget(&final_response, &request_ctx, start_time)?;
 
         // Record request completion
         self.metrics
             .record_request_complete(&request_id, final_response.status_code());
 
-        // Cache response (if applicable)
-        if self.should_cache_response(&final_response) {
-            // Note: Caching consumes response, so we need to clone or redesign
-            // Simplified handling here, can be improved in production
-            console_log!("Response should be cached");
+        Ok(final_response)
+    }
+
+    /// Apply per-request processing to a response served from cache
+    async fn serve_cached(
+        &self,
+        response: Response,
+        backend: Option<&str>,
+        env: &Env,
+        ctx: &Context,
+        request_ctx: &RequestContext,
+    ) -> Result<Response> {
+        // Answer the client's conditional request from the cached copy
+        if let Some(mut not_modified) = cache::not_modified(&response, request_ctx)? {
+            self.add_cors_headers(&mut not_modified, request_ctx.header("Origin").as_deref())?;
+            return Ok(not_modified);
         }
 
-        Ok(final_response)
+        // Fragments keep their own TTLs, assemble them on every hit
+        let response = match backend {
+            Some(backend) if esi::is_esi_route(&self.config, &request_ctx.path) => {
+                esi::assemble(
+                    response,
+                    backend,
+                    &self.cache_manager,
+                    env,
+                    ctx,
+                    request_ctx,
+                    &self.config,
+                )
+                .await?
+            }
+            _ => response,
+        };
+
+        // Watermarks and CORS are per client, apply them on every hit
+        let response = watermark::apply_watermark(response, &self.config, request_ctx).await?;
+        // Byte ranges are cut from the complete cached body, never fetched from the origin
+        let mut response = cache::byte_range(response, request_ctx).await?;
+        self.add_cors_headers(&mut response, request_ctx.header("Origin").as_deref())?;
+        // Cached headers never keep an encoding the body was decoded from
+        self.sent_as_stored
+            .set(response.headers().has("Content-Encoding")?);
+        Ok(response)
+    }
+
+    /// Serve the expired cache entry of a request whose backend failed, while
+    /// it is within `CACHE_STALE_IF_ERROR`
+    async fn stale_if_error(
+        &mut self,
+        cache_key: Option<&str>,
+        env: &Env,
+        ctx: &Context,
+        request_ctx: &RequestContext,
+    ) -> Result<Option<Response>> {
+        let cache_key = match cache_key {
+            Some(cache_key) => cache_key,
+            None => return Ok(None),
+        };
+
+        match self.cache_manager.lookup_stale(cache_key, env).await? {
+            Some(response) => {
+                console_log!("Backend failed, serving stale entry for key: {}", cache_key);
+                self.event.cache = Some("stale");
+                self.serve_cached(response, None, env, ctx, request_ctx)
+                    .await
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch a fresh copy of a soft-purged entry after its stale copy is served
+    async fn refresh_in_background(
+        &self,
+        req: &Request,
+        cache_key: &str,
+        request_ctx: &RequestContext,
+        env: &Env,
+        ctx: &Context,
+    ) -> Result<()> {
+        let colo = req.cf().map(|cf| cf.colo());
+        let backend = match self
+            .load_balancer
+            .get_backend(&self.health_checker, colo.as_deref())
+            .await
+        {
+            Some(backend) => backend,
+            None => return Ok(()),
+        };
+
+        let target_url = self.build_target_url(req, &backend)?;
+        let proxy_req = self
+            .create_proxy_request(req.clone()?, &target_url, request_ctx, None, false)
+            .await?;
+        let backend_base = target_url.split('/').take(3).collect::<Vec<_>>().join("/");
+        oauth2::authorize(proxy_req.headers(), &backend_base, &self.config, env).await?;
+        origin_signing::sign(&proxy_req, &self.config).await?;
+        sigv4::sign(&proxy_req, &request_ctx.path, &self.config, env).await?;
+        let timeout_ms = latency::timeout_for(&backend, &self.config);
+        let cache_manager = self.cache_manager.clone().with_origin(&backend_base);
+        let cache_key = cache_key.to_string();
+        let request_ctx = request_ctx.clone();
+        let env = env.clone();
+        ctx.wait_until(async move {
+            cache_manager
+                .refresh(cache_key, proxy_req, timeout_ms, request_ctx, env)
+                .await
+        });
+        Ok(())
+    }
+
+    /// Report the cache outcome to the client (`X-Cache`, `Age`) and in metrics.
+    /// Requests that did not use the cache count as bypasses.
+    fn record_cache_status(&mut self, response: &Response) -> Result<()> {
+        let status = *self.event.cache.get_or_insert("bypass");
+        let request_id = self.event.request_id.clone();
+        let x_cache = match status {
+            // Revalidation is only told apart in the tail event
+            "hit" | "coalesced" | "revalidated" => {
+                self.metrics.record_cache_hit(&request_id);
+                "HIT"
+            }
+            "stale" => {
+                self.metrics.record_cache_hit(&request_id);
+                "STALE"
+            }
+            "miss" => {
+                self.metrics.record_cache_miss(&request_id);
+                "MISS"
+            }
+            _ => {
+                self.metrics.record_cache_bypass(&request_id);
+                "BYPASS"
+            }
+        };
+
+        let headers = response.headers();
+        headers.set("X-Cache", x_cache)?;
+        // Cached responses carry their age, fresh backend responses are new
+        if !headers.has("Age")? {
+            headers.set("Age", "0")?;
+        }
+        Ok(())
+    }
+
+    /// Record and optionally flag requests exceeding their route latency budget
+    fn check_latency_budget(
+        &mut self,
+        response: &Response,
+        ctx: &RequestContext,
+        start_time: f64,
+    ) -> Result<()> {
+        let budget = match self
+            .config
+            .latency_budgets
+            .iter()
+            .find(|budget| utils::path_matches(&budget.path, &ctx.path))
+        {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+
+        let elapsed = js_sys::Date::now() - start_time;
+        if elapsed <= budget.max_ms as f64 {
+            return Ok(());
+        }
+
+        self.metrics.record_latency_budget_violation(
+            &ctx.request_id,
+            &budget.path,
+            elapsed,
+            budget.max_ms,
+        );
+        if self.config.latency_budget_header {
+            response.headers().set(
+                "X-Latency-Budget-Exceeded",
+                &format!("{elapsed:.0}ms/{}ms", budget.max_ms),
+            )?;
+        }
+
+        Ok(())
     }
 
     /// Build target URL
@@ -186,8 +1030,16 @@ impl ReverseProxy {
     }
 
     /// Create proxy request
-    async fn create_proxy_request(&self, mut req: Request, target_url: &str) -> Result<Request> {
+    async fn create_proxy_request(
+        &self,
+        mut req: Request,
+        target_url: &str,
+        request_ctx: &RequestContext,
+        revalidate: Option<&StaleEntry>,
+        manual_redirects: bool,
+    ) -> Result<Request> {
         let headers = req.headers().clone();
+        normalization::strip_unkeyed(&headers, &self.config, &request_ctx.path)?;
 
         // Add proxy-related headers
         if let Some(cf_ip) = headers.get("CF-Connecting-IP")? {
@@ -210,31 +1062,357 @@ impl ReverseProxy {
         headers.delete("Host")?;
         headers.delete("Origin")?;
 
+        // Strict mode: forward only allowlisted client headers. Validators are
+        // always kept, requests only get here without a fresh cached copy.
+        let headers = if self.config.request_header_matcher.is_empty() {
+            headers
+        } else {
+            let allowed = retain_allowed_headers(&headers, &self.config.request_header_matcher)?;
+            for name in ["If-None-Match", "If-Modified-Since"] {
+                if let Some(value) = headers.get(name)? {
+                    allowed.set(name, &value)?;
+                }
+            }
+            allowed
+        };
+        client_hints::forward(&req, &headers, &self.config)?;
+
+        // Propagate resolved tenant, never trusting a client-supplied value
+        if self.config.tenant_source != "none" {
+            headers.delete("X-Tenant-Id")?;
+            if let Some(tenant) = &request_ctx.tenant {
+                headers.set("X-Tenant-Id", tenant)?;
+            }
+        }
+
+        // Revalidate an expired cache entry with its stored validators
+        if let Some(entry) = revalidate {
+            headers.delete("If-None-Match")?;
+            headers.delete("If-Modified-Since")?;
+            if let Some(etag) = entry.etag() {
+                headers.set("If-None-Match", etag)?;
+            }
+            if let Some(last_modified) = entry.last_modified() {
+                headers.set("If-Modified-Since", last_modified)?;
+            }
+        }
+
+        // Forward access labels, never trusting a client-supplied value
+        headers.delete("X-Proxy-Labels")?;
+        if !request_ctx.labels.is_empty() {
+            headers.set("X-Proxy-Labels", &request_ctx.labels.join(","))?;
+        }
+        // Correlate origin logs with the proxy's and Cloudflare's
+        headers.set("X-Request-ID", &request_ctx.request_id)?;
+        headers.delete("X-Ray-ID")?;
+        if let Some(ray_id) = &request_ctx.ray_id {
+            headers.set("X-Ray-ID", ray_id)?;
+        }
+        headers.delete("X-Canary-Cohort")?;
+        if let Some(cohort) = &request_ctx.canary {
+            headers.set("X-Canary-Cohort", cohort)?;
+        }
+        headers.delete("X-Client-Reputation")?;
+        if let Some(score) = request_ctx.reputation {
+            headers.set("X-Client-Reputation", &score.to_string())?;
+        }
+
+        // Identify this hop for the backend and for loop detection
+        let id = &self.config.proxy_id;
+        let via = match headers.get("Via")? {
+            Some(via) => format!("{via}, 1.1 {id}"),
+            None => format!("1.1 {id}"),
+        };
+        headers.set("Via", &via)?;
+        let hops = match headers.get("X-Proxy-Loop")? {
+            Some(hops) => format!("{hops}, {id}"),
+            None => id.clone(),
+        };
+        headers.set("X-Proxy-Loop", &hops)?;
+
         // Apply custom headers
         for (key, value) in &self.config.custom_headers {
             headers.set(key, value)?;
         }
 
+        // Copy request body if present
+        let body = if req.method() == Method::Get || req.method() == Method::Head {
+            None
+        } else if self.config.content_sha256_enabled {
+            // Let backends detect bodies corrupted between the proxy and them
+            let (digest, body) = content_digest::stream_sha256(&mut req, true).await?;
+            headers.set(content_digest::CONTENT_SHA256_HEADER, &digest)?;
+            Some(body)
+        } else {
+            Some(req.bytes().await?)
+        };
+
         let mut init = RequestInit::new();
         init.with_method(req.method()).with_headers(headers);
-
-        // Copy request body if present
-        if req.method() != Method::Get && req.method() != Method::Head {
-            let body_bytes = req.bytes().await?;
-            init.with_body(Some(body_bytes.into()));
+        if manual_redirects {
+            init.with_redirect(RequestRedirect::Manual);
+        }
+        if let Some(body) = body {
+            init.with_body(Some(body.into()));
         }
 
         Request::new_with_init(target_url, &init)
     }
 
+    /// Send a backend request, trying the backend's `alternatives` in order
+    /// while its origin is unreachable (connection errors, or Cloudflare's
+    /// `521`-`523`, `525`, `526` and `530`). Timeouts are not retried, the origin
+    /// may have received the request.
+    async fn fetch_backend(
+        &self,
+        proxy_req: Request,
+        target_url: &str,
+        path: &str,
+        timeout_ms: u64,
+        env: &Env,
+    ) -> Result<Response> {
+        let backend = self.config.backend_configs.iter().find(|backend| {
+            !backend.alternatives.is_empty()
+                && target_url.starts_with(backend.url.trim_end_matches('/'))
+        });
+        let backend = match backend {
+            Some(backend) => backend,
+            None => return latency::fetch_with_timeout(proxy_req, timeout_ms).await,
+        };
+        let suffix = &target_url[backend.url.trim_end_matches('/').len()..];
+
+        let mut alternatives = backend.alternatives.iter();
+        let mut request = proxy_req;
+        loop {
+            let retry = request.clone()?;
+            let result = latency::fetch_with_timeout(request, timeout_ms).await;
+            let unreachable = match &result {
+                Ok(response) => UNREACHABLE_STATUSES.contains(&response.status_code()),
+                Err(e) => !latency::is_timeout(e),
+            };
+            let alternative = match alternatives.next() {
+                Some(alternative) if unreachable => alternative,
+                _ => return result,
+            };
+
+            let url = format!("{}{suffix}", alternative.trim_end_matches('/'));
+            console_log!("Origin of {} unreachable, trying {}", target_url, url);
+            request = retarget(retry, &url).await?;
+            // Alternatives have their own host, which AWS signatures cover
+            sigv4::sign(&request, path, &self.config, env).await?;
+        }
+    }
+
+    /// Fetch the pre-compressed sibling (`.br`, `.gz`) of a static asset in the
+    /// best encoding the client accepts. `None` when there is none, leaving
+    /// the original to be fetched.
+    async fn fetch_precompressed(
+        &self,
+        proxy_req: &Request,
+        target_url: &str,
+        request_ctx: &RequestContext,
+        timeout_ms: u64,
+        env: &Env,
+    ) -> Result<Option<Response>> {
+        for (encoding, suffix) in
+            precompressed::candidates(&proxy_req.method(), &self.config, request_ctx)
+        {
+            let url = precompressed::variant_url(target_url, suffix)?;
+            let request = retarget(proxy_req.clone()?, &url).await?;
+            let backend_base = url.split('/').take(3).collect::<Vec<_>>().join("/");
+            authorize_backend(
+                &request,
+                &backend_base,
+                &request_ctx.path,
+                &self.config,
+                env,
+            )
+            .await?;
+            match latency::fetch_with_timeout(request, timeout_ms).await {
+                Ok(response) if matches!(response.status_code(), 200 | 304) => {
+                    self.sent_as_stored.set(true);
+                    return precompressed::serve(response, encoding, &request_ctx.path).map(Some);
+                }
+                Ok(_) => {}
+                // The original request reports backend failures
+                Err(e) => {
+                    console_log!("Pre-compressed variant {} failed: {:?}", url, e);
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether a request is hedged: `GET` and `HEAD` requests under
+    /// `HEDGE_ROUTES` (all routes when empty) once `HEDGE_DELAY_MS` is set
+    fn is_hedged(&self, method: &Method, path: &str) -> bool {
+        self.config.hedge_delay_ms > 0
+            && matches!(method, Method::Get | Method::Head)
+            && (self.config.hedge_routes.is_empty()
+                || self
+                    .config
+                    .hedge_routes
+                    .iter()
+                    .any(|pattern| utils::path_matches(pattern, path)))
+    }
+
+    /// Send a request, hedging it on a second healthy backend when it has not
+    /// answered within `HEDGE_DELAY_MS`. Returns the result and its URL.
+    async fn fetch_hedged(
+        &mut self,
+        proxy_req: Request,
+        source: Request,
+        target_url: String,
+        path: &str,
+        timeout_ms: u64,
+        env: &Env,
+    ) -> (Result<Response>, String) {
+        let mut hedge_url = None;
+        let hedge = async {
+            // Body routes may target backends outside the pool, those are not hedged
+            let primary = self.load_balancer.backend_of(&target_url)?.clone();
+            let backend = self
+                .load_balancer
+                .failover_backend(&self.health_checker, std::slice::from_ref(&primary))
+                .await?;
+            let suffix = &target_url[primary.trim_end_matches('/').len()..];
+            let url = format!("{}{suffix}", backend.trim_end_matches('/'));
+            let backend_base = url.split('/').take(3).collect::<Vec<_>>().join("/");
+            let request = retarget(source, &url).await.ok()?;
+            if let Err(e) =
+                authorize_backend(&request, &backend_base, path, &self.config, env).await
+            {
+                console_log!(
+                    "Backend authentication for {} failed: {:?}",
+                    backend_base,
+                    e
+                );
+                return None;
+            }
+            console_log!(
+                "No response from {} within {}ms, hedging on {}",
+                target_url,
+                self.config.hedge_delay_ms,
+                backend_base
+            );
+            let timeout_ms = latency::timeout_for(&backend_base, &self.config);
+            hedge_url = Some(url);
+            Some((request, timeout_ms))
+        };
+        let (result, hedged) =
+            latency::fetch_hedged(proxy_req, timeout_ms, self.config.hedge_delay_ms, hedge).await;
+
+        match hedge_url.filter(|_| hedged) {
+            Some(url) => {
+                self.event.hedged = true;
+                (result, url)
+            }
+            None => (result, target_url),
+        }
+    }
+
+    /// Whether a failed request may be retried on another backend: idempotent
+    /// methods only, unless `RETRY_NON_IDEMPOTENT` is set
+    fn is_retryable(&self, method: &Method) -> bool {
+        self.config.retry_attempts > 0
+            && (self.config.retry_non_idempotent
+                || matches!(
+                    method,
+                    Method::Get
+                        | Method::Head
+                        | Method::Options
+                        | Method::Put
+                        | Method::Delete
+                        | Method::Trace
+                ))
+    }
+
+    /// Retry a request that failed or got a `RETRY_STATUSES` response on
+    /// other healthy backends, up to `RETRY_ATTEMPTS` times with the backoff
+    /// doubling from `RETRY_BACKOFF_MS`. Returns the last result and its URL.
+    async fn fail_over(
+        &mut self,
+        mut fetched: Result<Response>,
+        source: Request,
+        mut target_url: String,
+        path: &str,
+        fetch_start: &mut f64,
+        env: &Env,
+    ) -> Result<(Result<Response>, String)> {
+        let mut tried = Vec::new();
+        for attempt in 0..self.config.retry_attempts {
+            let reason = match &fetched {
+                Ok(response) if self.config.retry_statuses.contains(&response.status_code()) => {
+                    response.status_code().to_string()
+                }
+                Ok(_) => break,
+                Err(e) => e.to_string(),
+            };
+            // Body routes may target backends outside the pool, those stay put
+            let failed = match self.load_balancer.backend_of(&target_url) {
+                Some(backend) => backend.clone(),
+                None => break,
+            };
+            let failed_base = target_url.split('/').take(3).collect::<Vec<_>>().join("/");
+            self.health_checker.mark_unhealthy(&failed_base).await;
+            ewma::record(&failed_base, js_sys::Date::now() - *fetch_start, true);
+            let suffix = target_url[failed.trim_end_matches('/').len()..].to_string();
+            tried.push(failed);
+
+            let backend = match self
+                .load_balancer
+                .failover_backend(&self.health_checker, &tried)
+                .await
+            {
+                Some(backend) => backend,
+                None => break,
+            };
+            let url = format!("{}{suffix}", backend.trim_end_matches('/'));
+            let backend_base = url.split('/').take(3).collect::<Vec<_>>().join("/");
+            console_log!(
+                "Backend {} failed ({}), retrying on {}",
+                failed_base,
+                reason,
+                backend_base
+            );
+            let backoff = self.config.retry_backoff_ms * 2u64.pow(attempt.min(10));
+            Delay::from(Duration::from_millis(backoff)).await;
+
+            let request = retarget(source.clone()?, &url).await?;
+            if let Err(e) =
+                authorize_backend(&request, &backend_base, path, &self.config, env).await
+            {
+                console_log!(
+                    "Backend authentication for {} failed: {:?}",
+                    backend_base,
+                    e
+                );
+                break;
+            }
+            // A saturated backend is no better than the failed one
+            let _permit = match backpressure::acquire(&backend_base, &self.config).await {
+                Some(permit) => permit,
+                None => break,
+            };
+            let timeout_ms = latency::timeout_for(&backend_base, &self.config);
+            *fetch_start = js_sys::Date::now();
+            fetched = self
+                .fetch_backend(request, &url, path, timeout_ms, env)
+                .await;
+            target_url = url;
+            self.event.retries += 1;
+        }
+        Ok((fetched, target_url))
+    }
+
     /// Determine if response should be cached
     fn should_cache_response(&self, response: &Response) -> bool {
-        if !self.config.cache_enabled {
+        if !self.cache_manager.is_enabled() {
             return false;
         }
 
-        let status = response.status_code();
-        if !(200..300).contains(&status) {
+        if !cache::is_cacheable_status(response.status_code()) {
             return false;
         }
 
@@ -264,12 +1442,6 @@ impl ReverseProxy {
         Response::from_json(&health_status)
     }
 
-    /// Get proxy statistics
-    pub async fn get_stats(&self) -> Result<Response> {
-        let stats = self.metrics.get_stats().await;
-        Response::from_json(&stats)
-    }
-
     /// Extract target URL from path (e.g., /https://example.com/path)
     fn extract_target_url_from_path(&self, req: &Request) -> Result<Option<String>> {
         let url = req.url()?;
@@ -306,6 +1478,79 @@ impl ReverseProxy {
         (300..400).contains(&status)
     }
 
+    /// Follow redirects up to the configured hop limit, checking every target.
+    /// Returns the final response and URL; redirects that cannot be followed
+    /// (hop limit reached, or 307/308 of a request with a body) are returned as-is.
+    async fn follow_redirects(
+        &self,
+        mut response: Response,
+        mut target_url: String,
+        headers: Headers,
+        method: &str,
+        timeout_ms: u64,
+    ) -> Result<(Response, String)> {
+        let mut method = Method::from(method.to_string());
+        for _ in 0..self.config.url_proxy_max_redirects {
+            let status = response.status_code();
+            let location = match response.headers().get("Location")? {
+                Some(location) if self.is_redirect_response(&response) => location,
+                _ => break,
+            };
+
+            // 303 continues with GET, as do 301/302 for POST like browsers.
+            // Other redirects of requests with a body would need it again.
+            if (status == 303 && method != Method::Head)
+                || (matches!(status, 301 | 302) && method == Method::Post)
+            {
+                method = Method::Get;
+            }
+            if method != Method::Get && method != Method::Head {
+                break;
+            }
+
+            let next = match Url::parse(&target_url).and_then(|base| base.join(&location)) {
+                Ok(next) => next,
+                Err(_) => break,
+            };
+            if !utils::is_public_url(next.as_str()) {
+                console_log!("Refusing to follow redirect to {}", next);
+                return Ok((
+                    Response::error("Redirect target not allowed", 403)?,
+                    target_url,
+                ));
+            }
+
+            // Credentials are only meant for the origin they were sent to
+            let same_origin =
+                Url::parse(&target_url).is_ok_and(|current| current.origin() == next.origin());
+            if !same_origin {
+                headers.delete("Authorization")?;
+                headers.delete("Proxy-Authorization")?;
+                headers.delete("Cookie")?;
+            }
+            headers.delete("Content-Type")?;
+            headers.delete("Content-Length")?;
+
+            let mut init = RequestInit::new();
+            init.with_method(method.clone())
+                .with_headers(headers.clone())
+                .with_redirect(RequestRedirect::Manual);
+            let request = Request::new_with_init(next.as_str(), &init)?;
+
+            console_log!("Following {} redirect to {}", status, next);
+            response = match latency::fetch_with_timeout(request, timeout_ms).await {
+                Ok(response) => response,
+                Err(e) => {
+                    console_log!("Redirect target {} failed: {:?}", next, e);
+                    return Ok((Response::error("Backend unavailable", 502)?, target_url));
+                }
+            };
+            target_url = next.to_string();
+        }
+
+        Ok((response, target_url))
+    }
+
     /// Handle redirect response by modifying location header
     async fn handle_redirect_response(
         &self,
@@ -349,40 +1594,76 @@ impl ReverseProxy {
     }
 
     /// Add CORS headers to response
-    fn add_cors_headers(&self, response: &mut Response) -> Result<()> {
-        let headers = response.headers();
-        headers.set("Access-Control-Allow-Origin", "*")?;
-        headers.set(
-            "Access-Control-Allow-Methods",
-            "GET, POST, PUT, DELETE, OPTIONS, HEAD, PATCH",
-        )?;
-        headers.set("Access-Control-Allow-Headers", "Content-Type, Authorization, X-Requested-With, Accept, Origin, User-Agent, DNT, Cache-Control, X-Mx-ReqToken, Keep-Alive, X-Requested-With, If-Modified-Since")?;
-        headers.set("Access-Control-Max-Age", "86400")?;
-        headers.set("Access-Control-Allow-Credentials", "true")?;
-        Ok(())
+    fn add_cors_headers(&self, response: &mut Response, origin: Option<&str>) -> Result<()> {
+        cors::add_cors_headers(response, &self.config, origin)
+    }
+
+    /// Check if the request already carries this worker's id in `X-Proxy-Loop`
+    fn is_proxy_loop(&self, req: &Request) -> Result<bool> {
+        Ok(req.headers().get("X-Proxy-Loop")?.is_some_and(|hops| {
+            hops.split(',')
+                .any(|hop| hop.trim() == self.config.proxy_id)
+        }))
     }
 
     /// Handle CORS preflight requests
-    fn handle_cors_preflight(&self) -> Result<Response> {
+    fn handle_cors_preflight(&self, req: &Request) -> Result<Response> {
         let mut response = Response::empty()?;
-        self.add_cors_headers(&mut response)?;
+        let origin = req.headers().get("Origin")?;
+        self.add_cors_headers(&mut response, origin.as_deref())?;
         Ok(response)
     }
 }
 
+/// Check for a CORS preflight (OPTIONS with Origin and Access-Control-Request-Method)
+fn is_cors_preflight(req: &Request) -> Result<bool> {
+    let headers = req.headers();
+    Ok(req.method() == Method::Options
+        && headers.has("Origin")?
+        && headers.has("Access-Control-Request-Method")?)
+}
+
+/// Copy of a backend request sent to another URL
+async fn retarget(mut req: Request, url: &str) -> Result<Request> {
+    let mut init = RequestInit::new();
+    init.with_method(req.method())
+        .with_headers(req.headers().clone());
+    if req.method() != Method::Get && req.method() != Method::Head {
+        init.with_body(Some(req.bytes().await?.into()));
+    }
+    Request::new_with_init(url, &init)
+}
+
+/// Authenticate a request to a backend, signing with SigV4 last
+async fn authorize_backend(
+    proxy_req: &Request,
+    backend_base: &str,
+    path: &str,
+    config: &ProxyConfig,
+    env: &Env,
+) -> Result<()> {
+    oauth2::authorize(proxy_req.headers(), backend_base, config, env).await?;
+    origin_signing::sign(proxy_req, config).await?;
+    sigv4::sign(proxy_req, path, config, env).await
+}
+
 /// Main entry point
 #[event(fetch)]
 pub async fn main(req: Request, env: Env, ctx: Context) -> Result<Response> {
     utils::set_panic_hook();
 
-    // Create proxy instance
-    let mut proxy = match ReverseProxy::from_env(&env) {
-        Ok(proxy) => proxy,
+    // Create proxy instance, honoring stored configuration and in-progress rollouts
+    let (config, rollout) = match config_store::select_config(&req, &env).await {
+        Ok(selected) => selected,
         Err(e) => {
             console_log!("Failed to initialize proxy: {:?}", e);
             return Response::error("Proxy configuration error", 500);
         }
     };
+    let mut proxy = ReverseProxy::new(config);
+
+    // Emit lifecycle webhooks when the active configuration changed
+    ctx.wait_until(webhooks::detect_config_changes(env.clone()));
 
     let url = req.url()?;
     let path = url.path();
@@ -390,7 +1671,104 @@ pub async fn main(req: Request, env: Env, ctx: Context) -> Result<Response> {
     // Handle management endpoints
     match path {
         "/_proxy/health" => proxy.health_check().await,
-        "/_proxy/stats" => proxy.get_stats().await,
-        _ => proxy.handle_request(req, &env, &ctx).await,
+        "/_proxy/status" => status::handle_status(&req, &env, &proxy.config).await,
+        _ if admin::is_admin_path(path) => {
+            admin::handle_admin_request(req, &env, &proxy.config).await
+        }
+        _ => {
+            let start_time = js_sys::Date::now();
+            let throttle = match bandwidth::for_request(&req, &proxy.config) {
+                Ok(throttle) => throttle,
+                Err(e) => {
+                    console_log!("Failed to resolve bandwidth tier: {:?}", e);
+                    None
+                }
+            };
+            let result = proxy.handle_request(req, &env, &ctx).await;
+            if let Some(lease) = proxy.lease.take() {
+                ctx.wait_until(connections::release(
+                    lease,
+                    proxy.config.clone(),
+                    env.clone(),
+                ));
+            }
+            if ewma::is_enabled(&proxy.config) {
+                ctx.wait_until(ewma::sync(proxy.config.clone(), env.clone()));
+            }
+            if let Ok(response) = &result {
+                if let Err(e) = proxy.record_cache_status(response) {
+                    console_log!("Failed to set cache status headers: {:?}", e);
+                }
+                if let Err(e) = request_id::expose(response, &proxy.event) {
+                    console_log!("Failed to set request ID headers: {:?}", e);
+                }
+            }
+
+            // Structured per-request event for tail workers
+            proxy.event.duration_ms = js_sys::Date::now() - start_time;
+            match &result {
+                Ok(response) => proxy.event.status = response.status_code(),
+                Err(e) => {
+                    proxy.event.status = 500;
+                    proxy.event.error = Some(e.to_string());
+                }
+            }
+            events::emit(&proxy.config, &proxy.event);
+
+            // Outcomes feed the client's reputation
+            if proxy.config.reputation_enabled {
+                reputation::record_response(&proxy.event, &proxy.config);
+                ctx.wait_until(reputation::flush(env.clone()));
+            }
+
+            // Traffic counters for scheduled reports and dashboards
+            if reports::is_recording(&proxy.config) {
+                reports::record(&proxy.event, &proxy.config);
+                ctx.wait_until(reports::flush(env.clone()));
+            }
+
+            // Feed request outcome into rollout error-rate comparison
+            if rollout.version.is_some() {
+                let is_error = match &result {
+                    Ok(response) => response.status_code() >= 500,
+                    Err(_) => true,
+                };
+                config_store::record_outcome(rollout, is_error);
+                ctx.wait_until(config_store::flush_rollout_stats(
+                    env.clone(),
+                    proxy.config.clone(),
+                ));
+            }
+
+            // Paced last, so timings above reflect the proxy rather than the cap
+            match throttle {
+                Some(throttle) => result.and_then(|response| {
+                    bandwidth::apply(response, throttle, proxy.sent_as_stored.get())
+                }),
+                None => result,
+            }
+        }
     }
 }
+
+/// Scheduled entry point (cron triggers)
+#[event(scheduled)]
+pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    utils::set_panic_hook();
+
+    let config = match config_store::active_config(&env).await {
+        Ok(config) => config,
+        Err(e) => {
+            console_log!("Failed to load configuration for scheduled run: {:?}", e);
+            return;
+        }
+    };
+    let proxy = ReverseProxy::new(config);
+
+    cache_warm::run(&proxy, &env).await;
+    reports::run(&proxy.config, &env).await;
+    metrics_push::run(&proxy.config, &env).await;
+    alerts::check_error_budget(&proxy.config, &env).await;
+    tls_watch::run(&proxy.config, &env).await;
+    content_watch::run(&proxy.config, &env).await;
+}
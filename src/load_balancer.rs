@@ -1,6 +1,39 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+};
 
-use crate::health::HealthChecker;
+use sha2::{Digest, Sha256};
+use worker::{Env, Request, Result};
+
+use crate::{
+    backpressure,
+    config::{BackendConfig, ColoRoutingRule, ProxyConfig},
+    connections::{self, Lease},
+    drain::DrainState,
+    ewma,
+    health::HealthChecker,
+    latency,
+    maintenance::MaintenanceState,
+    utils,
+};
+
+/// Points per backend on the consistent hash ring, more spread keys more evenly
+const RING_POINTS: usize = 100;
+
+/// Points of the consistent hash ring with their backend, sorted by point
+type Ring = Vec<(u64, String)>;
+
+thread_local! {
+    /// Current weights of smooth weighted round robin, kept per isolate since
+    /// a load balancer only lives for one request
+    static CURRENT_WEIGHTS: RefCell<HashMap<String, i64>> = RefCell::new(HashMap::new());
+    /// Position of round robin, per isolate for the same reason
+    static NEXT_INDEX: Cell<usize> = const { Cell::new(0) };
+    /// Consistent hash ring and the backends it was built for, rebuilt when they change
+    static RING: RefCell<(Vec<String>, Ring)> =
+        const { RefCell::new((Vec::new(), Vec::new())) };
+}
 
 /// Load balancer strategy
 #[derive(Debug, Clone)]
@@ -9,6 +42,9 @@ pub enum LoadBalancerStrategy {
     Random,
     LeastConnections,
     WeightedRoundRobin,
+    ConsistentHash,
+    PowerOfTwoChoices,
+    LatencyAware,
 }
 
 impl From<&str> for LoadBalancerStrategy {
@@ -17,6 +53,9 @@ impl From<&str> for LoadBalancerStrategy {
             "random" => LoadBalancerStrategy::Random,
             "least_connections" => LoadBalancerStrategy::LeastConnections,
             "weighted_round_robin" => LoadBalancerStrategy::WeightedRoundRobin,
+            "consistent_hash" => LoadBalancerStrategy::ConsistentHash,
+            "power_of_two_choices" => LoadBalancerStrategy::PowerOfTwoChoices,
+            "latency_aware" => LoadBalancerStrategy::LatencyAware,
             _ => LoadBalancerStrategy::RoundRobin,
         }
     }
@@ -24,10 +63,13 @@ impl From<&str> for LoadBalancerStrategy {
 
 /// Load balancer
 pub struct LoadBalancer {
-    #[allow(dead_code)]
     backends: Vec<String>,
     strategy: LoadBalancerStrategy,
-    current_index: AtomicUsize,
+    colo_rules: Vec<ColoRoutingRule>,
+    weights: HashMap<String, u32>,
+    backups: HashSet<String>,
+    draining: DrainState,
+    maintenance: HashSet<String>,
 }
 
 impl LoadBalancer {
@@ -36,17 +78,176 @@ impl LoadBalancer {
         Self {
             backends: backends.to_vec(),
             strategy,
-            current_index: AtomicUsize::new(0),
+            colo_rules: Vec::new(),
+            weights: HashMap::new(),
+            backups: HashSet::new(),
+            draining: DrainState::new(),
+            maintenance: HashSet::new(),
+        }
+    }
+
+    /// Load balancer of the first `BACKEND_POOLS` pool matching the path,
+    /// with the pool's strategy, or of `BACKENDS` when no pool matches
+    pub fn for_path(config: &ProxyConfig, path: &str) -> Self {
+        let pool = config
+            .backend_pools
+            .iter()
+            .find(|pool| utils::path_matches(&pool.path, path));
+        let (backends, strategy) = match pool {
+            Some(pool) => (
+                &pool.backends,
+                pool.strategy
+                    .as_deref()
+                    .unwrap_or(&config.load_balancer_strategy),
+            ),
+            None => (&config.backends, config.load_balancer_strategy.as_str()),
+        };
+        Self::for_backends(config, backends, strategy)
+    }
+
+    /// Load balancer of `backends` with colo rules, weights and groups from `config`
+    pub fn for_backends(config: &ProxyConfig, backends: &[String], strategy: &str) -> Self {
+        Self::with_strategy(backends, LoadBalancerStrategy::from(strategy))
+            .with_colo_rules(&config.colo_routing_rules)
+            .with_backend_weights(&config.backend_configs)
+            .with_backend_groups(&config.backend_configs)
+    }
+
+    /// Set per-backend weights from `BACKEND_CONFIGS`
+    pub fn with_backend_weights(mut self, configs: &[BackendConfig]) -> Self {
+        self.weights = configs
+            .iter()
+            .map(|config| (config.url.trim_end_matches('/').to_string(), config.weight))
+            .collect();
+        self
+    }
+
+    /// Set the backup group from `BACKEND_CONFIGS`, other backends are primaries
+    pub fn with_backend_groups(mut self, configs: &[BackendConfig]) -> Self {
+        self.backups = configs
+            .iter()
+            .filter(|config| config.group.as_deref() == Some("backup"))
+            .map(|config| config.url.trim_end_matches('/').to_string())
+            .collect();
+        self
+    }
+
+    /// Set the backends being drained, which get no new traffic
+    pub fn set_draining(&mut self, draining: DrainState) {
+        self.draining = draining;
+    }
+
+    /// Set the backends in maintenance, which get no traffic at all
+    pub fn set_maintenance(&mut self, maintenance: &MaintenanceState) {
+        self.maintenance = maintenance.keys().cloned().collect();
+    }
+
+    fn in_maintenance(&self, backend: &str) -> bool {
+        self.maintenance.contains(backend.trim_end_matches('/'))
+    }
+
+    fn is_draining(&self, backend: &str) -> bool {
+        self.draining.contains_key(backend.trim_end_matches('/'))
+    }
+
+    fn is_backup(&self, backend: &str) -> bool {
+        self.backups.contains(backend.trim_end_matches('/'))
+    }
+
+    /// Healthy primaries, or the healthy backups once every primary is down.
+    /// Primaries whose requests keep failing in this isolate count as down
+    /// until `HEALTH_CHECK_INTERVAL` passes without failures, then traffic
+    /// shifts back to them.
+    async fn active_pool(&self, health_checker: &HealthChecker) -> Vec<String> {
+        let candidates: Vec<String> = self
+            .backends
+            .iter()
+            .filter(|backend| !self.is_draining(backend) && !self.in_maintenance(backend))
+            .cloned()
+            .collect();
+        let healthy = health_checker.healthy_among(&candidates).await;
+        if self.backups.is_empty() {
+            return healthy;
+        }
+
+        let (backups, primaries): (Vec<String>, Vec<String>) = healthy
+            .into_iter()
+            .partition(|backend| self.is_backup(backend));
+        let available: Vec<String> = primaries
+            .iter()
+            .filter(|backend| !health_checker.is_failing(backend))
+            .cloned()
+            .collect();
+        if !available.is_empty() {
+            available
+        } else if !backups.is_empty() {
+            backups
+        } else {
+            // Failing primaries beat no backend at all
+            primaries
         }
     }
 
-    /// Get next backend server
-    pub async fn get_backend(&self, health_checker: &HealthChecker) -> Option<String> {
-        let healthy_backends = health_checker.get_healthy_backends().await;
+    /// Pool with backends still ramping up after a recovery left out of a
+    /// share of the picks, so they get a growing slice of their normal traffic
+    fn ramp(pool: Vec<String>, health_checker: &HealthChecker) -> Vec<String> {
+        let ramped: Vec<String> = pool
+            .iter()
+            .filter(|backend| {
+                let share = health_checker.slow_start_share(backend);
+                share >= 1.0 || js_sys::Math::random() < share
+            })
+            .cloned()
+            .collect();
+        if ramped.is_empty() { pool } else { ramped }
+    }
+
+    /// Whether the backend is in the pool currently serving traffic, e.g. for
+    /// clients pinned to a backup while primaries were down. Draining backends
+    /// keep their pinned clients until the grace period ends.
+    pub async fn is_active(&self, backend: &str, health_checker: &HealthChecker) -> bool {
+        if self.in_maintenance(backend) {
+            return false;
+        }
+        if let Some(entry) = self.draining.get(backend.trim_end_matches('/')) {
+            return entry.in_grace_period() && health_checker.is_healthy(backend).await;
+        }
+        self.active_pool(health_checker)
+            .await
+            .iter()
+            .any(|active| active == backend)
+    }
+
+    /// Set per-colo routing overrides
+    pub fn with_colo_rules(mut self, rules: &[ColoRoutingRule]) -> Self {
+        self.colo_rules = rules.to_vec();
+        self
+    }
+
+    /// Get next backend server, honoring routing overrides for the serving colo
+    pub async fn get_backend(
+        &self,
+        health_checker: &HealthChecker,
+        colo: Option<&str>,
+    ) -> Option<String> {
+        let (pool, colo_weights) = self.colo_pool(colo, self.active_pool(health_checker).await);
+        let healthy_backends = Self::ramp(pool, health_checker);
         if healthy_backends.is_empty() {
             return None;
         }
 
+        // Weights of the colo's rule take over the spread of the strategies
+        // that pick without measuring backends
+        let spread = matches!(
+            self.strategy,
+            LoadBalancerStrategy::RoundRobin
+                | LoadBalancerStrategy::Random
+                | LoadBalancerStrategy::WeightedRoundRobin
+        );
+        if let Some(weights) = colo_weights.filter(|_| spread) {
+            return self.weighted_round_robin_select(&healthy_backends, &weights);
+        }
+
         match self.strategy {
             LoadBalancerStrategy::RoundRobin => self.round_robin_select(&healthy_backends),
             LoadBalancerStrategy::Random => self.random_select(&healthy_backends),
@@ -54,17 +255,140 @@ impl LoadBalancer {
                 self.least_connections_select(&healthy_backends)
             }
             LoadBalancerStrategy::WeightedRoundRobin => {
-                self.weighted_round_robin_select(&healthy_backends)
+                self.weighted_round_robin_select(&healthy_backends, &self.weights)
             }
+            // Without a request key, e.g. for background requests
+            LoadBalancerStrategy::ConsistentHash => self.round_robin_select(&healthy_backends),
+            LoadBalancerStrategy::PowerOfTwoChoices => {
+                self.power_of_two_choices_select(&healthy_backends)
+            }
+            LoadBalancerStrategy::LatencyAware => self.latency_aware_select(&healthy_backends),
         }
     }
 
+    /// Get the backend for a proxied request. With the least connections
+    /// strategy the request is counted against its backend until the
+    /// returned lease is released; with consistent hashing `hash_key` (see
+    /// [`hash_key`]) picks the backend.
+    pub async fn acquire_backend(
+        &self,
+        health_checker: &HealthChecker,
+        colo: Option<&str>,
+        hash_key: Option<&str>,
+        config: &ProxyConfig,
+        env: &Env,
+    ) -> Option<(String, Option<Lease>)> {
+        let hashed = matches!(self.strategy, LoadBalancerStrategy::ConsistentHash);
+        if !hashed && !matches!(self.strategy, LoadBalancerStrategy::LeastConnections) {
+            return self
+                .get_backend(health_checker, colo)
+                .await
+                .map(|backend| (backend, None));
+        }
+
+        // Colo rules narrow the pool, their weights don't apply to these
+        // strategies. The ring keeps every active backend, ramping ones take
+        // their share of keys on lookup, so it is not rebuilt on every request.
+        let (active, _) = self.colo_pool(colo, self.active_pool(health_checker).await);
+        let healthy_backends = Self::ramp(active.clone(), health_checker);
+        if healthy_backends.is_empty() {
+            return None;
+        }
+
+        if hashed {
+            let backend = match hash_key {
+                Some(key) => self.consistent_hash_select(&active, key, health_checker),
+                None => self.round_robin_select(&healthy_backends),
+            };
+            return backend.map(|backend| (backend, None));
+        }
+
+        match connections::acquire(&healthy_backends, config, env).await {
+            Some(lease) => Some((lease.backend.clone(), Some(lease))),
+            // Tracker unavailable, keep serving without connection counts
+            None => self
+                .round_robin_select(&healthy_backends)
+                .map(|backend| (backend, None)),
+        }
+    }
+
+    /// Healthy backend to retry a failed request on, other than those tried
+    pub async fn failover_backend(
+        &self,
+        health_checker: &HealthChecker,
+        tried: &[String],
+    ) -> Option<String> {
+        let pool: Vec<String> = self
+            .active_pool(health_checker)
+            .await
+            .into_iter()
+            .filter(|backend| !tried.contains(backend))
+            .collect();
+        self.round_robin_select(&pool)
+    }
+
+    /// Configured backend a target URL was built from
+    pub fn backend_of(&self, target_url: &str) -> Option<&String> {
+        self.backends
+            .iter()
+            .filter(|backend| target_url.starts_with(backend.trim_end_matches('/')))
+            .max_by_key(|backend| backend.len())
+    }
+
+    /// Pool of the serving colo's routing rule and the rule's weights, if it
+    /// has any. Backends weighted 0 are left out; if no backend of the rule's
+    /// pool is active, the whole pool is kept.
+    fn colo_pool(
+        &self,
+        colo: Option<&str>,
+        pool: Vec<String>,
+    ) -> (Vec<String>, Option<HashMap<String, u32>>) {
+        let rule = match colo.and_then(|colo| self.find_colo_rule(colo)) {
+            Some(rule) => rule,
+            None => return (pool, None),
+        };
+
+        let normalize = |backend: &str| backend.trim_end_matches('/').to_string();
+        let listed: HashSet<String> = rule.backends.iter().map(|b| normalize(b)).collect();
+        // Backends without an explicit weight keep the default weight of 1
+        let weights: HashMap<String, u32> = rule
+            .weights
+            .iter()
+            .map(|(backend, weight)| (normalize(backend), *weight))
+            .collect();
+        let preferred: Vec<String> = pool
+            .iter()
+            .filter(|backend| {
+                let backend = normalize(backend);
+                (listed.is_empty() || listed.contains(&backend))
+                    && weights.get(&backend) != Some(&0)
+            })
+            .cloned()
+            .collect();
+
+        if preferred.is_empty() {
+            (pool, None)
+        } else {
+            (preferred, (!weights.is_empty()).then_some(weights))
+        }
+    }
+
+    fn find_colo_rule(&self, colo: &str) -> Option<&ColoRoutingRule> {
+        self.colo_rules
+            .iter()
+            .find(|rule| rule.colos.iter().any(|c| c.eq_ignore_ascii_case(colo)))
+    }
+
     fn round_robin_select(&self, backends: &[String]) -> Option<String> {
         if backends.is_empty() {
             return None;
         }
 
-        let index = self.current_index.fetch_add(1, Ordering::Relaxed) % backends.len();
+        let index = NEXT_INDEX.with(|next| {
+            let index = next.get();
+            next.set(index.wrapping_add(1));
+            index
+        }) % backends.len();
         Some(backends[index].clone())
     }
 
@@ -73,37 +397,235 @@ impl LoadBalancer {
             return None;
         }
 
-        // Simple pseudo-random number generation (based on current time)
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_nanos() as usize;
-        let index = now % backends.len();
+        // The clock doesn't advance during a request in Workers, so a time-based
+        // index would send bursts to the same backend
+        let index = random_index(js_sys::Math::random(), backends.len());
         Some(backends[index].clone())
     }
 
+    /// Least connections without the tracker (background and ESI requests
+    /// are not counted), falls back to round robin
     fn least_connections_select(&self, backends: &[String]) -> Option<String> {
-        // Simplified implementation: fallback to round robin
-        // In actual implementation, need to track connection count for each backend
         self.round_robin_select(backends)
     }
 
-    fn weighted_round_robin_select(&self, backends: &[String]) -> Option<String> {
-        // Simplified implementation: fallback to round robin
-        // In actual implementation, need to select based on weights
-        self.round_robin_select(backends)
+    /// Smooth weighted round robin: every pick adds each backend's weight to its
+    /// current weight, takes the highest and subtracts the total from it, which
+    /// spreads heavier backends evenly instead of in bursts. Backends without a
+    /// `BACKEND_CONFIGS` (or colo rule) weight weigh 1, a weight of 0 takes a
+    /// backend out of rotation.
+    fn weighted_round_robin_select(
+        &self,
+        backends: &[String],
+        weights: &HashMap<String, u32>,
+    ) -> Option<String> {
+        let weighted: Vec<(&String, i64)> = backends
+            .iter()
+            .map(|backend| {
+                let weight = weights
+                    .get(backend.trim_end_matches('/'))
+                    .copied()
+                    .unwrap_or(1);
+                (backend, i64::from(weight))
+            })
+            .filter(|(_, weight)| *weight > 0)
+            .collect();
+        let total: i64 = weighted.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return self.round_robin_select(backends);
+        }
+
+        CURRENT_WEIGHTS.with(|current| {
+            let mut current = current.borrow_mut();
+            let mut selected: Option<(&String, i64)> = None;
+            for (backend, weight) in &weighted {
+                let entry = current.entry((*backend).clone()).or_insert(0);
+                *entry += weight;
+                if selected.is_none_or(|(_, best)| *entry > best) {
+                    selected = Some((backend, *entry));
+                }
+            }
+
+            let (backend, _) = selected?;
+            if let Some(entry) = current.get_mut(backend) {
+                *entry -= total;
+            }
+            Some(backend.clone())
+        })
+    }
+
+    /// Sample two distinct random backends and pick the one with the lower
+    /// recent latency times in-flight requests, as observed by this isolate.
+    /// Backends without latency samples win, so every backend gets measured.
+    fn power_of_two_choices_select(&self, backends: &[String]) -> Option<String> {
+        if backends.len() < 2 {
+            return backends.first().cloned();
+        }
+
+        let (first, second) = two_choices(
+            js_sys::Math::random(),
+            js_sys::Math::random(),
+            backends.len(),
+        );
+
+        let load = |backend: &String| {
+            latency::recent_latency(backend).unwrap_or(0.0)
+                * f64::from(backpressure::in_flight(backend) + 1)
+        };
+        let (a, b) = (&backends[first], &backends[second]);
+        Some(if load(b) < load(a) { b } else { a }.clone())
+    }
+
+    /// Backend with the lowest moving average of latency and errors. Backends
+    /// without recent samples go first, round robin, so they get measured.
+    fn latency_aware_select(&self, backends: &[String]) -> Option<String> {
+        let unmeasured: Vec<String> = backends
+            .iter()
+            .filter(|backend| ewma::score(backend).is_none())
+            .cloned()
+            .collect();
+        if !unmeasured.is_empty() {
+            return self.round_robin_select(&unmeasured);
+        }
+
+        backends
+            .iter()
+            .filter_map(|backend| ewma::score(backend).map(|score| (backend, score)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(backend, _)| backend.clone())
+    }
+
+    /// Place every backend on a hash ring at `RING_POINTS` points and pick the
+    /// first point at or after the key's hash. When a backend leaves the pool
+    /// only its own keys move, to the next points on the ring. A backend
+    /// ramping up after a recovery keeps a stable share of its keys, growing
+    /// with the ramp, and the rest fall through to the next backend on the ring.
+    fn consistent_hash_select(
+        &self,
+        backends: &[String],
+        key: &str,
+        health_checker: &HealthChecker,
+    ) -> Option<String> {
+        let hash = ring_hash(key);
+        RING.with(|cached| {
+            let mut cached = cached.borrow_mut();
+            if cached.0 != backends {
+                let mut ring: Ring = backends
+                    .iter()
+                    .flat_map(|backend| {
+                        (0..RING_POINTS).map(move |point| {
+                            (ring_hash(&format!("{backend}#{point}")), backend.clone())
+                        })
+                    })
+                    .collect();
+                ring.sort();
+                *cached = (backends.to_vec(), ring);
+            }
+
+            let ring = &cached.1;
+            let start = ring.partition_point(|(point, _)| *point < hash);
+            let mut passed: Vec<&String> = Vec::new();
+            for offset in 0..ring.len() {
+                let (_, backend) = &ring[(start + offset) % ring.len()];
+                if passed.contains(&backend) {
+                    continue;
+                }
+                let share = health_checker.slow_start_share(backend);
+                let draw = ring_hash(&format!("{backend}|{key}")) as f64 / u64::MAX as f64;
+                if share >= 1.0 || draw < share {
+                    return Some(backend.clone());
+                }
+                passed.push(backend);
+                if passed.len() == backends.len() {
+                    break;
+                }
+            }
+            // Every backend is ramping up, keep the key's own backend
+            passed.first().map(|backend| (*backend).clone())
+        })
     }
 
     /// Get all backends
-    #[allow(dead_code)]
     pub fn get_all_backends(&self) -> &[String] {
         &self.backends
     }
 
+    /// Backends clients may be pinned to: all backends, plus draining ones
+    /// already removed from the configuration
+    pub fn sticky_backends(&self) -> Vec<String> {
+        let mut backends = self.backends.clone();
+        for (backend, entry) in &self.draining {
+            let listed = backends
+                .iter()
+                .any(|listed| listed.trim_end_matches('/') == backend);
+            if !listed && entry.in_grace_period() {
+                backends.push(backend.clone());
+            }
+        }
+        backends
+    }
+
     /// Update backend list
     #[allow(dead_code)]
     pub fn update_backends(&mut self, backends: Vec<String>) {
         self.backends = backends;
-        self.current_index.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Key of a request for consistent hashing, from `LOAD_BALANCER_HASH_KEY`:
+/// `ip` (client IP), `header:<name>` or `cookie:<name>`. `None` when the
+/// request lacks it, so it is balanced round robin instead.
+pub fn hash_key(req: &Request, client_ip: Option<&str>, source: &str) -> Result<Option<String>> {
+    let key = if let Some(name) = source.strip_prefix("header:") {
+        req.headers().get(name)?
+    } else if let Some(name) = source.strip_prefix("cookie:") {
+        utils::cookie_value(req, name)?
+    } else {
+        client_ip.map(str::to_string)
+    };
+    Ok(key.filter(|key| !key.is_empty()))
+}
+
+fn ring_hash(value: &str) -> u64 {
+    let digest = Sha256::digest(value.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Backend index for a uniform draw in `[0, 1)`
+fn random_index(draw: f64, len: usize) -> usize {
+    (draw * len as f64) as usize % len
+}
+
+/// Two distinct backend indexes for two uniform draws, `len` must be at least 2
+fn two_choices(first_draw: f64, second_draw: f64, len: usize) -> (usize, usize) {
+    let first = random_index(first_draw, len);
+    let offset = 1 + random_index(second_draw, len - 1);
+    (first, (first + offset) % len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_index_stays_in_range() {
+        assert_eq!(random_index(0.0, 4), 0);
+        assert_eq!(random_index(0.999_999_999_999, 4), 3);
+        assert_eq!(random_index(0.5, 1), 0);
+    }
+
+    #[test]
+    fn two_choices_are_distinct() {
+        for len in 2..6 {
+            for first in [0.0, 0.3, 0.999_999_999_999] {
+                for second in [0.0, 0.5, 0.999_999_999_999] {
+                    let (a, b) = two_choices(first, second, len);
+                    assert!(a < len && b < len);
+                    assert_ne!(a, b, "len {len}, draws {first} and {second}");
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,185 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use worker::{kv::KvStore, *};
+
+use crate::{
+    admin::{AdminIdentity, AuditEntry, record_audit},
+    config::ProxyConfig,
+    webhooks::{ProxyEvent, WebhookEmitter},
+};
+
+/// KV key prefix of the backends in maintenance (`maintenance:{proxy_id}`)
+const MAINTENANCE_PREFIX: &str = "maintenance:";
+
+/// Maintenance state read from KV is reused for this long by an isolate
+const MAINTENANCE_CACHE_MS: f64 = 5_000.0;
+
+/// A backend taken out of load balancing for planned work
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MaintenanceEntry {
+    #[serde(default)]
+    pub since: Option<String>, // RFC 3339
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub set_by: Option<String>,
+}
+
+/// Backends in maintenance by URL
+pub type MaintenanceState = HashMap<String, MaintenanceEntry>;
+
+thread_local! {
+    /// Maintenance state of this isolate, with its load time
+    static LOADED: RefCell<Option<(f64, MaintenanceState)>> = const { RefCell::new(None) };
+}
+
+fn maintenance_key(config: &ProxyConfig) -> String {
+    format!("{MAINTENANCE_PREFIX}{}", config.proxy_id)
+}
+
+async fn read(kv: &KvStore, config: &ProxyConfig) -> Result<MaintenanceState> {
+    Ok(kv
+        .get(&maintenance_key(config))
+        .json::<MaintenanceState>()
+        .await?
+        .unwrap_or_default())
+}
+
+async fn write(kv: &KvStore, config: &ProxyConfig, state: &MaintenanceState) -> Result<()> {
+    kv.put(&maintenance_key(config), serde_json::to_string(state)?)?
+        .execute()
+        .await?;
+    LOADED.with(|loaded| loaded.borrow_mut().take());
+    Ok(())
+}
+
+/// Backends in maintenance, empty when none are or KV is unavailable
+pub async fn load(env: &Env, config: &ProxyConfig) -> MaintenanceState {
+    let now = js_sys::Date::now();
+    let cached = LOADED.with(|loaded| {
+        loaded
+            .borrow()
+            .as_ref()
+            .filter(|(loaded_at, _)| now - loaded_at < MAINTENANCE_CACHE_MS)
+            .map(|(_, state)| state.clone())
+    });
+    if let Some(state) = cached {
+        return state;
+    }
+
+    let state = match env.kv("PROXY_KV") {
+        Ok(kv) => match read(&kv, config).await {
+            Ok(state) => state,
+            Err(e) => {
+                console_log!("Invalid maintenance state: {:?}", e);
+                MaintenanceState::new()
+            }
+        },
+        Err(_) => MaintenanceState::new(),
+    };
+    let state: MaintenanceState = state
+        .into_iter()
+        .map(|(backend, entry)| (backend.trim_end_matches('/').to_string(), entry))
+        .collect();
+    LOADED.with(|loaded| *loaded.borrow_mut() = Some((now, state.clone())));
+    state
+}
+
+/// Maintenance request body
+#[derive(Debug, Deserialize)]
+struct MaintenanceRequest {
+    backend: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// List backends in maintenance
+pub async fn handle_list(env: &Env, config: &ProxyConfig) -> Result<Response> {
+    let state = read(&env.kv("PROXY_KV")?, config).await?;
+    Response::from_json(&serde_json::json!({
+        "backends": state,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
+
+/// Put a backend into maintenance
+pub async fn handle_enable(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let body: MaintenanceRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return Response::error("Invalid maintenance request", 400),
+    };
+    let backend = body.backend.trim_end_matches('/').to_string();
+    let known = config
+        .backends
+        .iter()
+        .chain(config.backend_pools.iter().flat_map(|pool| &pool.backends))
+        .any(|configured| configured.trim_end_matches('/') == backend);
+    if !known {
+        return Response::error(format!("Unknown backend: {backend}"), 400);
+    }
+
+    let kv = env.kv("PROXY_KV")?;
+    let mut state = read(&kv, config).await?;
+    let entry = MaintenanceEntry {
+        since: Some(Utc::now().to_rfc3339()),
+        reason: body.reason,
+        set_by: Some(identity.name.clone()),
+    };
+    let previous = state.insert(backend.clone(), entry.clone());
+    write(&kv, config, &state).await?;
+
+    let audit = AuditEntry::new(req, &identity.name, "enable_maintenance", &backend).with_change(
+        previous.map(|previous| serde_json::to_value(previous).unwrap_or_default()),
+        Some(serde_json::to_value(&entry)?),
+    );
+    record_audit(env, config, audit).await;
+    WebhookEmitter::new(config)
+        .emit(
+            ProxyEvent::MaintenanceToggled,
+            serde_json::json!({ "backend": backend, "maintenance": true, "reason": entry.reason }),
+        )
+        .await;
+
+    Response::from_json(&serde_json::json!({ "backend": backend, "maintenance": true }))
+}
+
+/// Return a backend from maintenance to load balancing
+pub async fn handle_disable(
+    req: &Request,
+    url: &Url,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let backend = match url.query_pairs().find(|(key, _)| key == "backend") {
+        Some((_, backend)) => backend.trim_end_matches('/').to_string(),
+        None => return Response::error("Backend is required", 400),
+    };
+
+    let kv = env.kv("PROXY_KV")?;
+    let mut state = read(&kv, config).await?;
+    let previous = match state.remove(&backend) {
+        Some(previous) => previous,
+        None => return Response::error("Backend is not in maintenance", 404),
+    };
+    write(&kv, config, &state).await?;
+
+    let audit = AuditEntry::new(req, &identity.name, "disable_maintenance", &backend)
+        .with_change(Some(serde_json::to_value(previous)?), None);
+    record_audit(env, config, audit).await;
+    WebhookEmitter::new(config)
+        .emit(
+            ProxyEvent::MaintenanceToggled,
+            serde_json::json!({ "backend": backend, "maintenance": false }),
+        )
+        .await;
+
+    Response::from_json(&serde_json::json!({ "backend": backend, "maintenance": false }))
+}
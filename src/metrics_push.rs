@@ -0,0 +1,165 @@
+use chrono::{DateTime, Duration, DurationRound, NaiveTime, TimeZone, Utc};
+use worker::*;
+
+use crate::{
+    config::ProxyConfig,
+    reports::{self, HourlyTraffic},
+};
+
+/// KV key holding the start (Unix seconds) of the next hour to push
+const PUSH_CURSOR_KEY: &str = "metrics_push:cursor";
+
+/// Minutes after the end of an hour before it is pushed, so isolates had
+/// time to flush their counters
+const FLUSH_GRACE_MINUTES: i64 = 5;
+
+/// Most hours pushed per run, older ones are skipped after long outages
+const MAX_PUSH_HOURS: i64 = 48;
+
+/// Push the hourly traffic counters finished since the last push
+pub async fn run(config: &ProxyConfig, env: &Env) {
+    let url = match &config.metrics_push_url {
+        Some(url) => url,
+        None => return,
+    };
+
+    if let Err(e) = push(url, config, env).await {
+        console_log!("Failed to push metrics: {:?}", e);
+    }
+}
+
+async fn push(url: &str, config: &ProxyConfig, env: &Env) -> Result<()> {
+    let kv = env.kv("PROXY_KV")?;
+    let end = (Utc::now() - Duration::minutes(FLUSH_GRACE_MINUTES))
+        .duration_trunc(Duration::hours(1))
+        .map_err(|e| Error::from(e.to_string()))?;
+    let start = kv
+        .get(PUSH_CURSOR_KEY)
+        .text()
+        .await?
+        .and_then(|cursor| cursor.parse::<i64>().ok())
+        .and_then(|cursor| Utc.timestamp_opt(cursor, 0).single())
+        .unwrap_or(end - Duration::hours(1))
+        .max(end - Duration::hours(MAX_PUSH_HOURS));
+    if start >= end {
+        return Ok(());
+    }
+
+    let mut hours = Vec::new();
+    let last_hour = end - Duration::hours(1);
+    for (date, stats) in reports::load_days(env, start.date_naive(), last_hour.date_naive()).await?
+    {
+        for (hour, traffic) in stats.hours {
+            if let Some(time) = NaiveTime::from_hms_opt(hour, 0, 0)
+                .map(|time| date.and_time(time).and_utc())
+                .filter(|time| *time >= start && *time < end)
+            {
+                hours.push((time, traffic));
+            }
+        }
+    }
+
+    if !hours.is_empty() {
+        let (body, content_type) = match config.metrics_push_format.as_str() {
+            "json" => (to_json(&hours, config)?, "application/json"),
+            _ => (
+                to_line_protocol(&hours, config),
+                "text/plain; charset=utf-8",
+            ),
+        };
+
+        let headers = Headers::new();
+        headers.set("Content-Type", content_type)?;
+        if let Some(authorization) = &config.metrics_push_auth {
+            headers.set("Authorization", authorization)?;
+        }
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post)
+            .with_headers(headers)
+            .with_body(Some(body.into()));
+
+        let response = Fetch::Request(Request::new_with_init(url, &init)?)
+            .send()
+            .await?;
+        // Keep the cursor so the same hours are pushed again on the next run
+        if !(200..300).contains(&response.status_code()) {
+            return Err(Error::from(format!(
+                "Metrics sink answered with status {}",
+                response.status_code()
+            )));
+        }
+        console_log!("Pushed {} hours of metrics", hours.len());
+    }
+
+    kv.put(PUSH_CURSOR_KEY, end.timestamp().to_string())?
+        .execute()
+        .await?;
+    Ok(())
+}
+
+/// InfluxDB line protocol, one `cf_proxy` point per hour plus one
+/// `cf_proxy_backend` point per backend and one `cf_proxy_tag` point per
+/// metric tag value, in nanosecond precision
+fn to_line_protocol(hours: &[(DateTime<Utc>, HourlyTraffic)], config: &ProxyConfig) -> String {
+    let proxy_id = escape_tag(&config.proxy_id);
+    let mut lines = Vec::new();
+    for (time, traffic) in hours {
+        let timestamp = time.timestamp() * 1_000_000_000;
+        lines.push(format!(
+            "cf_proxy,proxy_id={proxy_id} requests={}i,errors={}i,cache_hits={}i,cache_misses={}i {timestamp}",
+            traffic.requests, traffic.errors, traffic.cache_hits, traffic.cache_misses
+        ));
+        for (backend, backend_traffic) in &traffic.backends {
+            lines.push(format!(
+                "cf_proxy_backend,proxy_id={proxy_id},backend={} requests={}i,failures={}i {timestamp}",
+                escape_tag(backend),
+                backend_traffic.requests,
+                backend_traffic.failures
+            ));
+        }
+        for (tag, values) in &traffic.tags {
+            for (value, tag_traffic) in values {
+                lines.push(format!(
+                    "cf_proxy_tag,proxy_id={proxy_id},tag={},value={} requests={}i,failures={}i {timestamp}",
+                    escape_tag(tag),
+                    escape_tag(value),
+                    tag_traffic.requests,
+                    tag_traffic.failures
+                ));
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Generic JSON document with one entry per hour
+fn to_json(hours: &[(DateTime<Utc>, HourlyTraffic)], config: &ProxyConfig) -> Result<String> {
+    let points: Vec<_> = hours
+        .iter()
+        .map(|(time, traffic)| {
+            serde_json::json!({
+                "time": time.to_rfc3339(),
+                "requests": traffic.requests,
+                "errors": traffic.errors,
+                "cache_hits": traffic.cache_hits,
+                "cache_misses": traffic.cache_misses,
+                "backends": traffic.backends,
+                "tags": traffic.tags
+            })
+        })
+        .collect();
+
+    Ok(serde_json::to_string(&serde_json::json!({
+        "proxy_id": config.proxy_id,
+        "points": points
+    }))?)
+}
+
+/// Escape commas, spaces and equals signs in line protocol tag values
+fn escape_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(' ', "\\ ")
+        .replace('=', "\\=")
+}
@@ -1,22 +1,26 @@
-use regex::Regex;
 use worker::*;
 
-use crate::config::ProxyConfig;
+use crate::{
+    access::{AccessDenial, HeaderMatcher},
+    client_hints,
+    config::{ContentDispositionRule, ProxyConfig},
+    context::RequestContext,
+    utils,
+};
 
 /// Apply request middleware
-pub fn apply_request_middleware(req: Request, config: &ProxyConfig) -> Result<Request> {
-    // Access control check
-    if !check_access_control(&req, config)? {
-        return Err(Error::from("Access denied"));
-    }
-
+pub fn apply_request_middleware(req: Request, _config: &ProxyConfig) -> Result<Request> {
     // Simply return the request without modifying headers
     // Headers will be modified in create_proxy_request instead
     Ok(req)
 }
 
 /// Apply response middleware
-pub fn apply_response_middleware(response: Response, _config: &ProxyConfig) -> Result<Response> {
+pub fn apply_response_middleware(
+    response: Response,
+    config: &ProxyConfig,
+    ctx: &RequestContext,
+) -> Result<Response> {
     let headers = response.headers().clone();
 
     // Add security headers
@@ -32,82 +36,289 @@ pub fn apply_response_middleware(response: Response, _config: &ProxyConfig) -> R
     headers.delete("Server")?;
     headers.delete("X-Powered-By")?;
 
+    // Strip internal headers per route
+    apply_response_header_rules(&response, config, ctx)?;
+
+    // Override origin caching headers, also seen by the proxy cache
+    apply_cache_control_override(&response, config, ctx)?;
+
+    // Override Content-Disposition for forced download routes
+    apply_content_disposition(&response, config, ctx)?;
+
+    // Correct obviously wrong content types from misconfigured origins
+    if config.content_type_fixup_enabled {
+        fix_content_type(&response, config, ctx)?;
+    }
+
+    // Ask browsers for the client hints responsive images vary on
+    client_hints::request_hints(&response, config)?;
+
     // Simplified response construction
     Ok(response)
 }
 
-/// Check access control
-fn check_access_control(req: &Request, config: &ProxyConfig) -> Result<bool> {
-    let cf = req.cf();
-
-    for rule in &config.access_rules {
-        match rule.rule_type.as_str() {
-            "deny_ip" => {
-                if let Ok(Some(ip)) = req.headers().get("CF-Connecting-IP") {
-                    if ip == rule.pattern {
-                        console_log!("Access denied for IP: {}", ip);
-                        return Ok(false);
-                    }
-                }
-            }
-            "allow_country" => {
-                if let Some(cf_data) = cf {
-                    if let Some(country) = cf_data.country() {
-                        if country.as_str() != rule.pattern {
-                            console_log!("Access denied for country: {}", country.as_str());
-                            return Ok(false);
-                        }
-                    }
-                }
-            }
-            "deny_country" => {
-                if let Some(cf_data) = cf {
-                    if let Some(country) = cf_data.country() {
-                        if country.as_str() == rule.pattern {
-                            console_log!("Access denied for country: {}", country.as_str());
-                            return Ok(false);
-                        }
-                    }
-                }
-            }
-            "deny_user_agent" => {
-                if let Ok(Some(user_agent)) = req.headers().get("User-Agent") {
-                    if let Ok(regex) = Regex::new(&rule.pattern) {
-                        if regex.is_match(&user_agent) {
-                            console_log!("Access denied for User-Agent: {}", user_agent);
-                            return Ok(false);
-                        }
-                    }
-                }
-            }
-            _ => {}
+/// Apply the first matching response header allow/strip rule
+fn apply_response_header_rules(
+    response: &Response,
+    config: &ProxyConfig,
+    ctx: &RequestContext,
+) -> Result<()> {
+    let rule = match config
+        .response_header_rules
+        .iter()
+        .find(|rule| utils::path_matches(&rule.path, &ctx.path))
+    {
+        Some(rule) => rule,
+        None => return Ok(()),
+    };
+
+    let headers = response.headers();
+    let names: Vec<String> = headers.keys().map(|name| name.to_lowercase()).collect();
+    for name in names {
+        let allowed = rule.allow_matcher.is_empty() || rule.allow_matcher.matches(&name);
+        if !allowed || rule.strip_matcher.matches(&name) {
+            headers.delete(&name)?;
         }
     }
 
-    Ok(true)
+    Ok(())
 }
 
-/// Add CORS headers
-#[allow(dead_code)]
-pub fn add_cors_headers(response: &mut Response) -> Result<()> {
-    let headers = response.headers().clone();
-    headers.set("Access-Control-Allow-Origin", "*")?;
-    headers.set(
-        "Access-Control-Allow-Methods",
-        "GET, POST, PUT, DELETE, OPTIONS",
-    )?;
-    headers.set(
-        "Access-Control-Allow-Headers",
-        "Content-Type, Authorization, X-Requested-With",
-    )?;
-    headers.set("Access-Control-Max-Age", "86400")?;
+/// Apply the first matching Cache-Control override rule
+fn apply_cache_control_override(
+    response: &Response,
+    config: &ProxyConfig,
+    ctx: &RequestContext,
+) -> Result<()> {
+    if let Some(rule) = config
+        .cache_control_rules
+        .iter()
+        .find(|rule| utils::path_matches(&rule.path, &ctx.path))
+    {
+        let headers = response.headers();
+        headers.set("Cache-Control", &rule.cache_control)?;
+        // Legacy headers would contradict the override
+        headers.delete("Pragma")?;
+        headers.delete("Expires")?;
+    }
+
     Ok(())
 }
 
-/// Handle OPTIONS preflight request
-#[allow(dead_code)]
-pub fn handle_options_request() -> Result<Response> {
-    let mut response = Response::empty()?;
-    add_cors_headers(&mut response)?;
-    Ok(response)
+/// Apply the first matching Content-Disposition rule
+fn apply_content_disposition(
+    response: &Response,
+    config: &ProxyConfig,
+    ctx: &RequestContext,
+) -> Result<()> {
+    let file_name = ctx.path.rsplit('/').next().unwrap_or("");
+    let file_name = utils::percent_decode(file_name);
+    let (name, ext) = match file_name.rsplit_once('.') {
+        Some((name, ext)) => (name.to_string(), ext.to_lowercase()),
+        None => (file_name.clone(), String::new()),
+    };
+
+    let rule = match config
+        .content_disposition_rules
+        .iter()
+        .find(|rule| disposition_rule_matches(rule, &ctx.path, &ext))
+    {
+        Some(rule) => rule,
+        None => return Ok(()),
+    };
+
+    let disposition = rule.disposition.as_deref().unwrap_or("attachment");
+    let filename = match &rule.filename {
+        Some(template) => template
+            .replace("{filename}", &file_name)
+            .replace("{name}", &name)
+            .replace("{ext}", &ext)
+            .replace("{date}", &chrono::Utc::now().format("%Y-%m-%d").to_string()),
+        None => file_name.clone(),
+    };
+
+    let value = if filename.is_empty() {
+        disposition.to_string()
+    } else {
+        // Plain ASCII fallback plus RFC 5987 encoded name for non-ASCII characters
+        let fallback: String = filename
+            .chars()
+            .map(|c| {
+                if (c.is_ascii_graphic() && c != '"' && c != '\\') || c == ' ' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!(
+            "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{}",
+            utils::percent_encode(&filename)
+        )
+    };
+
+    response.headers().set("Content-Disposition", &value)?;
+    Ok(())
+}
+
+/// Replace generic or missing Content-Type based on the file extension
+fn fix_content_type(response: &Response, config: &ProxyConfig, ctx: &RequestContext) -> Result<()> {
+    let current = response
+        .headers()
+        .get("Content-Type")?
+        .unwrap_or_default()
+        .to_lowercase();
+    let mime = current.split(';').next().unwrap_or("").trim();
+
+    // Only touch types that carry no real information
+    if !matches!(
+        mime,
+        "" | "text/plain" | "application/octet-stream" | "binary/octet-stream"
+    ) {
+        return Ok(());
+    }
+
+    let ext = match ctx
+        .path
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+    {
+        Some((_, ext)) => ext.to_lowercase(),
+        None => return Ok(()),
+    };
+
+    let corrected = match config.content_type_overrides.get(&ext) {
+        Some(content_type) => content_type.clone(),
+        None => match default_content_type(&ext) {
+            Some(content_type) => content_type.to_string(),
+            None => return Ok(()),
+        },
+    };
+
+    if corrected != current {
+        console_log!(
+            "Correcting Content-Type for {}: {} -> {}",
+            ctx.path,
+            current,
+            corrected
+        );
+        response.headers().set("Content-Type", &corrected)?;
+    }
+
+    Ok(())
+}
+
+/// Built-in extension to Content-Type mapping
+fn default_content_type(ext: &str) -> Option<&'static str> {
+    let content_type = match ext {
+        "wasm" => "application/wasm",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "html" | "htm" => "text/html; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+/// Check whether a Content-Disposition rule applies to path and extension
+fn disposition_rule_matches(rule: &ContentDispositionRule, path: &str, ext: &str) -> bool {
+    if rule.path.is_none() && rule.extensions.is_empty() {
+        return false;
+    }
+
+    let path_ok = rule
+        .path
+        .as_ref()
+        .is_none_or(|pattern| utils::path_matches(pattern, path));
+    let ext_ok = rule.extensions.is_empty()
+        || rule
+            .extensions
+            .iter()
+            .any(|candidate| candidate.trim_start_matches('.').eq_ignore_ascii_case(ext));
+
+    path_ok && ext_ok
+}
+
+/// Keep only headers whose names match the allowlist (case-insensitive, `*` wildcards)
+pub fn retain_allowed_headers(headers: &Headers, allowlist: &HeaderMatcher) -> Result<Headers> {
+    let allowed = Headers::new();
+    for (name, value) in headers.entries() {
+        if allowlist.matches(&name.to_lowercase()) {
+            allowed.append(&name, &value)?;
+        }
+    }
+    Ok(allowed)
+}
+
+/// Rewrite Set-Cookie headers to `SameSite=None; Secure; Partitioned` (CHIPS)
+/// so proxied sessions keep working inside cross-site iframes
+pub fn partition_cookies(response: &Response) -> Result<()> {
+    let headers = response.headers();
+    let cookies: Vec<String> = headers
+        .entries()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("set-cookie"))
+        .map(|(_, value)| value)
+        .collect();
+
+    if cookies.is_empty() {
+        return Ok(());
+    }
+
+    headers.delete("Set-Cookie")?;
+    for cookie in cookies {
+        headers.append("Set-Cookie", &partition_cookie(&cookie))?;
+    }
+    Ok(())
+}
+
+/// Replace cookie attributes conflicting with partitioned third-party use
+fn partition_cookie(cookie: &str) -> String {
+    let mut parts = cookie.split(';').map(str::trim);
+    let name_value = parts.next().unwrap_or("");
+
+    // Domain is dropped as well, the origin domain never matches the proxy host
+    let attributes: Vec<&str> = parts
+        .filter(|attr| {
+            let name = attr.split('=').next().unwrap_or("").trim().to_lowercase();
+            !attr.is_empty()
+                && !matches!(
+                    name.as_str(),
+                    "samesite" | "secure" | "partitioned" | "domain"
+                )
+        })
+        .collect();
+
+    let mut rewritten = name_value.to_string();
+    for attr in attributes {
+        rewritten.push_str("; ");
+        rewritten.push_str(attr);
+    }
+    rewritten.push_str("; SameSite=None; Secure; Partitioned");
+    rewritten
+}
+
+/// Check access control, returning the denial if the request is blocked
+pub fn check_access_control(req: &Request, config: &ProxyConfig) -> Option<AccessDenial> {
+    let denial = config.access_matcher.deny(req)?;
+    console_log!(
+        "Access denied for {} (rule {})",
+        denial.reason,
+        denial.rule_id
+    );
+    Some(denial)
 }
@@ -0,0 +1,349 @@
+use chrono::Utc;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::*;
+
+use crate::{config::ProxyConfig, latency};
+
+/// KV key holding aggregated comparison counters
+const MIRROR_STATS_KEY: &str = "mirror:stats";
+
+/// R2 bucket binding receiving mismatch records
+const MIRROR_BUCKET: &str = "MIRROR_BUCKET";
+
+/// R2 key prefix for mismatch records
+const MISMATCH_PREFIX: &str = "mirror/";
+
+/// Header marking requests as copies, so the shadow backend can skip side
+/// effects such as emails or payments
+const SHADOW_HEADER: &str = "X-Proxy-Shadow";
+
+/// Largest body excerpt stored with a mismatch record
+const MAX_STORED_BODY_BYTES: usize = 64 * 1024;
+
+/// Largest body compared, larger responses are compared by status only
+const MAX_COMPARED_BODY_BYTES: usize = 1024 * 1024;
+
+/// Maximum number of mismatch records listed in the report
+const MAX_REPORTED_MISMATCHES: u32 = 50;
+
+/// Aggregated primary/shadow comparison counters
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MirrorStats {
+    pub compared: u64,
+    pub matched: u64,
+    pub status_mismatches: u64,
+    pub body_mismatches: u64,
+    pub shadow_errors: u64,
+    #[serde(default)]
+    pub oversized_bodies: u64, // Compared by status only
+    pub last_mismatch_at: Option<String>,
+}
+
+/// Stored record of a primary/shadow mismatch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MismatchRecord {
+    request_id: String,
+    method: String,
+    path: String,
+    primary_status: u16,
+    shadow_status: u16,
+    primary_body: String,
+    shadow_body: String,
+    timestamp: String,
+}
+
+/// A sampled request to replay against the shadow backend
+pub struct MirrorJob {
+    pub request_id: String,
+    pub path: String,
+    pub source: Request,
+    pub shadow_url: String,
+    pub primary: Response,
+}
+
+/// Decide whether this request is sampled for mirroring. Only `MIRROR_METHODS`
+/// are copied, in both modes, copies of other requests could repeat their side effects.
+pub fn should_mirror(config: &ProxyConfig, method: &Method) -> bool {
+    config.mirror_url.is_some()
+        && config
+            .mirror_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method.as_ref()))
+        && js_sys::Math::random() < config.mirror_sample_rate
+}
+
+/// Build shadow URL by swapping the backend origin of the target URL
+pub fn shadow_url(config: &ProxyConfig, target_url: &str) -> Option<String> {
+    let mirror = config.mirror_url.as_ref()?;
+    let target = Url::parse(target_url).ok()?;
+    let query = target
+        .query()
+        .map(|query| format!("?{query}"))
+        .unwrap_or_default();
+    Some(format!(
+        "{}{}{query}",
+        mirror.trim_end_matches('/'),
+        target.path()
+    ))
+}
+
+/// Replay request against the shadow backend and discard its response,
+/// without comparing or recording anything beyond failures
+pub async fn shadow(
+    mut source: Request,
+    request_id: String,
+    shadow_url: String,
+    config: ProxyConfig,
+) {
+    if let Err(e) = send(&mut source, &shadow_url, &config).await {
+        console_log!("Shadow request {} failed: {:?}", request_id, e);
+    }
+}
+
+/// Replay request against the shadow backend and record differences
+pub async fn compare(mut job: MirrorJob, env: Env, config: ProxyConfig) {
+    let method = job.source.method();
+    let outcome = match send_shadow(&mut job, &config).await {
+        Ok(shadow) => Some(shadow),
+        Err(e) => {
+            console_log!("Shadow request {} failed: {:?}", job.request_id, e);
+            None
+        }
+    };
+
+    let mut delta = MirrorStats {
+        compared: 1,
+        ..Default::default()
+    };
+
+    match outcome {
+        None => delta.shadow_errors = 1,
+        Some((shadow_status, shadow_body)) => {
+            let primary_status = job.primary.status_code();
+            let primary_body = read_capped(&mut job.primary)
+                .await
+                .unwrap_or(Some(Vec::new()));
+
+            let status_matches = primary_status == shadow_status;
+            let body_matches = match (&primary_body, &shadow_body) {
+                _ if !config.mirror_compare_bodies => true,
+                (Some(primary), Some(shadow)) => {
+                    bodies_match(primary, shadow, &config.mirror_ignore_fields)
+                }
+                _ => {
+                    delta.oversized_bodies = 1;
+                    true
+                }
+            };
+            let primary_body = primary_body.unwrap_or_default();
+            let shadow_body = shadow_body.unwrap_or_default();
+
+            if status_matches && body_matches {
+                delta.matched = 1;
+            } else {
+                delta.status_mismatches = u64::from(!status_matches);
+                delta.body_mismatches = u64::from(status_matches && !body_matches);
+                delta.last_mismatch_at = Some(Utc::now().to_rfc3339());
+
+                let record = MismatchRecord {
+                    request_id: job.request_id.clone(),
+                    method: method.to_string(),
+                    path: job.path.clone(),
+                    primary_status,
+                    shadow_status,
+                    primary_body: excerpt(&primary_body),
+                    shadow_body: excerpt(&shadow_body),
+                    timestamp: Utc::now().to_rfc3339(),
+                };
+                store_mismatch(&env, &record).await;
+            }
+        }
+    }
+
+    update_stats(&env, &delta).await;
+}
+
+/// Send a copy of the request to the shadow backend
+async fn send(source: &mut Request, shadow_url: &str, config: &ProxyConfig) -> Result<Response> {
+    let method = source.method();
+    let headers = source.headers().clone();
+    headers.set(SHADOW_HEADER, "true")?;
+    let mut init = RequestInit::new();
+    init.with_method(method.clone()).with_headers(headers);
+
+    if method != Method::Get && method != Method::Head {
+        let body_bytes = source.bytes().await?;
+        init.with_body(Some(body_bytes.into()));
+    }
+
+    let request = Request::new_with_init(shadow_url, &init)?;
+    latency::fetch_with_timeout(request, config.timeout * 1000).await
+}
+
+/// Send the shadow request, returning its status and body
+async fn send_shadow(job: &mut MirrorJob, config: &ProxyConfig) -> Result<(u16, Option<Vec<u8>>)> {
+    let mut response = send(&mut job.source, &job.shadow_url, config).await?;
+    let status = response.status_code();
+    Ok((status, read_capped(&mut response).await?))
+}
+
+/// Body of a response, `None` when it is larger than `MAX_COMPARED_BODY_BYTES`
+async fn read_capped(response: &mut Response) -> Result<Option<Vec<u8>>> {
+    let mut input = match response.stream() {
+        Ok(input) => input,
+        // Bodies built by the proxy itself are small and not streamable
+        Err(_) => return Ok(Some(response.bytes().await?)),
+    };
+    let mut body = Vec::new();
+    while let Some(chunk) = input.next().await {
+        body.extend(chunk?);
+        if body.len() > MAX_COMPARED_BODY_BYTES {
+            return Ok(None);
+        }
+    }
+    Ok(Some(body))
+}
+
+/// Compare bodies, as normalized JSON when both sides parse
+fn bodies_match(primary: &[u8], shadow: &[u8], ignore_fields: &[String]) -> bool {
+    match (
+        serde_json::from_slice::<Value>(primary),
+        serde_json::from_slice::<Value>(shadow),
+    ) {
+        (Ok(mut primary), Ok(mut shadow)) => {
+            strip_fields(&mut primary, ignore_fields);
+            strip_fields(&mut shadow, ignore_fields);
+            primary == shadow
+        }
+        _ => primary == shadow,
+    }
+}
+
+/// Remove volatile fields (timestamps, ids) at any depth
+fn strip_fields(value: &mut Value, fields: &[String]) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|key, _| !fields.contains(key));
+            map.values_mut()
+                .for_each(|value| strip_fields(value, fields));
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|value| strip_fields(value, fields)),
+        _ => {}
+    }
+}
+
+/// Truncated, lossy text excerpt of a body
+fn excerpt(body: &[u8]) -> String {
+    String::from_utf8_lossy(&body[..body.len().min(MAX_STORED_BODY_BYTES)]).to_string()
+}
+
+/// Store mismatch record in R2, grouped by day
+async fn store_mismatch(env: &Env, record: &MismatchRecord) {
+    let bucket = match env.bucket(MIRROR_BUCKET) {
+        Ok(bucket) => bucket,
+        Err(_) => {
+            console_log!(
+                "Mirror mismatch {} not stored: {} binding missing",
+                record.request_id,
+                MIRROR_BUCKET
+            );
+            return;
+        }
+    };
+
+    let key = format!(
+        "{MISMATCH_PREFIX}{}/{}.json",
+        Utc::now().format("%Y-%m-%d"),
+        record.request_id
+    );
+    let serialized = match serde_json::to_string(record) {
+        Ok(serialized) => serialized,
+        Err(_) => return,
+    };
+
+    if let Err(e) = bucket.put(key, serialized).execute().await {
+        console_log!("Failed to store mirror mismatch: {:?}", e);
+    }
+}
+
+/// Add comparison outcome to the aggregated counters
+async fn update_stats(env: &Env, delta: &MirrorStats) {
+    let kv = match env.kv("PROXY_KV") {
+        Ok(kv) => kv,
+        Err(_) => return,
+    };
+
+    // Read-modify-write is approximate under concurrency, which is fine for a report
+    let mut stats = kv
+        .get(MIRROR_STATS_KEY)
+        .json::<MirrorStats>()
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    stats.compared += delta.compared;
+    stats.matched += delta.matched;
+    stats.status_mismatches += delta.status_mismatches;
+    stats.body_mismatches += delta.body_mismatches;
+    stats.shadow_errors += delta.shadow_errors;
+    stats.oversized_bodies += delta.oversized_bodies;
+    if delta.last_mismatch_at.is_some() {
+        stats.last_mismatch_at = delta.last_mismatch_at.clone();
+    }
+
+    if let Ok(serialized) = serde_json::to_string(&stats) {
+        if let Ok(put) = kv.put(MIRROR_STATS_KEY, serialized) {
+            if let Err(e) = put.execute().await {
+                console_log!("Failed to store mirror stats: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Summarize comparisons and list mismatches for a day (`?date=YYYY-MM-DD`)
+pub async fn handle_report(url: &Url, env: &Env) -> Result<Response> {
+    let date = url
+        .query_pairs()
+        .find(|(key, _)| key == "date")
+        .map(|(_, date)| date.to_string())
+        .unwrap_or_else(|| Utc::now().format("%Y-%m-%d").to_string());
+
+    let stats = env
+        .kv("PROXY_KV")?
+        .get(MIRROR_STATS_KEY)
+        .json::<MirrorStats>()
+        .await?
+        .unwrap_or_default();
+
+    let mismatches: Vec<String> = match env.bucket(MIRROR_BUCKET) {
+        Ok(bucket) => bucket
+            .list()
+            .prefix(format!("{MISMATCH_PREFIX}{date}/"))
+            .limit(MAX_REPORTED_MISMATCHES)
+            .execute()
+            .await?
+            .objects()
+            .iter()
+            .map(|object| object.key())
+            .collect(),
+        Err(_) => vec![],
+    };
+
+    let match_rate = if stats.compared > 0 {
+        stats.matched as f64 / stats.compared as f64 * 100.0
+    } else {
+        0.0
+    };
+
+    Response::from_json(&serde_json::json!({
+        "stats": stats,
+        "match_rate": format!("{match_rate:.2}%"),
+        "date": date,
+        "mismatches": mismatches,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
@@ -0,0 +1,117 @@
+use chrono::Utc;
+use worker::*;
+
+use crate::{
+    config::{MockResponse, ProxyConfig},
+    context::RequestContext,
+    utils,
+};
+
+/// Mock response configured for the request, if any
+pub fn route_for<'a>(
+    config: &'a ProxyConfig,
+    method: &Method,
+    path: &str,
+) -> Option<&'a MockResponse> {
+    config.mock_responses.iter().find(|mock| {
+        utils::path_matches(&mock.path, path)
+            && (mock.methods.is_empty()
+                || mock
+                    .methods
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(method.as_ref())))
+    })
+}
+
+/// Value of a placeholder, `None` for unknown ones
+fn placeholder(name: &str, req: &Request, url: &Url, ctx: &RequestContext) -> Option<String> {
+    match name {
+        "request_id" => Some(ctx.request_id.clone()),
+        "ray_id" => Some(ctx.ray_id.clone().unwrap_or_default()),
+        "method" => Some(req.method().to_string()),
+        "path" => Some(ctx.path.clone()),
+        "timestamp" => Some(Utc::now().to_rfc3339()),
+        _ => {
+            if let Some(param) = name.strip_prefix("query.") {
+                Some(
+                    url.query_pairs()
+                        .find(|(key, _)| key == param)
+                        .map(|(_, value)| value.to_string())
+                        .unwrap_or_default(),
+                )
+            } else {
+                name.strip_prefix("header.")
+                    .map(|header| ctx.header(header).unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// Escape a request value for the body's content type, so clients can't break
+/// out of JSON strings or inject markup
+fn escape(value: &str, content_type: &str) -> String {
+    if content_type.contains("json") {
+        let quoted = serde_json::to_string(value).unwrap_or_default();
+        quoted[1..quoted.len() - 1].to_string()
+    } else if content_type.contains("html") || content_type.contains("xml") {
+        utils::escape_html(value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Fill in `{{...}}` placeholders, leaving unknown ones as written
+fn render(
+    template: &str,
+    content_type: &str,
+    req: &Request,
+    ctx: &RequestContext,
+) -> Result<String> {
+    let url = req.url()?;
+    let mut body = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        body.push_str(&rest[..start]);
+        let token = &rest[start..start + end + 2];
+        match placeholder(token[2..token.len() - 2].trim(), req, &url, ctx) {
+            Some(value) => body.push_str(&escape(&value, content_type)),
+            None => body.push_str(token),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    body.push_str(rest);
+    Ok(body)
+}
+
+/// Answer the request with the mock instead of a backend
+pub fn respond(mock: &MockResponse, req: &Request, ctx: &RequestContext) -> Result<Response> {
+    let headers = Headers::new();
+    for (name, value) in &mock.headers {
+        headers.set(name, value)?;
+    }
+    if !headers.has("Content-Type")? {
+        let trimmed = mock.body.trim_start();
+        let content_type = if trimmed.starts_with('{') || trimmed.starts_with('[') {
+            "application/json"
+        } else {
+            "text/plain; charset=utf-8"
+        };
+        headers.set("Content-Type", content_type)?;
+    }
+    if !headers.has("Cache-Control")? {
+        headers.set("Cache-Control", "no-store")?;
+    }
+    headers.set("X-Proxy-Mock", "true")?;
+
+    let content_type = headers
+        .get("Content-Type")?
+        .unwrap_or_default()
+        .to_lowercase();
+    let body = render(&mock.body, &content_type, req, ctx)?;
+    Ok(Response::ok(body)?
+        .with_status(mock.status.unwrap_or(200))
+        .with_headers(headers))
+}
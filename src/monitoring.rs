@@ -1,16 +1,44 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap};
 
 use chrono::Utc;
 use serde_json::Value;
 use worker::*;
 
+/// Counters kept across the requests of an isolate, `Metrics` only lives for one
+#[derive(Clone, Default)]
+struct Counters {
+    cache_hits: u64,
+    cache_misses: u64,
+    cache_bypasses: u64,
+    tenant_requests: HashMap<String, u64>,
+    label_requests: HashMap<String, u64>,
+    tag_requests: HashMap<String, HashMap<String, u64>>,
+    soft_404s: HashMap<String, u64>,
+    latency_budget_violations: HashMap<String, u64>,
+}
+
+/// Keys counted by name per dimension, tenant ids and paths may come from clients
+const MAX_TRACKED_KEYS: usize = 1000;
+
+thread_local! {
+    static COUNTERS: RefCell<Counters> = RefCell::new(Counters::default());
+}
+
+/// Count a key, or `other` once the dimension tracks `MAX_TRACKED_KEYS` keys
+fn increment_bounded(counts: &mut HashMap<String, u64>, key: &str) {
+    let key = if counts.contains_key(key) || counts.len() < MAX_TRACKED_KEYS {
+        key
+    } else {
+        "other"
+    };
+    *counts.entry(key.to_string()).or_insert(0) += 1;
+}
+
 /// Monitoring metrics
 pub struct Metrics {
     request_count: HashMap<String, u64>,
     error_count: HashMap<String, u64>,
     response_times: Vec<f64>,
-    cache_hits: u64,
-    cache_misses: u64,
 }
 
 impl Metrics {
@@ -19,8 +47,6 @@ impl Metrics {
             request_count: HashMap::new(),
             error_count: HashMap::new(),
             response_times: Vec::new(),
-            cache_hits: 0,
-            cache_misses: 0,
         }
     }
 
@@ -34,6 +60,37 @@ impl Metrics {
         console_log!("Request started: {}", request_id);
     }
 
+    /// Record request for tenant dimension
+    pub fn record_tenant(&mut self, tenant: &str) {
+        COUNTERS.with(|counters| {
+            increment_bounded(&mut counters.borrow_mut().tenant_requests, tenant);
+        });
+    }
+
+    /// Record request for access label dimension
+    pub fn record_label(&mut self, label: &str) {
+        COUNTERS.with(|counters| {
+            *counters
+                .borrow_mut()
+                .label_requests
+                .entry(label.to_string())
+                .or_insert(0) += 1;
+        });
+    }
+
+    /// Record request for a metric tag dimension
+    pub fn record_tag(&mut self, tag: &str, value: &str) {
+        COUNTERS.with(|counters| {
+            *counters
+                .borrow_mut()
+                .tag_requests
+                .entry(tag.to_string())
+                .or_default()
+                .entry(value.to_string())
+                .or_insert(0) += 1;
+        });
+    }
+
     /// Record request completion
     pub fn record_request_complete(&mut self, request_id: &str, status_code: u16) {
         console_log!(
@@ -62,17 +119,52 @@ impl Metrics {
 
     /// Record cache hit
     pub fn record_cache_hit(&mut self, request_id: &str) {
-        self.cache_hits += 1;
+        COUNTERS.with(|counters| counters.borrow_mut().cache_hits += 1);
         console_log!("Cache hit for request: {}", request_id);
     }
 
     /// Record cache miss
-    #[allow(dead_code)]
     pub fn record_cache_miss(&mut self, request_id: &str) {
-        self.cache_misses += 1;
+        COUNTERS.with(|counters| counters.borrow_mut().cache_misses += 1);
         console_log!("Cache miss for request: {}", request_id);
     }
 
+    /// Record request that did not use the cache
+    pub fn record_cache_bypass(&mut self, request_id: &str) {
+        COUNTERS.with(|counters| counters.borrow_mut().cache_bypasses += 1);
+        console_log!("Cache bypass for request: {}", request_id);
+    }
+
+    /// Record soft 404 (200 response with not-found content) by path
+    pub fn record_soft_404(&mut self, request_id: &str, path: &str) {
+        COUNTERS.with(|counters| increment_bounded(&mut counters.borrow_mut().soft_404s, path));
+        console_log!("Soft 404 recorded for {}: {}", request_id, path);
+    }
+
+    /// Record latency budget violation by route pattern
+    pub fn record_latency_budget_violation(
+        &mut self,
+        request_id: &str,
+        route: &str,
+        time_ms: f64,
+        budget_ms: u64,
+    ) {
+        COUNTERS.with(|counters| {
+            *counters
+                .borrow_mut()
+                .latency_budget_violations
+                .entry(route.to_string())
+                .or_insert(0) += 1;
+        });
+        console_log!(
+            "Latency budget exceeded for {} on {}: {}ms > {}ms",
+            request_id,
+            route,
+            time_ms,
+            budget_ms
+        );
+    }
+
     /// Get statistics
     pub async fn get_stats(&self) -> Value {
         let avg_response_time = if self.response_times.is_empty() {
@@ -89,8 +181,10 @@ impl Metrics {
             0.0
         };
 
-        let cache_hit_rate = if self.cache_hits + self.cache_misses > 0 {
-            (self.cache_hits as f64 / (self.cache_hits + self.cache_misses) as f64) * 100.0
+        let counters = COUNTERS.with(|counters| counters.borrow().clone());
+        let cache_lookups = counters.cache_hits + counters.cache_misses;
+        let cache_hit_rate = if cache_lookups > 0 {
+            (counters.cache_hits as f64 / cache_lookups as f64) * 100.0
         } else {
             0.0
         };
@@ -100,9 +194,16 @@ impl Metrics {
             "total_errors": total_errors,
             "error_rate": format!("{:.2}%", error_rate),
             "average_response_time": format!("{:.2}ms", avg_response_time),
-            "cache_hits": self.cache_hits,
-            "cache_misses": self.cache_misses,
+            "cache_hits": counters.cache_hits,
+            "cache_misses": counters.cache_misses,
+            "cache_bypasses": counters.cache_bypasses,
             "cache_hit_rate": format!("{:.2}%", cache_hit_rate),
+            "tenant_requests": counters.tenant_requests,
+            "label_requests": counters.label_requests,
+            "tag_requests": counters.tag_requests,
+            "soft_404s": counters.soft_404s.values().sum::<u64>(),
+            "soft_404_paths": counters.soft_404s,
+            "latency_budget_violations": counters.latency_budget_violations,
             "timestamp": Utc::now().to_rfc3339()
         })
     }
@@ -113,7 +214,6 @@ impl Metrics {
         self.request_count.clear();
         self.error_count.clear();
         self.response_times.clear();
-        self.cache_hits = 0;
-        self.cache_misses = 0;
+        COUNTERS.with(|counters| *counters.borrow_mut() = Counters::default());
     }
 }
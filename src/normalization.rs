@@ -0,0 +1,87 @@
+use worker::*;
+
+use crate::{
+    config::{CacheRouteRule, ProxyConfig},
+    utils,
+};
+
+/// Client headers that change what many origins render without being part
+/// of the cache key, the classic cache-poisoning vectors
+pub const UNKEYED_HEADERS: &[&str] = &[
+    "X-Forwarded-Host",
+    "X-Forwarded-Scheme",
+    "X-Forwarded-Server",
+    "X-Forwarded-Port",
+    "X-Forwarded-Prefix",
+    "X-Original-URL",
+    "X-Rewrite-URL",
+    "X-Original-Host",
+    "X-Host",
+    "X-HTTP-Method-Override",
+    "X-HTTP-Method",
+    "X-Method-Override",
+    "Forwarded",
+];
+
+/// Handling of unkeyed headers on a route
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnkeyedHeaders {
+    Forward, // Forwarded as sent
+    Strip,   // Removed before forwarding
+    Key,     // Forwarded and part of the cache key
+}
+
+impl From<&str> for UnkeyedHeaders {
+    fn from(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "strip" => UnkeyedHeaders::Strip,
+            "key" => UnkeyedHeaders::Key,
+            _ => UnkeyedHeaders::Forward,
+        }
+    }
+}
+
+/// Handling of unkeyed headers under a cache route: its `unkeyed_headers`,
+/// else stripping with the `hardened` profile and forwarding otherwise
+pub fn mode(config: &ProxyConfig, route: Option<&CacheRouteRule>) -> UnkeyedHeaders {
+    match route.and_then(|route| route.unkeyed_headers.as_deref()) {
+        Some(mode) => UnkeyedHeaders::from(mode),
+        None if config
+            .normalization_profile
+            .eq_ignore_ascii_case("hardened") =>
+        {
+            UnkeyedHeaders::Strip
+        }
+        None => UnkeyedHeaders::Forward,
+    }
+}
+
+/// Handling of unkeyed headers for a request path
+fn mode_for_path(config: &ProxyConfig, path: &str) -> UnkeyedHeaders {
+    let route = config
+        .cache_routes
+        .iter()
+        .find(|rule| utils::path_matches(&rule.path, path));
+    mode(config, route)
+}
+
+/// Remove the client's unkeyed headers when the route strips them
+pub fn strip_unkeyed(headers: &Headers, config: &ProxyConfig, path: &str) -> Result<()> {
+    if mode_for_path(config, path) == UnkeyedHeaders::Strip {
+        for name in UNKEYED_HEADERS {
+            headers.delete(name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Cache key part made of the unkeyed headers the client sent
+pub fn key_part(req: &Request) -> Result<String> {
+    let mut part = String::new();
+    for name in UNKEYED_HEADERS {
+        if let Some(value) = req.headers().get(name)? {
+            part.push_str(&format!("|{name}={value}"));
+        }
+    }
+    Ok(part)
+}
@@ -0,0 +1,200 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{
+    config::{OAuth2ClientConfig, ProxyConfig},
+    latency, secrets, utils,
+};
+
+/// KV key prefix of cached access tokens (`oauth2:token:{client hash}`)
+const TOKEN_PREFIX: &str = "oauth2:token:";
+
+/// Tokens are refreshed this many seconds before they expire
+const REFRESH_MARGIN_SECONDS: f64 = 60.0;
+
+/// Lifetime assumed when the token endpoint omits `expires_in`
+const DEFAULT_EXPIRES_IN: u64 = 3600;
+
+/// Timeout of token endpoint requests
+const TOKEN_TIMEOUT_MS: u64 = 10_000;
+
+/// Access token with its expiry in Unix milliseconds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedToken {
+    access_token: String,
+    expires_at: f64,
+}
+
+/// Token endpoint response
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+thread_local! {
+    /// Tokens fetched by this isolate, keyed like their KV entries
+    static TOKENS: RefCell<HashMap<String, CachedToken>> = RefCell::new(HashMap::new());
+}
+
+/// OAuth2 client of the backend at `backend` (scheme and host), if it has one
+fn client_for<'a>(config: &'a ProxyConfig, backend: &str) -> Option<&'a OAuth2ClientConfig> {
+    config
+        .backend_configs
+        .iter()
+        .find(|b| b.url.split('/').take(3).collect::<Vec<_>>().join("/") == backend)
+        .and_then(|b| b.oauth2.as_ref())
+}
+
+/// Tokens are shared by every backend using the same client and scope
+fn token_key(client: &OAuth2ClientConfig) -> String {
+    utils::sha256_hash(&format!(
+        "{}\n{}\n{}\n{}",
+        client.token_url,
+        client.client_id_var,
+        client.scope.as_deref().unwrap_or_default(),
+        client.audience.as_deref().unwrap_or_default()
+    ))
+}
+
+/// Set `Authorization: Bearer <token>` on a request to a backend that uses
+/// OAuth2 client credentials, fetching a new token when needed
+pub async fn authorize(
+    headers: &Headers,
+    backend: &str,
+    config: &ProxyConfig,
+    env: &Env,
+) -> Result<()> {
+    let client = match client_for(config, backend) {
+        Some(client) => client,
+        None => return Ok(()),
+    };
+
+    let token = access_token(client, env).await?;
+    headers.set("Authorization", &format!("Bearer {token}"))
+}
+
+/// Forget the token of a backend that rejected it, so the next request
+/// fetches a new one
+pub async fn invalidate(backend: &str, config: &ProxyConfig, env: &Env) {
+    let client = match client_for(config, backend) {
+        Some(client) => client,
+        None => return,
+    };
+
+    let key = token_key(client);
+    TOKENS.with(|tokens| tokens.borrow_mut().remove(&key));
+    if let Ok(kv) = env.kv("PROXY_KV") {
+        if let Err(e) = kv.delete(&format!("{TOKEN_PREFIX}{key}")).await {
+            console_log!("Failed to delete OAuth2 token of {}: {:?}", backend, e);
+        }
+    }
+    console_log!("Invalidated OAuth2 token of {}", backend);
+}
+
+/// Valid access token from this isolate, KV, or the token endpoint
+async fn access_token(client: &OAuth2ClientConfig, env: &Env) -> Result<String> {
+    let key = token_key(client);
+    let now = js_sys::Date::now();
+    let usable = |token: &CachedToken| token.expires_at - REFRESH_MARGIN_SECONDS * 1000.0 > now;
+
+    let local = TOKENS.with(|tokens| tokens.borrow().get(&key).filter(|t| usable(t)).cloned());
+    if let Some(token) = local {
+        return Ok(token.access_token);
+    }
+
+    let kv = env.kv("PROXY_KV")?;
+    let kv_key = format!("{TOKEN_PREFIX}{key}");
+    if let Some(token) = kv.get(&kv_key).json::<CachedToken>().await? {
+        if usable(&token) {
+            let access_token = token.access_token.clone();
+            TOKENS.with(|tokens| tokens.borrow_mut().insert(key, token));
+            return Ok(access_token);
+        }
+    }
+
+    let token = fetch_token(client, env).await?;
+    let ttl = ((token.expires_at - now) / 1000.0 - REFRESH_MARGIN_SECONDS) as u64;
+    // KV rejects expirations shorter than 60 seconds, such tokens stay per isolate
+    if ttl >= 60 {
+        if let Err(e) = kv
+            .put(&kv_key, serde_json::to_string(&token)?)?
+            .expiration_ttl(ttl)
+            .execute()
+            .await
+        {
+            console_log!("Failed to store OAuth2 token: {:?}", e);
+        }
+    }
+    let access_token = token.access_token.clone();
+    TOKENS.with(|tokens| tokens.borrow_mut().insert(key, token));
+    Ok(access_token)
+}
+
+/// Request a token with the client credentials grant
+async fn fetch_token(client: &OAuth2ClientConfig, env: &Env) -> Result<CachedToken> {
+    let client_id = credential(env, &client.client_id_var).await?;
+    let client_secret = credential(env, &client.client_secret_var).await?;
+
+    let mut form = vec![("grant_type", "client_credentials".to_string())];
+    if let Some(scope) = &client.scope {
+        form.push(("scope", scope.clone()));
+    }
+    if let Some(audience) = &client.audience {
+        form.push(("audience", audience.clone()));
+    }
+
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/x-www-form-urlencoded")?;
+    headers.set("Accept", "application/json")?;
+    if client.auth_method.as_deref() == Some("post") {
+        form.push(("client_id", client_id));
+        form.push(("client_secret", client_secret));
+    } else {
+        // client_secret_basic, credentials are form-encoded before base64 (RFC 6749 2.3.1)
+        let credentials = format!(
+            "{}:{}",
+            utils::percent_encode(&client_id),
+            utils::percent_encode(&client_secret)
+        );
+        headers.set(
+            "Authorization",
+            &format!("Basic {}", utils::base64_encode(credentials.as_bytes())),
+        )?;
+    }
+    let body = form
+        .iter()
+        .map(|(name, value)| format!("{name}={}", utils::percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let mut init = RequestInit::new();
+    init.with_method(Method::Post)
+        .with_headers(headers)
+        .with_body(Some(body.into()));
+    let request = Request::new_with_init(&client.token_url, &init)?;
+    let mut response = latency::fetch_with_timeout(request, TOKEN_TIMEOUT_MS).await?;
+    if !(200..300).contains(&response.status_code()) {
+        return Err(Error::from(format!(
+            "Token endpoint answered with status {}",
+            response.status_code()
+        )));
+    }
+
+    let token: TokenResponse = response.json().await?;
+    console_log!("Fetched OAuth2 token from {}", client.token_url);
+    Ok(CachedToken {
+        access_token: token.access_token,
+        expires_at: js_sys::Date::now()
+            + token.expires_in.unwrap_or(DEFAULT_EXPIRES_IN) as f64 * 1000.0,
+    })
+}
+
+async fn credential(env: &Env, name: &str) -> Result<String> {
+    secrets::get(env, name)
+        .await?
+        .ok_or_else(|| Error::from(format!("OAuth2 credential {name} is not set")))
+}
@@ -0,0 +1,106 @@
+use worker::*;
+
+use crate::{cache, config::ProxyConfig, context::RequestContext, utils};
+
+/// Encodings of pre-compressed siblings in order of preference, with their suffix
+const VARIANTS: &[(&str, &str)] = &[("br", ".br"), ("gzip", ".gz")];
+
+/// Types siblings are commonly stored with instead of the original's
+const GENERIC_TYPES: &[&str] = &[
+    "application/octet-stream",
+    "binary/octet-stream",
+    "application/x-brotli",
+    "application/brotli",
+    "application/gzip",
+    "application/x-gzip",
+];
+
+/// Content types of compressible static assets by extension
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("svg", "image/svg+xml"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("wasm", "application/wasm"),
+];
+
+/// Sibling encodings worth trying for a request, most preferred first: GET
+/// and HEAD requests under `PRECOMPRESSED_ROUTES` without a `Range`, for the
+/// encodings the client accepts
+pub fn candidates(
+    method: &Method,
+    config: &ProxyConfig,
+    ctx: &RequestContext,
+) -> Vec<(&'static str, &'static str)> {
+    if !matches!(method, Method::Get | Method::Head)
+        || ctx.path.ends_with('/')
+        || ctx.header("Range").is_some()
+        || !config
+            .precompressed_routes
+            .iter()
+            .any(|pattern| utils::path_matches(pattern, &ctx.path))
+    {
+        return vec![];
+    }
+
+    let accept_encoding = ctx.header("Accept-Encoding").unwrap_or_default();
+    VARIANTS
+        .iter()
+        .filter(|(encoding, _)| cache::accepts_encoding(&accept_encoding, encoding))
+        .copied()
+        .collect()
+}
+
+/// URL of the sibling with `suffix` appended to the path
+pub fn variant_url(target_url: &str, suffix: &str) -> Result<String> {
+    let mut url = Url::parse(target_url)?;
+    let path = format!("{}{suffix}", url.path());
+    url.set_path(&path);
+    Ok(url.to_string())
+}
+
+/// Serve a sibling's body as stored, labeled with its encoding and the
+/// original's content type
+pub fn serve(mut response: Response, encoding: &str, path: &str) -> Result<Response> {
+    let headers = response.headers().clone();
+    headers.set("Content-Encoding", encoding)?;
+    let content_type = headers
+        .get("Content-Type")?
+        .unwrap_or_default()
+        .to_lowercase();
+    let content_type = content_type.split(';').next().unwrap_or_default().trim();
+    if content_type.is_empty() || GENERIC_TYPES.contains(&content_type) {
+        let extension = path
+            .rsplit('/')
+            .next()
+            .and_then(|name| name.rsplit_once('.'));
+        match extension.and_then(|(_, extension)| {
+            CONTENT_TYPES
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        }) {
+            Some((_, content_type)) => headers.set("Content-Type", content_type)?,
+            None => headers.delete("Content-Type")?,
+        }
+    }
+    let vary = headers.get("Vary")?.unwrap_or_default();
+    if !vary.to_lowercase().contains("accept-encoding") {
+        headers.append("Vary", "Accept-Encoding")?;
+    }
+
+    // Sent as stored, the runtime must not encode the body again
+    let builder = Response::builder()
+        .with_status(response.status_code())
+        .with_headers(headers)
+        .with_encode_body(EncodeBody::Manual);
+    match response.stream() {
+        Ok(body) => builder.from_stream(body),
+        // HEAD and 304 responses have no body
+        Err(_) => Ok(builder.empty()),
+    }
+}
@@ -0,0 +1,500 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+};
+
+use chrono::{Datelike, Duration, NaiveDate, Timelike, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{alerts, config::ProxyConfig, events::RequestEvent};
+
+/// R2 bucket binding receiving traffic reports
+const REPORTS_BUCKET: &str = "REPORTS_BUCKET";
+
+/// R2 key prefix for traffic reports
+const REPORT_PREFIX: &str = "reports/";
+
+/// KV key prefix of daily traffic counters
+const TRAFFIC_STATS_PREFIX: &str = "report:traffic:";
+
+/// Days daily counters are kept in KV, for weekly reports and dashboards
+pub const TRAFFIC_STATS_DAYS: u32 = 35;
+
+/// Interval between flushes of this isolate's counters to KV
+const FLUSH_INTERVAL_MS: f64 = 60_000.0;
+
+/// Distinct paths counted per day, further paths are counted as "(other)"
+const MAX_TRACKED_PATHS: usize = 1000;
+
+/// Paths listed in a report
+const TOP_PATHS: usize = 20;
+
+/// Traffic counters of one day
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrafficStats {
+    pub requests: u64,
+    pub errors: u64, // 5xx responses and proxy errors
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub paths: BTreeMap<String, u64>,
+    pub backends: BTreeMap<String, BackendTraffic>,
+    #[serde(default)]
+    pub hours: BTreeMap<u32, HourlyTraffic>, // Keyed by UTC hour of the day
+}
+
+/// Traffic counters of one hour, for time series
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HourlyTraffic {
+    pub requests: u64,
+    pub errors: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub backends: BTreeMap<String, BackendTraffic>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, BTreeMap<String, BackendTraffic>>, // By metric tag and value
+}
+
+/// Requests a configured backend (or tag value) served, and how many of them failed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackendTraffic {
+    pub requests: u64,
+    pub failures: u64,
+}
+
+impl BackendTraffic {
+    fn add(&mut self, other: &BackendTraffic) {
+        self.requests += other.requests;
+        self.failures += other.failures;
+    }
+}
+
+impl HourlyTraffic {
+    fn add(&mut self, other: &HourlyTraffic) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        for (backend, traffic) in &other.backends {
+            self.backends
+                .entry(backend.clone())
+                .or_default()
+                .add(traffic);
+        }
+        for (tag, values) in &other.tags {
+            let counts = self.tags.entry(tag.clone()).or_default();
+            for (value, traffic) in values {
+                counts.entry(value.clone()).or_default().add(traffic);
+            }
+        }
+    }
+}
+
+impl TrafficStats {
+    fn add(&mut self, other: &TrafficStats) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        for (path, count) in &other.paths {
+            self.count_path(path, *count);
+        }
+        for (backend, traffic) in &other.backends {
+            self.backends
+                .entry(backend.clone())
+                .or_default()
+                .add(traffic);
+        }
+        for (hour, traffic) in &other.hours {
+            self.hours.entry(*hour).or_default().add(traffic);
+        }
+    }
+
+    fn count_path(&mut self, path: &str, count: u64) {
+        let path = if self.paths.len() < MAX_TRACKED_PATHS || self.paths.contains_key(path) {
+            path
+        } else {
+            "(other)"
+        };
+        *self.paths.entry(path.to_string()).or_insert(0) += count;
+    }
+}
+
+/// Report over one or more days of traffic
+#[derive(Debug, Serialize)]
+struct TrafficReport {
+    period: &'static str, // "daily" or "weekly"
+    start: String,
+    end: String,
+    generated_at: String,
+    requests: u64,
+    errors: u64,
+    error_rate: f64, // Percent
+    cache_hits: u64,
+    cache_misses: u64,
+    cache_hit_rate: f64, // Percent
+    top_paths: Vec<PathRequests>,
+    backends: Vec<BackendAvailability>,
+}
+
+#[derive(Debug, Serialize)]
+struct PathRequests {
+    path: String,
+    requests: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct BackendAvailability {
+    backend: String,
+    requests: u64,
+    failures: u64,
+    availability: f64, // Percent of requests without a failure
+}
+
+thread_local! {
+    /// Counters of this isolate not yet flushed to KV, keyed by date
+    static PENDING: RefCell<BTreeMap<String, TrafficStats>> = const { RefCell::new(BTreeMap::new()) };
+    static LAST_FLUSH: Cell<f64> = const { Cell::new(0.0) };
+}
+
+/// Count a finished request
+pub fn record(event: &RequestEvent, config: &ProxyConfig) {
+    let now = Utc::now();
+    let date = now.format("%Y-%m-%d").to_string();
+    let is_error = event.status >= 500 || event.error.is_some();
+    let (cache_hits, cache_misses) = match event.cache {
+        Some("hit" | "coalesced" | "revalidated" | "stale") => (1, 0),
+        Some("miss") => (0, 1),
+        _ => (0, 0),
+    };
+
+    let mut delta = HourlyTraffic {
+        requests: 1,
+        errors: u64::from(is_error),
+        cache_hits,
+        cache_misses,
+        backends: BTreeMap::new(),
+        tags: BTreeMap::new(),
+    };
+    // URL-proxy targets are arbitrary origins, only configured backends are reported
+    if let Some(backend) = event.backend.as_ref().filter(|backend| {
+        config.backends.contains(backend)
+            || config
+                .backend_pools
+                .iter()
+                .any(|pool| pool.backends.contains(backend))
+    }) {
+        delta.backends.insert(
+            backend.clone(),
+            BackendTraffic {
+                requests: 1,
+                failures: u64::from(is_error),
+            },
+        );
+    }
+
+    for (tag, value) in &event.tags {
+        delta.tags.entry(tag.clone()).or_default().insert(
+            value.clone(),
+            BackendTraffic {
+                requests: 1,
+                failures: u64::from(is_error),
+            },
+        );
+    }
+
+    PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let stats = pending.entry(date).or_default();
+        stats.requests += delta.requests;
+        stats.errors += delta.errors;
+        stats.cache_hits += delta.cache_hits;
+        stats.cache_misses += delta.cache_misses;
+        for (backend, traffic) in &delta.backends {
+            stats
+                .backends
+                .entry(backend.clone())
+                .or_default()
+                .add(traffic);
+        }
+        stats.count_path(&event.path, 1);
+        stats.hours.entry(now.hour()).or_default().add(&delta);
+    });
+}
+
+/// Whether requests are counted, for reports, dashboards, the status page,
+/// the metrics sink or error budget alerts
+pub fn is_recording(config: &ProxyConfig) -> bool {
+    config.reports_enabled
+        || config.grafana_enabled
+        || config.status_page_enabled
+        || config.metrics_push_url.is_some()
+        || (alerts::is_enabled(config) && config.alert_slo_target > 0.0)
+}
+
+/// Daily counters of the dates from `start` to `end`, skipping days without traffic
+pub async fn load_days(
+    env: &Env,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<Vec<(NaiveDate, TrafficStats)>> {
+    let kv = env.kv("PROXY_KV")?;
+    let mut days = Vec::new();
+    for date in start.iter_days().take_while(|date| *date <= end) {
+        if let Some(stats) = kv
+            .get(&format!("{TRAFFIC_STATS_PREFIX}{date}"))
+            .json::<TrafficStats>()
+            .await?
+        {
+            days.push((date, stats));
+        }
+    }
+    Ok(days)
+}
+
+/// Add this isolate's counters to the daily totals in KV
+pub async fn flush(env: Env) {
+    let now = js_sys::Date::now();
+    if now - LAST_FLUSH.with(Cell::get) < FLUSH_INTERVAL_MS {
+        return;
+    }
+    LAST_FLUSH.with(|last_flush| last_flush.set(now));
+    let pending = PENDING.with(|pending| std::mem::take(&mut *pending.borrow_mut()));
+
+    let kv = match env.kv("PROXY_KV") {
+        Ok(kv) => kv,
+        Err(_) => return,
+    };
+
+    // Read-modify-write is approximate under concurrency, which is fine for a report
+    for (date, delta) in pending {
+        let stats_key = format!("{TRAFFIC_STATS_PREFIX}{date}");
+        let mut stats = kv
+            .get(&stats_key)
+            .json::<TrafficStats>()
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        stats.add(&delta);
+
+        if let Ok(serialized) = serde_json::to_string(&stats) {
+            if let Ok(put) = kv.put(&stats_key, serialized) {
+                if let Err(e) = put
+                    .expiration_ttl(u64::from(TRAFFIC_STATS_DAYS) * 86400)
+                    .execute()
+                    .await
+                {
+                    console_log!("Failed to store traffic stats: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Write yesterday's report (and on Mondays last week's) to R2 unless already
+/// written, then delete reports older than the retention period
+pub async fn run(config: &ProxyConfig, env: &Env) {
+    if !config.reports_enabled {
+        return;
+    }
+
+    let bucket = match env.bucket(REPORTS_BUCKET) {
+        Ok(bucket) => bucket,
+        Err(_) => {
+            console_log!(
+                "Traffic reports not written: {} binding missing",
+                REPORTS_BUCKET
+            );
+            return;
+        }
+    };
+
+    let today = Utc::now().date_naive();
+    let yesterday = today - Duration::days(1);
+    let wrote_daily = match write_report(&bucket, env, "daily", yesterday, yesterday).await {
+        Ok(written) => written,
+        Err(e) => {
+            console_log!("Failed to write daily traffic report: {:?}", e);
+            false
+        }
+    };
+
+    if today.weekday() == Weekday::Mon {
+        let week_start = today - Duration::days(7);
+        if let Err(e) = write_report(&bucket, env, "weekly", week_start, yesterday).await {
+            console_log!("Failed to write weekly traffic report: {:?}", e);
+        }
+    }
+
+    // Once a day is enough for cleanup
+    if wrote_daily && config.reports_retention_days > 0 {
+        let cutoff = today - Duration::days(i64::from(config.reports_retention_days));
+        if let Err(e) = delete_expired(&bucket, cutoff).await {
+            console_log!("Failed to delete expired traffic reports: {:?}", e);
+        }
+    }
+}
+
+/// Write the JSON and CSV report of a period. Returns `false` when it was
+/// already written by an earlier run.
+async fn write_report(
+    bucket: &Bucket,
+    env: &Env,
+    period: &'static str,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<bool> {
+    let key = format!("{REPORT_PREFIX}{period}/{start}");
+    if bucket.head(format!("{key}.json")).await?.is_some() {
+        return Ok(false);
+    }
+
+    let mut stats = TrafficStats::default();
+    for (_, day) in load_days(env, start, end).await? {
+        stats.add(&day);
+    }
+
+    let report = build_report(period, start, end, stats);
+    // CSV first, so a present JSON report means the period is complete
+    bucket
+        .put(format!("{key}.csv"), to_csv(&report))
+        .http_metadata(HttpMetadata {
+            content_type: Some("text/csv".to_string()),
+            ..Default::default()
+        })
+        .execute()
+        .await?;
+    bucket
+        .put(
+            format!("{key}.json"),
+            serde_json::to_string_pretty(&report)?,
+        )
+        .http_metadata(HttpMetadata {
+            content_type: Some("application/json".to_string()),
+            ..Default::default()
+        })
+        .execute()
+        .await?;
+
+    console_log!("Wrote {} traffic report {}", period, key);
+    Ok(true)
+}
+
+fn build_report(
+    period: &'static str,
+    start: NaiveDate,
+    end: NaiveDate,
+    stats: TrafficStats,
+) -> TrafficReport {
+    let percent = |part: u64, total: u64| {
+        if total > 0 {
+            part as f64 / total as f64 * 100.0
+        } else {
+            0.0
+        }
+    };
+
+    let mut top_paths: Vec<PathRequests> = stats
+        .paths
+        .into_iter()
+        .map(|(path, requests)| PathRequests { path, requests })
+        .collect();
+    top_paths.sort_by_key(|path| std::cmp::Reverse(path.requests));
+    top_paths.truncate(TOP_PATHS);
+
+    let backends = stats
+        .backends
+        .into_iter()
+        .map(|(backend, traffic)| BackendAvailability {
+            backend,
+            requests: traffic.requests,
+            failures: traffic.failures,
+            availability: 100.0 - percent(traffic.failures, traffic.requests),
+        })
+        .collect();
+
+    TrafficReport {
+        period,
+        start: start.to_string(),
+        end: end.to_string(),
+        generated_at: Utc::now().to_rfc3339(),
+        requests: stats.requests,
+        errors: stats.errors,
+        error_rate: percent(stats.errors, stats.requests),
+        cache_hits: stats.cache_hits,
+        cache_misses: stats.cache_misses,
+        cache_hit_rate: percent(stats.cache_hits, stats.cache_hits + stats.cache_misses),
+        top_paths,
+        backends,
+    }
+}
+
+/// Flatten a report into `metric,name,value` rows
+fn to_csv(report: &TrafficReport) -> String {
+    let mut rows = vec![
+        "metric,name,value".to_string(),
+        format!("requests,,{}", report.requests),
+        format!("errors,,{}", report.errors),
+        format!("error_rate,,{:.2}", report.error_rate),
+        format!("cache_hits,,{}", report.cache_hits),
+        format!("cache_misses,,{}", report.cache_misses),
+        format!("cache_hit_rate,,{:.2}", report.cache_hit_rate),
+    ];
+    for path in &report.top_paths {
+        rows.push(format!(
+            "path_requests,{},{}",
+            csv_field(&path.path),
+            path.requests
+        ));
+    }
+    for backend in &report.backends {
+        let name = csv_field(&backend.backend);
+        rows.push(format!("backend_requests,{name},{}", backend.requests));
+        rows.push(format!("backend_failures,{name},{}", backend.failures));
+        rows.push(format!(
+            "backend_availability,{name},{:.2}",
+            backend.availability
+        ));
+    }
+    rows.join("\n") + "\n"
+}
+
+/// Quote a CSV field when it contains separators or quotes
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Delete reports whose period started before the cutoff date
+async fn delete_expired(bucket: &Bucket, cutoff: NaiveDate) -> Result<()> {
+    let mut cursor = None;
+    loop {
+        let mut list = bucket.list().prefix(REPORT_PREFIX.to_string());
+        if let Some(cursor) = cursor.take() {
+            list = list.cursor(cursor);
+        }
+        let page = list.execute().await?;
+
+        for object in page.objects() {
+            let key = object.key();
+            // Keys look like "reports/daily/2025-01-31.json"
+            let date = key
+                .rsplit('/')
+                .next()
+                .and_then(|name| name.get(..10))
+                .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+            if date.is_some_and(|date| date < cutoff) {
+                bucket.delete(key).await?;
+            }
+        }
+
+        match page.cursor() {
+            Some(next) if page.truncated() => cursor = Some(next),
+            _ => return Ok(()),
+        }
+    }
+}
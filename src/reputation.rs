@@ -0,0 +1,275 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{config::ProxyConfig, events::RequestEvent};
+
+/// KV key prefix of client reputation records (`reputation:{ip}`)
+const REPUTATION_PREFIX: &str = "reputation:";
+
+/// Counters lose half their weight per hour, so clients recover
+const HALF_LIFE_SECS: f64 = 3600.0;
+
+/// Requests needed before error ratios affect the score
+const MIN_REQUESTS: f64 = 10.0;
+
+/// Records read from KV are reused for this long by an isolate
+const RECORD_CACHE_MS: f64 = 60_000.0;
+
+/// Interval between flushes of this isolate's counters to KV
+const FLUSH_INTERVAL_MS: f64 = 10_000.0;
+
+/// Records are dropped a week after a client's last flushed request
+const RECORD_TTL_SECS: u64 = 7 * 86400;
+
+/// Records merged per flush, each costs a KV read and a write so a flush
+/// stays well within the subrequest limit of one invocation
+const MAX_FLUSHED_RECORDS: usize = 20;
+
+/// Clients with unflushed counters per isolate, new clients are not counted
+/// beyond this until a flush catches up
+const MAX_PENDING_RECORDS: usize = 10_000;
+
+/// Time-decayed counters of a client's requests
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Record {
+    requests: f64,
+    client_errors: f64, // 4xx other than 429
+    rate_limited: f64,  // 429
+    denials: f64,       // Requests denied by access rules
+    updated_at: i64,    // Unix seconds the counters are decayed to
+}
+
+impl Record {
+    /// Counters decayed to `now`
+    fn decayed(&self, now: i64) -> Self {
+        let elapsed = (now - self.updated_at).max(0) as f64;
+        let factor = 0.5f64.powf(elapsed / HALF_LIFE_SECS);
+        Self {
+            requests: self.requests * factor,
+            client_errors: self.client_errors * factor,
+            rate_limited: self.rate_limited * factor,
+            denials: self.denials * factor,
+            updated_at: now,
+        }
+    }
+
+    fn add(&mut self, delta: &Record, now: i64) {
+        *self = self.decayed(now);
+        let delta = delta.decayed(now);
+        self.requests += delta.requests;
+        self.client_errors += delta.client_errors;
+        self.rate_limited += delta.rate_limited;
+        self.denials += delta.denials;
+    }
+
+    /// Score from 0 (worst) to 100 (best). Up to 40 points are lost for the
+    /// ratio of 4xx responses, 30 for the ratio of 429s, 10 per recent
+    /// denial (at most 30) and 30 for Cloudflare's bot score.
+    fn score(&self, bot_score: Option<f64>) -> u8 {
+        let mut penalty = 0.0;
+        if self.requests >= MIN_REQUESTS {
+            penalty += 40.0 * self.client_errors / self.requests;
+            penalty += 30.0 * self.rate_limited / self.requests;
+        }
+        penalty += (10.0 * self.denials).min(30.0);
+        if let Some(bot_score) = bot_score {
+            // Bot scores go from 1 (automated) to 99 (human)
+            penalty += 0.3 * (100.0 - bot_score.clamp(1.0, 99.0));
+        }
+        (100.0 - penalty).round().clamp(0.0, 100.0) as u8
+    }
+}
+
+thread_local! {
+    /// Records read from KV, with their load time
+    static RECORDS: RefCell<HashMap<String, (f64, Record)>> = RefCell::new(HashMap::new());
+    /// Counters of this isolate not yet flushed to KV
+    static PENDING: RefCell<HashMap<String, Record>> = RefCell::new(HashMap::new());
+    static LAST_FLUSH: Cell<f64> = const { Cell::new(0.0) };
+}
+
+/// Reputation score of the client, `None` when scoring is off or the client
+/// IP is unknown
+pub async fn score(req: &Request, env: &Env, config: &ProxyConfig) -> Option<u8> {
+    if !config.reputation_enabled {
+        return None;
+    }
+    let ip = req.headers().get("CF-Connecting-IP").ok().flatten()?;
+
+    let now = js_sys::Date::now();
+    let cached = RECORDS.with(|records| {
+        records
+            .borrow()
+            .get(&ip)
+            .filter(|(loaded_at, _)| now - loaded_at < RECORD_CACHE_MS)
+            .map(|(_, record)| record.clone())
+    });
+    let mut record = match cached {
+        Some(record) => record,
+        None => {
+            let stored = match env.kv("PROXY_KV") {
+                Ok(kv) => kv
+                    .get(&format!("{REPUTATION_PREFIX}{ip}"))
+                    .json::<Record>()
+                    .await
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default(),
+                Err(_) => Record::default(),
+            };
+            RECORDS.with(|records| {
+                let mut records = records.borrow_mut();
+                records.retain(|_, (loaded_at, _)| now - *loaded_at < RECORD_CACHE_MS);
+                records.insert(ip.clone(), (now, stored.clone()));
+            });
+            stored
+        }
+    };
+
+    // Signals of this isolate count before they are flushed
+    let timestamp = Utc::now().timestamp();
+    if let Some(pending) = PENDING.with(|pending| pending.borrow().get(&ip).cloned()) {
+        record.add(&pending, timestamp);
+    }
+    Some(record.decayed(timestamp).score(bot_score(req)))
+}
+
+/// Cloudflare's bot score (`request.cf.botManagement.score`), only present
+/// on zones with Bot Management. Verified bots are not penalized.
+fn bot_score(req: &Request) -> Option<f64> {
+    let cf = req.cf()?;
+    let bot_management = js_sys::Reflect::get(cf.inner(), &"botManagement".into()).ok()?;
+    let verified = js_sys::Reflect::get(&bot_management, &"verifiedBot".into())
+        .ok()
+        .and_then(|verified| verified.as_bool());
+    if verified == Some(true) {
+        return None;
+    }
+    js_sys::Reflect::get(&bot_management, &"score".into())
+        .ok()?
+        .as_f64()
+}
+
+/// Count a finished request against its client
+pub fn record_response(event: &RequestEvent, config: &ProxyConfig) {
+    let status = event.status;
+    record(event.client_ip.as_deref(), config, |record| {
+        record.requests += 1.0;
+        match status {
+            429 => record.rate_limited += 1.0,
+            400..=499 => record.client_errors += 1.0,
+            _ => {}
+        }
+    });
+}
+
+/// Count a request denied by access rules against its client
+pub fn record_denial(req: &Request, config: &ProxyConfig) {
+    let ip = req.headers().get("CF-Connecting-IP").ok().flatten();
+    record(ip.as_deref(), config, |record| record.denials += 1.0);
+}
+
+fn record(ip: Option<&str>, config: &ProxyConfig, update: impl FnOnce(&mut Record)) {
+    let ip = match ip {
+        Some(ip) if config.reputation_enabled => ip,
+        _ => return,
+    };
+    let now = Utc::now().timestamp();
+    PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        if pending.len() >= MAX_PENDING_RECORDS && !pending.contains_key(ip) {
+            return;
+        }
+        let record = pending.entry(ip.to_string()).or_insert_with(|| Record {
+            updated_at: now,
+            ..Default::default()
+        });
+        *record = record.decayed(now);
+        update(record);
+    });
+}
+
+/// Merge this isolate's counters into the stored records, at most every
+/// `FLUSH_INTERVAL_MS`. The most active clients go first; the rest stay
+/// pending for the next flush.
+pub async fn flush(env: Env) {
+    let now = js_sys::Date::now();
+    if now - LAST_FLUSH.with(Cell::get) < FLUSH_INTERVAL_MS {
+        return;
+    }
+    let kv = match env.kv("PROXY_KV") {
+        Ok(kv) => kv,
+        Err(_) => return,
+    };
+    LAST_FLUSH.with(|last_flush| last_flush.set(now));
+
+    let batch = PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let mut ips: Vec<(String, f64)> = pending
+            .iter()
+            .map(|(ip, record)| (ip.clone(), record.requests + record.denials))
+            .collect();
+        ips.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ips.into_iter()
+            .take(MAX_FLUSHED_RECORDS)
+            .filter_map(|(ip, _)| pending.remove_entry(&ip))
+            .collect::<Vec<_>>()
+    });
+
+    // Read-modify-write is approximate under concurrency, which is fine for a score
+    let timestamp = Utc::now().timestamp();
+    for (ip, delta) in batch {
+        let key = format!("{REPUTATION_PREFIX}{ip}");
+        let stored = match kv.get(&key).json::<Record>().await {
+            Ok(stored) => stored,
+            Err(e) => {
+                console_log!("Failed to read client reputation: {:?}", e);
+                requeue(ip, &delta, timestamp);
+                continue;
+            }
+        };
+        let mut record = stored.unwrap_or_default();
+        record.add(&delta, timestamp);
+
+        let stored = match serde_json::to_string(&record) {
+            Ok(serialized) => match kv.put(&key, serialized) {
+                Ok(put) => put
+                    .expiration_ttl(RECORD_TTL_SECS)
+                    .execute()
+                    .await
+                    .map_err(Error::from),
+                Err(e) => Err(e.into()),
+            },
+            Err(e) => Err(e.into()),
+        };
+        match stored {
+            Ok(()) => {
+                RECORDS.with(|records| records.borrow_mut().insert(ip, (now, record)));
+            }
+            Err(e) => {
+                console_log!("Failed to store client reputation: {:?}", e);
+                requeue(ip, &delta, timestamp);
+            }
+        }
+    }
+}
+
+/// Return counters that could not be flushed, merged with any recorded since
+fn requeue(ip: String, delta: &Record, now: i64) {
+    PENDING.with(|pending| {
+        pending
+            .borrow_mut()
+            .entry(ip)
+            .or_insert_with(|| Record {
+                updated_at: now,
+                ..Default::default()
+            })
+            .add(delta, now);
+    });
+}
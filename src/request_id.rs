@@ -0,0 +1,48 @@
+use uuid::Uuid;
+use worker::*;
+
+use crate::{config::ProxyConfig, events::RequestEvent};
+
+/// Crockford's base32 alphabet used by ULIDs
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Cloudflare Ray ID of a request, without the data center suffix as it
+/// appears in Cloudflare logs
+pub fn ray_id(req: &Request) -> Option<String> {
+    let ray = req.headers().get("CF-Ray").ok().flatten()?;
+    let id = ray.split('-').next().unwrap_or_default().trim();
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+/// ULID: 48-bit millisecond timestamp and 80 random bits, sortable by time
+fn ulid() -> String {
+    let time = (js_sys::Date::now() as u128) & 0xFFFF_FFFF_FFFF;
+    // Random bits of a v4 UUID, skipping its version and variant bits
+    let uuid = Uuid::new_v4().as_u128();
+    let random = ((uuid >> 80) & 0x3FFFF) << 62 | (uuid & ((1 << 62) - 1));
+    let value = (time << 80) | random;
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD[((value >> (i * 5)) & 31) as usize] as char)
+        .collect()
+}
+
+/// Request ID in the `REQUEST_ID_FORMAT` scheme: `uuid` (v4), `ulid`, or
+/// `ray` (the Ray ID, falling back to a UUID outside Cloudflare)
+pub fn generate(req: &Request, config: &ProxyConfig) -> String {
+    match config.request_id_format.as_str() {
+        "ulid" => ulid(),
+        "ray" => ray_id(req).unwrap_or_else(|| Uuid::new_v4().to_string()),
+        _ => Uuid::new_v4().to_string(),
+    }
+}
+
+/// Expose the request and Ray IDs on a response, e.g. for support tickets
+pub fn expose(response: &Response, event: &RequestEvent) -> Result<()> {
+    let headers = response.headers();
+    headers.set("X-Request-ID", &event.request_id)?;
+    if let Some(ray_id) = &event.ray_id {
+        headers.set("X-Ray-ID", ray_id)?;
+    }
+    Ok(())
+}
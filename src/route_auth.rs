@@ -0,0 +1,82 @@
+use std::net::IpAddr;
+
+use worker::*;
+
+use crate::{access::IpNetwork, config::ProxyConfig, utils};
+
+/// Require a route auth token unless the request is exempt. Returns the
+/// rejection response when the request must not be proxied.
+pub fn check(req: &Request, config: &ProxyConfig) -> Result<Option<Response>> {
+    if config.route_auth_tokens.is_empty() || is_exempt(req, config)? {
+        return Ok(None);
+    }
+
+    let headers = req.headers();
+    let provided = match headers.get("Authorization")? {
+        Some(authorization) => authorization
+            .strip_prefix("Bearer ")
+            .map(|token| token.trim().to_string()),
+        None => headers.get("X-API-Key")?,
+    };
+
+    if let Some(provided) = provided {
+        if config
+            .route_auth_tokens
+            .iter()
+            .any(|token| utils::constant_time_eq(&provided, token))
+        {
+            return Ok(None);
+        }
+    }
+
+    let response = Response::error("Unauthorized", 401)?;
+    response
+        .headers()
+        .set("WWW-Authenticate", "Bearer realm=\"cf-proxy\"")?;
+    Ok(Some(response))
+}
+
+/// Check bypass lists: public paths, trusted networks and verified bots
+fn is_exempt(req: &Request, config: &ProxyConfig) -> Result<bool> {
+    let path = req.path();
+    if config
+        .auth_bypass_paths
+        .iter()
+        .any(|pattern| utils::path_matches(pattern, &path))
+    {
+        return Ok(true);
+    }
+
+    if !config.auth_bypass_networks.is_empty() {
+        let ip = req
+            .headers()
+            .get("CF-Connecting-IP")?
+            .and_then(|ip| ip.parse::<IpAddr>().ok());
+        if let Some(ip) = ip {
+            if config
+                .auth_bypass_networks
+                .iter()
+                .filter_map(|network| IpNetwork::parse(network))
+                .any(|network| network.contains(&ip))
+            {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(config.auth_bypass_verified_bots && is_verified_bot(req))
+}
+
+/// Cloudflare's verified bot flag (`request.cf.botManagement.verifiedBot`),
+/// only present on zones with Bot Management
+fn is_verified_bot(req: &Request) -> bool {
+    let cf = match req.cf() {
+        Some(cf) => cf,
+        None => return false,
+    };
+
+    js_sys::Reflect::get(cf.inner(), &"botManagement".into())
+        .and_then(|bot_management| js_sys::Reflect::get(&bot_management, &"verifiedBot".into()))
+        .map(|verified| verified.as_bool() == Some(true))
+        .unwrap_or(false)
+}
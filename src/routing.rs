@@ -0,0 +1,87 @@
+use regex::Regex;
+use serde_json::Value;
+use worker::*;
+
+use crate::{
+    config::{BodyRoutingRule, ProxyConfig},
+    utils,
+};
+
+/// Select backend from request body fields (e.g. webhook event type)
+pub async fn select_body_route(req: &Request, config: &ProxyConfig) -> Result<Option<String>> {
+    if config.body_routing_rules.is_empty()
+        || !matches!(req.method(), Method::Post | Method::Put | Method::Patch)
+    {
+        return Ok(None);
+    }
+
+    let path = req.url()?.path().to_string();
+    let rules: Vec<&BodyRoutingRule> = config
+        .body_routing_rules
+        .iter()
+        .filter(|rule| utils::path_matches(&rule.path, &path))
+        .collect();
+    if rules.is_empty() {
+        return Ok(None);
+    }
+
+    // Skip inspection of oversized bodies before buffering them
+    if let Ok(Some(length)) = req.headers().get("Content-Length") {
+        if length.parse::<usize>().unwrap_or(0) > config.body_routing_max_bytes {
+            return Ok(None);
+        }
+    }
+
+    // Inspect a clone so the original body can still be forwarded
+    let body = req.clone()?.bytes().await?;
+    if body.len() > config.body_routing_max_bytes {
+        return Ok(None);
+    }
+
+    let payload: Value = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(_) => return Ok(None),
+    };
+
+    for rule in rules {
+        if let Some(value) = lookup_json_path(&payload, &rule.field) {
+            if body_rule_matches(rule, value) {
+                console_log!("Body routing: {}={} -> {}", rule.field, value, rule.backend);
+                return Ok(Some(rule.backend.clone()));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Check a body routing rule condition against the field value
+fn body_rule_matches(rule: &BodyRoutingRule, value: &Value) -> bool {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    if let Some(expected) = &rule.equals {
+        return &text == expected;
+    }
+
+    if let Some(pattern) = &rule.pattern {
+        return Regex::new(pattern)
+            .map(|regex| regex.is_match(&text))
+            .unwrap_or(false);
+    }
+
+    // Without a condition the field only needs to be present
+    true
+}
+
+/// Look up a dot-separated path (e.g. "event.type" or "items.0.id") in JSON
+pub fn lookup_json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |current, segment| match current {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+}
@@ -0,0 +1,327 @@
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use worker::*;
+
+use crate::{
+    admin::{AdminIdentity, AuditEntry, record_audit},
+    config::ProxyConfig,
+    utils,
+    webhooks::{ProxyEvent, WebhookEmitter},
+};
+
+/// KV key prefix of encrypted secrets (`secret:{name}`)
+const SECRET_PREFIX: &str = "secret:";
+
+/// Worker secrets holding the master keys of KV-stored secrets
+const MASTER_KEY_VAR: &str = "SECRETS_MASTER_KEY";
+const PREVIOUS_MASTER_KEY_VAR: &str = "SECRETS_PREVIOUS_MASTER_KEY";
+
+/// Secret encrypted with AES-256-GCM under a master key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EncryptedSecret {
+    key_id: String, // Identifies the master key without revealing it
+    nonce: String,
+    ciphertext: String,
+    updated_at: String,
+}
+
+/// Master key with its public identifier
+struct MasterKey {
+    id: String,
+    key: [u8; 32],
+}
+
+impl MasterKey {
+    fn from_env(env: &Env, name: &str) -> Option<Self> {
+        let secret = env.secret(name).ok()?.to_string();
+        if secret.is_empty() {
+            return None;
+        }
+        Some(Self {
+            id: utils::sha256_hash(&format!("key-id:{secret}"))[..8].to_string(),
+            key: Sha256::digest(secret.as_bytes()).into(),
+        })
+    }
+
+    /// The name is authenticated too, so stored values cannot be swapped
+    fn encrypt(&self, name: &str, value: &str) -> Result<EncryptedSecret> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.key));
+        let nonce = random_nonce();
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: name.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| Error::from("Failed to encrypt secret"))?;
+        Ok(EncryptedSecret {
+            key_id: self.id.clone(),
+            nonce: utils::base64_encode(&nonce),
+            ciphertext: utils::base64_encode(&ciphertext),
+            updated_at: Utc::now().to_rfc3339(),
+        })
+    }
+
+    fn decrypt(&self, name: &str, secret: &EncryptedSecret) -> Result<String> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.key));
+        let nonce = utils::base64_decode(&secret.nonce)?;
+        if nonce.len() != 12 {
+            return Err(Error::from("Invalid secret nonce"));
+        }
+        let ciphertext = utils::base64_decode(&secret.ciphertext)?;
+        let payload = Payload {
+            msg: &ciphertext,
+            aad: name.as_bytes(),
+        };
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| Error::from("Failed to decrypt secret"))?;
+        String::from_utf8(plaintext).map_err(|_| Error::from("Secret is not valid UTF-8"))
+    }
+}
+
+/// Current and (during a rotation window) previous master key
+struct MasterKeys {
+    current: Option<MasterKey>,
+    previous: Option<MasterKey>,
+}
+
+impl MasterKeys {
+    fn from_env(env: &Env) -> Self {
+        Self {
+            current: MasterKey::from_env(env, MASTER_KEY_VAR),
+            previous: MasterKey::from_env(env, PREVIOUS_MASTER_KEY_VAR),
+        }
+    }
+
+    fn decrypt(&self, name: &str, secret: &EncryptedSecret) -> Result<String> {
+        [&self.current, &self.previous]
+            .into_iter()
+            .flatten()
+            .find(|key| key.id == secret.key_id)
+            .ok_or_else(|| Error::from(format!("Master key {} is not configured", secret.key_id)))?
+            .decrypt(name, secret)
+    }
+}
+
+/// 96 random bits, the random bytes of a v4 UUID (skipping version and variant)
+fn random_nonce() -> [u8; 12] {
+    let uuid = uuid::Uuid::new_v4();
+    let bytes = uuid.as_bytes();
+    let mut nonce = [0u8; 12];
+    nonce[..6].copy_from_slice(&bytes[..6]);
+    nonce[6..].copy_from_slice(&bytes[10..]);
+    nonce
+}
+
+/// Read a secret: a Worker secret, a plain variable, or a KV-stored secret
+/// encrypted under the current or previous master key
+pub async fn get(env: &Env, name: &str) -> Result<Option<String>> {
+    if let Ok(secret) = env.secret(name) {
+        return Ok(Some(secret.to_string()));
+    }
+    if let Ok(var) = env.var(name) {
+        return Ok(Some(var.to_string()));
+    }
+
+    let stored = env
+        .kv("PROXY_KV")?
+        .get(&format!("{SECRET_PREFIX}{name}"))
+        .json::<EncryptedSecret>()
+        .await?;
+    match stored {
+        Some(stored) => MasterKeys::from_env(env).decrypt(name, &stored).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Request body for storing a secret
+#[derive(Debug, Deserialize)]
+struct PutSecretRequest {
+    name: String,
+    value: String,
+}
+
+/// List stored secrets, never their values
+pub async fn handle_list(env: &Env) -> Result<Response> {
+    let keys = MasterKeys::from_env(env);
+    let secrets: Vec<serde_json::Value> = load_all(env)
+        .await?
+        .into_iter()
+        .map(|(name, secret)| {
+            serde_json::json!({
+                "name": name,
+                "key_id": secret.key_id,
+                "current_key": keys.current.as_ref().is_some_and(|key| key.id == secret.key_id),
+                "updated_at": secret.updated_at
+            })
+        })
+        .collect();
+    Response::from_json(&serde_json::json!({
+        "current_key_id": keys.current.as_ref().map(|key| key.id.clone()),
+        "previous_key_id": keys.previous.as_ref().map(|key| key.id.clone()),
+        "secrets": secrets
+    }))
+}
+
+/// Encrypt and store a secret under the current master key
+pub async fn handle_put(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let body: PutSecretRequest = match req.json().await {
+        Ok(body) => body,
+        Err(_) => return Response::error("Invalid secret", 400),
+    };
+    if body.name.is_empty() || body.value.is_empty() {
+        return Response::error("Secret name and value are required", 400);
+    }
+    let key = match MasterKeys::from_env(env).current {
+        Some(key) => key,
+        None => return Response::error("SECRETS_MASTER_KEY is not configured", 503),
+    };
+
+    let encrypted = key.encrypt(&body.name, &body.value)?;
+    env.kv("PROXY_KV")?
+        .put(
+            &format!("{SECRET_PREFIX}{}", body.name),
+            serde_json::to_string(&encrypted)?,
+        )?
+        .execute()
+        .await?;
+
+    // Values never reach the audit log
+    let entry = AuditEntry::new(req, &identity.name, "put_secret", &body.name);
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({
+        "name": body.name,
+        "key_id": encrypted.key_id
+    }))
+}
+
+/// Delete a stored secret
+pub async fn handle_delete(
+    req: &Request,
+    url: &Url,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let name = match url.query_pairs().find(|(key, _)| key == "name") {
+        Some((_, name)) => name.to_string(),
+        None => return Response::error("Secret name is required", 400),
+    };
+
+    let kv = env.kv("PROXY_KV")?;
+    let key = format!("{SECRET_PREFIX}{name}");
+    if kv.get(&key).text().await?.is_none() {
+        return Response::error("Secret not found", 404);
+    }
+    kv.delete(&key).await?;
+
+    let entry = AuditEntry::new(req, &identity.name, "delete_secret", &name);
+    record_audit(env, config, entry).await;
+
+    Response::from_json(&serde_json::json!({ "deleted": name }))
+}
+
+/// Re-encrypt every stored secret under the current master key, after the
+/// old key moved to `SECRETS_PREVIOUS_MASTER_KEY`
+pub async fn handle_rotate(
+    req: &Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let keys = MasterKeys::from_env(env);
+    let current = match &keys.current {
+        Some(key) => key,
+        None => return Response::error("SECRETS_MASTER_KEY is not configured", 503),
+    };
+
+    let kv = env.kv("PROXY_KV")?;
+    let mut rotated = Vec::new();
+    let mut unchanged = 0;
+    let mut failed = Vec::new();
+    for (name, secret) in load_all(env).await? {
+        if secret.key_id == current.id {
+            unchanged += 1;
+            continue;
+        }
+
+        let result = match keys
+            .decrypt(&name, &secret)
+            .and_then(|value| current.encrypt(&name, &value))
+        {
+            Ok(encrypted) => match kv.put(
+                &format!("{SECRET_PREFIX}{name}"),
+                serde_json::to_string(&encrypted)?,
+            ) {
+                Ok(put) => put.execute().await.map_err(Error::from),
+                Err(e) => Err(Error::from(e)),
+            },
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => rotated.push(name),
+            Err(e) => {
+                console_log!("Failed to rotate secret {}: {:?}", name, e);
+                failed.push(name);
+            }
+        }
+    }
+
+    let summary = serde_json::json!({
+        "key_id": current.id,
+        "rotated": rotated,
+        "unchanged": unchanged,
+        "failed": failed
+    });
+    let entry = AuditEntry::new(req, &identity.name, "rotate_secrets", "secrets")
+        .with_change(None, Some(summary.clone()));
+    record_audit(env, config, entry).await;
+    WebhookEmitter::new(config)
+        .emit(ProxyEvent::SecretsRotated, summary.clone())
+        .await;
+
+    let response = Response::from_json(&summary)?;
+    Ok(if failed.is_empty() {
+        response
+    } else {
+        response.with_status(500)
+    })
+}
+
+/// Load all stored secrets with their names
+async fn load_all(env: &Env) -> Result<Vec<(String, EncryptedSecret)>> {
+    let kv = env.kv("PROXY_KV")?;
+    let mut secrets = Vec::new();
+    let mut cursor = None;
+    loop {
+        let mut list = kv.list().prefix(SECRET_PREFIX.to_string());
+        if let Some(cursor) = cursor.take() {
+            list = list.cursor(cursor);
+        }
+        let listing = list.execute().await?;
+
+        for key in &listing.keys {
+            if let Ok(Some(secret)) = kv.get(&key.name).json::<EncryptedSecret>().await {
+                let name = key.name[SECRET_PREFIX.len()..].to_string();
+                secrets.push((name, secret));
+            }
+        }
+
+        match listing.cursor {
+            Some(next) if !listing.list_complete => cursor = Some(next),
+            _ => return Ok(secrets),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use chrono::Utc;
+use serde::Serialize;
+use worker::*;
+
+use crate::{
+    cache::CacheManager, coalesce, config::ProxyConfig, config_store, health::HealthChecker,
+    latency, utils,
+};
+
+/// Prefix of the keys written by the self-test, expiring on their own
+const SELFTEST_PREFIX: &str = "selftest:";
+
+/// Result of one check
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Pass,
+    Fail,
+    Skip, // Feature not configured
+}
+
+#[derive(Debug, Serialize)]
+struct CheckResult {
+    name: &'static str,
+    status: CheckStatus,
+    detail: String,
+    duration_ms: f64,
+}
+
+/// Run the post-deploy checklist, answering `200` when every check passed
+/// or was skipped and `503` otherwise
+pub async fn handle_selftest(env: &Env, config: &ProxyConfig) -> Result<Response> {
+    let run_id = utils::generate_request_id();
+    let mut checks = Vec::new();
+
+    let start = js_sys::Date::now();
+    let outcome = check_config(env).await;
+    checks.push(result("config", outcome, start));
+
+    let start = js_sys::Date::now();
+    let outcome = check_kv(env, &run_id).await;
+    checks.push(result("kv", outcome, start));
+
+    let start = js_sys::Date::now();
+    let outcome = check_durable_object(env, config, &run_id).await;
+    checks.push(result("durable_object", outcome, start));
+
+    let start = js_sys::Date::now();
+    let outcome = check_backend(config).await;
+    checks.push(result("backend", outcome, start));
+
+    let start = js_sys::Date::now();
+    let outcome = check_cache(env, config, &run_id).await;
+    checks.push(result("cache", outcome, start));
+
+    let passed = !checks
+        .iter()
+        .any(|check| matches!(check.status, CheckStatus::Fail));
+    console_log!(
+        "Self-test {}: {}",
+        run_id,
+        if passed { "pass" } else { "fail" }
+    );
+
+    let response = Response::from_json(&serde_json::json!({
+        "status": if passed { "pass" } else { "fail" },
+        "proxy_id": config.proxy_id,
+        "checks": checks,
+        "timestamp": Utc::now().to_rfc3339()
+    }))?;
+    Ok(response.with_status(if passed { 200 } else { 503 }))
+}
+
+fn result(name: &'static str, outcome: Result<Option<String>>, start: f64) -> CheckResult {
+    let (status, detail) = match outcome {
+        Ok(Some(detail)) => (CheckStatus::Pass, detail),
+        Ok(None) => (CheckStatus::Skip, "not configured".to_string()),
+        Err(e) => (CheckStatus::Fail, e.to_string()),
+    };
+    CheckResult {
+        name,
+        status,
+        detail,
+        duration_ms: js_sys::Date::now() - start,
+    }
+}
+
+/// The active configuration, including published overrides, parses
+async fn check_config(env: &Env) -> Result<Option<String>> {
+    let config = config_store::active_config(env).await?;
+    if config.backends.is_empty() {
+        return Err(Error::from("no backends configured"));
+    }
+    Ok(Some(format!(
+        "{} backends configured",
+        config.backends.len()
+    )))
+}
+
+/// A value written to KV reads back unchanged
+async fn check_kv(env: &Env, run_id: &str) -> Result<Option<String>> {
+    let kv = env.kv("PROXY_KV")?;
+    let key = format!("{SELFTEST_PREFIX}kv:{run_id}");
+    kv.put(&key, run_id)?.expiration_ttl(60).execute().await?;
+    let value = kv.get(&key).text().await?;
+    kv.delete(&key).await?;
+
+    // KV reads are eventually consistent, but local writes are visible right away
+    match value {
+        Some(value) if value == run_id => Ok(Some("write and read succeeded".to_string())),
+        Some(_) => Err(Error::from("read a different value than written")),
+        None => Err(Error::from("written value not found")),
+    }
+}
+
+/// The coalescing Durable Object answers, when coalescing is enabled
+async fn check_durable_object(
+    env: &Env,
+    config: &ProxyConfig,
+    run_id: &str,
+) -> Result<Option<String>> {
+    if !config.cache_coalescing_enabled {
+        return Ok(None);
+    }
+
+    coalesce::ping(&format!("{SELFTEST_PREFIX}{run_id}"), env).await?;
+    Ok(Some("coalescer answered".to_string()))
+}
+
+/// The first backend answers its health check, or any non-5xx status when
+/// health checks are disabled
+async fn check_backend(config: &ProxyConfig) -> Result<Option<String>> {
+    let backend = match config.backends.first() {
+        Some(backend) => backend,
+        None => return Err(Error::from("no backends configured")),
+    };
+
+    if config.health_check_enabled {
+        return if HealthChecker::new(config)
+            .perform_health_check(backend)
+            .await
+        {
+            Ok(Some(format!("{backend} passed its health check")))
+        } else {
+            Err(Error::from(format!("{backend} failed its health check")))
+        };
+    }
+
+    let request = Request::new(backend, Method::Get)?;
+    let response = latency::fetch_with_timeout(request, config.health_check_timeout * 1000).await?;
+    if response.status_code() >= 500 {
+        return Err(Error::from(format!(
+            "{backend} answered with status {}",
+            response.status_code()
+        )));
+    }
+    Ok(Some(format!(
+        "{backend} answered with status {}",
+        response.status_code()
+    )))
+}
+
+/// A response stored in the configured cache backend is served back
+async fn check_cache(env: &Env, config: &ProxyConfig, run_id: &str) -> Result<Option<String>> {
+    let cache_manager = CacheManager::new(config);
+    if !cache_manager.is_enabled() {
+        return Ok(None);
+    }
+
+    let key = format!("{SELFTEST_PREFIX}cache:{run_id}");
+    let response = Response::ok(run_id)?;
+    cache_manager
+        .cache_response_with_ttl(&key, response, 60, env)
+        .await?;
+    let cached = cache_manager.get_cached_response(&key, env).await?;
+    cache_manager.remove(&key, env).await?;
+
+    let body = match cached {
+        Some(mut cached) => cached.text().await?,
+        None => return Err(Error::from("stored response not found")),
+    };
+    if body != run_id {
+        return Err(Error::from("cached body differs from the stored one"));
+    }
+    Ok(Some(format!(
+        "round trip through {} succeeded",
+        config.cache_backend
+    )))
+}
@@ -0,0 +1,207 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use worker::*;
+
+use crate::{
+    config::{ProxyConfig, SigV4Route},
+    secrets, utils,
+};
+
+/// Payload hash of requests signed without hashing their body (S3 only)
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Default secrets holding the AWS credentials
+const DEFAULT_ACCESS_KEY_VAR: &str = "AWS_ACCESS_KEY_ID";
+const DEFAULT_SECRET_KEY_VAR: &str = "AWS_SECRET_ACCESS_KEY";
+const DEFAULT_SESSION_TOKEN_VAR: &str = "AWS_SESSION_TOKEN";
+
+/// Signing rule of the first route matching `path`
+fn route_for<'a>(config: &'a ProxyConfig, path: &str) -> Option<&'a SigV4Route> {
+    config
+        .sigv4_routes
+        .iter()
+        .find(|route| utils::path_matches(&route.path, path))
+}
+
+/// Sign a backend request with AWS Signature Version 4 when its route
+/// (`path`, the client's path) is configured for an AWS service. Any
+/// `Authorization` sent by the client is replaced.
+pub async fn sign(request: &Request, path: &str, config: &ProxyConfig, env: &Env) -> Result<()> {
+    let route = match route_for(config, path) {
+        Some(route) => route,
+        None => return Ok(()),
+    };
+
+    let access_key = credential(
+        env,
+        route
+            .access_key_id_var
+            .as_deref()
+            .unwrap_or(DEFAULT_ACCESS_KEY_VAR),
+    )
+    .await?
+    .ok_or_else(|| Error::from("AWS access key id is not set"))?;
+    let secret_key = credential(
+        env,
+        route
+            .secret_access_key_var
+            .as_deref()
+            .unwrap_or(DEFAULT_SECRET_KEY_VAR),
+    )
+    .await?
+    .ok_or_else(|| Error::from("AWS secret access key is not set"))?;
+    let session_token = credential(
+        env,
+        route
+            .session_token_var
+            .as_deref()
+            .unwrap_or(DEFAULT_SESSION_TOKEN_VAR),
+    )
+    .await?;
+
+    // S3 accepts unhashed payloads, so its bodies can stream through unbuffered
+    let is_s3 = route.service == "s3";
+    let payload_hash = match request.method() {
+        _ if is_s3 => UNSIGNED_PAYLOAD.to_string(),
+        Method::Get | Method::Head | Method::Options | Method::Delete => sha256_hex(&[]),
+        _ => sha256_hex(&request.clone()?.bytes().await?),
+    };
+
+    let url = request.url()?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(Error::from("Backend URL has no host")),
+    };
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let headers = request.headers();
+    headers.delete("Authorization")?;
+    headers.set("X-Amz-Date", &amz_date)?;
+    if is_s3 {
+        headers.set("X-Amz-Content-Sha256", &payload_hash)?;
+    }
+    match &session_token {
+        Some(token) => headers.set("X-Amz-Security-Token", token)?,
+        None => headers.delete("X-Amz-Security-Token")?,
+    }
+
+    // Host and every x-amz-* header are signed, other headers may be
+    // changed on the way to the origin
+    let mut signed: Vec<(String, String)> = headers
+        .entries()
+        .filter(|(name, _)| name.to_lowercase().starts_with("x-amz-"))
+        .map(|(name, value)| {
+            let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            (name.to_lowercase(), value)
+        })
+        .collect();
+    signed.push(("host".to_string(), host));
+    signed.sort();
+    let canonical_headers: String = signed
+        .iter()
+        .map(|(name, value)| format!("{name}:{value}\n"))
+        .collect();
+    let signed_headers = signed
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method().to_string().to_uppercase(),
+        canonical_uri(url.path(), is_s3),
+        canonical_query(url.query().unwrap_or_default()),
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+    let scope = format!("{date}/{}/{}/aws4_request", route.region, route.service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        sha256_hex(canonical_request.as_bytes())
+    );
+
+    let signing_key = [
+        route.region.as_str(),
+        route.service.as_str(),
+        "aws4_request",
+    ]
+    .iter()
+    .fold(
+        hmac(format!("AWS4{secret_key}").as_bytes(), date.as_bytes()),
+        |key, part| hmac(&key, part.as_bytes()),
+    );
+    let signature = hex::encode(hmac(&signing_key, string_to_sign.as_bytes()));
+
+    headers.set(
+        "Authorization",
+        &format!(
+            "AWS4-HMAC-SHA256 Credential={access_key}/{scope}, SignedHeaders={signed_headers}, Signature={signature}"
+        ),
+    )
+}
+
+/// Path with every segment URI-encoded, twice for services other than S3
+fn canonical_uri(path: &str, is_s3: bool) -> String {
+    if path.is_empty() {
+        return "/".to_string();
+    }
+    path.split('/')
+        .map(|segment| {
+            let encoded = utils::percent_encode(&utils::percent_decode(segment));
+            if is_s3 {
+                encoded
+            } else {
+                utils::percent_encode(&encoded)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Query parameters URI-encoded and sorted by name, then value
+fn canonical_query(query: &str) -> String {
+    let mut params: Vec<(String, String)> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                utils::percent_encode(&utils::percent_decode(name)),
+                utils::percent_encode(&utils::percent_decode(value)),
+            )
+        })
+        .collect();
+    params.sort();
+    params
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length, so this never fails in practice
+    let mut mac = match Hmac::<Sha256>::new_from_slice(key) {
+        Ok(mac) => mac,
+        Err(_) => return Vec::new(),
+    };
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Credential from a secret, if set
+async fn credential(env: &Env, name: &str) -> Result<Option<String>> {
+    Ok(secrets::get(env, name)
+        .await?
+        .filter(|value| !value.is_empty()))
+}
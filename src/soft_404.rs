@@ -0,0 +1,97 @@
+use std::cell::RefCell;
+
+use futures_util::{StreamExt, stream};
+use regex::Regex;
+use worker::*;
+
+use crate::{config::ProxyConfig, context::RequestContext, utils};
+
+/// Largest body inspected for not-found patterns
+const MAX_INSPECTED_BYTES: usize = 256 * 1024;
+
+thread_local! {
+    /// Compiled `SOFT_404_PATTERNS`, recompiled when the configured patterns change
+    static PATTERNS: RefCell<(Vec<String>, Vec<Regex>)> = const { RefCell::new((Vec::new(), Vec::new())) };
+}
+
+/// Whether the text matches any of the not-found patterns
+fn matches(patterns: &[String], text: &str) -> bool {
+    PATTERNS.with(|compiled| {
+        let mut compiled = compiled.borrow_mut();
+        if compiled.0 != patterns {
+            let regexes = patterns
+                .iter()
+                .filter_map(|pattern| match Regex::new(pattern) {
+                    Ok(regex) => Some(regex),
+                    Err(e) => {
+                        console_log!("Invalid soft 404 pattern {}: {:?}", pattern, e);
+                        None
+                    }
+                })
+                .collect();
+            *compiled = (patterns.to_vec(), regexes);
+        }
+        compiled.1.iter().any(|regex| regex.is_match(text))
+    })
+}
+
+/// Detect 200 responses that are really "not found" pages.
+/// Returns the (possibly converted) response and whether it was a soft 404.
+pub async fn detect_soft_404(
+    mut response: Response,
+    config: &ProxyConfig,
+    ctx: &RequestContext,
+) -> Result<(Response, bool)> {
+    if config.soft_404_patterns.is_empty() || response.status_code() != 200 {
+        return Ok((response, false));
+    }
+
+    let content_type = response
+        .headers()
+        .get("Content-Type")?
+        .unwrap_or_default()
+        .to_lowercase();
+    if !(content_type.starts_with("text/") || content_type.contains("json")) {
+        return Ok((response, false));
+    }
+
+    if let Ok(Some(length)) = response.headers().get("Content-Length") {
+        if length.parse::<usize>().unwrap_or(0) > MAX_INSPECTED_BYTES {
+            return Ok((response, false));
+        }
+    }
+
+    // Bodies without a length are read up to the limit, then passed on unread
+    let body = match response.stream() {
+        Ok(mut input) => {
+            let mut body = Vec::new();
+            while let Some(chunk) = input.next().await {
+                body.extend(chunk?);
+                if body.len() > MAX_INSPECTED_BYTES {
+                    let rest = stream::iter(vec![Ok(body)]).chain(input);
+                    let passed = Response::from_stream(rest)?
+                        .with_status(response.status_code())
+                        .with_headers(response.headers().clone());
+                    return Ok((passed, false));
+                }
+            }
+            body
+        }
+        // Bodies built by the proxy itself are small and not streamable
+        Err(_) => response.bytes().await?,
+    };
+    let text = String::from_utf8_lossy(&body[..body.len().min(MAX_INSPECTED_BYTES)]);
+    let is_soft_404 = matches(&config.soft_404_patterns, &text);
+
+    let rebuilt = utils::rebuild_response(&response, body)?;
+    if !is_soft_404 {
+        return Ok((rebuilt, false));
+    }
+
+    console_log!("Soft 404 detected for {} {}", ctx.request_id, ctx.path);
+    if config.soft_404_convert {
+        Ok((rebuilt.with_status(404), true))
+    } else {
+        Ok((rebuilt, true))
+    }
+}
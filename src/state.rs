@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{
+    access_lists,
+    admin::{AdminIdentity, AuditEntry, record_audit},
+    config::ProxyConfig,
+    config_store::{self, ConfigDocument, ConfigRollout},
+    utils,
+    webhooks::{ProxyEvent, WebhookEmitter},
+};
+
+/// Version of the state document format written by this worker
+const STATE_FORMAT_VERSION: u32 = 1;
+
+/// Dynamic proxy state: runtime configuration (backends, flags and other
+/// overrides), its rollout, and access lists
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateDocument {
+    pub format_version: u32,
+    #[serde(default)]
+    pub exported_at: Option<String>,
+    #[serde(default)]
+    pub config: Option<ConfigDocument>,
+    #[serde(default)]
+    pub rollout: Option<ConfigRollout>,
+    #[serde(default)]
+    pub access_lists: BTreeMap<String, String>, // Compiled lists, base64-encoded
+}
+
+impl StateDocument {
+    /// Short description for audit entries
+    fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "config_version": self.config.as_ref().map(|doc| doc.version),
+            "rollout_version": self.rollout.as_ref().map(|rollout| rollout.candidate.version),
+            "access_lists": self.access_lists.keys().collect::<Vec<_>>()
+        })
+    }
+}
+
+/// Export the dynamic state as one JSON document
+pub async fn handle_export(env: &Env) -> Result<Response> {
+    let state = read_state(env).await?;
+    Response::from_json(&state)
+}
+
+/// Replace the dynamic state with an exported document
+pub async fn handle_import(
+    req: &mut Request,
+    env: &Env,
+    config: &ProxyConfig,
+    identity: &AdminIdentity,
+) -> Result<Response> {
+    let state: StateDocument = match req.json().await {
+        Ok(state) => state,
+        Err(_) => return Response::error("Invalid state document", 400),
+    };
+
+    if state.format_version > STATE_FORMAT_VERSION {
+        return Response::error(
+            format!("Unsupported state format {}", state.format_version),
+            400,
+        );
+    }
+
+    if let Some(reason) = config_store::check_import(
+        state.config.as_ref(),
+        state.rollout.as_ref(),
+        &config.config_allowed_hosts,
+    ) {
+        return Response::error(reason, 400);
+    }
+
+    let mut lists = BTreeMap::new();
+    for (name, encoded) in &state.access_lists {
+        match utils::base64_decode(encoded) {
+            Ok(bytes) => lists.insert(name.clone(), bytes),
+            Err(_) => return Response::error(format!("Invalid access list: {name}"), 400),
+        };
+    }
+    if let Some(name) = access_lists::check_import(&lists) {
+        return Response::error(format!("Invalid access list: {name}"), 400);
+    }
+
+    let previous = read_state(env).await?;
+
+    // Validate everything before writing, so a bad document changes nothing
+    let kv = env.kv("PROXY_KV")?;
+    config_store::import_documents(&kv, state.config.as_ref(), state.rollout.as_ref()).await?;
+    access_lists::import_lists(&kv, &lists).await?;
+
+    let entry = AuditEntry::new(req, &identity.name, "import_state", "state")
+        .with_change(Some(previous.summary()), Some(state.summary()));
+    record_audit(env, config, entry).await;
+
+    WebhookEmitter::new(config)
+        .emit(
+            ProxyEvent::ConfigChanged,
+            serde_json::json!({
+                "version": state.config.as_ref().map(|doc| doc.version),
+                "imported": true
+            }),
+        )
+        .await;
+
+    Response::from_json(&serde_json::json!({
+        "imported": state.summary(),
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
+
+/// Read the current dynamic state from KV
+async fn read_state(env: &Env) -> Result<StateDocument> {
+    let kv = env.kv("PROXY_KV")?;
+    let (config, rollout) = config_store::export_documents(&kv).await?;
+    let access_lists = access_lists::export_lists(&kv)
+        .await?
+        .into_iter()
+        .map(|(name, bytes)| (name, utils::base64_encode(&bytes)))
+        .collect();
+
+    Ok(StateDocument {
+        format_version: STATE_FORMAT_VERSION,
+        exported_at: Some(Utc::now().to_rfc3339()),
+        config,
+        rollout,
+        access_lists,
+    })
+}
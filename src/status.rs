@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, NaiveTime, Timelike, Utc};
+use serde::Serialize;
+use worker::*;
+
+use crate::{
+    config::ProxyConfig,
+    reports::{self, BackendTraffic},
+    utils,
+};
+
+/// Days of history shown on the status page
+const STATUS_HISTORY_DAYS: i64 = 7;
+
+/// Requests an hour needs before its availability is judged
+const MIN_HOURLY_REQUESTS: u64 = 10;
+
+/// Availability (percent) below which a component is degraded
+const DEGRADED_BELOW: f64 = 99.0;
+
+/// Availability (percent) below which a component is down
+const OUTAGE_BELOW: f64 = 90.0;
+
+/// State of a component or of the whole proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ComponentState {
+    Unknown,
+    Operational,
+    Degraded,
+    PartialOutage,
+    MajorOutage,
+}
+
+impl ComponentState {
+    fn from_traffic(traffic: &BackendTraffic) -> Self {
+        if traffic.requests < MIN_HOURLY_REQUESTS {
+            return ComponentState::Unknown;
+        }
+        let availability = availability(traffic);
+        if availability < OUTAGE_BELOW {
+            ComponentState::MajorOutage
+        } else if availability < DEGRADED_BELOW {
+            ComponentState::Degraded
+        } else {
+            ComponentState::Operational
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            ComponentState::Unknown => "No recent data",
+            ComponentState::Operational => "Operational",
+            ComponentState::Degraded => "Degraded performance",
+            ComponentState::PartialOutage => "Partial outage",
+            ComponentState::MajorOutage => "Major outage",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StatusPage {
+    status: ComponentState,
+    components: Vec<ComponentStatus>,
+    incidents: Vec<Incident>,
+    updated_at: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ComponentStatus {
+    name: String,
+    status: ComponentState,
+}
+
+/// Consecutive hours in which a component was degraded or down
+#[derive(Debug, Serialize)]
+struct Incident {
+    component: String,
+    status: ComponentState, // Worst state during the window
+    started_at: String,
+    ended_at: Option<String>, // `None` while ongoing
+    min_availability: f64,
+}
+
+/// Serve the public status page, as HTML or as JSON (`?format=json` or
+/// `Accept: application/json`)
+pub async fn handle_status(req: &Request, env: &Env, config: &ProxyConfig) -> Result<Response> {
+    if !config.status_page_enabled {
+        return Response::error("Not Found", 404);
+    }
+
+    let page = build_status(env, config).await?;
+
+    let wants_json = req
+        .url()?
+        .query_pairs()
+        .any(|(key, value)| key == "format" && value == "json")
+        || req
+            .headers()
+            .get("Accept")?
+            .is_some_and(|accept| accept.contains("application/json"));
+    let mut response = if wants_json {
+        Response::from_json(&page)?
+    } else {
+        Response::from_html(render_html(&page, config))?
+    };
+    response
+        .headers_mut()
+        .set("Cache-Control", "public, max-age=60")?;
+    response
+        .headers_mut()
+        .set("Access-Control-Allow-Origin", "*")?;
+    Ok(response)
+}
+
+/// Public component name of a backend, never its URL
+fn component_name(config: &ProxyConfig, index: usize, backend: &str) -> String {
+    config
+        .backend_configs
+        .iter()
+        .find(|b| b.url == backend)
+        .and_then(|b| b.name.clone())
+        .unwrap_or_else(|| format!("Backend {}", index + 1))
+}
+
+fn availability(traffic: &BackendTraffic) -> f64 {
+    if traffic.requests == 0 {
+        return 100.0;
+    }
+    (traffic.requests - traffic.failures) as f64 / traffic.requests as f64 * 100.0
+}
+
+async fn build_status(env: &Env, config: &ProxyConfig) -> Result<StatusPage> {
+    let now = Utc::now();
+    let days = reports::load_days(
+        env,
+        (now - Duration::days(STATUS_HISTORY_DAYS)).date_naive(),
+        now.date_naive(),
+    )
+    .await?;
+
+    // Hourly traffic per backend, oldest first
+    let mut history: BTreeMap<DateTime<Utc>, BTreeMap<String, BackendTraffic>> = BTreeMap::new();
+    for (date, stats) in days {
+        for (hour, traffic) in stats.hours {
+            if let Some(time) = NaiveTime::from_hms_opt(hour, 0, 0) {
+                history.insert(date.and_time(time).and_utc(), traffic.backends);
+            }
+        }
+    }
+
+    let current_hour = now
+        .date_naive()
+        .and_time(NaiveTime::from_hms_opt(now.hour(), 0, 0).unwrap_or_default())
+        .and_utc();
+    let mut components = Vec::new();
+    let mut incidents = Vec::new();
+    for (index, backend) in config.backends.iter().enumerate() {
+        let name = component_name(config, index, backend);
+        let traffic_at = |time: DateTime<Utc>| {
+            history
+                .get(&time)
+                .and_then(|backends| backends.get(backend))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        // The current hour may have too few requests yet, fall back to the previous one
+        let mut status = ComponentState::from_traffic(&traffic_at(current_hour));
+        if status == ComponentState::Unknown {
+            status = ComponentState::from_traffic(&traffic_at(current_hour - Duration::hours(1)));
+        }
+        components.push(ComponentStatus {
+            name: name.clone(),
+            status,
+        });
+
+        let mut open: Option<Incident> = None;
+        for (time, backends) in &history {
+            let traffic = backends.get(backend).cloned().unwrap_or_default();
+            let state = ComponentState::from_traffic(&traffic);
+            if state >= ComponentState::Degraded {
+                let incident = open.get_or_insert_with(|| Incident {
+                    component: name.clone(),
+                    status: state,
+                    started_at: time.to_rfc3339(),
+                    ended_at: None,
+                    min_availability: 100.0,
+                });
+                incident.status = incident.status.max(state);
+                incident.min_availability = incident.min_availability.min(availability(&traffic));
+            } else if let Some(mut incident) = open.take() {
+                incident.ended_at = Some(time.to_rfc3339());
+                incidents.push(incident);
+            }
+        }
+        // An incident in an hour that is over has ended, even without newer traffic
+        if let Some(mut incident) = open {
+            let last_hour = history.keys().next_back().copied().unwrap_or(current_hour);
+            if last_hour < current_hour {
+                incident.ended_at = Some((last_hour + Duration::hours(1)).to_rfc3339());
+            }
+            incidents.push(incident);
+        }
+    }
+    incidents.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+    let down = components
+        .iter()
+        .filter(|component| component.status == ComponentState::MajorOutage)
+        .count();
+    let status = if components.is_empty() {
+        ComponentState::Unknown
+    } else if down == components.len() {
+        ComponentState::MajorOutage
+    } else if down > 0 {
+        ComponentState::PartialOutage
+    } else if components
+        .iter()
+        .any(|component| component.status == ComponentState::Degraded)
+    {
+        ComponentState::Degraded
+    } else if components
+        .iter()
+        .all(|component| component.status == ComponentState::Unknown)
+    {
+        ComponentState::Unknown
+    } else {
+        ComponentState::Operational
+    };
+
+    Ok(StatusPage {
+        status,
+        components,
+        incidents,
+        updated_at: now.to_rfc3339(),
+    })
+}
+
+fn render_html(page: &StatusPage, config: &ProxyConfig) -> String {
+    let title = utils::escape_html(&config.status_page_title);
+    let components: String = page
+        .components
+        .iter()
+        .map(|component| {
+            format!(
+                "<li><span>{}</span><strong class=\"{}\">{}</strong></li>",
+                utils::escape_html(&component.name),
+                state_class(component.status),
+                component.status.label()
+            )
+        })
+        .collect();
+    let incidents: String = if page.incidents.is_empty() {
+        format!("<p>No incidents in the last {STATUS_HISTORY_DAYS} days.</p>")
+    } else {
+        let items: String = page
+            .incidents
+            .iter()
+            .map(|incident| {
+                format!(
+                    "<li><strong class=\"{}\">{}</strong> {} from {} to {} (availability {:.1}%)</li>",
+                    state_class(incident.status),
+                    incident.status.label(),
+                    utils::escape_html(&incident.component),
+                    incident.started_at,
+                    incident.ended_at.as_deref().unwrap_or("now"),
+                    incident.min_availability
+                )
+            })
+            .collect();
+        format!("<ul>{items}</ul>")
+    };
+
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<meta http-equiv=\"refresh\" content=\"60\">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 40rem; margin: 2rem auto; padding: 0 1rem; color: #222; }}
+ul {{ list-style: none; padding: 0; }}
+li {{ display: flex; justify-content: space-between; gap: 1rem; padding: 0.5rem 0; border-bottom: 1px solid #eee; }}
+.ok {{ color: #1a7f37; }} .warn {{ color: #9a6700; }} .down {{ color: #cf222e; }} .unknown {{ color: #777; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<h2 class=\"{}\">{}</h2>
+<ul>{components}</ul>
+<h3>Recent incidents</h3>
+{incidents}
+<p><small>Updated {}</small></p>
+</body>
+</html>
+",
+        state_class(page.status),
+        page.status.label(),
+        page.updated_at
+    )
+}
+
+fn state_class(state: ComponentState) -> &'static str {
+    match state {
+        ComponentState::Unknown => "unknown",
+        ComponentState::Operational => "ok",
+        ComponentState::Degraded => "warn",
+        ComponentState::PartialOutage | ComponentState::MajorOutage => "down",
+    }
+}
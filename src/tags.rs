@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    config::{MetricTagRule, ProxyConfig},
+    context::RequestContext,
+    utils,
+};
+
+/// Whether every condition of a rule holds for the request
+fn matches(rule: &MetricTagRule, ctx: &RequestContext) -> bool {
+    if let Some(path) = &rule.path {
+        if !utils::path_matches(path, &ctx.path) {
+            return false;
+        }
+    }
+    if let Some(header) = &rule.header {
+        let pattern = rule.pattern.as_deref().unwrap_or("*");
+        match ctx.header(header) {
+            Some(value) if utils::path_matches(pattern, &value) => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Metric tags of a request: each tag takes the value of its first matching
+/// rule, and is left out when none match
+pub fn resolve(config: &ProxyConfig, ctx: &RequestContext) -> BTreeMap<String, String> {
+    let mut tags = BTreeMap::new();
+    for rule in &config.metric_tags {
+        if !tags.contains_key(&rule.tag) && matches(rule, ctx) {
+            tags.insert(rule.tag.clone(), rule.value.clone());
+        }
+    }
+    tags
+}
@@ -0,0 +1,91 @@
+use base64::Engine;
+use regex::Regex;
+use worker::*;
+
+use crate::{config::ProxyConfig, utils};
+
+/// Resolve tenant id from hostname, header, or JWT claim
+pub fn resolve_tenant(req: &Request, config: &ProxyConfig) -> Result<Option<String>> {
+    let tenant = match config.tenant_source.as_str() {
+        "host" => {
+            let url = req.url()?;
+            let host = url.host_str().unwrap_or("");
+            tenant_from_host(host, &config.tenant_host_pattern)
+        }
+        "header" => req.headers().get(&config.tenant_header)?,
+        "jwt" => req.headers().get("Authorization")?.and_then(|value| {
+            jwt_claim(&value, &config.tenant_jwt_claim, &config.tenant_jwt_secret)
+        }),
+        _ => None,
+    };
+
+    // Tenant ids end up in cache keys and headers, keep them tame
+    Ok(tenant.filter(|id| {
+        !id.is_empty()
+            && id.len() <= 64
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    }))
+}
+
+/// Extract tenant from the first capture group of the host pattern,
+/// or the leftmost label when no pattern is configured
+fn tenant_from_host(host: &str, pattern: &str) -> Option<String> {
+    if pattern.is_empty() {
+        return host
+            .split('.')
+            .next()
+            .filter(|_| host.matches('.').count() >= 2)
+            .map(|label| label.to_string());
+    }
+
+    let regex = Regex::new(pattern).ok()?;
+    regex
+        .captures(host)
+        .and_then(|captures| captures.get(1))
+        .map(|m| m.as_str().to_string())
+}
+
+fn decode_segment(segment: &str) -> Option<Vec<u8>> {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(segment.trim_end_matches('='))
+        .ok()
+}
+
+/// Read a claim from a bearer JWT, only once its HS256 signature is verified
+/// with `secret` and it has not expired. The tenant namespaces cache entries
+/// and rate limits, so a forged token must never select another tenant.
+fn jwt_claim(authorization: &str, claim: &str, secret: &str) -> Option<String> {
+    if secret.is_empty() {
+        return None;
+    }
+    let token = authorization.strip_prefix("Bearer ")?.trim();
+    let mut segments = token.split('.');
+    let (header, payload, signature) = (segments.next()?, segments.next()?, segments.next()?);
+    if segments.next().is_some() {
+        return None;
+    }
+
+    let header: serde_json::Value = serde_json::from_slice(&decode_segment(header)?).ok()?;
+    if header.get("alg")?.as_str()? != "HS256" {
+        return None;
+    }
+    let expected = utils::hmac_sha256_hex(secret, &token.as_bytes()[..token.rfind('.')?]);
+    if !utils::constant_time_eq(&hex::encode(decode_segment(signature)?), &expected) {
+        return None;
+    }
+
+    let claims: serde_json::Value = serde_json::from_slice(&decode_segment(payload)?).ok()?;
+    if let Some(exp) = claims.get("exp").and_then(|exp| exp.as_f64()) {
+        if exp * 1000.0 <= js_sys::Date::now() {
+            return None;
+        }
+    }
+
+    match claims.get(claim)? {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
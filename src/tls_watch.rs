@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use worker::*;
+
+use crate::{
+    alerts::{self, Alert},
+    config::ProxyConfig,
+    latency,
+};
+
+/// KV key holding the TLS observations of every backend
+const TLS_STATUS_KEY: &str = "tls_watch:status";
+
+/// Cloudflare status codes for failed TLS to the origin
+const TLS_ERROR_STATUSES: &[u16] = &[525, 526];
+
+/// Fragments of fetch errors caused by TLS
+const TLS_ERROR_HINTS: &[&str] = &["tls", "ssl", "certificate", "handshake", "x509"];
+
+/// Observed TLS state of a backend, updated on every cron check
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsObservation {
+    pub checked_at: String,
+    pub ok: bool,
+    pub status: Option<u16>,
+    pub server: Option<String>,
+    pub hsts: Option<String>, // Strict-Transport-Security of the backend
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    pub failing_since: Option<String>,
+    pub last_ok_at: Option<String>,
+}
+
+/// Outcome of probing a backend over HTTPS
+enum Probe {
+    Ok {
+        status: u16,
+        server: Option<String>,
+        hsts: Option<String>,
+    },
+    TlsFailure(String),
+    OtherFailure(String), // Timeouts and unrelated errors, not judged either way
+}
+
+/// Probe every HTTPS backend and alert on consistent TLS failures
+pub async fn run(config: &ProxyConfig, env: &Env) {
+    if !config.tls_watch_enabled {
+        return;
+    }
+
+    if let Err(e) = check_backends(config, env).await {
+        console_log!("TLS watchdog failed: {:?}", e);
+    }
+}
+
+async fn check_backends(config: &ProxyConfig, env: &Env) -> Result<()> {
+    let kv = env.kv("PROXY_KV")?;
+    let previous = kv
+        .get(TLS_STATUS_KEY)
+        .json::<BTreeMap<String, TlsObservation>>()
+        .await?
+        .unwrap_or_default();
+
+    let backends: Vec<&String> = config
+        .backends
+        .iter()
+        .filter(|backend| backend.starts_with("https://"))
+        .collect();
+    let probes = join_all(
+        backends
+            .iter()
+            .map(|backend| probe(backend, config.health_check_timeout * 1000)),
+    )
+    .await;
+
+    let now = Utc::now().to_rfc3339();
+    let mut observations = BTreeMap::new();
+    let mut failing = Vec::new();
+    for (backend, probe) in backends.into_iter().zip(probes) {
+        let mut observation = previous.get(backend).cloned().unwrap_or_default();
+        observation.checked_at = now.clone();
+        match probe {
+            Probe::Ok {
+                status,
+                server,
+                hsts,
+            } => {
+                observation.ok = true;
+                observation.status = Some(status);
+                observation.server = server;
+                observation.hsts = hsts;
+                observation.last_error = None;
+                observation.consecutive_failures = 0;
+                observation.failing_since = None;
+                observation.last_ok_at = Some(now.clone());
+            }
+            Probe::TlsFailure(error) => {
+                console_log!("TLS check of {} failed: {}", backend, error);
+                observation.ok = false;
+                observation.last_error = Some(error);
+                observation.consecutive_failures += 1;
+                observation.failing_since.get_or_insert_with(|| now.clone());
+                if observation.consecutive_failures >= config.tls_watch_failure_threshold {
+                    failing.push(format!(
+                        "{} - {} consecutive failures since {}: {}",
+                        backend,
+                        observation.consecutive_failures,
+                        observation.failing_since.as_deref().unwrap_or(&now),
+                        observation.last_error.as_deref().unwrap_or_default()
+                    ));
+                }
+            }
+            Probe::OtherFailure(error) => {
+                observation.last_error = Some(error);
+            }
+        }
+        observations.insert(backend.clone(), observation);
+    }
+
+    kv.put(TLS_STATUS_KEY, serde_json::to_string(&observations)?)?
+        .execute()
+        .await?;
+
+    if !failing.is_empty() {
+        let details = format!(
+            "TLS connections to these backends keep failing:\n\n{}",
+            failing.join("\n")
+        );
+        alerts::send(
+            Alert::BackendTlsFailing,
+            details,
+            config.clone(),
+            env.clone(),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// HEAD the backend over HTTPS, any response proves a working TLS connection
+/// unless Cloudflare reports an origin TLS error
+async fn probe(backend: &str, timeout_ms: u64) -> Probe {
+    let mut init = RequestInit::new();
+    init.with_method(Method::Head);
+    let req = match Request::new_with_init(backend, &init) {
+        Ok(req) => req,
+        Err(e) => return Probe::OtherFailure(e.to_string()),
+    };
+
+    match latency::fetch_with_timeout(req, timeout_ms).await {
+        Ok(response) if TLS_ERROR_STATUSES.contains(&response.status_code()) => Probe::TlsFailure(
+            format!("origin TLS error (status {})", response.status_code()),
+        ),
+        Ok(response) => {
+            let headers = response.headers();
+            Probe::Ok {
+                status: response.status_code(),
+                server: headers.get("Server").ok().flatten(),
+                hsts: headers.get("Strict-Transport-Security").ok().flatten(),
+            }
+        }
+        Err(e) => {
+            let error = e.to_string();
+            let lowercase = error.to_lowercase();
+            if TLS_ERROR_HINTS.iter().any(|hint| lowercase.contains(hint)) {
+                Probe::TlsFailure(error)
+            } else {
+                Probe::OtherFailure(error)
+            }
+        }
+    }
+}
+
+/// Latest TLS observations of the backends
+pub async fn handle_status(env: &Env) -> Result<Response> {
+    let backends = env
+        .kv("PROXY_KV")?
+        .get(TLS_STATUS_KEY)
+        .json::<BTreeMap<String, TlsObservation>>()
+        .await?
+        .unwrap_or_default();
+
+    Response::from_json(&serde_json::json!({
+        "backends": backends,
+        "timestamp": Utc::now().to_rfc3339()
+    }))
+}
@@ -32,19 +32,16 @@ pub fn is_valid_url(url: &str) -> bool {
 }
 
 /// Generate request ID
-#[allow(dead_code)]
 pub fn generate_request_id() -> String {
     uuid::Uuid::new_v4().to_string()
 }
 
 /// Safely get header value
-#[allow(dead_code)]
 pub fn get_header_value(headers: &Headers, name: &str) -> Option<String> {
     headers.get(name).ok().flatten()
 }
 
 /// Get client IP
-#[allow(dead_code)]
 pub fn get_client_ip(headers: &Headers, cf: Option<&Cf>) -> Option<String> {
     // Prefer using Cloudflare provided IP
     if let Some(_cf_data) = cf {
@@ -147,15 +144,22 @@ fn is_bot(ua: &str) -> bool {
         .any(|&indicator| ua_lower.contains(indicator))
 }
 
+/// Get a request cookie value by name
+pub fn cookie_value(req: &Request, name: &str) -> Result<Option<String>> {
+    let cookies = req.headers().get("Cookie")?.unwrap_or_default();
+    Ok(cookies.split(';').find_map(|cookie| {
+        let (key, value) = cookie.trim().split_once('=')?;
+        (key == name).then(|| value.to_string())
+    }))
+}
+
 /// Base64 encoding
-#[allow(dead_code)]
 pub fn base64_encode(data: &[u8]) -> String {
     use base64::Engine;
     base64::engine::general_purpose::STANDARD.encode(data)
 }
 
 /// Base64 decoding
-#[allow(dead_code)]
 pub fn base64_decode(data: &str) -> Result<Vec<u8>> {
     use base64::Engine;
     base64::engine::general_purpose::STANDARD
@@ -164,7 +168,6 @@ pub fn base64_decode(data: &str) -> Result<Vec<u8>> {
 }
 
 /// Calculate SHA-256 hash
-#[allow(dead_code)]
 pub fn sha256_hash(data: &str) -> String {
     use sha2::{Digest, Sha256};
     let mut hasher = Sha256::new();
@@ -172,6 +175,31 @@ pub fn sha256_hash(data: &str) -> String {
     hex::encode(hasher.finalize())
 }
 
+/// Calculate HMAC-SHA256 and return hex digest
+pub fn hmac_sha256_hex(secret: &str, data: &[u8]) -> String {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    // HMAC accepts keys of any length, so this never fails in practice
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return String::new(),
+    };
+    mac.update(data);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Compare two strings in constant time
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
 /// Verify HMAC-SHA256 signature
 #[allow(dead_code)]
 pub fn verify_hmac_sha256(data: &str, signature: &str, secret: &str) -> bool {
@@ -182,6 +210,46 @@ pub fn verify_hmac_sha256(data: &str, signature: &str, secret: &str) -> bool {
     computed_hash == signature
 }
 
+/// Percent-encode a string (RFC 3986 unreserved characters are kept)
+pub fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Decode percent-encoded string, leaving invalid sequences untouched
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex = [bytes[i + 1], bytes[i + 2]];
+            if let Ok(byte) = u8::from_str_radix(&String::from_utf8_lossy(&hex), 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
 /// Clean and validate path
 #[allow(dead_code)]
 pub fn clean_path(path: &str) -> String {
@@ -207,6 +275,100 @@ pub fn clean_path(path: &str) -> String {
     }
 }
 
+/// Check that a URL is an HTTP(S) URL of a public host, rejecting loopback,
+/// private, link-local and other internal addresses
+pub fn is_public_url(url: &str) -> bool {
+    let url = match url::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+
+    match url.host() {
+        Some(url::Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            !(domain == "localhost"
+                || domain.ends_with(".localhost")
+                || domain.ends_with(".local")
+                || domain.ends_with(".internal"))
+        }
+        Some(url::Host::Ipv4(ip)) => is_public_ipv4(ip),
+        Some(url::Host::Ipv6(ip)) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(ip),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (first & 0xfe00) == 0xfc00 // Unique local fc00::/7
+                    || (first & 0xffc0) == 0xfe80) // Link-local fe80::/10
+            }
+        },
+        None => false,
+    }
+}
+
+fn is_public_ipv4(ip: std::net::Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))) // Carrier-grade NAT 100.64.0.0/10
+}
+
+/// Match path against a simple glob pattern (`*` matches any characters)
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    if !pattern.contains('*') {
+        return pattern == path;
+    }
+
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let last = parts.len() - 1;
+    let mut remaining = path;
+
+    for (i, part) in parts.iter().enumerate() {
+        if i == 0 {
+            match remaining.strip_prefix(part) {
+                Some(rest) => remaining = rest,
+                None => return false,
+            }
+        } else if i == last {
+            return remaining.ends_with(part);
+        } else {
+            match remaining.find(part) {
+                Some(pos) => remaining = &remaining[pos + part.len()..],
+                None => return false,
+            }
+        }
+    }
+
+    true
+}
+
+/// Rebuild response with a new body, keeping status and headers
+pub fn rebuild_response(response: &Response, body: Vec<u8>) -> Result<Response> {
+    let headers = response.headers().clone();
+    // Body length changes, let the runtime recompute it
+    headers.delete("Content-Length")?;
+
+    Ok(Response::from_bytes(body)?
+        .with_status(response.status_code())
+        .with_headers(headers))
+}
+
+/// Escape text for safe inclusion in HTML
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
 pub fn set_panic_hook() {
     #[cfg(feature = "debug")]
     {
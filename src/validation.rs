@@ -0,0 +1,132 @@
+use serde_json::Value;
+use worker::*;
+
+use crate::{
+    config::{ProxyConfig, SchemaRule},
+    context::RequestContext,
+    utils,
+};
+
+/// Maximum number of schema errors reported back to the client
+const MAX_REPORTED_ERRORS: usize = 20;
+
+/// Validate the request body against the matching route schema.
+/// Returns a 422 response with error details when the body is invalid.
+pub async fn validate_request(req: &Request, config: &ProxyConfig) -> Result<Option<Response>> {
+    let method = req.method().to_string();
+    let path = req.url()?.path().to_string();
+    let schema = match find_rule(config, &path, &method).and_then(|rule| rule.request.as_ref()) {
+        Some(schema) => schema,
+        None => return Ok(None),
+    };
+
+    // Inspect a clone so the original body can still be forwarded
+    let body = req.clone()?.bytes().await?;
+    let payload: Value = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            return invalid_body_response(vec![serde_json::json!({
+                "path": "",
+                "message": format!("Invalid JSON: {e}")
+            })])
+            .map(Some);
+        }
+    };
+
+    let errors = schema_errors(schema, &payload);
+    if errors.is_empty() {
+        return Ok(None);
+    }
+
+    console_log!(
+        "Rejecting {} {}: {} schema violation(s)",
+        method,
+        path,
+        errors.len()
+    );
+    invalid_body_response(errors).map(Some)
+}
+
+/// Validate JSON response bodies in debug mode, reporting violations in a header
+pub async fn validate_response(
+    mut response: Response,
+    config: &ProxyConfig,
+    ctx: &RequestContext,
+    method: &str,
+) -> Result<Response> {
+    if !config.schema_validate_responses {
+        return Ok(response);
+    }
+
+    let schema = match find_rule(config, &ctx.path, method).and_then(|rule| rule.response.as_ref())
+    {
+        Some(schema) => schema,
+        None => return Ok(response),
+    };
+
+    let content_type = response.headers().get("Content-Type")?.unwrap_or_default();
+    if !content_type.to_lowercase().contains("application/json") {
+        return Ok(response);
+    }
+
+    let body = response.cloned()?.bytes().await?;
+    let errors = match serde_json::from_slice::<Value>(&body) {
+        Ok(payload) => schema_errors(schema, &payload),
+        Err(e) => vec![serde_json::json!({ "path": "", "message": format!("Invalid JSON: {e}") })],
+    };
+
+    if !errors.is_empty() {
+        console_log!(
+            "Response schema violations for {} {}: {}",
+            ctx.request_id,
+            ctx.path,
+            Value::Array(errors.clone())
+        );
+        response
+            .headers()
+            .set("X-Schema-Violations", &errors.len().to_string())?;
+    }
+
+    Ok(response)
+}
+
+/// Find the first schema rule matching path and method
+fn find_rule<'a>(config: &'a ProxyConfig, path: &str, method: &str) -> Option<&'a SchemaRule> {
+    config.schema_rules.iter().find(|rule| {
+        utils::path_matches(&rule.path, path)
+            && (rule.methods.is_empty()
+                || rule.methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+    })
+}
+
+/// Collect schema violations as JSON error details
+fn schema_errors(schema: &Value, instance: &Value) -> Vec<Value> {
+    let validator = match jsonschema::validator_for(schema) {
+        Ok(validator) => validator,
+        Err(e) => {
+            // A broken schema must not block traffic
+            console_log!("Invalid JSON Schema in SCHEMA_RULES: {}", e);
+            return vec![];
+        }
+    };
+
+    validator
+        .iter_errors(instance)
+        .take(MAX_REPORTED_ERRORS)
+        .map(|error| {
+            serde_json::json!({
+                "path": error.instance_path.to_string(),
+                "message": error.to_string()
+            })
+        })
+        .collect()
+}
+
+/// Build a 422 response listing validation errors
+fn invalid_body_response(errors: Vec<Value>) -> Result<Response> {
+    Response::from_json(&serde_json::json!({
+        "error": "Request body failed schema validation",
+        "details": errors
+    }))
+    .map(|response| response.with_status(422))
+}
@@ -0,0 +1,83 @@
+use worker::*;
+
+use crate::{
+    config::{ProxyConfig, WatermarkRule},
+    context::RequestContext,
+    utils,
+};
+
+/// Embed a per-client watermark into responses on matching routes
+pub async fn apply_watermark(
+    mut response: Response,
+    config: &ProxyConfig,
+    ctx: &RequestContext,
+) -> Result<Response> {
+    let rule = match find_rule(config, &ctx.path) {
+        Some(rule) => rule,
+        None => return Ok(response),
+    };
+
+    let mark = client_mark(config, ctx);
+    console_log!(
+        "Watermarking response {} with mark {}",
+        ctx.request_id,
+        mark
+    );
+
+    let content_type = response
+        .headers()
+        .get("Content-Type")?
+        .unwrap_or_default()
+        .to_lowercase();
+
+    match rule.mode.as_str() {
+        "html_comment" if content_type.contains("text/html") => {
+            let mut body = response.bytes().await?;
+            body.extend_from_slice(format!("\n<!-- {mark} -->\n").as_bytes());
+            utils::rebuild_response(&response, body)
+        }
+        "json_field" if content_type.contains("application/json") => {
+            let body = response.bytes().await?;
+            let field = rule.field.as_deref().unwrap_or("_wm");
+            let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+                Ok(serde_json::Value::Object(mut object)) => {
+                    object.insert(field.to_string(), serde_json::Value::String(mark));
+                    serde_json::to_vec(&object)?
+                }
+                // Only objects can carry an extra field, leave other payloads untouched
+                _ => body,
+            };
+            utils::rebuild_response(&response, body)
+        }
+        "header" => {
+            let header = rule.field.as_deref().unwrap_or("X-Content-Mark");
+            response.headers().set(header, &mark)?;
+            Ok(response)
+        }
+        _ => Ok(response),
+    }
+}
+
+/// Check if responses of the path are watermarked
+pub fn is_watermarked(config: &ProxyConfig, path: &str) -> bool {
+    find_rule(config, path).is_some()
+}
+
+/// Find the first watermark rule matching the path
+fn find_rule<'a>(config: &'a ProxyConfig, path: &str) -> Option<&'a WatermarkRule> {
+    config
+        .watermark_rules
+        .iter()
+        .find(|rule| utils::path_matches(&rule.path, path))
+}
+
+/// Derive a stable mark from the client API key, falling back to client IP
+fn client_mark(config: &ProxyConfig, ctx: &RequestContext) -> String {
+    let client_id = ctx
+        .header(&config.watermark_key_header)
+        .or_else(|| ctx.client_ip.clone())
+        .unwrap_or_else(|| "anonymous".to_string());
+
+    let hash = utils::sha256_hash(&format!("{}:{}", config.watermark_secret, client_id));
+    hash[..16].to_string()
+}
@@ -0,0 +1,222 @@
+use std::cell::RefCell;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use worker::*;
+
+use crate::{config::ProxyConfig, config_store, utils};
+
+/// KV key holding the fingerprint of the last seen configuration
+const CONFIG_FINGERPRINT_KEY: &str = "proxy:config_fingerprint";
+
+thread_local! {
+    /// Hash of the configuration this isolate last compared with the stored one
+    static LAST_CHECKED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Notable proxy lifecycle events
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyEvent {
+    ConfigChanged,
+    BackendAdded,
+    BackendRemoved,
+    MaintenanceToggled,
+    BanApplied,
+    ContractViolation,
+    SecretsRotated,
+    ContentChanged,
+}
+
+impl ProxyEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProxyEvent::ConfigChanged => "config_changed",
+            ProxyEvent::BackendAdded => "backend_added",
+            ProxyEvent::BackendRemoved => "backend_removed",
+            ProxyEvent::MaintenanceToggled => "maintenance_toggled",
+            ProxyEvent::BanApplied => "ban_applied",
+            ProxyEvent::ContractViolation => "contract_violation",
+            ProxyEvent::SecretsRotated => "secrets_rotated",
+            ProxyEvent::ContentChanged => "content_changed",
+        }
+    }
+}
+
+/// Signed webhook emitter
+#[derive(Debug, Clone)]
+pub struct WebhookEmitter {
+    url: Option<String>,
+    secret: String,
+    events: Vec<String>,
+}
+
+impl WebhookEmitter {
+    pub fn new(config: &ProxyConfig) -> Self {
+        Self {
+            url: config.webhook_url.clone(),
+            secret: config.webhook_secret.clone(),
+            events: config.webhook_events.clone(),
+        }
+    }
+
+    /// Check if webhooks are configured at all
+    pub fn is_enabled(&self) -> bool {
+        self.url.is_some()
+    }
+
+    /// Check if the event is subscribed (empty list means all events)
+    fn is_subscribed(&self, event: ProxyEvent) -> bool {
+        self.events.is_empty() || self.events.iter().any(|name| name == event.as_str())
+    }
+
+    /// Send a signed event to the configured endpoint
+    pub async fn emit(&self, event: ProxyEvent, details: Value) {
+        let url = match &self.url {
+            Some(url) if self.is_subscribed(event) => url,
+            _ => return,
+        };
+
+        if let Err(e) = self.send(url, event, details).await {
+            console_log!("Failed to deliver webhook {}: {:?}", event.as_str(), e);
+        }
+    }
+
+    async fn send(&self, url: &str, event: ProxyEvent, details: Value) -> Result<()> {
+        let timestamp = Utc::now().timestamp().to_string();
+        let body = serde_json::json!({
+            "id": utils::generate_request_id(),
+            "event": event.as_str(),
+            "details": details,
+            "timestamp": Utc::now().to_rfc3339()
+        })
+        .to_string();
+
+        // Signature covers timestamp and body to prevent replays with altered payloads
+        let signature =
+            utils::hmac_sha256_hex(&self.secret, format!("{timestamp}.{body}").as_bytes());
+
+        let headers = Headers::new();
+        headers.set("Content-Type", "application/json")?;
+        headers.set("X-Proxy-Event", event.as_str())?;
+        headers.set("X-Proxy-Timestamp", &timestamp)?;
+        headers.set("X-Proxy-Signature", &format!("sha256={signature}"))?;
+
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post)
+            .with_headers(headers)
+            .with_body(Some(body.into()));
+
+        let response = Fetch::Request(Request::new_with_init(url, &init)?)
+            .send()
+            .await?;
+        console_log!(
+            "Webhook {} delivered with status {}",
+            event.as_str(),
+            response.status_code()
+        );
+        Ok(())
+    }
+}
+
+/// Fingerprint of the configuration used for change detection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ConfigFingerprint {
+    hash: String,
+    backends: Vec<String>,
+}
+
+/// Compare the active configuration with the last seen one and emit change events.
+/// Rollout candidates are ignored; they are reported once promoted.
+pub async fn detect_config_changes(env: Env) {
+    // Stored configuration can change without a deployment, so compare on
+    // every request but only consult KV when this isolate sees a new hash
+    let config = match config_store::active_config(&env).await {
+        Ok(config) => config,
+        Err(_) => return,
+    };
+
+    let emitter = WebhookEmitter::new(&config);
+    if !emitter.is_enabled() {
+        return;
+    }
+
+    let kv = match env.kv("PROXY_KV") {
+        Ok(kv) => kv,
+        Err(_) => return,
+    };
+
+    let serialized = serde_json::to_value(&config)
+        .map(|value| value.to_string())
+        .unwrap_or_default();
+    let current = ConfigFingerprint {
+        hash: utils::sha256_hash(&serialized),
+        backends: config.backends.clone(),
+    };
+    let seen = LAST_CHECKED.with(|last| {
+        last.borrow_mut()
+            .replace(current.hash.clone())
+            .is_some_and(|hash| hash == current.hash)
+    });
+    if seen {
+        return;
+    }
+
+    let previous = kv
+        .get(CONFIG_FINGERPRINT_KEY)
+        .json::<ConfigFingerprint>()
+        .await
+        .ok()
+        .flatten();
+
+    match previous {
+        Some(previous) if previous.hash == current.hash => return,
+        Some(previous) => {
+            emitter
+                .emit(
+                    ProxyEvent::ConfigChanged,
+                    serde_json::json!({
+                        "previous_hash": previous.hash,
+                        "current_hash": current.hash
+                    }),
+                )
+                .await;
+
+            for backend in current
+                .backends
+                .iter()
+                .filter(|b| !previous.backends.contains(b))
+            {
+                emitter
+                    .emit(
+                        ProxyEvent::BackendAdded,
+                        serde_json::json!({ "backend": backend }),
+                    )
+                    .await;
+            }
+
+            for backend in previous
+                .backends
+                .iter()
+                .filter(|b| !current.backends.contains(b))
+            {
+                emitter
+                    .emit(
+                        ProxyEvent::BackendRemoved,
+                        serde_json::json!({ "backend": backend }),
+                    )
+                    .await;
+            }
+        }
+        // First deployment with webhooks, just record the baseline
+        None => {}
+    }
+
+    if let Ok(fingerprint) = serde_json::to_string(&current) {
+        if let Ok(put) = kv.put(CONFIG_FINGERPRINT_KEY, fingerprint) {
+            if let Err(e) = put.execute().await {
+                console_log!("Failed to store config fingerprint: {:?}", e);
+            }
+        }
+    }
+}